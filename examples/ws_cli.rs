@@ -51,7 +51,7 @@ pub fn main() {
                 "echo.websocket.org", "/"))
             .map_err(|e| error!("Error {}", e))
         })
-        .and_then(move |(out, inp, ())| {
+        .and_then(move |(out, inp, _)| {
             println!("Connected");
             let (tx, rx) = unbounded();
 
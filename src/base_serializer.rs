@@ -12,37 +12,97 @@ use tk_bufstream::Buf;
 use enums::Version;
 
 quick_error! {
+    /// Error adding a header to the message
+    ///
+    /// Use `kind()` if you only need to know the category of the error.
+    ///
+    /// Note: calling header methods in the wrong state (e.g. after headers
+    /// are done) is a programming error, so it panics instead of returning
+    /// an error.
     #[derive(Debug)]
     pub enum HeaderError {
+        /// `Content-Length` is added twice
         DuplicateContentLength {
             description("Content-Length is added twice")
+            display("Content-Length is added twice")
         }
+        /// `Transfer-Encoding` is added twice
         DuplicateTransferEncoding {
             description("Transfer-Encoding is added twice")
+            display("Transfer-Encoding is added twice")
         }
-        InvalidHeaderName {
+        /// Header name contains invalid characters
+        InvalidHeaderName(name: String) {
             description("Header name contains invalid characters")
+            display("Header name {:?} contains invalid characters", name)
         }
-        InvalidHeaderValue {
+        /// Header value contains invalid characters (i.e. newlines)
+        InvalidHeaderValue(name: String) {
             description("Header value contains invalid characters")
+            display("Value of the header {:?} contains invalid characters",
+                    name)
         }
+        /// `Transfer-Encoding` is added when `Content-Length` is already set
         TransferEncodingAfterContentLength {
             description("Transfer encoding added when Content-Length is \
                 already specified")
+            display("Transfer encoding added when Content-Length is \
+                already specified")
         }
+        /// `Content-Length` is added when `Transfer-Encoding` is already set
         ContentLengthAfterTransferEncoding {
             description("Content-Length added after Transfer-Encoding")
+            display("Content-Length added after Transfer-Encoding")
         }
+        /// Headers are finished without specifying length of the body
         CantDetermineBodySize {
             description("Neither Content-Length nor Transfer-Encoding \
                 is present in the headers")
+            display("Neither Content-Length nor Transfer-Encoding \
+                is present in the headers")
         }
+        /// Body length header is added using generic `add_header` method
         BodyLengthHeader {
             description("Content-Length and Transfer-Encoding must be set \
                 using the specialized methods")
+            display("Content-Length and Transfer-Encoding must be set \
+                using the specialized methods")
         }
+        /// Body length is set for the message that must not contain body
         RequireBodyless {
             description("This message must not contain body length fields.")
+            display("This message must not contain body length fields.")
+        }
+    }
+}
+
+/// A category of the `HeaderError`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderErrorKind {
+    /// Header name contains invalid characters
+    InvalidName,
+    /// Header value contains invalid characters
+    InvalidValue,
+    /// Header that must be unique is added twice
+    Duplicate,
+    /// Body length headers are missing, conflicting or not allowed
+    BodyLength,
+}
+
+impl HeaderError {
+    /// Returns a category of the error
+    pub fn kind(&self) -> HeaderErrorKind {
+        use self::HeaderError::*;
+        use self::HeaderErrorKind as K;
+        match *self {
+            InvalidHeaderName(..) => K::InvalidName,
+            InvalidHeaderValue(..) => K::InvalidValue,
+            DuplicateContentLength | DuplicateTransferEncoding => K::Duplicate,
+            TransferEncodingAfterContentLength |
+            ContentLengthAfterTransferEncoding |
+            CantDetermineBodySize |
+            BodyLengthHeader |
+            RequireBodyless => K::BodyLength,
         }
     }
 }
@@ -51,7 +111,38 @@ quick_error! {
 ///
 /// Note: while we pass buffer to each method, we expect that the same buffer
 /// is passed each time
+///
+/// Valid transitions (calling a method in any other state panics, header
+/// methods don't change the state unless specified):
+///
+/// ```text
+/// ResponseStart --response_continue--> FinalResponseStart
+/// ResponseStart | FinalResponseStart --response_status--> Headers
+/// RequestStart --request_line--> Headers
+///
+/// Headers --add_length--> FixedHeaders
+/// Headers --add_chunked--> ChunkedHeaders
+/// Headers | FixedHeaders | ChunkedHeaders --add_header/format_header--> *
+///
+/// Headers (body denied) --done_headers--> Bodyless
+/// Headers (request) --done_headers--> FixedBody (zero length)
+/// FixedHeaders --done_headers--> FixedBody
+/// ChunkedHeaders --done_headers--> ChunkedBody
+///
+/// FixedBody | ChunkedBody --write_body--> *
+/// Bodyless | FixedBody | ChunkedBody --done--> Done
+/// ```
+///
+/// Fields of the variants: `version` is written in the status line,
+/// `body` tells whether the body is allowed, `close` makes `done_headers`
+/// add `Connection: close`, `is_head` means the body is not written (a
+/// response to `HEAD`), and `content_length` of `FixedBody` is the number
+/// of bytes left to write.
+///
+/// This type is exported only with the `low_level` feature, it's used by
+/// encoders of this crate and is useful to implement your own ones.
 #[derive(Debug)]
+#[allow(missing_docs)]
 pub enum MessageState {
     /// Nothing has been sent.
     ResponseStart { version: Version, body: Body, close: bool },
@@ -82,6 +173,7 @@ pub enum MessageState {
     Done,
 }
 
+/// Whether the message may contain a body, used in `MessageState`
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Body {
     /// Message contains a body.
@@ -96,7 +188,7 @@ pub enum Body {
     Request,
 }
 
-fn invalid_header(value: &[u8]) -> bool {
+pub fn invalid_header(value: &[u8]) -> bool {
     return value.iter().any(|&x| x == b'\r' || x == b'\n')
 }
 
@@ -199,7 +291,7 @@ impl MessageState {
         -> Result<(), HeaderError>
     {
         if invalid_header(name.as_bytes()) {
-            return Err(HeaderError::InvalidHeaderName);
+            return Err(HeaderError::InvalidHeaderName(name.to_string()));
         }
         let start = buf.len();
         buf.write_all(name.as_bytes()).unwrap();
@@ -209,7 +301,7 @@ impl MessageState {
         buf.write_all(value).unwrap();
         if invalid_header(&buf[value_start..]) {
             buf.remove_range(start..);
-            return Err(HeaderError::InvalidHeaderValue);
+            return Err(HeaderError::InvalidHeaderValue(name.to_string()));
         }
 
         buf.write_all(b"\r\n").unwrap();
@@ -221,7 +313,7 @@ impl MessageState {
         -> Result<(), HeaderError>
     {
         if invalid_header(name.as_bytes()) {
-            return Err(HeaderError::InvalidHeaderName);
+            return Err(HeaderError::InvalidHeaderName(name.to_string()));
         }
         let start = buf.len();
         buf.write_all(name.as_bytes()).unwrap();
@@ -231,7 +323,7 @@ impl MessageState {
         write!(buf, "{}", value).unwrap();
         if invalid_header(&buf[value_start..]) {
             buf.remove_range(start..);
-            return Err(HeaderError::InvalidHeaderValue);
+            return Err(HeaderError::InvalidHeaderValue(name.to_string()));
         }
 
         buf.write_all(b"\r\n").unwrap();
@@ -525,7 +617,7 @@ impl MessageState {
 mod test {
     use tk_bufstream::{Buf};
 
-    use super::{MessageState, Body};
+    use super::{MessageState, Body, HeaderErrorKind};
     use enums::Version;
 
     #[test]
@@ -577,6 +669,44 @@ mod test {
         buf
     }
 
+    #[test]
+    fn header_error_display() {
+        fn add(msg: &mut MessageState, buf: &mut Buf)
+            -> Result<(), Box<::std::error::Error>>
+        {
+            msg.add_header(buf, "X-Foo", b"1\r\n")?;
+            Ok(())
+        }
+        do_request(|mut msg, buf| {
+            msg.request_line(buf, "GET", "/", Version::Http11);
+            assert_eq!(add(&mut msg, buf).unwrap_err().to_string(),
+                "Value of the header \"X-Foo\" contains invalid characters");
+            assert_eq!(msg.add_header(buf, "X\nY", b"1").unwrap_err()
+                       .to_string(),
+                       "Header name \"X\\nY\" contains invalid characters");
+            msg.add_length(buf, 0).unwrap();
+            assert_eq!(msg.add_length(buf, 0).unwrap_err().to_string(),
+                       "Content-Length is added twice");
+        });
+    }
+
+    #[test]
+    fn header_error_kind() {
+        do_request(|mut msg, buf| {
+            msg.request_line(buf, "GET", "/", Version::Http11);
+            assert_eq!(msg.add_header(buf, "X\nY", b"1").unwrap_err().kind(),
+                       HeaderErrorKind::InvalidName);
+            assert_eq!(msg.add_header(buf, "X", b"1\r\n").unwrap_err().kind(),
+                       HeaderErrorKind::InvalidValue);
+            msg.add_length(buf, 0).unwrap();
+            assert_eq!(msg.add_length(buf, 0).unwrap_err().kind(),
+                       HeaderErrorKind::Duplicate);
+            assert_eq!(msg.add_header(buf, "Content-Length", b"1")
+                       .unwrap_err().kind(),
+                       HeaderErrorKind::BodyLength);
+        });
+    }
+
     #[test]
     fn minimal_request() {
         assert_eq!(&do_request(|mut msg, buf| {
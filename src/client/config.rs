@@ -12,6 +12,7 @@ impl Config {
             keep_alive_timeout: Duration::new(4, 0),
             safe_pipeline_timeout: Duration::from_millis(300),
             max_request_timeout: Duration::new(15, 0),
+            accept_encoding: false,
         }
     }
     /// A number of inflight requests until we start returning
@@ -97,6 +98,16 @@ impl Config {
         self
     }
 
+    /// Send `Accept-Encoding: gzip, deflate` with every request
+    ///
+    /// The header is not added if request already has `Accept-Encoding`.
+    /// Use `DecompressBody` to decode the response. Default is `false`.
+    #[cfg(feature="deflate")]
+    pub fn accept_encoding(&mut self, value: bool) -> &mut Self {
+        self.accept_encoding = value;
+        self
+    }
+
     /// Create a Arc'd config clone to pass to the constructor
     ///
     /// This is just a convenience method.
@@ -0,0 +1,240 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::io::{self, Write};
+use std::mem;
+use std::str::from_utf8;
+
+use flate2::write::{GzDecoder, ZlibDecoder, DeflateDecoder};
+use futures::{Stream, Async, Poll};
+use tokio_io::AsyncRead;
+
+use client::{Error, ResponseHead, BodyStream};
+use client::errors::ErrorEnum;
+
+
+enum Decoder {
+    Identity,
+    Gzip(GzDecoder<Vec<u8>>),
+    /// Encoding format is not known until the first two bytes are read
+    Deflate(Vec<u8>),
+    Zlib(ZlibDecoder<Vec<u8>>),
+    RawDeflate(DeflateDecoder<Vec<u8>>),
+}
+
+/// A stream of the response body that decodes `Content-Encoding`
+///
+/// Supports `gzip` (and `x-gzip`), `deflate` and `identity` encodings. For
+/// `deflate` both zlib format (which is the right one) and raw deflate
+/// stream (which is sent by some servers) are accepted.
+///
+/// Chunks of the decompressed body are yielded as soon as they are
+/// decompressed. Truncated compressed stream yields an error at the end
+/// of the body.
+pub struct DecompressBody<S> {
+    body: BodyStream<S>,
+    decoder: Decoder,
+    done: bool,
+}
+
+fn decode_error(e: io::Error) -> Error {
+    ErrorEnum::Decompress(e).into()
+}
+
+/// Checks whether data starts with zlib header (RFC 1950)
+fn is_zlib(data: &[u8]) -> bool {
+    data[0] & 0x0F == 8 && (data[0] as u16 * 256 + data[1] as u16) % 31 == 0
+}
+
+impl<S> DecompressBody<S> {
+    /// Wrap the body of the response
+    ///
+    /// Returns `UnsupportedContentEncoding` error if encoding of response
+    /// body isn't supported (including multiple encodings applied).
+    pub fn new(head: &ResponseHead, body: BodyStream<S>)
+        -> Result<DecompressBody<S>, Error>
+    {
+        let mut decoder = Decoder::Identity;
+        let mut encodings = head.all_headers().iter()
+            .filter(|&&(ref name, _)| {
+                name.eq_ignore_ascii_case("Content-Encoding")
+            })
+            .flat_map(|&(_, ref value)| value.split(|&x| x == b','))
+            .map(|x| from_utf8(x).map(|x| x.trim()).unwrap_or("\u{FFFD}"))
+            .filter(|x| x.len() > 0 && !x.eq_ignore_ascii_case("identity"));
+        if let Some(enc) = encodings.next() {
+            decoder = if enc.eq_ignore_ascii_case("gzip") ||
+                enc.eq_ignore_ascii_case("x-gzip")
+            {
+                Decoder::Gzip(GzDecoder::new(Vec::new()))
+            } else if enc.eq_ignore_ascii_case("deflate") {
+                Decoder::Deflate(Vec::new())
+            } else {
+                return Err(ErrorEnum::UnsupportedContentEncoding(
+                    enc.to_string()).into());
+            };
+        }
+        if let Some(enc) = encodings.next() {
+            return Err(ErrorEnum::UnsupportedContentEncoding(
+                enc.to_string()).into());
+        }
+        Ok(DecompressBody {
+            body: body,
+            decoder: decoder,
+            done: false,
+        })
+    }
+    /// Returns the underlying body stream
+    pub fn into_inner(self) -> BodyStream<S> {
+        self.body
+    }
+    /// Decodes chunk of data, returns uncompressed bytes
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<u8>, io::Error> {
+        use self::Decoder::*;
+        if let Deflate(ref mut head) = self.decoder {
+            head.extend(data);
+            if head.len() < 2 {
+                return Ok(Vec::new());
+            }
+        }
+        let data = match mem::replace(&mut self.decoder, Identity) {
+            Deflate(head) => {
+                self.decoder = if is_zlib(&head) {
+                    Zlib(ZlibDecoder::new(Vec::new()))
+                } else {
+                    RawDeflate(DeflateDecoder::new(Vec::new()))
+                };
+                return self.decode(&head);
+            }
+            decoder => {
+                self.decoder = decoder;
+                data
+            }
+        };
+        match self.decoder {
+            Identity => Ok(data.to_vec()),
+            Gzip(ref mut d) => {
+                d.write_all(data)?;
+                Ok(mem::replace(d.get_mut(), Vec::new()))
+            }
+            Zlib(ref mut d) => {
+                d.write_all(data)?;
+                Ok(mem::replace(d.get_mut(), Vec::new()))
+            }
+            RawDeflate(ref mut d) => {
+                d.write_all(data)?;
+                Ok(mem::replace(d.get_mut(), Vec::new()))
+            }
+            Deflate(..) => unreachable!(),
+        }
+    }
+    /// Checks that the compressed stream is complete, returns the rest
+    /// of the data
+    fn finish(&mut self) -> Result<Vec<u8>, io::Error> {
+        use self::Decoder::*;
+        fn check(bytes: u64) -> Result<(), io::Error> {
+            if bytes == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                    "empty compressed stream"));
+            }
+            Ok(())
+        }
+        match self.decoder {
+            Identity => Ok(Vec::new()),
+            Gzip(ref mut d) => {
+                d.try_finish()?;
+                Ok(mem::replace(d.get_mut(), Vec::new()))
+            }
+            Zlib(ref mut d) => {
+                check(d.total_in())?;
+                d.try_finish()?;
+                Ok(mem::replace(d.get_mut(), Vec::new()))
+            }
+            RawDeflate(ref mut d) => {
+                check(d.total_in())?;
+                d.try_finish()?;
+                Ok(mem::replace(d.get_mut(), Vec::new()))
+            }
+            Deflate(_) => Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                "truncated compressed stream")),
+        }
+    }
+}
+
+impl<S: AsyncRead> Stream for DecompressBody<S> {
+    type Item = Vec<u8>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        loop {
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+            match self.body.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    let data = self.decode(&chunk).map_err(decode_error)?;
+                    if data.len() > 0 {
+                        return Ok(Async::Ready(Some(data)));
+                    }
+                }
+                Async::Ready(None) => {
+                    self.done = true;
+                    let data = self.finish().map_err(decode_error)?;
+                    if data.len() > 0 {
+                        return Ok(Async::Ready(Some(data)));
+                    }
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder, DeflateEncoder};
+    use futures::{Future, Stream};
+    use tk_bufstream::IoBuf;
+
+    use client::{Error, ResponseProto};
+    use super::DecompressBody;
+
+    fn response(encoding: &str, body: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut data = format!("HTTP/1.1 200 OK\r\n\
+            Content-Encoding: {}\r\n\
+            Content-Length: {}\r\n\r\n", encoding, body.len()).into_bytes();
+        data.extend(body);
+        let (_, rx) = IoBuf::new(Cursor::new(data)).split();
+        let (head, body) = ResponseProto::new(rx, false).wait()?;
+        DecompressBody::new(&head, body)?.concat2().wait()
+    }
+
+    #[test]
+    fn gzip() {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(b"hello world").unwrap();
+        let data = enc.finish().unwrap();
+        assert_eq!(response("gzip", &data).unwrap(), b"hello world");
+        assert!(response("gzip", &data[..data.len()-4]).is_err());
+    }
+
+    #[test]
+    fn deflate() {
+        let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(b"hello zlib").unwrap();
+        let data = enc.finish().unwrap();
+        assert_eq!(response("deflate", &data).unwrap(), b"hello zlib");
+        let mut enc = DeflateEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(b"hello deflate").unwrap();
+        let data = enc.finish().unwrap();
+        assert_eq!(response("Deflate", &data).unwrap(), b"hello deflate");
+    }
+
+    #[test]
+    fn identity_and_unknown() {
+        assert_eq!(response("identity", b"plain").unwrap(), b"plain");
+        assert!(response("br", b"plain").is_err());
+        assert!(response("gzip, gzip", b"plain").is_err());
+    }
+}
@@ -6,7 +6,7 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
 
 use tk_bufstream::WriteBuf;
-use futures::{Future, Async};
+use futures::{Future, Async, Poll};
 use tokio_io::AsyncWrite;
 
 use enums::Version;
@@ -29,6 +29,7 @@ pub struct Encoder<S> {
     // TODO(tailhook) we could use smaller atomic, but they are unstable
     state: Arc<AtomicUsize>,
     close_signal: Arc<AtomicBool>,
+    accept_encoding: bool,
 }
 
 /// This structure returned from `Encoder::done` and works as a continuation
@@ -37,6 +38,22 @@ pub struct EncoderDone<S> {
     buf: WriteBuf<S>,
 }
 
+/// A writer of the chunked request body, created by `Encoder::body_chunked`
+pub struct ChunkedWriter<S> {
+    encoder: Encoder<S>,
+}
+
+/// A writer of the fixed-size request body, created by
+/// `Encoder::body_length`
+///
+/// Writes are buffered, so they never return `WouldBlock`. Writing more
+/// than declared length returns an error, so does `done()` if less bytes
+/// were written.
+pub struct BodyWriter<S> {
+    encoder: Encoder<S>,
+    remaining: u64,
+}
+
 /// A future that yields `Encoder` again after buffer has less bytes
 ///
 /// This future is created by `Encoder::wait_flush(x)``
@@ -97,6 +114,9 @@ impl<S> Encoder<S> {
         {
             self.close_signal.store(true, Ordering::SeqCst);
         }
+        if name.eq_ignore_ascii_case("Accept-Encoding") {
+            self.accept_encoding = false;
+        }
         self.message.add_header(&mut self.buf.out_buf, name, value.as_ref())
     }
 
@@ -111,6 +131,9 @@ impl<S> Encoder<S> {
         if name.eq_ignore_ascii_case("Connection") {
             unimplemented!();
         }
+        if name.eq_ignore_ascii_case("Accept-Encoding") {
+            self.accept_encoding = false;
+        }
         self.message.format_header(&mut self.buf.out_buf, name, value)
     }
 
@@ -152,6 +175,11 @@ impl<S> Encoder<S> {
     ///
     /// Panics when the request is in a wrong state.
     pub fn done_headers(&mut self) -> Result<(), HeaderError> {
+        if self.accept_encoding {
+            self.accept_encoding = false;
+            self.message.add_header(&mut self.buf.out_buf,
+                "Accept-Encoding", b"gzip, deflate")?;
+        }
         self.message.done_headers(&mut self.buf.out_buf)
         .map(|always_support_body| assert!(always_support_body))
     }
@@ -177,6 +205,33 @@ impl<S> Encoder<S> {
         self.message.done(&mut self.buf.out_buf);
         EncoderDone { buf: self.buf }
     }
+    /// Finish headers with `Content-Length` and return a writer for the body
+    ///
+    /// This is a shortcut for `add_length(len)` and `done_headers()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the request is in a wrong state.
+    pub fn body_length(mut self, len: u64)
+        -> Result<BodyWriter<S>, HeaderError>
+    {
+        self.add_length(len)?;
+        self.done_headers()?;
+        Ok(BodyWriter { encoder: self, remaining: len })
+    }
+    /// Finish headers with `Transfer-Encoding: chunked` and return a writer
+    /// for the body
+    ///
+    /// This is a shortcut for `add_chunked()` and `done_headers()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the request is in a wrong state.
+    pub fn body_chunked(mut self) -> Result<ChunkedWriter<S>, HeaderError> {
+        self.add_chunked()?;
+        self.done_headers()?;
+        Ok(ChunkedWriter { encoder: self })
+    }
 
     /// Flush the data to underlying socket
     ///
@@ -206,6 +261,85 @@ impl<S> Encoder<S> {
     }
 }
 
+impl<S> ChunkedWriter<S> {
+    /// Write a chunk of the body into the buffer
+    ///
+    /// Empty chunks are ignored, as zero-length chunk marks end of body.
+    pub fn write_chunk(&mut self, data: &[u8]) {
+        self.encoder.write_body(data)
+    }
+    /// Flush the data to underlying socket, see `Encoder::flush`
+    pub fn flush(&mut self) -> Result<(), io::Error>
+        where S: AsyncWrite
+    {
+        self.encoder.flush()
+    }
+    /// Returns bytes currently lying in the buffer
+    pub fn bytes_buffered(&mut self) -> usize {
+        self.encoder.bytes_buffered()
+    }
+    /// Write the final (zero-length) chunk and finish the request
+    pub fn done(self) -> EncoderDone<S> {
+        self.encoder.done()
+    }
+}
+
+impl<S> io::Write for ChunkedWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_chunk(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S> BodyWriter<S> {
+    /// Returns number of bytes that are still expected to be written
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+    /// Finish the request
+    ///
+    /// Returns an error if less bytes than declared in `Content-Length`
+    /// were written.
+    pub fn done(self) -> Result<EncoderDone<S>, io::Error> {
+        if self.remaining > 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                format!("request body is {} bytes shorter than declared",
+                        self.remaining)));
+        }
+        Ok(self.encoder.done())
+    }
+}
+
+impl<S> io::Write for BodyWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "request body is longer than declared"));
+        }
+        self.encoder.write_body(buf);
+        self.remaining -= buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        // data is sent by the connection, see `AsyncWrite::shutdown`
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for BodyWriter<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.encoder.flush()?;
+        if self.encoder.bytes_buffered() > 0 {
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(()))
+        }
+    }
+}
+
 impl<S: AsyncWrite> Future for WaitFlush<S> {
     type Item = Encoder<S>;
     type Error = io::Error;
@@ -232,9 +366,15 @@ pub fn new<S>(io: WriteBuf<S>,
         buf: io,
         state: state,
         close_signal: close_signal,
+        accept_encoding: false,
     }
 }
 
+/// Makes encoder add `Accept-Encoding` header unless it's set by user
+pub fn set_accept_encoding<S>(e: &mut Encoder<S>) {
+    e.accept_encoding = true;
+}
+
 impl<S> io::Write for Encoder<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         // TODO(tailhook) we might want to propatage error correctly
@@ -246,3 +386,87 @@ impl<S> io::Write for Encoder<S> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, AtomicBool};
+
+    use tk_bufstream::{IoBuf, MockData};
+
+    use enums::Version;
+    use super::{new, get_inner, set_accept_encoding};
+
+    #[test]
+    fn accept_encoding() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut e = new(tx, Arc::new(AtomicUsize::new(0)),
+                        Arc::new(AtomicBool::new(false)));
+        set_accept_encoding(&mut e);
+        e.request_line("GET", "/", Version::Http11);
+        e.done_headers().unwrap();
+        get_inner(e.done()).flush().unwrap();
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+                   "GET / HTTP/1.1\r\nAccept-Encoding: gzip, deflate\r\n\r\n");
+
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut e = new(tx, Arc::new(AtomicUsize::new(0)),
+                        Arc::new(AtomicBool::new(false)));
+        set_accept_encoding(&mut e);
+        e.request_line("GET", "/", Version::Http11);
+        e.add_header("accept-encoding", "gzip").unwrap();
+        e.done_headers().unwrap();
+        get_inner(e.done()).flush().unwrap();
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+                   "GET / HTTP/1.1\r\naccept-encoding: gzip\r\n\r\n");
+    }
+
+    #[test]
+    fn fixed_body() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut e = new(tx, Arc::new(AtomicUsize::new(0)),
+                        Arc::new(AtomicBool::new(false)));
+        e.request_line("POST", "/", Version::Http11);
+        let mut body = e.body_length(5).unwrap();
+        body.write_all(b"hel").unwrap();
+        assert!(body.write(b"lo!").is_err());
+        assert_eq!(body.remaining(), 2);
+        body.write_all(b"lo").unwrap();
+        get_inner(body.done().unwrap()).flush().unwrap();
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+                   "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn short_body() {
+        let (tx, _) = IoBuf::new(MockData::new()).split();
+        let mut e = new(tx, Arc::new(AtomicUsize::new(0)),
+                        Arc::new(AtomicBool::new(false)));
+        e.request_line("POST", "/", Version::Http11);
+        let mut body = e.body_length(5).unwrap();
+        body.write_all(b"hel").unwrap();
+        assert!(body.done().is_err());
+    }
+
+    #[test]
+    fn chunked_body() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut e = new(tx, Arc::new(AtomicUsize::new(0)),
+                        Arc::new(AtomicBool::new(false)));
+        e.request_line("PUT", "/upload", Version::Http11);
+        let mut body = e.body_chunked().unwrap();
+        body.write_chunk(b"hello");
+        body.write_chunk(b"");
+        body.write_chunk(b" world");
+        get_inner(body.done()).flush().unwrap();
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+                   "PUT /upload HTTP/1.1\r\n\
+                    Transfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+    }
+}
@@ -1,3 +1,5 @@
+use std::error::Error as StdError;
+use std::fmt;
 use std::io;
 use std::convert::From;
 
@@ -5,22 +7,27 @@ use futures::sync::mpsc::SendError;
 use httparse::Error as HttpError;
 use httparse::InvalidChunkSize;
 
+use {AnnotatedError, ConnectionId};
+use base_serializer::HeaderError;
+
 
 quick_error! {
     #[derive(Debug)]
     /// HTTP client error
-    pub enum Error wraps pub ErrorEnum {
+    pub enum ErrorEnum {
         /// I/O (basically networking) error occured during request
         Io(err: io::Error) {
             description("IO error")
             display("IO error: {}", err)
             from()
+            cause(err)
         }
         /// Bad response headers received
         Header(err: HttpError) {
             description("bad headers")
             display("bad headers: {}", err)
             from()
+            cause(err)
         }
         /// Bad chunk size received
         ChunkSize(err: InvalidChunkSize) {
@@ -94,6 +101,35 @@ quick_error! {
         RequestTimeout {
             description("request timed out")
         }
+        /// Proxy refused to establish a tunnel (non-2xx response to
+        /// `CONNECT`)
+        TunnelRejected { status: u16 } {
+            description("proxy rejected tunnel request")
+            display("proxy rejected tunnel request with status {}", status)
+        }
+        /// Data is received after proxy response before request is sent
+        /// through the tunnel
+        PrematureTunnelData {
+            description("data received in tunnel before request is sent")
+        }
+        /// Request header (or request target) contains invalid characters
+        InvalidRequestHeader(err: HeaderError) {
+            description("invalid request header")
+            display("invalid request header: {}", err)
+            from()
+            cause(err)
+        }
+        /// Response has `Content-Encoding` that can't be decoded
+        UnsupportedContentEncoding(encoding: String) {
+            description("unsupported content encoding")
+            display("unsupported content encoding: {:?}", encoding)
+        }
+        /// Error decompressing response body
+        Decompress(err: io::Error) {
+            description("error decompressing response body")
+            display("error decompressing response body: {}", err)
+            cause(err)
+        }
         /// Connection timed out on keep alive
         KeepAliveTimeout {
             description("connection timed out being on keep-alive")
@@ -106,6 +142,39 @@ quick_error! {
     }
 }
 
+/// HTTP client error
+#[derive(Debug)]
+pub struct Error(ErrorEnum);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<ErrorEnum> for Error {
+    fn from(err: ErrorEnum) -> Error {
+        Error(err)
+    }
+}
+
+impl StdError for Error {
+    #[allow(deprecated)]
+    fn cause(&self) -> Option<&StdError> {
+        self.0.cause()
+    }
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match self.0 {
+            ErrorEnum::Io(ref err) => Some(err),
+            ErrorEnum::Header(ref err) => Some(err),
+            ErrorEnum::InvalidRequestHeader(ref err) => Some(err),
+            ErrorEnum::Decompress(ref err) => Some(err),
+            ErrorEnum::Custom(ref err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
 impl<T> From<SendError<T>> for ErrorEnum {
     fn from(_: SendError<T>) -> ErrorEnum {
         ErrorEnum::PoolError
@@ -119,6 +188,13 @@ impl Error {
     {
         Error(ErrorEnum::Custom(err.into()))
     }
+    /// Wrap the error with the id of the connection to make it more
+    /// useful in logs
+    pub fn with_connection_id(self, id: ConnectionId)
+        -> AnnotatedError<Error>
+    {
+        AnnotatedError::new(id, self)
+    }
 
     /// Tries to catch all the conditions where this isn't error
     ///
@@ -144,3 +220,13 @@ fn send_sync() {
     fn send_sync<T: Send+Sync>(_: T) {}
     send_sync(Error::from(ErrorEnum::Canceled));
 }
+
+#[test]
+fn source() {
+    use std::error::Error as StdError;
+    let err = Error::from(ErrorEnum::Decompress(
+        io::Error::new(io::ErrorKind::InvalidData, "test")));
+    assert_eq!(err.source().unwrap().to_string(), "test");
+    let err = Error::from(ErrorEnum::from(InvalidChunkSize));
+    assert!(err.source().is_none());
+}
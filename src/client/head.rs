@@ -1,11 +1,13 @@
 #[allow(unused_imports)]
 use std::ascii::AsciiExt;
 use std::slice::Iter as SliceIter;
+use std::str::from_utf8;
 
 use httparse::Header;
 
-use enums::{Status};
+use enums::{Status, Version};
 use client::Head;
+use client::client::BodyKind;
 
 
 /// Iterator over all meaningful headers for the response
@@ -36,6 +38,24 @@ impl<'a> Head<'a> {
     pub fn raw_status(&self) -> (u16, &'a str) {
         (self.code, self.reason)
     }
+    /// Version of HTTP response
+    pub fn version(&self) -> Version {
+        self.version
+    }
+    /// Returns `true` if status is a redirect (301, 302, 303, 307 or 308)
+    pub fn is_redirect(&self) -> bool {
+        matches!(self.code, 301 | 302 | 303 | 307 | 308)
+    }
+    /// Returns the value of the `Location` header
+    ///
+    /// The value is returned as is, so it may be either an absolute URL or
+    /// a reference relative to the request. `None` is returned if there is
+    /// no header or it's not valid utf-8.
+    pub fn location(&self) -> Option<&'a str> {
+        self.headers.iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Location"))
+            .and_then(|h| from_utf8(h.value).ok())
+    }
     /// Iterator over the headers of HTTP request
     ///
     /// This iterator strips the following kinds of headers:
@@ -64,6 +84,29 @@ impl<'a> Head<'a> {
     pub fn all_headers(&self) -> &'a [Header<'a>] {
         self.headers
     }
+    /// Return `true` if connection can't be reused after this response
+    ///
+    /// This is true if `Connection: close` header exists or if the response
+    /// is HTTP/1.0
+    pub fn connection_close(&self) -> bool {
+        self.connection_close
+    }
+    /// Returns the value of the `Connection` header (all of them, if multiple)
+    pub fn connection_header(&'a self) -> Option<&'a str> {
+        self.connection_header.as_ref().map(|x| &x[..])
+    }
+    /// Returns size of the response body if either `Content-Length` is set
+    /// or it is known that response body is zero-length
+    ///
+    /// If response length can't be determined in advance (such as when there
+    /// is a `Transfer-Encoding` or response is read until end of
+    /// connection) `None` is returned
+    pub fn body_length(&self) -> Option<u64> {
+        match self.body_kind {
+            BodyKind::Fixed(x) => Some(x),
+            _ => None,
+        }
+    }
 }
 
 
@@ -7,13 +7,24 @@ mod errors;
 mod head;
 mod parser;
 mod proto;
+mod pool;
 mod recv_mode;
+mod request_builder;
+mod response;
+mod tunnel;
+#[cfg(feature="deflate")] mod decompress;
 pub mod buffered;
 
 pub use self::errors::Error;
 pub use self::client::{Client, Codec};
-pub use self::encoder::{Encoder, EncoderDone, WaitFlush};
+pub use self::encoder::{Encoder, EncoderDone, WaitFlush, ChunkedWriter,
+                        BodyWriter};
 pub use self::proto::{Proto};
+pub use self::pool::{ConnectionPool, PooledConnection};
+pub use self::request_builder::{RequestBuilder, SendBody};
+pub use self::response::{ResponseProto, ResponseHead, BodyStream};
+pub use self::tunnel::ProxyTunnelProto;
+#[cfg(feature="deflate")] pub use self::decompress::DecompressBody;
 
 use std::borrow::Cow;
 use std::time::Duration;
@@ -31,6 +42,7 @@ pub struct Config {
     keep_alive_timeout: Duration,
     safe_pipeline_timeout: Duration,
     max_request_timeout: Duration,
+    accept_encoding: bool,
 }
 
 /// A borrowed structure that represents response headers
@@ -47,7 +47,8 @@ pub struct Parser<S, C: Codec<S>> {
 }
 
 
-fn scan_headers<'x>(is_head: bool, code: u16, headers: &'x [httparse::Header])
+pub fn scan_headers<'x>(is_head: bool, code: u16,
+    headers: &'x [httparse::Header])
     -> Result<(BodyKind, Option<Cow<'x, str>>, bool), ErrorEnum>
 {
     /// Implements the body length algorithm for requests:
@@ -0,0 +1,220 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use tk_bufstream::{IoBuf, WriteBuf, ReadBuf};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use client::Head;
+use client::client::BodyKind;
+
+
+type Key = (String, u16);
+type Idle<S> = HashMap<Key, Vec<(WriteBuf<S>, ReadBuf<S>)>>;
+
+/// A pool of idle keep-alive connections
+///
+/// Connections are keyed by `(host, port)`. The pool is cheap to clone, all
+/// clones share the same set of idle connections.
+///
+/// This is a low-level building block: it doesn't establish connections by
+/// itself. Use `get()` to fetch an idle connection and `wrap()` for newly
+/// established one. In both cases you get a `PooledConnection` which is
+/// returned to the pool when dropped, if the connection is reusable.
+pub struct ConnectionPool<S> {
+    idle: Rc<RefCell<Idle<S>>>,
+    max_idle: usize,
+}
+
+/// A guard of a connection fetched from `ConnectionPool`
+///
+/// On drop the connection is put back to the pool if all of the following
+/// is true:
+///
+/// 1. `response_received()` was called and response allows keep-alive
+///    (i.e. no `Connection: close`, not HTTP/1.0 and body length is known
+///    either by `Content-Length` or `Transfer-Encoding: chunked`)
+/// 2. response body is fully consumed (see `body_done()`)
+/// 3. there are no unsent or unread bytes in the buffers
+pub struct PooledConnection<S> {
+    key: Key,
+    io: Option<(WriteBuf<S>, ReadBuf<S>)>,
+    keep_alive: bool,
+    body_done: bool,
+    pool: Weak<RefCell<Idle<S>>>,
+    max_idle: usize,
+}
+
+impl<S> ConnectionPool<S> {
+    /// Create a pool which keeps at most 16 idle connections per host
+    pub fn new() -> ConnectionPool<S> {
+        ConnectionPool::with_max_idle(16)
+    }
+    /// Create a pool which keeps at most `max_idle` connections per host
+    pub fn with_max_idle(max_idle: usize) -> ConnectionPool<S> {
+        ConnectionPool {
+            idle: Rc::new(RefCell::new(HashMap::new())),
+            max_idle: max_idle,
+        }
+    }
+    /// Fetch an idle connection to the `host` and `port` if there is one
+    ///
+    /// Note: connection might be already closed by peer, you should be
+    /// prepared to retry the request on a fresh connection.
+    pub fn get(&self, host: &str, port: u16) -> Option<PooledConnection<S>> {
+        let key = (host.to_string(), port);
+        let io = self.idle.borrow_mut().get_mut(&key).and_then(|v| v.pop());
+        io.map(|io| self.guard(key, io))
+    }
+    /// Wrap a newly established connection to the `host` and `port`
+    pub fn wrap(&self, host: &str, port: u16, conn: S) -> PooledConnection<S>
+        where S: AsyncRead + AsyncWrite
+    {
+        self.guard((host.to_string(), port), IoBuf::new(conn).split())
+    }
+    /// Number of idle connections to the `host` and `port`
+    pub fn idle_connections(&self, host: &str, port: u16) -> usize {
+        self.idle.borrow().get(&(host.to_string(), port))
+            .map(|v| v.len()).unwrap_or(0)
+    }
+    fn guard(&self, key: Key, io: (WriteBuf<S>, ReadBuf<S>))
+        -> PooledConnection<S>
+    {
+        PooledConnection {
+            key: key,
+            io: Some(io),
+            keep_alive: false,
+            body_done: false,
+            pool: Rc::downgrade(&self.idle),
+            max_idle: self.max_idle,
+        }
+    }
+}
+
+impl<S> Clone for ConnectionPool<S> {
+    fn clone(&self) -> ConnectionPool<S> {
+        ConnectionPool {
+            idle: self.idle.clone(),
+            max_idle: self.max_idle,
+        }
+    }
+}
+
+impl<S> PooledConnection<S> {
+    /// Returns buffers of the connection
+    pub fn buffers(&mut self) -> (&mut WriteBuf<S>, &mut ReadBuf<S>) {
+        let &mut (ref mut out, ref mut inp) = self.io.as_mut()
+            .expect("buffers are still here");
+        (out, inp)
+    }
+    /// Register headers of the response
+    ///
+    /// This checks whether connection may be reused. Responses with
+    /// `Connection: close`, HTTP/1.0 responses and ones with body delimited
+    /// by the end of connection are not reusable.
+    pub fn response_received(&mut self, head: &Head) {
+        self.keep_alive = !head.connection_close &&
+            head.body_kind != BodyKind::Eof;
+        self.body_done = head.body_kind == BodyKind::Fixed(0);
+    }
+    /// Mark response body as fully read
+    ///
+    /// This should be called when response body is read from the buffer
+    /// (i.e. `Content-Length` bytes or the last chunk is processed).
+    pub fn body_done(&mut self) {
+        self.body_done = true;
+    }
+    /// Returns true if connection will be returned to the pool on drop
+    pub fn is_reusable(&self) -> bool {
+        let (ref out, ref inp) = *self.io.as_ref()
+            .expect("buffers are still here");
+        self.keep_alive && self.body_done &&
+            out.out_buf.len() == 0 && inp.in_buf.len() == 0 && !inp.done()
+    }
+    /// Take buffers out of the guard, so connection is never returned to
+    /// the pool
+    pub fn detach(mut self) -> (WriteBuf<S>, ReadBuf<S>) {
+        self.io.take().expect("buffers are still here")
+    }
+}
+
+impl<S> Drop for PooledConnection<S> {
+    fn drop(&mut self) {
+        if self.io.is_none() || !self.is_reusable() {
+            return;
+        }
+        if let Some(pool) = self.pool.upgrade() {
+            let mut pool = pool.borrow_mut();
+            let conns = pool.entry(self.key.clone()).or_insert_with(Vec::new);
+            if conns.len() < self.max_idle {
+                conns.push(self.io.take().unwrap());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httparse::Header;
+    use tk_bufstream::MockData;
+
+    use enums::Version;
+    use client::Head;
+    use client::client::BodyKind;
+    use super::ConnectionPool;
+
+    fn head<'x>(body: BodyKind, close: bool, headers: &'x [Header<'x>])
+        -> Head<'x>
+    {
+        Head {
+            version: Version::Http11,
+            code: 200,
+            reason: "OK",
+            headers: headers,
+            body_kind: body,
+            connection_header: None,
+            connection_close: close,
+        }
+    }
+
+    #[test]
+    fn reuse() {
+        let pool = ConnectionPool::new();
+        let mut conn = pool.wrap("example.com", 80, MockData::new());
+        conn.response_received(&head(BodyKind::Fixed(10), false, &[]));
+        assert!(!conn.is_reusable());
+        conn.body_done();
+        assert!(conn.is_reusable());
+        drop(conn);
+        assert_eq!(pool.idle_connections("example.com", 80), 1);
+        assert!(pool.get("example.com", 81).is_none());
+        assert!(pool.get("example.com", 80).is_some());
+        // not returned back as no response was received
+        assert_eq!(pool.idle_connections("example.com", 80), 0);
+    }
+
+    #[test]
+    fn chunked() {
+        let pool = ConnectionPool::new();
+        let mut conn = pool.wrap("example.com", 80, MockData::new());
+        conn.response_received(&head(BodyKind::Chunked, false, &[]));
+        conn.body_done();
+        drop(conn);
+        assert_eq!(pool.idle_connections("example.com", 80), 1);
+    }
+
+    #[test]
+    fn no_reuse() {
+        let pool = ConnectionPool::new();
+        let mut conn = pool.wrap("example.com", 80, MockData::new());
+        conn.response_received(&head(BodyKind::Fixed(0), true, &[]));
+        drop(conn);
+        let mut conn = pool.wrap("example.com", 80, MockData::new());
+        conn.response_received(&head(BodyKind::Eof, false, &[]));
+        conn.body_done();
+        drop(conn);
+        let conn = pool.wrap("example.com", 80, MockData::new());
+        drop(conn.detach());
+        assert_eq!(pool.idle_connections("example.com", 80), 0);
+    }
+}
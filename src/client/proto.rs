@@ -325,8 +325,11 @@ impl<S: AsyncRead + AsyncWrite, C: Codec<S>> Sink for PureProto<S, C> {
                         (AsyncSink::NotReady(item), OutState::Idle(io, time))
                     } else {
                         let state = Arc::new(AtomicUsize::new(0));
-                        let e = encoder::new(io,
+                        let mut e = encoder::new(io,
                                 state.clone(), self.close.clone());
+                        if self.config.accept_encoding {
+                            encoder::set_accept_encoding(&mut e);
+                        }
                         let fut = item.start_write(e);
                         self.waiting.push_back(Waiting {
                             codec: item,
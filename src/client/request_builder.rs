@@ -0,0 +1,236 @@
+use futures::{Future, Stream, Async};
+use tk_bufstream::WriteBuf;
+use tokio_io::AsyncWrite;
+
+use enums::Version;
+use base_serializer::{MessageState, HeaderError};
+use client::Error;
+use client::errors::ErrorEnum;
+
+
+/// Buffer size at which `SendBody` stops polling the body stream
+const WATERMARK: usize = 65536;
+
+
+/// A builder of a single plain HTTP request
+///
+/// Unlike `Proto` this doesn't do any connection management: it just
+/// serializes request into a buffer, so it's useful when you drive the
+/// connection yourself (e.g. using `ConnectionPool`). Pair it with
+/// `ResponseProto` to read the response.
+///
+/// By default it's `GET /` request using HTTP/1.1. Note: you must add
+/// `Host` header yourself.
+pub struct RequestBuilder<S> {
+    buf: WriteBuf<S>,
+    method: String,
+    path: String,
+    version: Version,
+    headers: Vec<(String, Vec<u8>)>,
+}
+
+/// A future that writes request body from stream, created by
+/// `RequestBuilder::body_stream`
+///
+/// Resolves to the output buffer back when the whole request is flushed to
+/// the network.
+pub struct SendBody<S, B> {
+    message: MessageState,
+    buf: Option<WriteBuf<S>>,
+    stream: B,
+    finished: bool,
+}
+
+impl<S> RequestBuilder<S> {
+    /// Create a request builder that writes into the specified buffer
+    pub fn new(buf: WriteBuf<S>) -> RequestBuilder<S> {
+        RequestBuilder {
+            buf: buf,
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            version: Version::Http11,
+            headers: Vec::new(),
+        }
+    }
+    /// Set request method (`GET` by default)
+    pub fn method(mut self, method: &str) -> RequestBuilder<S> {
+        self.method = method.to_string();
+        self
+    }
+    /// Set request target (`/` by default)
+    pub fn path(mut self, path: &str) -> RequestBuilder<S> {
+        self.path = path.to_string();
+        self
+    }
+    /// Set HTTP version (`HTTP/1.1` by default)
+    pub fn version(mut self, version: Version) -> RequestBuilder<S> {
+        self.version = version;
+        self
+    }
+    /// Add a header to the request
+    ///
+    /// Headers are validated when request is written, so invalid headers
+    /// (including `Content-Length` and `Transfer-Encoding` which are set
+    /// automatically) are reported by `done`, `body` or `body_stream`.
+    pub fn header<N, V>(mut self, name: N, value: V) -> RequestBuilder<S>
+        where N: Into<String>,
+              V: Into<Vec<u8>>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+    fn write_head<F>(mut self, length: F)
+        -> Result<(MessageState, WriteBuf<S>), HeaderError>
+        where F: FnOnce(&mut MessageState, &mut WriteBuf<S>)
+                 -> Result<(), HeaderError>
+    {
+        let mut message = MessageState::RequestStart;
+        message.request_line(&mut self.buf.out_buf,
+            &self.method, &self.path, self.version);
+        for &(ref name, ref value) in &self.headers {
+            message.add_header(&mut self.buf.out_buf, name, value)?;
+        }
+        length(&mut message, &mut self.buf)?;
+        message.done_headers(&mut self.buf.out_buf)?;
+        Ok((message, self.buf))
+    }
+    /// Write request without a body into the buffer
+    ///
+    /// The request is sent when buffer is flushed.
+    pub fn done(self) -> Result<WriteBuf<S>, HeaderError> {
+        let (mut message, mut buf) = self.write_head(|_, _| Ok(()))?;
+        message.done(&mut buf.out_buf);
+        Ok(buf)
+    }
+    /// Write request with the body into the buffer
+    ///
+    /// `Content-Length` header is added automatically. The request is sent
+    /// when buffer is flushed.
+    pub fn body(self, data: &[u8]) -> Result<WriteBuf<S>, HeaderError> {
+        let (mut message, mut buf) = self.write_head(|m, buf| {
+            m.add_length(&mut buf.out_buf, data.len() as u64)
+        })?;
+        message.write_body(&mut buf.out_buf, data);
+        message.done(&mut buf.out_buf);
+        Ok(buf)
+    }
+    /// Write request headers and return a future that sends body from
+    /// the stream using chunked encoding
+    ///
+    /// If you want to forward other errors from the stream use
+    /// `Error::custom`.
+    pub fn body_stream<B>(self, stream: B)
+        -> Result<SendBody<S, B>, HeaderError>
+        where B: Stream<Error=Error>,
+              B::Item: AsRef<[u8]>,
+    {
+        let (message, buf) = self.write_head(|m, buf| {
+            m.add_chunked(&mut buf.out_buf)
+        })?;
+        Ok(SendBody {
+            message: message,
+            buf: Some(buf),
+            stream: stream,
+            finished: false,
+        })
+    }
+}
+
+impl<S, B> Future for SendBody<S, B>
+    where S: AsyncWrite,
+          B: Stream<Error=Error>,
+          B::Item: AsRef<[u8]>,
+{
+    type Item = WriteBuf<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<WriteBuf<S>>, Error> {
+        let mut buf = self.buf.take().expect("future is polled twice");
+        while !self.finished {
+            buf.flush().map_err(ErrorEnum::Io)?;
+            if buf.out_buf.len() >= WATERMARK {
+                break;
+            }
+            match self.stream.poll() {
+                Ok(Async::Ready(Some(chunk))) => {
+                    self.message.write_body(&mut buf.out_buf,
+                                            chunk.as_ref());
+                }
+                Ok(Async::Ready(None)) => {
+                    self.message.done(&mut buf.out_buf);
+                    self.finished = true;
+                }
+                Ok(Async::NotReady) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        buf.flush().map_err(ErrorEnum::Io)?;
+        if self.finished && buf.out_buf.len() == 0 {
+            return Ok(Async::Ready(buf));
+        }
+        self.buf = Some(buf);
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use futures::stream::iter_ok;
+    use tk_bufstream::{IoBuf, MockData};
+
+    use client::Error;
+    use super::RequestBuilder;
+
+    fn output(mock: &MockData) -> String {
+        String::from_utf8(mock.output(..)).unwrap()
+    }
+
+    #[test]
+    fn get() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut buf = RequestBuilder::new(tx)
+            .path("/hello")
+            .header("Host", "example.com")
+            .done().unwrap();
+        buf.flush().unwrap();
+        assert_eq!(output(&mock),
+                   "GET /hello HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    }
+
+    #[test]
+    fn post() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut buf = RequestBuilder::new(tx)
+            .method("POST")
+            .header("Host", "example.com")
+            .body(b"hello").unwrap();
+        buf.flush().unwrap();
+        assert_eq!(output(&mock),
+                   "POST / HTTP/1.1\r\nHost: example.com\r\n\
+                    Content-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn length_header() {
+        let (tx, _) = IoBuf::new(MockData::new()).split();
+        assert!(RequestBuilder::new(tx)
+            .header("Content-Length", "5")
+            .body(b"hello").is_err());
+    }
+
+    #[test]
+    fn stream() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let body = iter_ok::<_, Error>(vec![&b"hello"[..], b"", b"world"]);
+        RequestBuilder::new(tx)
+            .method("PUT")
+            .body_stream(body).unwrap()
+            .wait().unwrap();
+        assert_eq!(output(&mock),
+                   "PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+}
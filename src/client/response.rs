@@ -0,0 +1,278 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+
+use futures::{Future, Stream, Async, Poll};
+use httparse;
+use tk_bufstream::ReadBuf;
+use tokio_io::AsyncRead;
+
+use enums::{Version, Status};
+use body_parser::BodyProgress;
+use chunked;
+use client::Error;
+use client::client::BodyKind;
+use client::errors::ErrorEnum;
+use client::parser::scan_headers;
+
+
+/// Number of headers to allocate on a stack
+const MIN_HEADERS: usize = 16;
+/// A hard limit on the number of headers
+const MAX_HEADERS: usize = 1024;
+
+
+/// Response headers returned by `ResponseProto`
+///
+/// Unlike `Head` this structure owns the data, so it may outlive the
+/// input buffer.
+#[derive(Debug, Clone)]
+pub struct ResponseHead {
+    version: Version,
+    code: u16,
+    reason: String,
+    headers: Vec<(String, Vec<u8>)>,
+    body_kind: BodyKind,
+    connection_close: bool,
+}
+
+/// A future that reads response headers from the buffer
+///
+/// This is a counterpart of the `RequestBuilder`: it doesn't do any
+/// connection management and reads a single response. It resolves to
+/// response headers and a stream of the response body.
+pub struct ResponseProto<S> {
+    io: Option<ReadBuf<S>>,
+    is_head: bool,
+}
+
+/// A stream of chunks of the response body
+///
+/// Both fixed size and chunked bodies are supported, as well as ones
+/// delimited by closing the connection. Chunks are yielded as soon as they
+/// are read from the network. Empty body yields no chunks.
+pub struct BodyStream<S> {
+    io: ReadBuf<S>,
+    progress: BodyProgress,
+    done: bool,
+}
+
+impl<S> ResponseProto<S> {
+    /// Create a future that reads response from the buffer
+    ///
+    /// `is_head` should be true if request method is `HEAD`, as response
+    /// has no body in this case despite the `Content-Length` header.
+    pub fn new(io: ReadBuf<S>, is_head: bool) -> ResponseProto<S> {
+        ResponseProto {
+            io: Some(io),
+            is_head: is_head,
+        }
+    }
+    fn parse_headers(&mut self) -> Result<Option<ResponseHead>, Error> {
+        let ref mut buf = self.io.as_mut()
+            .expect("buffer still exists")
+            .in_buf;
+        let (head, bytes) = {
+            let mut vec;
+            let mut headers = [httparse::EMPTY_HEADER; MIN_HEADERS];
+            let mut raw = httparse::Response::new(&mut headers);
+            let mut result = raw.parse(&buf[..]);
+            if matches!(result, Err(httparse::Error::TooManyHeaders)) {
+                vec = vec![httparse::EMPTY_HEADER; MAX_HEADERS];
+                raw = httparse::Response::new(&mut vec);
+                result = raw.parse(&buf[..]);
+            }
+            let bytes = match result.map_err(ErrorEnum::Header)? {
+                httparse::Status::Complete(bytes) => bytes,
+                httparse::Status::Partial => return Ok(None),
+            };
+            let ver = raw.version.unwrap();
+            let code = raw.code.unwrap();
+            let (body_kind, _, close) = scan_headers(self.is_head,
+                                                     code, raw.headers)?;
+            let head = ResponseHead {
+                version: if ver == 1
+                    { Version::Http11 } else { Version::Http10 },
+                code: code,
+                reason: raw.reason.unwrap().to_string(),
+                headers: raw.headers.iter()
+                    .map(|h| (h.name.to_string(), h.value.to_vec()))
+                    .collect(),
+                body_kind: body_kind,
+                connection_close: close || ver == 0,
+            };
+            (head, bytes)
+        };
+        buf.consume(bytes);
+        Ok(Some(head))
+    }
+}
+
+impl<S: AsyncRead> Future for ResponseProto<S> {
+    type Item = (ResponseHead, BodyStream<S>);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<(ResponseHead, BodyStream<S>), Error> {
+        loop {
+            if let Some(head) = self.parse_headers()? {
+                let progress = match head.body_kind {
+                    BodyKind::Fixed(x) => BodyProgress::Fixed(x as usize),
+                    BodyKind::Chunked
+                    => BodyProgress::Chunked(chunked::State::new()),
+                    BodyKind::Eof => BodyProgress::Eof,
+                };
+                let body = BodyStream {
+                    io: self.io.take().expect("buffer still exists"),
+                    progress: progress,
+                    done: false,
+                };
+                return Ok(Async::Ready((head, body)));
+            }
+            let io = self.io.as_mut().expect("poll after complete");
+            if io.read().map_err(ErrorEnum::Io)? == 0 {
+                if io.done() {
+                    return Err(ErrorEnum::ResetOnResponseHeaders.into());
+                } else {
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
+
+impl ResponseHead {
+    /// Returns status if it is one of the supported statuses otherwise None
+    pub fn status(&self) -> Option<Status> {
+        Status::from(self.code)
+    }
+    /// Returns raw status code and reason as received
+    pub fn raw_status(&self) -> (u16, &str) {
+        (self.code, &self.reason)
+    }
+    /// Version of HTTP response
+    pub fn version(&self) -> Version {
+        self.version
+    }
+    /// Returns the value of the first header with the specified name
+    ///
+    /// Header name is compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+    /// All headers of HTTP response (including hop-by-hop ones)
+    pub fn all_headers(&self) -> &[(String, Vec<u8>)] {
+        &self.headers
+    }
+    /// Return `true` if connection can't be reused after this response
+    ///
+    /// This is true if `Connection: close` header exists, if the response
+    /// is HTTP/1.0 or if body is delimited by closing the connection
+    pub fn connection_close(&self) -> bool {
+        self.connection_close || self.body_kind == BodyKind::Eof
+    }
+    /// Returns size of the response body if either `Content-Length` is set
+    /// or it is known that response body is zero-length
+    pub fn body_length(&self) -> Option<u64> {
+        match self.body_kind {
+            BodyKind::Fixed(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl<S> BodyStream<S> {
+    /// Returns true if the whole body has been read
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+    /// Returns the underlying buffer
+    ///
+    /// If body is read to the end (and `connection_close()` is false), the
+    /// buffer may be used to read the next response.
+    pub fn into_inner(self) -> ReadBuf<S> {
+        self.io
+    }
+}
+
+impl<S: AsyncRead> Stream for BodyStream<S> {
+    type Item = Vec<u8>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        loop {
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+            self.progress.parse(&mut self.io)
+                .map_err(ErrorEnum::ChunkSize)?;
+            let (bytes, done) = self.progress.check_buf(&self.io);
+            if bytes > 0 {
+                let chunk = self.io.in_buf[..bytes].to_vec();
+                self.progress.consume(&mut self.io, bytes);
+                return Ok(Async::Ready(Some(chunk)));
+            }
+            if done {
+                self.done = true;
+                continue;
+            }
+            if self.io.done() {
+                return Err(ErrorEnum::ResetOnResponseBody.into());
+            }
+            if self.io.read().map_err(ErrorEnum::Io)? == 0 &&
+                !self.io.done()
+            {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use futures::{Future, Stream};
+    use tk_bufstream::IoBuf;
+
+    use enums::{Version, Status};
+    use super::ResponseProto;
+
+    fn response(data: &[u8], is_head: bool) -> (super::ResponseHead, Vec<u8>)
+    {
+        // cursor signals end of stream when there is no more data
+        let (_, rx) = IoBuf::new(Cursor::new(data.to_vec())).split();
+        let (head, body) = ResponseProto::new(rx, is_head).wait().unwrap();
+        let body = body.concat2().wait().unwrap();
+        (head, body)
+    }
+
+    #[test]
+    fn fixed() {
+        let (head, body) = response(b"HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\nX-Test: 1\r\n\r\nhello", false);
+        assert_eq!(head.status(), Some(Status::Ok));
+        assert_eq!(head.version(), Version::Http11);
+        assert_eq!(head.header("x-test"), Some(&b"1"[..]));
+        assert_eq!(head.body_length(), Some(5));
+        assert!(!head.connection_close());
+        assert_eq!(body, b"hello");
+    }
+
+    #[test]
+    fn chunked() {
+        let (head, body) = response(b"HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", false);
+        assert_eq!(head.body_length(), None);
+        assert_eq!(body, b"hello world");
+    }
+
+    #[test]
+    fn head_and_eof() {
+        let (_, body) = response(b"HTTP/1.1 200 OK\r\n\
+            Content-Length: 5\r\n\r\n", true);
+        assert_eq!(body, b"");
+        let (head, body) = response(b"HTTP/1.0 200 OK\r\n\r\nhello", false);
+        assert!(head.connection_close());
+        assert_eq!(body, b"hello");
+    }
+}
@@ -0,0 +1,196 @@
+use std::io::{self, Write};
+
+use futures::{Future, Async, Poll};
+use httparse;
+use tk_bufstream::Buf;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use base_serializer::{HeaderError, invalid_header};
+use client::Error;
+use client::errors::ErrorEnum;
+
+
+/// Number of headers to allocate on a stack
+const MIN_HEADERS: usize = 16;
+/// A hard limit on the number of headers
+const MAX_HEADERS: usize = 1024;
+
+
+/// A future that establishes a tunnel through HTTP proxy using `CONNECT`
+///
+/// Resolves to the connection itself when proxy replied with `2xx` status,
+/// so you can start TLS or websocket handshake (`HandshakeProto::new`) over
+/// it as if it was a direct connection to the target.
+///
+/// Fails with `TunnelRejected` error when proxy replies with other status
+/// (e.g. `407 Proxy Authentication Required`). If target or authorization
+/// contain newlines the future fails on the first poll without writing
+/// anything.
+pub struct ProxyTunnelProto<S> {
+    sock: Option<S>,
+    target: String,
+    authorization: Option<Vec<u8>>,
+    input: Buf,
+    output: Option<Buf>,
+}
+
+impl<S> ProxyTunnelProto<S> {
+    /// Create a future that requests tunnel to `target` (in `host:port`
+    /// form) over the already established connection to a proxy
+    pub fn new<T: Into<String>>(sock: S, target: T) -> ProxyTunnelProto<S> {
+        ProxyTunnelProto {
+            sock: Some(sock),
+            target: target.into(),
+            authorization: None,
+            input: Buf::new(),
+            output: None,
+        }
+    }
+    /// Send `Proxy-Authorization` header with the specified value
+    ///
+    /// The value should contain a scheme, e.g. `Basic dXNlcjpwYXNz`.
+    pub fn with_proxy_authorization<V: Into<Vec<u8>>>(mut self, value: V)
+        -> ProxyTunnelProto<S>
+    {
+        self.authorization = Some(value.into());
+        self
+    }
+    fn request(&self) -> Result<Buf, Error> {
+        if invalid_header(self.target.as_bytes()) {
+            return Err(ErrorEnum::from(
+                HeaderError::InvalidHeaderValue("Host".into())).into());
+        }
+        if self.authorization.as_ref().map_or(false, |v| invalid_header(v)) {
+            return Err(ErrorEnum::from(HeaderError::InvalidHeaderValue(
+                "Proxy-Authorization".into())).into());
+        }
+        let mut buf = Buf::new();
+        write!(buf, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n",
+               self.target).unwrap();
+        if let Some(ref value) = self.authorization {
+            buf.extend(b"Proxy-Authorization: ");
+            buf.extend(value);
+            buf.extend(b"\r\n");
+        }
+        buf.extend(b"\r\n");
+        return Ok(buf);
+    }
+    fn parse_response(&mut self) -> Result<bool, Error> {
+        let mut vec;
+        let mut headers = [httparse::EMPTY_HEADER; MIN_HEADERS];
+        let mut raw = httparse::Response::new(&mut headers);
+        let mut result = raw.parse(&self.input[..]);
+        if matches!(result, Err(httparse::Error::TooManyHeaders)) {
+            vec = vec![httparse::EMPTY_HEADER; MAX_HEADERS];
+            raw = httparse::Response::new(&mut vec);
+            result = raw.parse(&self.input[..]);
+        }
+        let bytes = match result.map_err(ErrorEnum::Header)? {
+            httparse::Status::Complete(bytes) => bytes,
+            httparse::Status::Partial => return Ok(false),
+        };
+        let code = raw.code.unwrap();
+        if code < 200 || code >= 300 {
+            return Err(ErrorEnum::TunnelRejected { status: code }.into());
+        }
+        if self.input.len() > bytes {
+            // target can't speak before we do, and there is no way to
+            // return the data along with the connection
+            return Err(ErrorEnum::PrematureTunnelData.into());
+        }
+        Ok(true)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Future for ProxyTunnelProto<S> {
+    type Item = S;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<S, Error> {
+        if self.output.is_none() {
+            self.output = Some(self.request()?);
+        }
+        {
+            let sock = self.sock.as_mut().expect("poll after complete");
+            let output = self.output.as_mut().expect("request is written");
+            while output.len() > 0 {
+                match output.write_to(sock) {
+                    Ok(0) => {
+                        return Err(ErrorEnum::Io(
+                            io::ErrorKind::WriteZero.into()).into());
+                    }
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => return Err(ErrorEnum::Io(e).into()),
+                }
+            }
+        }
+        loop {
+            let res = self.input.read_from(
+                self.sock.as_mut().expect("poll after complete"));
+            match res {
+                Ok(0) => return Err(ErrorEnum::ResetOnResponseHeaders.into()),
+                Ok(_) => {
+                    if self.parse_response()? {
+                        return Ok(Async::Ready(self.sock.take().unwrap()));
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(ErrorEnum::Io(e).into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use tk_bufstream::MockData;
+
+    use client::errors::ErrorEnum;
+    use super::ProxyTunnelProto;
+
+    #[test]
+    fn established() {
+        let mock = MockData::new();
+        mock.add_input("HTTP/1.1 200 Connection established\r\n\r\n");
+        ProxyTunnelProto::new(mock.clone(), "example.com:443")
+            .with_proxy_authorization("Basic dXNlcjpwYXNz")
+            .wait().map_err(|_| "tunnel failed").unwrap();
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+                   "CONNECT example.com:443 HTTP/1.1\r\n\
+                    Host: example.com:443\r\n\
+                    Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n");
+    }
+
+    #[test]
+    fn rejected() {
+        let mock = MockData::new();
+        mock.add_input("HTTP/1.1 407 Proxy Authentication Required\r\n\
+                        Content-Length: 0\r\n\r\n");
+        match ProxyTunnelProto::new(mock, "example.com:443").wait() {
+            Err(e) => assert_eq!(e.to_string(),
+                ErrorEnum::TunnelRejected { status: 407 }.to_string()),
+            Ok(_) => panic!("tunnel must be rejected"),
+        }
+    }
+    #[test]
+    fn newlines() {
+        let mock = MockData::new();
+        let err = ProxyTunnelProto::new(mock.clone(),
+            "example.com:443\r\nX-Injected: 1")
+            .wait().err().unwrap();
+        assert_eq!(err.to_string(), "invalid request header: \
+            Value of the header \"Host\" contains invalid characters");
+        let err = ProxyTunnelProto::new(mock.clone(), "example.com:443")
+            .with_proxy_authorization("Basic x\r\n\r\nGET / HTTP/1.1")
+            .wait().err().unwrap();
+        assert_eq!(err.to_string(), "invalid request header: \
+            Value of the header \"Proxy-Authorization\" \
+            contains invalid characters");
+        assert_eq!(mock.output(..), b"");
+    }
+}
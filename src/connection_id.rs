@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An identifier of a connection to correlate log messages
+///
+/// `server::Server` assigns a new one to every accepted connection, and
+/// websocket handshakes and loops log with it too. Displays as `conn#42`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(pub u64);
+
+/// An error annotated with the id of the connection it happened on
+///
+/// Created by `with_connection_id` method of the errors of this crate.
+/// Displays as `[conn#42] original error`.
+#[derive(Debug)]
+pub struct AnnotatedError<E> {
+    id: ConnectionId,
+    error: E,
+}
+
+impl ConnectionId {
+    /// Returns a new id, unique in this process
+    pub fn next() -> ConnectionId {
+        ConnectionId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "conn#{}", self.0)
+    }
+}
+
+impl<E> AnnotatedError<E> {
+    /// Annotate an error
+    pub fn new(id: ConnectionId, error: E) -> AnnotatedError<E> {
+        AnnotatedError {
+            id: id,
+            error: error,
+        }
+    }
+    /// Returns the id of the connection
+    pub fn connection_id(&self) -> ConnectionId {
+        self.id
+    }
+    /// Returns a reference to the original error
+    pub fn error(&self) -> &E {
+        &self.error
+    }
+    /// Returns the original error
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for AnnotatedError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.id, self.error)
+    }
+}
+
+impl<E: Error + 'static> Error for AnnotatedError<E> {
+    fn cause(&self) -> Option<&Error> {
+        Some(&self.error)
+    }
+    fn source(&self) -> Option<&(Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use server;
+    use websocket;
+    use super::ConnectionId;
+
+    #[test]
+    fn display() {
+        let err = server::Error::custom("oops")
+            .with_connection_id(ConnectionId(42));
+        assert_eq!(err.to_string(), "[conn#42] custom error: oops");
+        assert_eq!(err.connection_id(), ConnectionId(42));
+        let err = websocket::Error::custom("oops")
+            .with_connection_id(ConnectionId(7));
+        assert_eq!(err.to_string(), "[conn#7] custom error: oops");
+    }
+
+    #[test]
+    fn unique() {
+        assert!(ConnectionId::next() != ConnectionId::next());
+    }
+}
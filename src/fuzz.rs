@@ -0,0 +1,94 @@
+//! Entry points for fuzzing the parsers
+//!
+//! These functions are enabled by the `fuzz` feature. They feed arbitrary
+//! bytes to the parsers and discard the results, so they can be called
+//! from a `cargo-fuzz` or `afl.rs` target as is:
+//!
+//! ```rust,ignore
+//! fuzz_target!(|data: &[u8]| tk_http::fuzz::fuzz_parse_response(data));
+//! ```
+//!
+//! Panics are not caught: any panic in these functions is a bug in the
+//! parser.
+use std::io::Cursor;
+
+use futures::{Future, Stream};
+use tk_bufstream::{Buf, Decode, IoBuf};
+
+use client::ResponseProto;
+use mock::MockTransport;
+use websocket::{ClientCodec, ServerCodec, FragmentationMode};
+use websocket::client::{HandshakeProto, SimpleAuthorizer};
+
+
+/// Limit of the websocket frame and message size for fuzzing
+const MAX_FRAME_SIZE: usize = 65536;
+
+
+/// Parse data as a response: status line, headers and the body
+///
+/// The body is read to the end of data, both as a response to `GET` and
+/// to `HEAD` request.
+pub fn fuzz_parse_response(data: &[u8]) {
+    for &is_head in &[false, true] {
+        let (_, rx) = IoBuf::new(Cursor::new(data.to_vec())).split();
+        if let Ok((_, body)) = ResponseProto::new(rx, is_head).wait() {
+            body.for_each(|_| Ok(())).wait().ok();
+        }
+    }
+}
+
+/// Parse data as a response to a websocket handshake
+///
+/// If the handshake succeeds, the rest of data is read as websocket
+/// frames.
+pub fn fuzz_parse_handshake(data: &[u8]) {
+    let transport = MockTransport::new();
+    transport.feed(data);
+    transport.close();
+    let proto = HandshakeProto::new(transport,
+        SimpleAuthorizer::new("example.com", "/"));
+    if let Ok((_, rx, _)) = proto.wait() {
+        rx.for_each(|_| Ok(())).wait().ok();
+    }
+}
+
+/// Parse data as a sequence of websocket frames
+///
+/// Frames are parsed by both server and client codecs, and by the client
+/// codec which reassembles fragmented messages.
+pub fn fuzz_parse_frame(data: &[u8]) {
+    fn decode<D: Decode>(mut codec: D, data: &[u8]) {
+        let mut buf = Buf::new();
+        buf.extend(data);
+        while let Ok(Some(_)) = codec.decode(&mut buf) {}
+    }
+    decode(ServerCodec, data);
+    decode(ClientCodec::new().with_max_frame_size(MAX_FRAME_SIZE), data);
+    decode(ClientCodec::new()
+        .with_max_frame_size(MAX_FRAME_SIZE)
+        .with_fragmentation(FragmentationMode::Reassemble {
+            max_message_size: MAX_FRAME_SIZE,
+        }), data);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fuzz_parse_response, fuzz_parse_handshake, fuzz_parse_frame};
+
+    #[test]
+    fn smoke() {
+        fuzz_parse_response(b"HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n");
+        fuzz_parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n");
+        fuzz_parse_response(b"\xff\x00garbage");
+        fuzz_parse_handshake(b"HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\nConnection: upgrade\r\n\
+            Sec-WebSocket-Accept: xxx\r\n\r\n\x81\x05hello");
+        fuzz_parse_handshake(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+        fuzz_parse_handshake(b"");
+        fuzz_parse_frame(b"\x81\x05hello\x01\x03hel\x80\x02lo\x88\x00");
+        fuzz_parse_frame(b"\x82\x7f\xff\xff\xff\xff\xff\xff\xff\xff");
+        fuzz_parse_frame(b"");
+    }
+}
@@ -24,14 +24,26 @@ extern crate byteorder;
 #[macro_use] extern crate matches;
 #[macro_use] extern crate log;
 #[cfg(feature="date_header")]extern crate httpdate;
+#[cfg(feature="deflate")]extern crate flate2;
+#[cfg(feature="tls")]extern crate rustls;
+#[cfg(feature="tls")]extern crate tokio_rustls;
+#[cfg(feature="tls")]extern crate webpki;
+#[cfg(feature="tls")]extern crate untrusted;
 
 pub mod server;
 pub mod client;
 pub mod websocket;
+#[cfg(any(test, feature="testing"))] pub mod mock;
+#[cfg(feature="fuzz")] pub mod fuzz;
 mod enums;
 mod headers;
 mod base_serializer;
 mod chunked;
 mod body_parser;
+mod connection_id;
 
 pub use enums::{Version, Status};
+pub use base_serializer::{HeaderError, HeaderErrorKind};
+#[cfg(feature="low_level")]
+pub use base_serializer::{MessageState, Body};
+pub use connection_id::{ConnectionId, AnnotatedError};
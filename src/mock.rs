@@ -0,0 +1,291 @@
+//! A mock transport for testing protocol implementations offline
+//!
+//! This module is available only with the `testing` feature (which is also
+//! enabled by `fuzz`).
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use futures::{Async, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use websocket::Error;
+use websocket::client::{Authorizer, Encoder, EncoderDone, Head};
+
+
+/// A transport that reads pre-baked bytes and records writes
+///
+/// Clones share the same buffers, so keep a clone to feed data and inspect
+/// the output after the transport is passed to a protocol.
+///
+/// Every chunk passed to `feed` is returned by a separate `read`, so tests
+/// can exercise data split at arbitrary points. When there is no data
+/// `WouldBlock` is returned (until `close` is called, then it's end of
+/// stream). Note: the transport doesn't wake up a task when data is fed,
+/// so the protocol must be polled again manually.
+///
+/// This is what it adds over `tk_bufstream::MockData`: the latter merges
+/// everything added so far into a single `read` and never reports end of
+/// stream, so it can't exercise partial reads or a premature close.
+#[derive(Debug, Clone)]
+pub struct MockTransport {
+    inner: Rc<RefCell<Inner>>,
+}
+
+/// A websocket authorizer that writes a predetermined request
+///
+/// Only the request line is written (besides the headers written by the
+/// handshake itself). When response is received every header from
+/// `response_headers` must be present with exactly this value (names are
+/// case-insensitive), otherwise handshake fails with a custom error.
+/// On success `result` is returned.
+#[derive(Debug, Clone)]
+pub struct MockAuthorizer {
+    /// Path written in the request line
+    pub expected_path: String,
+    /// Headers expected in the response
+    pub response_headers: Vec<(String, String)>,
+    /// Returned from `headers_received`
+    pub result: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    read_data: VecDeque<Vec<u8>>,
+    write_data: Vec<u8>,
+    closed: bool,
+    shut_down: bool,
+}
+
+impl MockTransport {
+    /// Create a transport with no input data
+    pub fn new() -> MockTransport {
+        MockTransport {
+            inner: Rc::new(RefCell::new(Inner {
+                read_data: VecDeque::new(),
+                write_data: Vec::new(),
+                closed: false,
+                shut_down: false,
+            })),
+        }
+    }
+    /// Add a chunk of data to be read
+    ///
+    /// # Panics
+    ///
+    /// When the transport is closed.
+    pub fn feed(&self, data: &[u8]) {
+        let mut inner = self.inner.borrow_mut();
+        assert!(!inner.closed, "can't feed a closed transport");
+        if data.len() > 0 {
+            inner.read_data.push_back(data.to_vec());
+        }
+    }
+    /// Signal end of stream after all the fed data is read
+    pub fn close(&self) {
+        self.inner.borrow_mut().closed = true;
+    }
+    /// Returns `true` if `AsyncWrite::shutdown` has been called
+    pub fn is_shut_down(&self) -> bool {
+        self.inner.borrow().shut_down
+    }
+    /// Returns all bytes written so far
+    pub fn written(&self) -> Vec<u8> {
+        self.inner.borrow().write_data.clone()
+    }
+    /// Asserts that exactly `expected` bytes have been written and clears
+    /// the output, so the next call checks only the new data
+    ///
+    /// # Panics
+    ///
+    /// When written data doesn't match, showing both as (lossy) strings.
+    pub fn assert_written(&self, expected: &[u8]) {
+        let data = ::std::mem::replace(
+            &mut self.inner.borrow_mut().write_data, Vec::new());
+        assert!(data == expected, "written {:?}, expected {:?}",
+            String::from_utf8_lossy(&data),
+            String::from_utf8_lossy(expected));
+    }
+}
+
+impl MockAuthorizer {
+    /// Create an authorizer for a `path` that expects no specific headers
+    pub fn new<P: Into<String>>(path: P) -> MockAuthorizer {
+        MockAuthorizer {
+            expected_path: path.into(),
+            response_headers: Vec::new(),
+            result: None,
+        }
+    }
+}
+
+impl<S> Authorizer<S> for MockAuthorizer {
+    type Result = Option<Vec<u8>>;
+    fn write_headers(&mut self, mut e: Encoder<S>) -> EncoderDone<S> {
+        e.request_line(&self.expected_path);
+        e.done()
+    }
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Result, Error>
+    {
+        for &(ref name, ref value) in &self.response_headers {
+            let found = headers.all_headers().iter().any(|h| {
+                h.name.eq_ignore_ascii_case(name) &&
+                h.value == value.as_bytes()
+            });
+            if !found {
+                return Err(Error::custom(format!(
+                    "expected header {}: {}", name, value)));
+            }
+        }
+        Ok(self.result.clone())
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        let closed = inner.closed;
+        let (bytes, rest) = match inner.read_data.front_mut() {
+            Some(chunk) => {
+                let bytes = min(buf.len(), chunk.len());
+                buf[..bytes].copy_from_slice(&chunk[..bytes]);
+                chunk.drain(..bytes);
+                (bytes, chunk.len())
+            }
+            None if closed => return Ok(0),
+            None => return Err(io::ErrorKind::WouldBlock.into()),
+        };
+        if rest == 0 {
+            inner.read_data.pop_front();
+        }
+        Ok(bytes)
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().write_data.extend(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MockTransport {}
+
+impl AsyncWrite for MockTransport {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.borrow_mut().shut_down = true;
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::str::from_utf8;
+
+    use futures::{Future, Async};
+    use futures::future::lazy;
+
+    use websocket::Accept;
+    use websocket::client::{HandshakeProto, SimpleAuthorizer};
+    use super::{MockTransport, MockAuthorizer};
+
+    fn accept(request: &[u8]) -> Accept {
+        let request = from_utf8(request).unwrap();
+        let key = request.lines()
+            .find(|x| x.starts_with("Sec-WebSocket-Key: "))
+            .unwrap()[19..].to_string();
+        Accept::from_key_bytes(key.as_bytes())
+    }
+
+    #[test]
+    fn chunks() {
+        let mut mock = MockTransport::new();
+        mock.feed(b"hello");
+        mock.feed(b" world");
+        let mut buf = [0u8; 3];
+        assert_eq!(mock.read(&mut buf).unwrap(), 3);
+        assert_eq!(mock.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(mock.read(&mut buf).unwrap(), 3);
+        assert_eq!(mock.read(&mut buf).unwrap(), 3);
+        assert!(mock.read(&mut buf).is_err());
+        mock.close();
+        assert_eq!(mock.read(&mut buf).unwrap(), 0);
+        mock.write_all(b"abc").unwrap();
+        mock.assert_written(b"abc");
+        mock.write_all(b"def").unwrap();
+        mock.assert_written(b"def");
+    }
+
+    #[test]
+    fn websocket_handshake() {
+        let mock = MockTransport::new();
+        let mut proto = HandshakeProto::new(mock.clone(),
+            SimpleAuthorizer::new("example.com", "/chat"));
+        lazy(|| {
+            assert!(proto.poll().unwrap().is_not_ready());
+            let request = mock.written();
+            let request = from_utf8(&request).unwrap();
+            assert!(request.starts_with("GET /chat HTTP/1.1\r\n"));
+            mock.feed(b"HTTP/1.1 101 Switching Protocols\r\n\
+                Connection: upgrade\r\nUpgrade: websocket\r\n");
+            assert!(proto.poll().unwrap().is_not_ready());
+            mock.feed(format!("Sec-WebSocket-Accept: {}\r\n\r\n",
+                              accept(request.as_bytes())).as_bytes());
+            assert!(matches!(proto.poll().unwrap(), Async::Ready(..)));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn mock_authorizer() {
+        let mock = MockTransport::new();
+        let mut auth = MockAuthorizer::new("/chat");
+        auth.response_headers.push(("X-Room".into(), "lobby".into()));
+        auth.result = Some(b"ok".to_vec());
+        let mut proto = HandshakeProto::new(mock.clone(), auth);
+        lazy(|| {
+            assert!(proto.poll().unwrap().is_not_ready());
+            assert!(mock.written().starts_with(b"GET /chat HTTP/1.1\r\n"));
+            mock.feed(format!("HTTP/1.1 101 Switching Protocols\r\n\
+                Connection: upgrade\r\nUpgrade: websocket\r\n\
+                Sec-WebSocket-Accept: {}\r\nx-room: lobby\r\n\r\n",
+                accept(&mock.written())).as_bytes());
+            match proto.poll().unwrap() {
+                Async::Ready((_, _, result)) => {
+                    assert_eq!(result, Some(b"ok".to_vec()));
+                }
+                Async::NotReady => panic!("handshake is not done"),
+            }
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn mock_authorizer_mismatch() {
+        let mock = MockTransport::new();
+        let mut auth = MockAuthorizer::new("/");
+        auth.response_headers.push(("X-Room".into(), "lobby".into()));
+        let mut proto = HandshakeProto::new(mock.clone(), auth);
+        lazy(|| {
+            assert!(proto.poll().unwrap().is_not_ready());
+            mock.feed(format!("HTTP/1.1 101 Switching Protocols\r\n\
+                Connection: upgrade\r\nUpgrade: websocket\r\n\
+                Sec-WebSocket-Accept: {}\r\nX-Room: hall\r\n\r\n",
+                accept(&mock.written())).as_bytes());
+            let err = proto.poll().err().unwrap();
+            assert_eq!(err.to_string(),
+                       "custom error: expected header X-Room: lobby");
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
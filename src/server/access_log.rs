@@ -0,0 +1,398 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::{Future, Async, Poll};
+use tk_bufstream::{ReadBuf, WriteBuf};
+use tokio_io::AsyncWrite;
+
+use enums::Version;
+use server::{Codec, Dispatcher, Encoder, EncoderDone, Error, Head, RecvMode};
+use server::encoder::{get_stats, flush_done};
+use server::error::ErrorEnum;
+
+
+/// A function that is called by `AccessLogger` for every response
+pub type LogFn = fn(&RequestInfo, &ResponseInfo, Duration);
+
+/// A dispatcher wrapper that calls a log function for every response
+///
+/// The duration passed to the log function is measured from the moment
+/// when request headers are parsed to the moment when the whole response
+/// is flushed to the network. Note: as the response future doesn't
+/// complete until the response is flushed, the next pipelined response
+/// can't start in the meantime.
+///
+/// Create a logger for every connection, so it knows the peer address:
+///
+/// ```rust,ignore
+/// let disp = AccessLogger::new(MyDispatcher, log_combined)
+///     .with_peer_addr(addr);
+/// Proto::new(socket, &cfg, disp, &handle)
+/// ```
+pub struct AccessLogger<D> {
+    dispatcher: D,
+    peer_addr: Option<SocketAddr>,
+    log_fn: LogFn,
+}
+
+/// A codec created by `AccessLogger`
+pub struct AccessLogCodec<C> {
+    codec: C,
+    request: Option<RequestInfo>,
+    start: Instant,
+    log_fn: LogFn,
+}
+
+/// A response future created by `AccessLogCodec`
+pub struct AccessLogFuture<F, S> {
+    future: F,
+    done: Option<EncoderDone<S>>,
+    request: RequestInfo,
+    start: Instant,
+    log_fn: LogFn,
+}
+
+/// Request data that is stored for the access log
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    /// Address of the client if it is set by `with_peer_addr`
+    pub peer_addr: Option<SocketAddr>,
+    /// Time when request headers were received
+    pub time: SystemTime,
+    /// Request method
+    pub method: String,
+    /// Request target as is (i.e. including query string)
+    pub target: String,
+    /// Version of HTTP request
+    pub version: Version,
+    /// The `Host` header or a host from the request target
+    pub host: Option<String>,
+    /// The `Referer` header
+    pub referer: Option<String>,
+    /// The `User-Agent` header
+    pub user_agent: Option<String>,
+}
+
+/// Response data that is stored for the access log
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseInfo {
+    /// Status code of the response (zero if response has no status line,
+    /// which means encoder was used wrongly)
+    pub status: u16,
+    /// Number of bytes in the response body, without headers and chunked
+    /// encoding overhead
+    pub body_bytes: u64,
+}
+
+/// Formats a request in the Apache Combined Log Format
+///
+/// It looks like this:
+///
+/// ```text
+/// 127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326
+///     "http://example.com/start.html" "Mozilla/4.08"
+/// ```
+///
+/// (without the line break). Time is always in UTC. Quotes and control
+/// characters in the request line and headers are escaped.
+pub struct CombinedLogFormat<'a> {
+    /// The request to format
+    pub request: &'a RequestInfo,
+    /// The response to format
+    pub response: &'a ResponseInfo,
+}
+
+/// A log function that logs every request in Combined Log Format
+///
+/// Lines are logged with `info` level and `tk_http::access_log` target.
+pub fn log_combined(req: &RequestInfo, resp: &ResponseInfo, _: Duration) {
+    info!(target: "tk_http::access_log", "{}", CombinedLogFormat {
+        request: req,
+        response: resp,
+    });
+}
+
+fn header(head: &Head, name: &str) -> Option<String> {
+    head.all_headers().iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| String::from_utf8_lossy(h.value).into_owned())
+}
+
+impl RequestInfo {
+    fn new(head: &Head, peer_addr: Option<SocketAddr>) -> RequestInfo {
+        RequestInfo {
+            peer_addr: peer_addr,
+            time: SystemTime::now(),
+            method: head.method().to_string(),
+            target: head.raw_request_target().to_string(),
+            version: head.version(),
+            host: head.host().map(|x| x.to_string()),
+            referer: header(head, "Referer"),
+            user_agent: header(head, "User-Agent"),
+        }
+    }
+}
+
+impl<D> AccessLogger<D> {
+    /// Wrap a dispatcher
+    pub fn new(dispatcher: D, log_fn: LogFn) -> AccessLogger<D> {
+        AccessLogger {
+            dispatcher: dispatcher,
+            peer_addr: None,
+            log_fn: log_fn,
+        }
+    }
+    /// Set address of the client to put into the log
+    pub fn with_peer_addr(mut self, addr: SocketAddr) -> AccessLogger<D> {
+        self.peer_addr = Some(addr);
+        self
+    }
+}
+
+impl<S, D> Dispatcher<S> for AccessLogger<D>
+    where S: AsyncWrite,
+          D: Dispatcher<S>,
+{
+    type Codec = AccessLogCodec<D::Codec>;
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        let start = Instant::now();
+        let codec = self.dispatcher.headers_received(headers)?;
+        Ok(AccessLogCodec {
+            codec: codec,
+            request: Some(RequestInfo::new(headers, self.peer_addr)),
+            start: start,
+            log_fn: self.log_fn,
+        })
+    }
+}
+
+impl<S, C> Codec<S> for AccessLogCodec<C>
+    where S: AsyncWrite,
+          C: Codec<S>,
+{
+    type ResponseFuture = AccessLogFuture<C::ResponseFuture, S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.codec.recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.codec.data_received(data, end)
+    }
+    fn start_response(&mut self, e: Encoder<S>) -> Self::ResponseFuture {
+        AccessLogFuture {
+            future: self.codec.start_response(e),
+            done: None,
+            request: self.request.take()
+                .expect("start_response is called once"),
+            start: self.start,
+            log_fn: self.log_fn,
+        }
+    }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        self.codec.hijack(output, input)
+    }
+}
+
+impl<S, F> Future for AccessLogFuture<F, S>
+    where S: AsyncWrite,
+          F: Future<Item=EncoderDone<S>, Error=Error>,
+{
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        if self.done.is_none() {
+            match self.future.poll()? {
+                Async::Ready(done) => self.done = Some(done),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        let flushed = flush_done(self.done.as_mut().unwrap())
+            .map_err(ErrorEnum::Io)?;
+        if !flushed {
+            return Ok(Async::NotReady);
+        }
+        let done = self.done.take().unwrap();
+        let stats = get_stats(&done);
+        (self.log_fn)(&self.request, &ResponseInfo {
+            status: stats.status,
+            body_bytes: stats.body_bytes,
+        }, self.start.elapsed());
+        Ok(Async::Ready(done))
+    }
+}
+
+fn write_escaped(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            ' '..='~' => write!(f, "{}", c)?,
+            _ => {
+                let mut buf = [0u8; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    write!(f, "\\x{:02x}", b)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_header(f: &mut fmt::Formatter, value: &Option<String>)
+    -> fmt::Result
+{
+    match *value {
+        Some(ref value) => {
+            f.write_str("\"")?;
+            write_escaped(f, value)?;
+            f.write_str("\"")
+        }
+        None => f.write_str("\"-\""),
+    }
+}
+
+/// Writes time in `10/Oct/2000:13:55:36 +0000` format
+fn write_time(f: &mut fmt::Formatter, time: SystemTime) -> fmt::Result {
+    const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May",
+        "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0);
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = secs / 86400 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    write!(f, "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day, MONTHS[month as usize - 1], year,
+        secs % 86400 / 3600, secs % 3600 / 60, secs % 60)
+}
+
+impl<'a> fmt::Display for CombinedLogFormat<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let req = self.request;
+        match req.peer_addr {
+            Some(addr) => write!(f, "{} - - [", addr.ip())?,
+            None => f.write_str("- - - [")?,
+        }
+        write_time(f, req.time)?;
+        f.write_str("] \"")?;
+        write_escaped(f, &req.method)?;
+        f.write_str(" ")?;
+        write_escaped(f, &req.target)?;
+        write!(f, " {}\" {} ", req.version, self.response.status)?;
+        if self.response.body_bytes > 0 {
+            write!(f, "{} ", self.response.body_bytes)?;
+        } else {
+            f.write_str("- ")?;
+        }
+        write_header(f, &req.referer)?;
+        f.write_str(" ")?;
+        write_header(f, &req.user_agent)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use futures::{Future, Async};
+    use futures::future::{FutureResult, ok};
+    use tk_bufstream::{IoBuf, MockData};
+
+    use enums::{Version, Status};
+    use server::{Codec, Encoder, EncoderDone, Error, RecvMode};
+    use server::encoder::{new, ResponseConfig};
+    use super::{AccessLogCodec, RequestInfo, ResponseInfo};
+    use super::CombinedLogFormat;
+
+    static LOGGED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Hello;
+
+    impl Codec<MockData> for Hello {
+        type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::buffered_upfront(0)
+        }
+        fn data_received(&mut self, data: &[u8], _: bool)
+            -> Result<Async<usize>, Error>
+        {
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::NotFound);
+            e.add_length(5).unwrap();
+            e.done_headers().unwrap();
+            e.write_body(b"hello");
+            ok(e.done())
+        }
+    }
+
+    fn log(_: &RequestInfo, resp: &ResponseInfo, _: Duration) {
+        assert_eq!(resp.body_bytes, 5);
+        LOGGED.store(resp.status as usize, Ordering::SeqCst);
+    }
+
+    fn request() -> RequestInfo {
+        RequestInfo {
+            peer_addr: Some("127.0.0.1:1234".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::new(971185536, 0),
+            method: "GET".to_string(),
+            target: "/a\"b".to_string(),
+            version: Version::Http11,
+            host: None,
+            referer: None,
+            user_agent: Some("curl/7.0 \u{e9}".to_string()),
+        }
+    }
+
+    #[test]
+    fn combined() {
+        assert_eq!(CombinedLogFormat {
+            request: &request(),
+            response: &ResponseInfo { status: 200, body_bytes: 2326 },
+        }.to_string(), "127.0.0.1 - - [10/Oct/2000:13:45:36 +0000] \
+            \"GET /a\\\"b HTTP/1.1\" 200 2326 \"-\" \"curl/7.0 \\xc3\\xa9\"");
+        let mut req = request();
+        req.peer_addr = None;
+        req.time = UNIX_EPOCH + Duration::new(951782400, 0);
+        assert_eq!(CombinedLogFormat {
+            request: &req,
+            response: &ResponseInfo { status: 304, body_bytes: 0 },
+        }.to_string(), "- - - [29/Feb/2000:00:00:00 +0000] \
+            \"GET /a\\\"b HTTP/1.1\" 304 - \"-\" \"curl/7.0 \\xc3\\xa9\"");
+    }
+
+    #[test]
+    fn logged_after_flush() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut codec = AccessLogCodec {
+            codec: Hello,
+            request: Some(request()),
+            start: ::std::time::Instant::now(),
+            log_fn: log,
+        };
+        codec.start_response(new(tx, ResponseConfig {
+            is_head: false,
+            do_close: false,
+            version: Version::Http11,
+        })).wait().unwrap();
+        assert_eq!(LOGGED.load(Ordering::SeqCst), 404);
+        assert!(mock.output(..).ends_with(b"\r\n\r\nhello"));
+    }
+}
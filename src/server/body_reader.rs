@@ -0,0 +1,298 @@
+use std::io::{self, Read};
+use std::mem;
+
+use futures::{Future, Async, Poll};
+use httparse::{self, parse_chunk_size, EMPTY_HEADER};
+use tk_bufstream::ReadBuf;
+use tokio_io::AsyncRead;
+
+use server::error::{Error, ErrorEnum};
+
+
+/// Number of trailer headers to allocate on a stack
+const MAX_TRAILERS: usize = 64;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+    Done,
+}
+
+/// A reader of the chunked (`Transfer-Encoding: chunked`) request body
+///
+/// This is useful when you `Hijack` connection (for example to implement
+/// a proxy) but still need to read the request body. Reader yields
+/// dechunked bytes, chunk extensions are ignored.
+///
+/// `read()` returns zero when the last (zero-length) chunk and trailer
+/// headers are read. Trailer headers are available with `trailers()`.
+pub struct ChunkedBodyReader<S> {
+    io: ReadBuf<S>,
+    state: State,
+    trailers: Vec<(String, Vec<u8>)>,
+}
+
+/// A future that reads the rest of the body and returns trailer headers
+///
+/// This future is created by `ChunkedBodyReader::trailers()`. On completion
+/// it yields trailers and a buffer that can be used to read next request.
+pub struct FutureTrailers<S>(Option<ChunkedBodyReader<S>>);
+
+/// A reader wrapper that fails when body exceeds the limit
+///
+/// `Config::max_request_body_size` can't limit the body of a hijacked
+/// request, so wrap `ChunkedBodyReader`, `PartBody` or whatever reader of
+/// the body you use into this. Reading more than `limit` bytes returns
+/// `RequestTooLong` error (wrapped into `io::Error`).
+pub struct BodyLimiter<R> {
+    reader: R,
+    remaining: u64,
+}
+
+fn to_io(e: ErrorEnum) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, Error::from(e))
+}
+
+impl<S> ChunkedBodyReader<S> {
+    /// Create a reader from buffer, right after request headers
+    pub fn new(io: ReadBuf<S>) -> ChunkedBodyReader<S> {
+        ChunkedBodyReader {
+            io: io,
+            state: State::Size,
+            trailers: Vec::new(),
+        }
+    }
+    /// Returns true if whole body (including trailers) is read
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+    /// Returns a future that reads the rest of the body and yields trailer
+    /// headers
+    ///
+    /// Note: body data that is not read yet is skipped.
+    pub fn trailers(self) -> FutureTrailers<S> {
+        FutureTrailers(Some(self))
+    }
+    /// Returns the underlying buffer
+    ///
+    /// Note: if the body is not read to the end, the buffer contains
+    /// leftovers of the body.
+    pub fn into_inner(self) -> ReadBuf<S> {
+        self.io
+    }
+    /// Parses whatever is in buffer, returns number of bytes copied
+    ///
+    /// Returns `Ok(None)` if more bytes are needed for progress.
+    fn parse(&mut self, out: &mut [u8]) -> Result<Option<usize>, ErrorEnum> {
+        use self::State::*;
+        loop {
+            let ref mut buf = self.io.in_buf;
+            match self.state {
+                Size => match parse_chunk_size(&buf[..])? {
+                    httparse::Status::Complete((bytes, 0)) => {
+                        buf.consume(bytes);
+                        self.state = Trailers;
+                    }
+                    httparse::Status::Complete((bytes, size)) => {
+                        buf.consume(bytes);
+                        self.state = Data(size);
+                    }
+                    httparse::Status::Partial => return Ok(None),
+                },
+                Data(left) => {
+                    if buf.len() == 0 {
+                        return Ok(None);
+                    }
+                    let n = [left as usize, buf.len(), out.len()].iter()
+                        .cloned().min().unwrap();
+                    out[..n].copy_from_slice(&buf[..n]);
+                    buf.consume(n);
+                    self.state = if n as u64 == left {
+                        DataEnd
+                    } else {
+                        Data(left - n as u64)
+                    };
+                    return Ok(Some(n));
+                }
+                DataEnd => {
+                    if buf.len() < 2 {
+                        return Ok(None);
+                    }
+                    if &buf[..2] != b"\r\n" {
+                        return Err(ErrorEnum::ChunkParseError(
+                            httparse::InvalidChunkSize));
+                    }
+                    buf.consume(2);
+                    self.state = Size;
+                }
+                Trailers => {
+                    let mut headers = [EMPTY_HEADER; MAX_TRAILERS];
+                    let bytes = match httparse::parse_headers(&buf[..],
+                                                              &mut headers)?
+                    {
+                        httparse::Status::Complete((bytes, headers)) => {
+                            self.trailers.extend(headers.iter().map(|h| {
+                                (h.name.to_string(), h.value.to_vec())
+                            }));
+                            bytes
+                        }
+                        httparse::Status::Partial => return Ok(None),
+                    };
+                    buf.consume(bytes);
+                    self.state = Done;
+                }
+                Done => return Ok(Some(0)),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead> Read for ChunkedBodyReader<S> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.len() == 0 {
+            return Ok(0);
+        }
+        loop {
+            match self.parse(out).map_err(to_io)? {
+                Some(n) => return Ok(n),
+                None => {}
+            }
+            if self.io.read()? == 0 {
+                if self.io.done() {
+                    return Err(to_io(ErrorEnum::ConnectionReset));
+                }
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for ChunkedBodyReader<S> {}
+
+impl<R> BodyLimiter<R> {
+    /// Wrap a reader allowing to read at most `limit` bytes
+    pub fn new(reader: R, limit: u64) -> BodyLimiter<R> {
+        BodyLimiter {
+            reader: reader,
+            remaining: limit,
+        }
+    }
+    /// Returns a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for BodyLimiter<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        // read a byte more than allowed to find out if body is too long
+        let max = if (out.len() as u64) > self.remaining {
+            self.remaining as usize + 1
+        } else {
+            out.len()
+        };
+        let bytes = self.reader.read(&mut out[..max])?;
+        if bytes as u64 > self.remaining {
+            return Err(to_io(ErrorEnum::RequestTooLong));
+        }
+        self.remaining -= bytes as u64;
+        Ok(bytes)
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for BodyLimiter<R> {}
+
+impl<S: AsyncRead> Future for FutureTrailers<S> {
+    type Item = (Vec<(String, Vec<u8>)>, ReadBuf<S>);
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        {
+            let reader = self.0.as_mut().expect("future is polled twice");
+            let mut tmp = [0u8; 4096];
+            loop {
+                match reader.parse(&mut tmp)? {
+                    Some(0) => break,
+                    Some(_) => continue,
+                    None => {}
+                }
+                if reader.io.read().map_err(ErrorEnum::Io)? == 0 {
+                    if reader.io.done() {
+                        return Err(ErrorEnum::ConnectionReset.into());
+                    }
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+        let mut reader = self.0.take().unwrap();
+        let trailers = mem::replace(&mut reader.trailers, Vec::new());
+        Ok(Async::Ready((trailers, reader.io)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use futures::Future;
+    use tk_bufstream::{MockData, IoBuf};
+
+    use super::{ChunkedBodyReader, BodyLimiter};
+
+    fn reader(data: &str) -> ChunkedBodyReader<MockData> {
+        let mock = MockData::new();
+        mock.add_input(data);
+        ChunkedBodyReader::new(IoBuf::new(mock).split().1)
+    }
+
+    #[test]
+    fn simple() {
+        let mut r = reader("4\r\nhell\r\n1;ext=1\r\no\r\n0\r\n\r\nnext");
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello");
+        assert!(r.is_done());
+        assert_eq!(&r.into_inner().in_buf[..], b"next");
+    }
+
+    #[test]
+    fn trailers() {
+        let r = reader("5\r\nhello\r\n0\r\nX-Checksum: 123\r\n\r\n");
+        let (trailers, _) = r.trailers().wait().unwrap();
+        assert_eq!(trailers, vec![
+            ("X-Checksum".to_string(), b"123".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn invalid_size() {
+        let mut r = reader("x\r\nhello\r\n0\r\n\r\n");
+        let mut data = Vec::new();
+        assert!(r.read_to_end(&mut data).is_err());
+    }
+
+    #[test]
+    fn no_crlf_after_chunk() {
+        let mut r = reader("2\r\nhello\r\n0\r\n\r\n");
+        let mut data = Vec::new();
+        assert!(r.read_to_end(&mut data).is_err());
+    }
+
+    #[test]
+    fn limit() {
+        let mut data = Vec::new();
+        BodyLimiter::new(reader("5\r\nhello\r\n0\r\n\r\n"), 5)
+            .read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello");
+        let mut data = Vec::new();
+        assert!(BodyLimiter::new(reader("5\r\nhello\r\n0\r\n\r\n"), 4)
+            .read_to_end(&mut data).is_err());
+    }
+}
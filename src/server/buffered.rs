@@ -134,6 +134,25 @@ impl<S, H, I, T, U> Service<S> for WebsocketService<H, I, T, U>
 }
 
 impl Request {
+    pub(crate) fn from_head(addr: SocketAddr, headers: &Head) -> Request {
+        let up = headers.get_websocket_upgrade();
+        Request {
+            peer_addr: addr,
+            method: headers.method().to_string(),
+            // TODO(tailhook) process other forms of path
+            path: headers.path().unwrap().to_string(),
+            host: headers.host().map(|x| x.to_string()),
+            version: headers.version(),
+            headers: headers.headers().map(|(name, value)| {
+                (name.to_string(), value.to_vec())
+            }).collect(),
+            body: Vec::new(),
+            websocket_handshake: up.unwrap_or(None),
+        }
+    }
+    pub(crate) fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
     /// Returns peer address that initiated HTTP connection
     pub fn peer_addr(&self) -> SocketAddr {
         self.peer_addr
@@ -251,23 +270,10 @@ impl<S, N: NewService<S>> Dispatcher<S> for BufferedDispatcher<S, N> {
         -> Result<Self::Codec, Error>
     {
         // TODO(tailhook) strip hop-by-hop headers
-        let up = headers.get_websocket_upgrade();
         Ok(BufferedCodec {
             max_request_length: self.max_request_length,
             service: self.service.new(),
-            request: Some(Request {
-                peer_addr: self.addr,
-                method: headers.method().to_string(),
-                // TODO(tailhook) process other forms of path
-                path: headers.path().unwrap().to_string(),
-                host: headers.host().map(|x| x.to_string()),
-                version: headers.version(),
-                headers: headers.headers().map(|(name, value)| {
-                    (name.to_string(), value.to_vec())
-                }).collect(),
-                body: Vec::new(),
-                websocket_handshake: up.unwrap_or(None),
-            }),
+            request: Some(Request::from_head(self.addr, headers)),
             handle: self.handle.clone(),
         })
     }
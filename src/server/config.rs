@@ -16,6 +16,9 @@ impl Config {
             input_body_whole_timeout: Duration::new(3600, 0),
             output_body_byte_timeout: Duration::new(15, 0),
             output_body_whole_timeout: Duration::new(3600, 0),
+            max_request_body_size: 64 << 20,
+            #[cfg(feature="date_header")]
+            date_header: true,
         }
     }
     /// A number of inflight requests until we stop reading more requests
@@ -83,4 +86,37 @@ impl Config {
         self.output_body_whole_timeout = value;
         self
     }
+    /// Maximum size of the request body (default is 64 MiB)
+    ///
+    /// Both fixed-size and chunked bodies are limited, in any `RecvMode`
+    /// except `Hijack` (use `BodyLimiter` in the latter case). Requests
+    /// with larger bodies fail with `RequestTooLong` error which closes
+    /// the connection. `RecvMode::buffered_upfront` limit is applied
+    /// too, the smaller one wins.
+    pub fn max_request_body_size(&mut self, value: u64) -> &mut Self {
+        self.max_request_body_size = value;
+        self
+    }
+    /// Write `Date` header with the current time into every response
+    ///
+    /// The header is written right after the status line, so it's written
+    /// even if the codec doesn't call `Encoder::add_date` itself (calling
+    /// it is a no-op in this case). Default is `true`.
+    #[cfg(feature="date_header")]
+    pub fn date_header(&mut self, value: bool) -> &mut Self {
+        self.date_header = value;
+        self
+    }
+}
+
+/// Default config that doesn't write `Date` header, so tests can compare
+/// responses byte by byte
+#[cfg(all(test, feature="date_header"))]
+pub fn without_date() -> Arc<Config> {
+    Config::new().date_header(false).done()
+}
+
+#[cfg(all(test, not(feature="date_header")))]
+pub fn without_date() -> Arc<Config> {
+    Arc::new(Config::new())
 }
@@ -0,0 +1,291 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::sync::Arc;
+
+use futures::Async;
+use tk_bufstream::{ReadBuf, WriteBuf};
+
+use enums::Status;
+use server::{Codec, Dispatcher, Encoder, EncoderDone, Error, Head, RecvMode};
+use server::encoder::add_upgrade_header;
+use server::short_circuit::{Reply, ReplyFuture, ShortCircuit};
+
+
+/// Cross-origin resource sharing settings for `CorsMiddleware`
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// Origins which are allowed to make requests, e.g.
+    /// `https://example.com`, compared case-insensitively
+    ///
+    /// A single `*` allows any origin. Origins allowed only by `*` get
+    /// `Access-Control-Allow-Origin: *` and never get credentials, as the
+    /// fetch standard forbids credentialed requests with a wildcard.
+    pub allowed_origins: Vec<String>,
+    /// Allow requests with cookies and HTTP authentication
+    pub allow_credentials: bool,
+    /// Number of seconds the browser may cache preflight response
+    pub max_age: u32,
+}
+
+/// A dispatcher wrapper that handles CORS preflight requests
+///
+/// It answers `OPTIONS` requests having `Access-Control-Request-Method: GET`
+/// by itself: with `204 No Content` and `Access-Control-Allow-*` headers if
+/// `Origin` is allowed, and with `403 Forbidden` otherwise. Other preflight
+/// requests are passed to the wrapped dispatcher.
+///
+/// For websocket handshakes from an allowed origin, the
+/// `Access-Control-Allow-Origin` header is added to the `101 Switching
+/// Protocols` response written by the wrapped dispatcher. Note: browsers
+/// don't enforce CORS on websockets, so you still need to check the
+/// `Origin` header to protect from cross-site websocket hijacking.
+pub struct CorsMiddleware<D> {
+    dispatcher: D,
+    config: Arc<CorsConfig>,
+}
+
+/// A codec created by `CorsMiddleware`
+pub struct CorsCodec<C> {
+    kind: ShortCircuit<Preflight, C>,
+    /// Origin and credentials flag for a websocket handshake
+    upgrade: Option<(String, bool)>,
+}
+
+struct Preflight {
+    /// Value of `Access-Control-Allow-Origin` and credentials flag
+    origin: Option<(String, bool)>,
+    headers: Option<String>,
+    config: Arc<CorsConfig>,
+}
+
+fn header<'x>(head: &'x Head, name: &str) -> Option<&'x [u8]> {
+    head.all_headers().iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| h.value)
+}
+
+impl CorsConfig {
+    /// Create a config that allows no origins
+    pub fn new() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allow_credentials: false,
+            max_age: 0,
+        }
+    }
+    /// Returns `true` if origin is in the allow list
+    pub fn origin_allowed(&self, origin: &str) -> bool {
+        self.allow_origin(origin).is_some()
+    }
+    /// Returns value of `Access-Control-Allow-Origin` for the origin and
+    /// whether credentials are allowed
+    fn allow_origin(&self, origin: &str) -> Option<(String, bool)> {
+        if self.allowed_origins.iter()
+            .any(|x| x != "*" && x.eq_ignore_ascii_case(origin))
+        {
+            Some((origin.to_string(), self.allow_credentials))
+        } else if self.allowed_origins.iter().any(|x| x == "*") {
+            Some(("*".to_string(), false))
+        } else {
+            None
+        }
+    }
+    /// Create a `Arc`'d config for use with `CorsMiddleware`
+    pub fn done(&self) -> Arc<CorsConfig> {
+        Arc::new(self.clone())
+    }
+}
+
+impl<D> CorsMiddleware<D> {
+    /// Wrap a dispatcher
+    pub fn new(dispatcher: D, config: &Arc<CorsConfig>) -> CorsMiddleware<D> {
+        CorsMiddleware {
+            dispatcher: dispatcher,
+            config: config.clone(),
+        }
+    }
+}
+
+impl<S, D: Dispatcher<S>> Dispatcher<S> for CorsMiddleware<D> {
+    type Codec = CorsCodec<D::Codec>;
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        let origin = header(headers, "Origin")
+            .and_then(|x| String::from_utf8(x.to_vec()).ok())
+            .and_then(|x| self.config.allow_origin(&x));
+        let preflight = headers.method() == "OPTIONS" &&
+            header(headers, "Access-Control-Request-Method")
+            .map(|x| x == b"GET").unwrap_or(false);
+        if preflight {
+            return Ok(CorsCodec {
+                kind: ShortCircuit::Reply(Preflight {
+                    origin: origin,
+                    headers: header(headers,
+                                    "Access-Control-Request-Headers")
+                        .map(|x| String::from_utf8_lossy(x).into_owned()),
+                    config: self.config.clone(),
+                }),
+                upgrade: None,
+            });
+        }
+        let codec = self.dispatcher.headers_received(headers)?;
+        let upgrade = headers.get_websocket_upgrade()
+            .map(|x| x.is_some()).unwrap_or(false);
+        Ok(CorsCodec {
+            kind: ShortCircuit::Pass(codec),
+            upgrade: if upgrade { origin } else { None },
+        })
+    }
+}
+
+impl Reply for Preflight {
+    fn write<S>(&self, mut e: Encoder<S>) -> EncoderDone<S> {
+        let (origin, credentials) = match self.origin {
+            Some((ref origin, credentials)) => (origin, credentials),
+            None => {
+                e.status(Status::Forbidden);
+                e.add_length(0).unwrap();
+                e.done_headers().unwrap();
+                return e.done();
+            }
+        };
+        e.status(Status::NoContent);
+        e.add_header("Access-Control-Allow-Origin", origin).unwrap();
+        e.add_header("Access-Control-Allow-Methods", "GET").unwrap();
+        if let Some(ref headers) = self.headers {
+            // requested headers are echoed back, we don't restrict them
+            e.add_header("Access-Control-Allow-Headers", headers).unwrap();
+        }
+        if credentials {
+            e.add_header("Access-Control-Allow-Credentials", "true")
+                .unwrap();
+        }
+        if self.config.max_age > 0 {
+            e.format_header("Access-Control-Max-Age", self.config.max_age)
+                .unwrap();
+        }
+        e.add_header("Vary", "Origin").unwrap();
+        e.done_headers().unwrap();
+        e.done()
+    }
+}
+
+impl<S, C: Codec<S>> Codec<S> for CorsCodec<C> {
+    type ResponseFuture = ReplyFuture<S, C::ResponseFuture>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.kind.recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.kind.data_received(data, end)
+    }
+    fn start_response(&mut self, mut e: Encoder<S>) -> Self::ResponseFuture {
+        if let Some((ref origin, credentials)) = self.upgrade {
+            add_upgrade_header(&mut e,
+                "Access-Control-Allow-Origin", origin.as_bytes());
+            if credentials {
+                add_upgrade_header(&mut e,
+                    "Access-Control-Allow-Credentials", b"true");
+            }
+        }
+        self.kind.start_response(e)
+    }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        self.kind.hijack(output, input)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use enums::Status;
+    use server::test_util::{Stub, serve};
+    use super::{CorsConfig, CorsMiddleware};
+
+    fn cors(request: &str) -> String {
+        cors_with(&CorsConfig {
+            allowed_origins: vec!["https://example.com".to_string()],
+            allow_credentials: true,
+            max_age: 600,
+        }.done(), request)
+    }
+
+    fn cors_with(cors: &Arc<CorsConfig>, request: &str) -> String {
+        serve(CorsMiddleware::new(Stub(Status::SwitchingProtocol), cors),
+              request)
+    }
+
+    #[test]
+    fn preflight() {
+        assert_eq!(cors("OPTIONS /ws HTTP/1.1\r\nHost: x\r\n\
+            Origin: https://Example.com\r\n\
+            Access-Control-Request-Method: GET\r\n\
+            Access-Control-Request-Headers: x-token\r\n\r\n"),
+            "HTTP/1.1 204 No Content\r\n\
+             Access-Control-Allow-Origin: https://Example.com\r\n\
+             Access-Control-Allow-Methods: GET\r\n\
+             Access-Control-Allow-Headers: x-token\r\n\
+             Access-Control-Allow-Credentials: true\r\n\
+             Access-Control-Max-Age: 600\r\n\
+             Vary: Origin\r\n\r\n");
+        assert_eq!(cors("OPTIONS /ws HTTP/1.1\r\nHost: x\r\n\
+            Origin: https://evil.com\r\n\
+            Access-Control-Request-Method: GET\r\n\r\n"),
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn websocket() {
+        let request = "GET /ws HTTP/1.1\r\nHost: x\r\n\
+            Connection: upgrade\r\nUpgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+        assert_eq!(cors(&format!("{}Origin: https://example.com\r\n\r\n",
+                                  request)),
+            "HTTP/1.1 101 Switching Protocol\r\n\
+             Access-Control-Allow-Origin: https://example.com\r\n\
+             Access-Control-Allow-Credentials: true\r\n\
+             Upgrade: websocket\r\n\r\n");
+        assert_eq!(cors(&format!("{}Origin: https://evil.com\r\n\r\n",
+                                  request)),
+            "HTTP/1.1 101 Switching Protocol\r\n\
+             Upgrade: websocket\r\n\r\n");
+    }
+
+    #[test]
+    fn wildcard_with_credentials() {
+        let cors = CorsConfig {
+            allowed_origins: vec!["https://example.com".to_string(),
+                                  "*".to_string()],
+            allow_credentials: true,
+            max_age: 0,
+        }.done();
+        assert_eq!(cors_with(&cors, "OPTIONS /ws HTTP/1.1\r\nHost: x\r\n\
+            Origin: https://evil.com\r\n\
+            Access-Control-Request-Method: GET\r\n\r\n"),
+            "HTTP/1.1 204 No Content\r\n\
+             Access-Control-Allow-Origin: *\r\n\
+             Access-Control-Allow-Methods: GET\r\n\
+             Vary: Origin\r\n\r\n");
+        assert_eq!(cors_with(&cors, "GET /ws HTTP/1.1\r\nHost: x\r\n\
+            Connection: upgrade\r\nUpgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Origin: https://evil.com\r\n\r\n"),
+            "HTTP/1.1 101 Switching Protocol\r\n\
+             Access-Control-Allow-Origin: *\r\n\
+             Upgrade: websocket\r\n\r\n");
+        // explicitly listed origins still get credentials
+        assert_eq!(cors_with(&cors, "OPTIONS /ws HTTP/1.1\r\nHost: x\r\n\
+            Origin: https://example.com\r\n\
+            Access-Control-Request-Method: GET\r\n\r\n"),
+            "HTTP/1.1 204 No Content\r\n\
+             Access-Control-Allow-Origin: https://example.com\r\n\
+             Access-Control-Allow-Methods: GET\r\n\
+             Access-Control-Allow-Credentials: true\r\n\
+             Vary: Origin\r\n\r\n");
+    }
+}
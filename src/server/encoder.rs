@@ -1,3 +1,5 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
 use std::io;
 use std::fmt::Display;
 
@@ -5,9 +7,10 @@ use futures::{Future, Poll, Async};
 use tk_bufstream::{WriteBuf, WriteRaw, FutureWriteRaw};
 use tokio_io::AsyncWrite;
 
-use base_serializer::{MessageState, HeaderError};
+use base_serializer::{MessageState, HeaderError, invalid_header};
 use enums::{Version, Status};
 use super::headers::Head;
+use super::{Config, EntityTag};
 
 
 /// This a response writer that you receive in `Codec`
@@ -17,12 +20,26 @@ use super::headers::Head;
 pub struct Encoder<S> {
     state: MessageState,
     io: WriteBuf<S>,
+    stats: Stats,
+    /// Headers added by middleware to the `101 Switching Protocols`
+    upgrade_headers: Vec<(String, Vec<u8>)>,
+    /// Write `Date` header right after the status line
+    #[cfg(feature="date_header")]
+    auto_date: bool,
 }
 
 /// This structure returned from `Encoder::done` and works as a continuation
 /// that should be returned from the future that writes request.
 pub struct EncoderDone<S> {
     buf: WriteBuf<S>,
+    stats: Stats,
+}
+
+/// Status code and body size of the response, used for access logging
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub status: u16,
+    pub body_bytes: u64,
 }
 
 /// This structure contains all needed info to start response of the request
@@ -42,7 +59,7 @@ pub struct ResponseConfig {
 /// A future that yields `RawBody` after buffer is empty
 ///
 /// This future is created by `Encoder::raw_body()``
-pub struct FutureRawBody<S>(FutureWriteRaw<S>);
+pub struct FutureRawBody<S>(FutureWriteRaw<S>, Stats);
 
 /// A future that yields `Encoder` again after buffer has less bytes
 ///
@@ -60,6 +77,7 @@ pub struct WaitFlush<S>(Option<Encoder<S>>, usize);
 /// reconstruct original object, `EncoderDone` in this case.
 pub struct RawBody<S> {
     io: WriteRaw<S>,
+    stats: Stats,
 }
 
 
@@ -92,7 +110,10 @@ impl<S> Encoder<S> {
     /// as a final status code.
     pub fn status(&mut self, status: Status) {
         self.state.response_status(&mut self.io.out_buf,
-            status.code(), status.reason())
+            status.code(), status.reason());
+        self.stats.status = status.code();
+        self.write_upgrade_headers();
+        self.write_auto_date();
     }
 
     /// Write custom status line
@@ -105,7 +126,27 @@ impl<S> Encoder<S> {
     /// When the status code is 100 (Continue). 100 is not allowed
     /// as a final status code.
     pub fn custom_status(&mut self, code: u16, reason: &str) {
-        self.state.response_status(&mut self.io.out_buf, code, reason)
+        self.state.response_status(&mut self.io.out_buf, code, reason);
+        self.stats.status = code;
+        self.write_upgrade_headers();
+        self.write_auto_date();
+    }
+    #[cfg(feature="date_header")]
+    fn write_auto_date(&mut self) {
+        if self.auto_date {
+            self.write_date();
+        }
+    }
+    #[cfg(not(feature="date_header"))]
+    fn write_auto_date(&mut self) {}
+    fn write_upgrade_headers(&mut self) {
+        if self.stats.status != 101 {
+            return;
+        }
+        for (name, value) in self.upgrade_headers.drain(..) {
+            self.state.add_header(&mut self.io.out_buf, &name, &value)
+                .expect("upgrade headers are validated");
+        }
     }
 
     /// Add a header to the message.
@@ -180,13 +221,28 @@ impl<S> Encoder<S> {
     /// ```ignore
     /// enc.format_header("Date", HttpDate::from(SystemTime::now()));
     /// ```
+    ///
+    /// Does nothing if the header is written automatically (see
+    /// `Config::date_header`).
     #[cfg(feature="date_header")]
     pub fn add_date(&mut self) {
+        if !self.auto_date {
+            self.write_date();
+        }
+    }
+    #[cfg(feature="date_header")]
+    fn write_date(&mut self) {
         use httpdate::HttpDate;
         use std::time::SystemTime;
         self.format_header("Date", HttpDate::from(SystemTime::now()))
             .expect("always valid to add a date")
     }
+    /// Add an `ETag` header
+    ///
+    /// Same as `format_header("ETag", tag)`.
+    pub fn etag(&mut self, tag: &EntityTag) -> Result<(), HeaderError> {
+        self.format_header("ETag", tag)
+    }
     /// Returns true if at least `status()` method has been called
     ///
     /// This is mostly useful to find out whether we can build an error page
@@ -229,7 +285,8 @@ impl<S> Encoder<S> {
     /// determine response body length (either Content-Length or
     /// Transfer-Encoding).
     pub fn write_body(&mut self, data: &[u8]) {
-        self.state.write_body(&mut self.io.out_buf, data)
+        self.state.write_body(&mut self.io.out_buf, data);
+        self.stats.body_bytes += data.len() as u64;
     }
     /// Returns true if `done()` method is already called and everything
     /// was okay.
@@ -246,7 +303,7 @@ impl<S> Encoder<S> {
     /// When the response is in the wrong state.
     pub fn done(mut self) -> EncoderDone<S> {
         self.state.done(&mut self.io.out_buf);
-        EncoderDone { buf: self.io }
+        EncoderDone { buf: self.io, stats: self.stats }
     }
     /// Returns a raw body for zero-copy writing techniques
     ///
@@ -269,7 +326,7 @@ impl<S> Encoder<S> {
     /// This method panics if it's called when headers are not written yet.
     pub fn raw_body(self) -> FutureRawBody<S> {
         assert!(self.state.is_after_headers());
-        FutureRawBody(self.io.borrow_raw())
+        FutureRawBody(self.io.borrow_raw(), self.stats)
     }
 
     /// Flush the data to underlying socket
@@ -304,7 +361,7 @@ impl<S> RawBody<S> {
     /// Returns `EncoderDone` object that might be passed back to the HTTP
     /// protocol
     pub fn done(self) -> EncoderDone<S> {
-        EncoderDone { buf: self.io.into_buf() }
+        EncoderDone { buf: self.io.into_buf(), stats: self.stats }
     }
 }
 
@@ -328,7 +385,9 @@ impl<S: AsyncWrite> AsyncWrite for Encoder<S> {
 
 impl<S: AsyncWrite> io::Write for RawBody<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.io.get_mut().write(buf)
+        let bytes = self.io.get_mut().write(buf)?;
+        self.stats.body_bytes += bytes as u64;
+        Ok(bytes)
     }
     fn flush(&mut self) -> io::Result<()> {
         self.io.get_mut().flush()
@@ -345,6 +404,18 @@ pub fn get_inner<S>(e: EncoderDone<S>) -> WriteBuf<S> {
     e.buf
 }
 
+pub fn get_stats<S>(e: &EncoderDone<S>) -> Stats {
+    e.stats
+}
+
+/// Flushes the buffer, returns true if it's empty
+pub fn flush_done<S: AsyncWrite>(e: &mut EncoderDone<S>)
+    -> Result<bool, io::Error>
+{
+    e.buf.flush()?;
+    Ok(e.buf.out_buf.len() == 0)
+}
+
 pub fn new<S>(io: WriteBuf<S>, cfg: ResponseConfig) -> Encoder<S> {
     use base_serializer::Body::*;
 
@@ -357,9 +428,48 @@ pub fn new<S>(io: WriteBuf<S>, cfg: ResponseConfig) -> Encoder<S> {
             close: cfg.do_close || cfg.version == Version::Http10,
         },
         io: io,
+        stats: Stats::default(),
+        upgrade_headers: Vec::new(),
+        #[cfg(feature="date_header")]
+        auto_date: false,
     }
 }
 
+/// Same as `new` but also applies server-wide settings from `Config`
+#[cfg(feature="date_header")]
+pub fn new_with_config<S>(io: WriteBuf<S>, cfg: ResponseConfig,
+    config: &Config)
+    -> Encoder<S>
+{
+    Encoder {
+        auto_date: config.date_header,
+        .. new(io, cfg)
+    }
+}
+
+#[cfg(not(feature="date_header"))]
+pub fn new_with_config<S>(io: WriteBuf<S>, cfg: ResponseConfig,
+    _config: &Config)
+    -> Encoder<S>
+{
+    new(io, cfg)
+}
+
+/// Adds a header which is written only if the status is 101
+///
+/// Returns `false` if the header name or value is invalid.
+pub fn add_upgrade_header<S>(e: &mut Encoder<S>, name: &str, value: &[u8])
+    -> bool
+{
+    let valid = !invalid_header(name.as_bytes()) && !invalid_header(value) &&
+        !name.eq_ignore_ascii_case("Content-Length") &&
+        !name.eq_ignore_ascii_case("Transfer-Encoding");
+    if valid {
+        e.upgrade_headers.push((name.to_string(), value.to_vec()));
+    }
+    valid
+}
+
 impl ResponseConfig {
     pub fn from(req: &Head) -> ResponseConfig {
         ResponseConfig {
@@ -374,7 +484,8 @@ impl<S: AsyncWrite> Future for FutureRawBody<S> {
     type Item = RawBody<S>;
     type Error = io::Error;
     fn poll(&mut self) -> Poll<RawBody<S>, io::Error> {
-        self.0.poll().map(|x| x.map(|y| RawBody { io: y }))
+        let stats = self.1;
+        self.0.poll().map(|x| x.map(|y| RawBody { io: y, stats: stats }))
     }
 }
 
@@ -409,7 +520,9 @@ mod sendfile {
             -> Result<usize, io::Error>
         {
             // TODO(tailhook) check the data written
-            self.io.get_mut().write_file(file)
+            let bytes = self.io.get_mut().write_file(file)?;
+            self.stats.body_bytes += bytes as u64;
+            Ok(bytes)
         }
         fn poll_write(&self) -> Async<()> {
             self.io.get_ref().poll_write()
@@ -423,7 +536,7 @@ mod test {
     use {Status};
 
     use base_serializer::{MessageState, Body};
-    use super::{Encoder, EncoderDone};
+    use super::{Encoder, EncoderDone, EntityTag};
     use enums::Version;
 
     fn do_response11_str<F>(fun: F) -> String
@@ -437,12 +550,17 @@ mod test {
                     close: false,
                 },
                 io: IoBuf::new(mock.clone()).split().0,
+                stats: Default::default(),
+                upgrade_headers: Vec::new(),
+                #[cfg(feature="date_header")]
+                auto_date: false,
             });
         {done}.buf.flush().unwrap();
         String::from_utf8_lossy(&mock.output(..)).to_string()
     }
 
     #[test]
+    #[cfg(feature="date_header")]
     fn date_header() {
         assert!(do_response11_str(|mut enc| {
                 enc.status(Status::Ok);
@@ -452,4 +570,29 @@ mod test {
                 enc.done()
             }).starts_with("HTTP/1.1 200 OK\r\nDate: "));
     }
+
+    #[test]
+    #[cfg(feature="date_header")]
+    fn auto_date() {
+        let out = do_response11_str(|mut enc| {
+            enc.auto_date = true;
+            enc.status(Status::Ok);
+            enc.add_date();
+            enc.add_length(0).unwrap();
+            enc.done_headers().unwrap();
+            enc.done()
+        });
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\nDate: "));
+        assert_eq!(out.matches("Date: ").count(), 1);
+    }
+
+    #[test]
+    fn etag() {
+        assert_eq!(do_response11_str(|mut enc| {
+                enc.status(Status::NotModified);
+                enc.etag(&EntityTag::weak("v1")).unwrap();
+                enc.done_headers().unwrap();
+                enc.done()
+            }), "HTTP/1.1 304 Not Modified\r\nETag: W/\"v1\"\r\n\r\n");
+    }
 }
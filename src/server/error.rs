@@ -1,24 +1,30 @@
+use std::error::Error as StdError;
+use std::fmt;
 use std::io;
 use std::convert::From;
 
 use httparse;
 
+use {AnnotatedError, ConnectionId};
+
 
 quick_error! {
     /// HTTP server error
     #[derive(Debug)]
-    pub enum Error wraps pub ErrorEnum {
+    pub enum ErrorEnum {
         /// Socket IO error
         Io(err: io::Error) {
             description("I/O error")
             display("I/O error: {}", err)
             from()
+            cause(err)
         }
         /// Error parsing http headers
         ParseError(err: httparse::Error) {
             description("parse error")
             display("parse error: {:?}", err)
             from()
+            cause(err)
         }
         /// Error parsing http chunk
         ChunkParseError(err: httparse::InvalidChunkSize) {
@@ -60,10 +66,16 @@ quick_error! {
         UnsupportedBody {
             description("this kind of request body is not supported (CONNECT)")
         }
-        /// Request body is larger than x in `RecvMode::Buffered(x)` or >64bit
+        /// Request body is larger than x in `RecvMode::Buffered(x)`, than
+        /// `Config::max_request_body_size` or than `BodyLimiter` allows
         RequestTooLong {
             description("request body is too big")
         }
+        /// Malformed `multipart/*` request body
+        MultipartInvalid(reason: &'static str) {
+            description("invalid multipart body")
+            display("invalid multipart body: {}", reason)
+        }
         Timeout {
             description("timeout while reading or writing request")
         }
@@ -75,6 +87,37 @@ quick_error! {
     }
 }
 
+/// HTTP server error
+#[derive(Debug)]
+pub struct Error(ErrorEnum);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<ErrorEnum> for Error {
+    fn from(err: ErrorEnum) -> Error {
+        Error(err)
+    }
+}
+
+impl StdError for Error {
+    #[allow(deprecated)]
+    fn cause(&self) -> Option<&StdError> {
+        self.0.cause()
+    }
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match self.0 {
+            ErrorEnum::Io(ref err) => Some(err),
+            ErrorEnum::ParseError(ref err) => Some(err),
+            ErrorEnum::Custom(ref err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
 impl Error {
     /// Create an error instance wrapping custom error
     pub fn custom<E: Into<Box<::std::error::Error + Send + Sync>>>(err: E)
@@ -82,6 +125,13 @@ impl Error {
     {
         Error(ErrorEnum::Custom(err.into()))
     }
+    /// Wrap the error with the id of the connection to make it more
+    /// useful in logs
+    pub fn with_connection_id(self, id: ConnectionId)
+        -> AnnotatedError<Error>
+    {
+        AnnotatedError::new(id, self)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -95,3 +145,15 @@ fn send_sync() {
     fn send_sync<T: Send+Sync>(_: T) {}
     send_sync(Error::from(ErrorEnum::Timeout));
 }
+
+#[test]
+fn source() {
+    use std::error::Error as StdError;
+    let err = Error::from(io::Error::new(io::ErrorKind::BrokenPipe, "test"));
+    assert_eq!(err.source().unwrap().to_string(), "test");
+    let err = Error::from(ErrorEnum::from(httparse::Error::Token));
+    assert!(err.source().unwrap().is::<httparse::Error>());
+    assert!(Error::from(ErrorEnum::Timeout).source().is_none());
+    let err = err.with_connection_id(ConnectionId(1));
+    assert!(err.source().unwrap().is::<Error>());
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+
+
+/// An entity tag as used in `ETag` and `If-None-Match` headers (RFC 7232)
+///
+/// Displays in the header format, i.e. `"abc"` or `W/"abc"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    weak: bool,
+    tag: String,
+}
+
+fn is_etag_char(c: u8) -> bool {
+    c == 0x21 || c >= 0x23 && c != 0x7F
+}
+
+impl EntityTag {
+    /// Create a strong entity tag (tag is without quotes)
+    ///
+    /// # Panics
+    ///
+    /// When tag contains quotes, whitespace or control characters
+    pub fn strong(tag: &str) -> EntityTag {
+        EntityTag::new(false, tag)
+    }
+    /// Create a weak entity tag (tag is without `W/` and quotes)
+    ///
+    /// # Panics
+    ///
+    /// When tag contains quotes, whitespace or control characters
+    pub fn weak(tag: &str) -> EntityTag {
+        EntityTag::new(true, tag)
+    }
+    fn new(weak: bool, tag: &str) -> EntityTag {
+        assert!(tag.bytes().all(is_etag_char),
+            "entity tag {:?} contains invalid characters", tag);
+        EntityTag {
+            weak: weak,
+            tag: tag.to_string(),
+        }
+    }
+    /// Parse an entity tag in the header format (`"abc"` or `W/"abc"`)
+    pub fn parse(value: &str) -> Option<EntityTag> {
+        let value = value.trim();
+        let (weak, value) = if value.starts_with("W/") {
+            (true, &value[2..])
+        } else {
+            (false, value)
+        };
+        if value.len() < 2 || !value.starts_with('"') ||
+            !value.ends_with('"')
+        {
+            return None;
+        }
+        let tag = &value[1..value.len()-1];
+        if !tag.bytes().all(is_etag_char) {
+            return None;
+        }
+        Some(EntityTag {
+            weak: weak,
+            tag: tag.to_string(),
+        })
+    }
+    /// Returns `true` if this is a weak tag
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+    /// Returns the tag without quotes and weakness indicator
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+    /// Strong comparison: both tags are strong and equal
+    ///
+    /// This is what `If-Match` and `Range` requests use.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+    /// Weak comparison: tags are equal regardless of weakness
+    ///
+    /// This is what `If-None-Match` requests use.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+/// Splits a list of entity tags (as in `If-None-Match`) at commas
+///
+/// Commas inside quotes are part of the tag, so unlike `str::split` this
+/// doesn't break tags like `"a,b"` apart.
+pub(crate) fn split_list(value: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (idx, c) in value.bytes().enumerate() {
+        match c {
+            b'"' => quoted = !quoted,
+            b',' if !quoted => {
+                items.push(&value[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+    items
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EntityTag, split_list};
+
+    #[test]
+    fn format() {
+        assert_eq!(EntityTag::strong("abc").to_string(), "\"abc\"");
+        assert_eq!(EntityTag::weak("abc").to_string(), "W/\"abc\"");
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(EntityTag::parse(" W/\"abc\""),
+                   Some(EntityTag::weak("abc")));
+        assert_eq!(EntityTag::parse("\"\""), Some(EntityTag::strong("")));
+        assert_eq!(EntityTag::parse("abc"), None);
+        assert_eq!(EntityTag::parse("w/\"abc\""), None);
+        assert_eq!(EntityTag::parse("\"a\"b\""), None);
+        assert_eq!(EntityTag::parse("\""), None);
+    }
+
+    #[test]
+    fn list() {
+        assert_eq!(split_list("\"a\", W/\"b\""), vec!["\"a\"", " W/\"b\""]);
+        assert_eq!(split_list("\"a,b\",\"c\""), vec!["\"a,b\"", "\"c\""]);
+        assert_eq!(split_list("*"), vec!["*"]);
+    }
+
+    #[test]
+    fn compare() {
+        // examples from RFC 7232, section 2.3.2
+        let w1 = EntityTag::weak("1");
+        let w2 = EntityTag::weak("2");
+        let s1 = EntityTag::strong("1");
+        assert!(!w1.strong_eq(&w1));
+        assert!(w1.weak_eq(&w1));
+        assert!(!w1.strong_eq(&w2));
+        assert!(!w1.weak_eq(&w2));
+        assert!(!w1.strong_eq(&s1));
+        assert!(w1.weak_eq(&s1));
+        assert!(s1.strong_eq(&s1));
+        assert!(s1.weak_eq(&s1));
+    }
+
+    #[test]
+    #[should_panic(expected="contains invalid characters")]
+    fn invalid() {
+        EntityTag::strong("a\"b");
+    }
+}
@@ -25,7 +25,6 @@ const MAX_HEADERS: usize = 1024;
 
 struct RequestConfig<'a> {
     body: BodyKind,
-    #[allow(dead_code)] // TODO(tailhook) implement Expect support
     expect_continue: bool,
     connection_close: bool,
     connection: Option<Cow<'a, str>>,
@@ -54,6 +53,7 @@ pub struct Head<'a> {
     body_kind: BodyKind,
     connection_close: bool,
     connection_header: Option<Cow<'a, str>>,
+    expect_continue: bool,
 }
 
 /// Iterator over all meaningful headers for the request
@@ -153,6 +153,16 @@ impl<'a> Head<'a> {
     pub fn all_headers(&self) -> &'a [Header<'a>] {
         self.headers
     }
+    /// Returns the value of the first header with the specified name
+    ///
+    /// Header name is compared case-insensitively. The value is a slice of
+    /// the input buffer, so nothing is copied or allocated. Note: unlike
+    /// `headers()` this doesn't skip hop-by-hop headers.
+    pub fn header_raw(&self, name: &str) -> Option<&'a [u8]> {
+        self.headers.iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value)
+    }
     /// Return `true` if `Connection: close` header exists
     pub fn connection_close(&self) -> bool {
         self.connection_close
@@ -162,6 +172,18 @@ impl<'a> Head<'a> {
         self.connection_header.as_ref().map(|x| &x[..])
     }
 
+    /// Returns `true` if client waits for `100 Continue` before sending
+    /// the request body (`Expect: 100-continue` in an HTTP/1.1 request
+    /// that has a body)
+    ///
+    /// You don't need to send `100 Continue` yourself: it's sent by the
+    /// protocol when it starts reading the body. Return
+    /// `RecvMode::discard_body()` from `Codec::recv_mode` to reject request
+    /// without receiving the body.
+    pub fn expects_continue(&self) -> bool {
+        self.expect_continue
+    }
+
     /// Returns true if there was transfer-encoding or content-length != 0
     ///
     /// I.e. `false` may mean either `Content-Length: 0` or there were no
@@ -305,10 +327,10 @@ fn scan_headers<'x>(raw_request: &'x Request)
 }
 
 pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
-    -> Result<Option<(BodyKind, D::Codec, ResponseConfig)>, Error>
+    -> Result<Option<(BodyKind, D::Codec, ResponseConfig, bool)>, Error>
     where D: Dispatcher<S>,
 {
-    let (body_kind, codec, cfg, bytes) = {
+    let (body_kind, codec, cfg, expect, bytes) = {
         let mut vec;
         let mut headers = [EMPTY_HEADER; MIN_HEADERS];
 
@@ -338,17 +360,19 @@ pub fn parse_headers<S, D>(buffer: &mut Buf, disp: &mut D)
                     // enough to ignore nowadays
                     connection_close: cfg.connection_close || ver == 0,
                     connection_header: cfg.connection,
+                    expect_continue: cfg.expect_continue && ver == 1 &&
+                        cfg.body != BodyKind::Fixed(0),
                 };
                 let codec = disp.headers_received(&head)?;
-                // TODO(tailhook) send 100-expect response headers
                 let response_config = ResponseConfig::from(&head);
-                (cfg.body, codec, response_config, bytes)
+                (cfg.body, codec, response_config, head.expect_continue,
+                 bytes)
             }
             _ => return Ok(None),
         }
     };
     buffer.consume(bytes);
-    Ok(Some((body_kind, codec, cfg)))
+    Ok(Some((body_kind, codec, cfg, expect)))
 }
 
 impl<'a> Iterator for HeaderIter<'a> {
@@ -0,0 +1,160 @@
+use futures::Async;
+use tk_bufstream::{ReadBuf, WriteBuf};
+
+use enums::Status;
+use server::{Codec, Dispatcher, Encoder, EncoderDone, Error, Head, RecvMode};
+use server::short_circuit::{Reply, ReplyFuture, ShortCircuit};
+
+
+/// A health check endpoint for load balancers
+///
+/// Responds to `GET path` with `200 OK` and `OK` body when `check` returns
+/// `true`, and with `503 Service Unavailable` otherwise.
+///
+/// Use `HealthCheck` to serve it in front of a dispatcher, or match it in
+/// a `Router` by `path` and write the response with `write_response`.
+#[derive(Debug, Clone, Copy)]
+pub struct HealthCheckHandler {
+    /// Path of the endpoint (query string is ignored when matching)
+    pub path: &'static str,
+    /// Returns `true` if the service is healthy
+    pub check: fn() -> bool,
+}
+
+/// A dispatcher wrapper that answers health check requests by itself
+///
+/// Health check requests never reach the wrapped dispatcher. So wrap the
+/// outermost dispatcher (i.e. wrap authentication middleware, not the
+/// other way around) for load balancers to get through without
+/// credentials.
+pub struct HealthCheck<D> {
+    dispatcher: D,
+    handler: HealthCheckHandler,
+}
+
+/// A codec created by `HealthCheck`
+pub struct HealthCheckCodec<C> {
+    kind: ShortCircuit<HealthCheckHandler, C>,
+}
+
+impl HealthCheckHandler {
+    /// Returns `true` if the request should be served by this handler
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        let path = match path.find(|c| c == '?' || c == '#') {
+            Some(idx) => &path[..idx],
+            None => path,
+        };
+        method == "GET" && path == self.path
+    }
+    /// Run the check and write the response
+    pub fn write_response<S>(&self, mut e: Encoder<S>) -> EncoderDone<S> {
+        let body: &[u8] = if (self.check)() {
+            e.status(Status::Ok);
+            b"OK"
+        } else {
+            e.status(Status::ServiceUnavailable);
+            b"Service Unavailable"
+        };
+        e.add_header("Content-Type", "text/plain").unwrap();
+        e.add_length(body.len() as u64).unwrap();
+        if e.done_headers().unwrap() {
+            e.write_body(body);
+        }
+        e.done()
+    }
+}
+
+impl<D> HealthCheck<D> {
+    /// Wrap a dispatcher
+    pub fn new(dispatcher: D, handler: HealthCheckHandler) -> HealthCheck<D> {
+        HealthCheck {
+            dispatcher: dispatcher,
+            handler: handler,
+        }
+    }
+}
+
+impl<S, D: Dispatcher<S>> Dispatcher<S> for HealthCheck<D> {
+    type Codec = HealthCheckCodec<D::Codec>;
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        let check = headers.path()
+            .map(|path| self.handler.matches(headers.method(), path))
+            .unwrap_or(false);
+        if check {
+            return Ok(HealthCheckCodec {
+                kind: ShortCircuit::Reply(self.handler),
+            });
+        }
+        let codec = self.dispatcher.headers_received(headers)?;
+        Ok(HealthCheckCodec { kind: ShortCircuit::Pass(codec) })
+    }
+}
+
+impl Reply for HealthCheckHandler {
+    fn write<S>(&self, e: Encoder<S>) -> EncoderDone<S> {
+        self.write_response(e)
+    }
+}
+
+impl<S, C: Codec<S>> Codec<S> for HealthCheckCodec<C> {
+    type ResponseFuture = ReplyFuture<S, C::ResponseFuture>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.kind.recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.kind.data_received(data, end)
+    }
+    fn start_response(&mut self, e: Encoder<S>) -> Self::ResponseFuture {
+        self.kind.start_response(e)
+    }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        self.kind.hijack(output, input)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use enums::Status;
+    use server::test_util::{Stub, serve};
+    use super::{HealthCheck, HealthCheckHandler};
+
+    static HEALTHY: AtomicBool = AtomicBool::new(true);
+
+    fn healthy() -> bool {
+        HEALTHY.load(Ordering::SeqCst)
+    }
+
+    fn check(request: &str) -> String {
+        let handler = HealthCheckHandler { path: "/health", check: healthy };
+        // rejects all the requests like authentication middleware would
+        serve(HealthCheck::new(Stub(Status::Unauthorized), handler), request)
+    }
+
+    #[test]
+    fn health() {
+        assert_eq!(check("GET /health?lb=1 HTTP/1.1\r\nHost: x\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: 2\r\n\r\nOK");
+        assert_eq!(check("GET /private HTTP/1.1\r\nHost: x\r\n\r\n"),
+            "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n");
+        HEALTHY.store(false, Ordering::SeqCst);
+        assert!(check("GET /health HTTP/1.1\r\nHost: x\r\n\r\n")
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        HEALTHY.store(true, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn matches() {
+        let handler = HealthCheckHandler { path: "/health", check: healthy };
+        assert!(handler.matches("GET", "/health"));
+        assert!(!handler.matches("POST", "/health"));
+        assert!(!handler.matches("GET", "/health/x"));
+    }
+}
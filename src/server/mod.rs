@@ -6,9 +6,24 @@ mod codec;
 mod proto;
 mod encoder;
 mod request_target;
+mod request_head;
 mod headers;
 mod websocket;
 mod recv_mode;
+mod body_reader;
+mod multipart;
+mod sse;
+mod router;
+mod access_log;
+mod pipeline;
+mod cors;
+mod shutdown;
+mod etag;
+mod health;
+mod tcp;
+mod rate_limit;
+mod short_circuit;
+#[cfg(test)] mod test_util;
 pub mod buffered;
 
 pub use self::error::Error;
@@ -18,7 +33,26 @@ pub use self::codec::{Codec, Dispatcher};
 pub use self::proto::Proto;
 pub use self::headers::{Head, HeaderIter};
 pub use self::request_target::RequestTarget;
+pub use self::request_head::{RequestHead, parse_request};
 pub use self::websocket::{WebsocketHandshake};
+pub use self::body_reader::{ChunkedBodyReader, FutureTrailers, BodyLimiter};
+pub use self::multipart::{MultipartReader, MultipartPart, PartBody};
+pub use self::multipart::multipart_boundary;
+pub use self::sse::SseEncoder;
+pub use self::router::{Router, RouteMatch};
+pub use self::access_log::{AccessLogger, AccessLogCodec, AccessLogFuture};
+pub use self::access_log::{RequestInfo, ResponseInfo, LogFn};
+pub use self::access_log::{CombinedLogFormat, log_combined};
+pub use self::pipeline::{PipelineDispatcher, PipelineCodec, PipelineFuture};
+pub use self::pipeline::{BufferedResponse, ResponseQueue};
+pub use self::cors::{CorsConfig, CorsMiddleware, CorsCodec};
+pub use self::shutdown::{ShutdownHandle, GracefulShutdown, Drain};
+pub use self::shutdown::GracefulClose;
+pub use self::etag::EntityTag;
+pub use self::health::{HealthCheckHandler, HealthCheck, HealthCheckCodec};
+pub use self::tcp::{Server, Serve, Handler};
+pub use self::rate_limit::{RateLimit, RateLimitMode, RateLimiter};
+pub use self::rate_limit::RateLimiterCodec;
 
 use std::time::Duration;
 
@@ -35,6 +69,9 @@ pub struct Config {
     input_body_whole_timeout: Duration,
     output_body_byte_timeout: Duration,
     output_body_whole_timeout: Duration,
+    max_request_body_size: u64,
+    #[cfg(feature="date_header")]
+    date_header: bool,
 }
 
 /// This type is returned from `headers_received` handler of either
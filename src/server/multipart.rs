@@ -0,0 +1,549 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::rc::Rc;
+use std::str::from_utf8;
+
+use futures::{Stream, Async, Poll};
+use httparse::{self, EMPTY_HEADER};
+use tk_bufstream::ReadBuf;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use server::error::{Error, ErrorEnum};
+
+
+/// Number of headers of a single part to allocate on a stack
+const MAX_PART_HEADERS: usize = 16;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Headers,
+    Body,
+    Done,
+}
+
+struct Inner<S> {
+    io: ReadBuf<S>,
+    /// Delimiter including leading CRLF, i.e. `\r\n--boundary`
+    delimiter: Vec<u8>,
+    state: State,
+    at_start: bool,
+    part: usize,
+}
+
+/// A reader of the `multipart/form-data` (or any other `multipart/*`)
+/// request body
+///
+/// Like `ChunkedBodyReader` this is useful when you `Hijack` connection and
+/// need to read the body incrementally, for example to store uploaded files
+/// without keeping them in memory. The buffer must be positioned right at
+/// the start of the body. If body has `Transfer-Encoding: chunked` wrap
+/// the `ChunkedBodyReader` into a buffer first.
+///
+/// The reader is a stream of parts. Part data is read from `PartBody`,
+/// its unread data is skipped when the next part is requested. Nested
+/// multipart bodies may be read by wrapping `PartBody` into a buffer:
+///
+/// ```rust,ignore
+/// let boundary = multipart_boundary(ctype).unwrap();
+/// let nested = IoBuf::new(part.into_body()).split().1;
+/// let nested = MultipartReader::new(nested, boundary);
+/// ```
+pub struct MultipartReader<S> {
+    inner: Rc<RefCell<Inner<S>>>,
+}
+
+/// A single part of the multipart body, yielded by `MultipartReader`
+pub struct MultipartPart<S> {
+    headers: Vec<(String, Vec<u8>)>,
+    body: PartBody<S>,
+}
+
+/// A reader of the data of a single part
+///
+/// `read()` returns zero when the part is over. It also returns zero if
+/// `MultipartReader` has already switched to the next part.
+///
+/// Writing to it always fails, `AsyncWrite` is implemented only to allow
+/// wrapping the part into `IoBuf`.
+pub struct PartBody<S> {
+    inner: Rc<RefCell<Inner<S>>>,
+    part: usize,
+}
+
+fn to_io(e: ErrorEnum) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, Error::from(e))
+}
+
+fn invalid(reason: &'static str) -> ErrorEnum {
+    ErrorEnum::MultipartInvalid(reason)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Returns parameter of the header value like `text/plain; charset=utf-8`
+///
+/// Quotes are stripped, but escapes inside quoted string are not decoded
+/// (browsers percent-encode quotes in file names anyway).
+fn param<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = match value.find(';') {
+        Some(idx) => &value[idx+1..],
+        None => return None,
+    };
+    loop {
+        rest = rest.trim();
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => return None,
+        };
+        let key = rest[..eq].trim();
+        rest = rest[eq+1..].trim();
+        let val = if rest.starts_with('"') {
+            let end = match rest[1..].find('"') {
+                Some(end) => end + 1,
+                None => return None,
+            };
+            let val = &rest[1..end];
+            rest = &rest[end+1..];
+            val
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            let val = rest[..end].trim();
+            rest = &rest[end..];
+            val
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(val);
+        }
+        match rest.find(';') {
+            Some(idx) => rest = &rest[idx+1..],
+            None => return None,
+        }
+    }
+}
+
+/// Extracts boundary from the value of `Content-Type` header
+///
+/// Returns `None` if content type is not `multipart/*` or there is
+/// no boundary.
+pub fn multipart_boundary(content_type: &[u8]) -> Option<&str> {
+    let value = match from_utf8(content_type) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    let media_type = value.split(';').next().unwrap_or("").trim();
+    if media_type.as_bytes().get(..10)
+        .map_or(true, |p| !p.eq_ignore_ascii_case(b"multipart/"))
+    {
+        return None;
+    }
+    param(value, "boundary").and_then(|b| {
+        if b.len() > 0 { Some(b) } else { None }
+    })
+}
+
+impl<S> Inner<S> {
+    /// Reads part data into `out`, returns `Some(0)` when part is over
+    ///
+    /// Returns `Ok(None)` if more bytes are needed for progress.
+    fn parse_body(&mut self, out: &mut [u8])
+        -> Result<Option<usize>, ErrorEnum>
+    {
+        if self.state != State::Body {
+            return Ok(Some(0));
+        }
+        let ref mut buf = self.io.in_buf;
+        let n = match find(&buf[..], &self.delimiter) {
+            Some(0) => {
+                buf.consume(self.delimiter.len());
+                self.state = State::Delimiter;
+                return Ok(Some(0));
+            }
+            Some(pos) => pos,
+            // the tail may be a start of the delimiter
+            None => buf.len().saturating_sub(self.delimiter.len() - 1),
+        };
+        if n == 0 {
+            return Ok(None);
+        }
+        let n = ::std::cmp::min(n, out.len());
+        out[..n].copy_from_slice(&buf[..n]);
+        buf.consume(n);
+        Ok(Some(n))
+    }
+    /// Skips to the headers of the next part and parses them
+    ///
+    /// Returns `Ok(None)` if more bytes are needed for progress and
+    /// `Ok(Some(None))` if there are no more parts.
+    fn parse_part(&mut self)
+        -> Result<Option<Option<Vec<(String, Vec<u8>)>>>, ErrorEnum>
+    {
+        use self::State::*;
+        loop {
+            match self.state {
+                Preamble => {
+                    let ref mut buf = self.io.in_buf;
+                    // first delimiter may omit leading CRLF
+                    let first = &self.delimiter[2..];
+                    if self.at_start {
+                        if buf.len() < first.len() &&
+                            first.starts_with(&buf[..])
+                        {
+                            return Ok(None);
+                        }
+                        self.at_start = false;
+                        if buf[..].starts_with(first) {
+                            buf.consume(first.len());
+                            self.state = Delimiter;
+                            continue;
+                        }
+                    }
+                    match find(&buf[..], &self.delimiter) {
+                        Some(pos) => {
+                            buf.consume(pos + self.delimiter.len());
+                            self.state = Delimiter;
+                        }
+                        None => {
+                            let n = buf.len()
+                                .saturating_sub(self.delimiter.len() - 1);
+                            buf.consume(n);
+                            return Ok(None);
+                        }
+                    }
+                }
+                Delimiter => {
+                    let ref mut buf = self.io.in_buf;
+                    if buf.len() < 2 {
+                        return Ok(None);
+                    }
+                    if &buf[..2] == b"--" {
+                        // epilogue is left in the buffer
+                        buf.consume(2);
+                        self.state = Done;
+                        continue;
+                    }
+                    // transport padding
+                    let pos = match buf[..].iter()
+                        .position(|&x| x != b' ' && x != b'\t')
+                    {
+                        Some(pos) => pos,
+                        None => return Ok(None),
+                    };
+                    if buf.len() < pos + 2 {
+                        return Ok(None);
+                    }
+                    if &buf[pos..pos+2] != b"\r\n" {
+                        return Err(invalid("no line break after boundary"));
+                    }
+                    buf.consume(pos + 2);
+                    self.state = Headers;
+                }
+                Headers => {
+                    let ref mut buf = self.io.in_buf;
+                    let mut headers = [EMPTY_HEADER; MAX_PART_HEADERS];
+                    let (bytes, result) = match httparse::parse_headers(
+                        &buf[..], &mut headers)?
+                    {
+                        httparse::Status::Complete((bytes, headers)) => {
+                            (bytes, headers.iter().map(|h| {
+                                (h.name.to_string(), h.value.to_vec())
+                            }).collect())
+                        }
+                        httparse::Status::Partial => return Ok(None),
+                    };
+                    buf.consume(bytes);
+                    self.state = Body;
+                    self.part += 1;
+                    return Ok(Some(Some(result)));
+                }
+                Body => {
+                    let mut tmp = [0u8; 4096];
+                    match self.parse_body(&mut tmp)? {
+                        Some(_) => continue,
+                        None => return Ok(None),
+                    }
+                }
+                Done => return Ok(Some(None)),
+            }
+        }
+    }
+}
+
+impl<S> MultipartReader<S> {
+    /// Create a reader from buffer, right after request headers
+    ///
+    /// `boundary` is a parameter of the `Content-Type` header, see
+    /// `multipart_boundary`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if boundary is empty.
+    pub fn new<B: AsRef<[u8]>>(io: ReadBuf<S>, boundary: B)
+        -> MultipartReader<S>
+    {
+        let boundary = boundary.as_ref();
+        assert!(boundary.len() > 0, "multipart boundary must not be empty");
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend(boundary);
+        MultipartReader {
+            inner: Rc::new(RefCell::new(Inner {
+                io: io,
+                delimiter: delimiter,
+                state: State::Preamble,
+                at_start: true,
+                part: 0,
+            })),
+        }
+    }
+    /// Returns true if the closing delimiter has been read
+    pub fn is_done(&self) -> bool {
+        self.inner.borrow().state == State::Done
+    }
+    /// Returns the underlying buffer
+    ///
+    /// Note: if the body is not read to the end, the buffer contains
+    /// leftovers of the body. When it's read to the end, buffer may contain
+    /// the epilogue.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any `PartBody` still exists.
+    pub fn into_inner(self) -> ReadBuf<S> {
+        match Rc::try_unwrap(self.inner) {
+            Ok(inner) => inner.into_inner().io,
+            Err(_) => panic!("PartBody must be dropped before into_inner()"),
+        }
+    }
+}
+
+impl<S: AsyncRead> Stream for MultipartReader<S> {
+    type Item = MultipartPart<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<MultipartPart<S>>, Error> {
+        let mut inner = self.inner.borrow_mut();
+        loop {
+            match inner.parse_part()? {
+                Some(Some(headers)) => {
+                    return Ok(Async::Ready(Some(MultipartPart {
+                        headers: headers,
+                        body: PartBody {
+                            inner: self.inner.clone(),
+                            part: inner.part,
+                        },
+                    })));
+                }
+                Some(None) => return Ok(Async::Ready(None)),
+                None => {}
+            }
+            if inner.io.read().map_err(ErrorEnum::Io)? == 0 {
+                if inner.io.done() {
+                    return Err(ErrorEnum::ConnectionReset.into());
+                }
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+impl<S> MultipartPart<S> {
+    /// Returns headers of the part
+    pub fn headers(&self) -> &[(String, Vec<u8>)] {
+        &self.headers
+    }
+    /// Returns the value of the first header with the specified name
+    ///
+    /// Header name is compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+    fn disposition_param(&self, name: &str) -> Option<&str> {
+        self.header("Content-Disposition")
+            .and_then(|v| from_utf8(v).ok())
+            .and_then(|v| param(v, name))
+    }
+    /// Returns the `name` parameter of the `Content-Disposition` header
+    ///
+    /// This is the name of the form field.
+    pub fn name(&self) -> Option<&str> {
+        self.disposition_param("name")
+    }
+    /// Returns the `filename` parameter of the `Content-Disposition` header
+    ///
+    /// Note: the file name is sent by the client as is, don't use it as
+    /// a path without sanitizing.
+    pub fn filename(&self) -> Option<&str> {
+        self.disposition_param("filename")
+    }
+    /// Returns the value of `Content-Type` header if it's valid utf-8
+    ///
+    /// Per RFC 7578 absent content type means `text/plain`.
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type").and_then(|v| from_utf8(v).ok())
+    }
+    /// Returns a reader of the part data
+    pub fn body(&mut self) -> &mut PartBody<S> {
+        &mut self.body
+    }
+    /// Returns the reader of the part data, dropping the headers
+    pub fn into_body(self) -> PartBody<S> {
+        self.body
+    }
+}
+
+impl<S: AsyncRead> Read for PartBody<S> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.len() == 0 {
+            return Ok(0);
+        }
+        let mut inner = self.inner.borrow_mut();
+        if inner.part != self.part {
+            return Ok(0);
+        }
+        loop {
+            match inner.parse_body(out).map_err(to_io)? {
+                Some(n) => return Ok(n),
+                None => {}
+            }
+            if inner.io.read()? == 0 {
+                if inner.io.done() {
+                    return Err(to_io(ErrorEnum::ConnectionReset));
+                }
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for PartBody<S> {}
+
+// Writing is not supported, it's implemented only to allow wrapping
+// `PartBody` into `IoBuf` to read nested multipart bodies
+impl<S> io::Write for PartBody<S> {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Other,
+                           "part body can't be written"))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S> AsyncWrite for PartBody<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use futures::{Future, Stream};
+    use tk_bufstream::IoBuf;
+
+    use super::{MultipartReader, multipart_boundary};
+
+    fn reader(data: &[u8]) -> MultipartReader<Cursor<Vec<u8>>> {
+        // cursor signals end of stream when there is no more data
+        let (_, rx) = IoBuf::new(Cursor::new(data.to_vec())).split();
+        MultipartReader::new(rx, "XyZ")
+    }
+
+    fn read_all<R: Read>(mut r: R) -> Vec<u8> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn form() {
+        let r = reader(b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            hello\r\n--XyZ  \r\n\
+            Content-Disposition: form-data; name=\"file\"; \
+                filename=\"a;b.bin\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n\
+            \x00\xff\r\n--Xy\r\n-\r\n--XyZ--\r\nepilogue");
+        let (part, r) = r.into_future().map_err(|(e, _)| e).wait().unwrap();
+        let mut part = part.unwrap();
+        assert_eq!(part.name(), Some("title"));
+        assert_eq!(part.filename(), None);
+        assert_eq!(part.content_type(), None);
+        assert_eq!(read_all(part.body()), b"hello");
+        drop(part);
+        let (part, r) = r.into_future().map_err(|(e, _)| e).wait().unwrap();
+        let part = part.unwrap();
+        assert_eq!(part.name(), Some("file"));
+        assert_eq!(part.filename(), Some("a;b.bin"));
+        assert_eq!(part.content_type(), Some("application/octet-stream"));
+        assert_eq!(read_all(part.into_body()), b"\x00\xff\r\n--Xy\r\n-");
+        let (part, r) = r.into_future().map_err(|(e, _)| e).wait().unwrap();
+        assert!(part.is_none());
+        assert!(r.is_done());
+        assert_eq!(&r.into_inner().in_buf[..], b"\r\nepilogue");
+    }
+
+    #[test]
+    fn skip_unread() {
+        let r = reader(b"--XyZ\r\n\r\nfirst\r\n\
+                         --XyZ\r\n\r\nsecond\r\n--XyZ--");
+        let parts = r.collect().wait().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].headers().is_empty());
+        let bodies = parts.into_iter().map(|p| read_all(p.into_body()))
+            .collect::<Vec<_>>();
+        assert_eq!(bodies, vec![b"".to_vec(), b"".to_vec()]);
+    }
+
+    #[test]
+    fn nested() {
+        let r = reader(b"--XyZ\r\n\
+            Content-Type: multipart/mixed; boundary=inner\r\n\r\n\
+            --inner\r\n\r\none\r\n--inner\r\n\r\ntwo\r\n--inner--\r\n\
+            --XyZ--\r\n");
+        let (part, _) = r.into_future().map_err(|(e, _)| e).wait().unwrap();
+        let part = part.unwrap();
+        let boundary = multipart_boundary(
+            part.header("Content-Type").unwrap()).unwrap().to_string();
+        let (_, rx) = IoBuf::new(part.into_body()).split();
+        let bodies = MultipartReader::new(rx, boundary)
+            .map(|p| read_all(p.into_body()))
+            .collect().wait().unwrap();
+        assert_eq!(bodies, vec![b"one".to_vec(), b"two".to_vec()]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(reader(b"--XyZ\r\n\r\nunterminated")
+            .collect().wait().is_err());
+        assert!(reader(b"--XyZ\r\n\r\ndata\r\n--XyZjunk\r\n\r\n--XyZ--")
+            .collect().wait().is_err());
+    }
+
+    #[test]
+    fn boundary() {
+        let parse = multipart_boundary;
+        assert_eq!(parse(b"multipart/form-data; boundary=abc"), Some("abc"));
+        assert_eq!(parse(b"Multipart/Mixed; charset=x; boundary=\"a b\""),
+                   Some("a b"));
+        assert_eq!(parse(b"text/plain; boundary=abc"), None);
+        assert_eq!(parse(b"multipart/form-data"), None);
+        assert_eq!(parse(b"multipart/form-data; boundary=\"\""), None);
+    }
+
+    #[test]
+    fn non_ascii_content_type() {
+        // byte 10 is in the middle of a character
+        let ctype = "multipart\u{e9}form-data; boundary=abc";
+        assert_eq!(multipart_boundary(ctype.as_bytes()), None);
+        assert_eq!(multipart_boundary("\u{e9}".as_bytes()), None);
+    }
+}
@@ -0,0 +1,333 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use futures::{Async, Future, Poll};
+
+use enums::Status;
+use server::buffered::Request;
+use super::{Error, Encoder, EncoderDone, Dispatcher, Codec, Head, RecvMode};
+
+
+/// A fully buffered response returned by the handler of `PipelineDispatcher`
+///
+/// `Content-Length` is added automatically.
+#[derive(Debug)]
+pub struct BufferedResponse {
+    status: Status,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+/// A dispatcher that runs handlers of pipelined requests concurrently
+///
+/// Similarly to `BufferedDispatcher` request is fully buffered before
+/// calling a handler. But here the handler is called as soon as the request
+/// is received (not when previous responses are written), and it returns
+/// a future of the `BufferedResponse` instead of writing to an `Encoder`.
+///
+/// So when client pipelines requests, handlers may complete in any order,
+/// and responses are parked in a `ResponseQueue` until all the preceding
+/// responses are written. The number of requests in flight is limited by
+/// `Config::inflight_request_limit`.
+///
+/// Websocket handshakes are passed to the handler as usual requests.
+pub struct PipelineDispatcher<H, F: Future> {
+    addr: SocketAddr,
+    max_request_length: usize,
+    service: Rc<H>,
+    queue: ResponseQueue<F>,
+}
+
+/// A codec counterpart of the `PipelineDispatcher`
+pub struct PipelineCodec<H, F: Future> {
+    max_request_length: usize,
+    service: Rc<H>,
+    queue: ResponseQueue<F>,
+    request: Option<Request>,
+    seq: Option<u64>,
+}
+
+/// A future returned by `PipelineCodec::start_response`
+///
+/// It waits until the handler's response is ready and writes it.
+pub struct PipelineFuture<S, F: Future> {
+    queue: ResponseQueue<F>,
+    seq: u64,
+    encoder: Option<Encoder<S>>,
+}
+
+enum Slot<F: Future> {
+    Pending(F),
+    Done(Result<F::Item, F::Error>),
+}
+
+struct Queue<F: Future> {
+    /// Sequence number of the first slot
+    first: u64,
+    slots: VecDeque<Slot<F>>,
+}
+
+/// A queue of response futures which are polled concurrently, but
+/// are taken out strictly in order
+///
+/// Responses that complete earlier than the ones preceding them are kept in
+/// the queue until all preceding responses are taken out.
+pub struct ResponseQueue<F: Future>(Rc<RefCell<Queue<F>>>);
+
+impl BufferedResponse {
+    /// Create a response with the status and an empty body
+    pub fn new(status: Status) -> BufferedResponse {
+        BufferedResponse {
+            status: status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+    /// Add a header
+    ///
+    /// Headers are validated when the response is written, so an invalid
+    /// header yields an error of the response future. `Content-Length`
+    /// and `Transfer-Encoding` must not be set here.
+    pub fn header<V: AsRef<[u8]>>(mut self, name: &str, value: V)
+        -> BufferedResponse
+    {
+        self.headers.push((name.to_string(), value.as_ref().to_vec()));
+        self
+    }
+    /// Set the response body
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> BufferedResponse {
+        self.body = body.into();
+        self
+    }
+    /// Returns the status of the response
+    pub fn status(&self) -> Status {
+        self.status
+    }
+    /// Write the response into the encoder
+    pub fn write<S>(self, mut e: Encoder<S>) -> Result<EncoderDone<S>, Error>
+    {
+        e.status(self.status);
+        for (name, value) in self.headers {
+            e.add_header(&name, value).map_err(Error::custom)?;
+        }
+        if self.status.response_has_body() {
+            e.add_length(self.body.len() as u64).map_err(Error::custom)?;
+        }
+        if e.done_headers().map_err(Error::custom)? {
+            e.write_body(&self.body);
+        }
+        Ok(e.done())
+    }
+}
+
+impl<F: Future> ResponseQueue<F> {
+    /// Create an empty queue
+    pub fn new() -> ResponseQueue<F> {
+        ResponseQueue(Rc::new(RefCell::new(Queue {
+            first: 0,
+            slots: VecDeque::new(),
+        })))
+    }
+    /// Add a future to the queue, returns its sequence number
+    pub fn push(&self, future: F) -> u64 {
+        let mut queue = self.0.borrow_mut();
+        queue.slots.push_back(Slot::Pending(future));
+        queue.first + queue.slots.len() as u64 - 1
+    }
+    /// Returns number of futures in the queue (including completed ones)
+    pub fn len(&self) -> usize {
+        self.0.borrow().slots.len()
+    }
+    /// Returns `true` if the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().slots.is_empty()
+    }
+    /// Poll all the pending futures and take out the result of `seq` if
+    /// it's complete
+    ///
+    /// # Panics
+    ///
+    /// When `seq` is not the first in the queue.
+    pub fn poll(&self, seq: u64) -> Poll<F::Item, F::Error> {
+        let mut queue = self.0.borrow_mut();
+        assert_eq!(queue.first, seq, "responses must be taken in order");
+        for slot in queue.slots.iter_mut() {
+            let result = match *slot {
+                Slot::Pending(ref mut f) => match f.poll() {
+                    Ok(Async::Ready(x)) => Ok(x),
+                    Ok(Async::NotReady) => continue,
+                    Err(e) => Err(e),
+                },
+                Slot::Done(..) => continue,
+            };
+            *slot = Slot::Done(result);
+        }
+        if !matches!(queue.slots.front(), Some(&Slot::Done(..))) {
+            return Ok(Async::NotReady);
+        }
+        queue.first += 1;
+        match queue.slots.pop_front() {
+            Some(Slot::Done(result)) => result.map(Async::Ready),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<F: Future> Clone for ResponseQueue<F> {
+    fn clone(&self) -> ResponseQueue<F> {
+        ResponseQueue(self.0.clone())
+    }
+}
+
+impl<H, F: Future> PipelineDispatcher<H, F> {
+    /// Create a dispatcher for a connection from `addr`
+    pub fn new(addr: SocketAddr, service: H) -> PipelineDispatcher<H, F> {
+        PipelineDispatcher {
+            addr: addr,
+            max_request_length: 10_485_760,
+            service: Rc::new(service),
+            queue: ResponseQueue::new(),
+        }
+    }
+    /// Sets max request length
+    pub fn max_request_length(&mut self, value: usize) {
+        self.max_request_length = value;
+    }
+}
+
+impl<S, H, F> Dispatcher<S> for PipelineDispatcher<H, F>
+    where H: Fn(Request) -> F,
+          F: Future<Item=BufferedResponse, Error=Error>,
+{
+    type Codec = PipelineCodec<H, F>;
+
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        Ok(PipelineCodec {
+            max_request_length: self.max_request_length,
+            service: self.service.clone(),
+            queue: self.queue.clone(),
+            request: Some(Request::from_head(self.addr, headers)),
+            seq: None,
+        })
+    }
+}
+
+impl<S, H, F> Codec<S> for PipelineCodec<H, F>
+    where H: Fn(Request) -> F,
+          F: Future<Item=BufferedResponse, Error=Error>,
+{
+    type ResponseFuture = PipelineFuture<S, F>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::buffered_upfront(self.max_request_length)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        assert!(end);
+        let mut request = self.request.take()
+            .expect("request is received once");
+        request.set_body(data.to_vec());
+        self.seq = Some(self.queue.push((self.service)(request)));
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, e: Encoder<S>) -> PipelineFuture<S, F> {
+        PipelineFuture {
+            queue: self.queue.clone(),
+            seq: self.seq.expect("response is started after request body"),
+            encoder: Some(e),
+        }
+    }
+}
+
+impl<S, F> Future for PipelineFuture<S, F>
+    where F: Future<Item=BufferedResponse, Error=Error>,
+{
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        match self.queue.poll(self.seq)? {
+            Async::Ready(response) => {
+                let e = self.encoder.take()
+                    .expect("future is polled after completion");
+                response.write(e).map(Async::Ready)
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::{Async, Future};
+    use futures::future::lazy;
+    use futures::sync::oneshot::{channel, Sender};
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+
+    use enums::Status;
+    use server::{Proto, Error};
+    use server::config::without_date;
+    use server::buffered::Request;
+    use super::{BufferedResponse, PipelineDispatcher, ResponseQueue};
+
+    type Response = Box<Future<Item=BufferedResponse, Error=Error>>;
+
+    #[test]
+    fn queue_order() {
+        let queue = ResponseQueue::new();
+        let (tx1, rx1) = channel::<u32>();
+        let (tx2, rx2) = channel::<u32>();
+        assert_eq!(queue.push(rx1), 0);
+        assert_eq!(queue.push(rx2), 1);
+        lazy(|| {
+            tx2.send(2).unwrap();
+            assert!(queue.poll(0).unwrap().is_not_ready());
+            tx1.send(1).unwrap();
+            assert_eq!(queue.poll(0).unwrap(), Async::Ready(1));
+            assert_eq!(queue.len(), 1);
+            assert_eq!(queue.poll(1).unwrap(), Async::Ready(2));
+            assert!(queue.is_empty());
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut lp = Core::new().unwrap();
+        let senders = Rc::new(RefCell::new(Vec::<Sender<&str>>::new()));
+        let tx = senders.clone();
+        let mock = MockData::new();
+        let proto = Proto::new(mock.clone(), &without_date(),
+            PipelineDispatcher::new("127.0.0.1:1".parse().unwrap(),
+                move |req: Request| -> Response {
+                    let (stx, srx) = channel();
+                    tx.borrow_mut().push(stx);
+                    let path = req.path().to_string();
+                    Box::new(srx.map_err(|_| unreachable!())
+                        .map(move |x| BufferedResponse::new(Status::Ok)
+                            .body(format!("{} {}", path, x))))
+                }),
+            &lp.handle());
+        lp.handle().spawn(proto.map_err(|e| panic!("{}", e)));
+        mock.add_input("GET /a HTTP/1.1\r\nHost: x\r\n\r\n\
+                        GET /b HTTP/1.1\r\nHost: x\r\n\r\n");
+        lp.turn(Some(Duration::from_millis(10)));
+        assert_eq!(senders.borrow().len(), 2);
+        senders.borrow_mut().pop().unwrap().send("second").unwrap();
+        lp.turn(Some(Duration::from_millis(10)));
+        assert_eq!(mock.output(..), b"");
+        senders.borrow_mut().pop().unwrap().send("first").unwrap();
+        lp.turn(Some(Duration::from_millis(10)));
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\n/a first\
+             HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n/b second");
+    }
+}
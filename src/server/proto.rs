@@ -1,3 +1,4 @@
+use std::cmp::min;
 use std::mem;
 use std::sync::Arc;
 use std::collections::VecDeque;
@@ -30,6 +31,11 @@ struct BodyState<C> {
     progress: BodyProgress,
     response_config: ResponseConfig,
     codec: C,
+    /// Bytes of body passed to the codec
+    received: u64,
+    limit: u64,
+    /// Client waits for `100 Continue` that is not sent yet
+    expect_continue: bool,
 }
 
 enum InState<C> {
@@ -54,6 +60,8 @@ pub struct PureProto<S, D: Dispatcher<S>> {
     /// Long-term deadline for reading (headers- or input body_whole- timeout)
     read_deadline: Instant,
     response_deadline: Instant,
+    /// Don't start new requests and close connection when idle
+    close_when_idle: bool,
 }
 
 /// A low-level HTTP/1.x server protocol handler
@@ -63,20 +71,26 @@ pub struct Proto<S, D: Dispatcher<S>> {
     timeout: Timeout,
 }
 
-fn new_body(mode: BodyKind, recv_mode: Mode)
+fn body_limit(recv_mode: Mode, config: &Config) -> u64 {
+    match recv_mode {
+        Mode::BufferedUpfront(b) => {
+            min(b as u64, config.max_request_body_size)
+        }
+        _ => config.max_request_body_size,
+    }
+}
+
+fn new_body(mode: BodyKind, limit: u64)
     -> Result<BodyProgress, ErrorEnum>
 {
     use super::codec::BodyKind as B;
-    use super::recv_mode::Mode as M;
     use body_parser::BodyProgress as P;
-    match (mode, recv_mode) {
+    match mode {
         // TODO(tailhook) check size < usize
-        (B::Unsupported, _) => Err(ErrorEnum::UnsupportedBody),
-        (B::Fixed(x), M::BufferedUpfront(b)) if x > b as u64 => {
-            Err(ErrorEnum::RequestTooLong)
-        }
-        (B::Fixed(x), _)  => Ok(P::Fixed(x as usize)),
-        (B::Chunked, _) => Ok(P::Chunked(chunked::State::new())),
+        B::Unsupported => Err(ErrorEnum::UnsupportedBody),
+        B::Fixed(x) if x > limit => Err(ErrorEnum::RequestTooLong),
+        B::Fixed(x) => Ok(P::Fixed(x as usize)),
+        B::Chunked => Ok(P::Chunked(chunked::State::new())),
     }
 }
 
@@ -97,6 +111,21 @@ impl<S: AsyncRead+AsyncWrite, D: Dispatcher<S>> Proto<S, D> {
     }
 }
 
+impl<S, D: Dispatcher<S>> Proto<S, D> {
+    /// Close the connection as soon as all the requests received so far
+    /// are served
+    ///
+    /// Requests that are not started yet are not processed, and keep-alive
+    /// connection closes right after the last response is flushed. This is
+    /// used for graceful shutdown, see `GracefulShutdown`.
+    ///
+    /// Note: the future must be polled after this call, for the connection
+    /// to close if it's idle already.
+    pub fn close_when_idle(&mut self) {
+        self.proto.close_when_idle = true;
+    }
+}
+
 impl<S, D: Dispatcher<S>> PureProto<S, D> {
     pub fn new(conn: S, cfg: &Arc<Config>, dispatcher: D)
         -> PureProto<S, D>
@@ -116,6 +145,7 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
             last_byte_written: Instant::now(),
             read_deadline: Instant::now() + cfg.first_byte_timeout,
             response_deadline: Instant::now(),  // irrelevant at start
+            close_when_idle: false,
         }
     }
     /// Resturns Ok(true) if new data has been read
@@ -145,7 +175,9 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                 }
             }
             let (next, cont) = match mem::replace(&mut self.reading, Closed) {
-                KeepAlive | Connected if inbuf.in_buf.len() > 0 => {
+                KeepAlive | Connected
+                if inbuf.in_buf.len() > 0 && !self.close_when_idle
+                => {
                     self.read_deadline = Instant::now()
                         + self.config.headers_timeout;
                     (Headers, true)
@@ -156,21 +188,38 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                     match parse_headers(&mut inbuf.in_buf,
                                         &mut self.dispatcher)?
                     {
-                        Some((body, mut codec, cfg)) => {
+                        Some((body, mut codec, mut cfg, expect)) => {
                             changed = true;
                             let mode = codec.recv_mode();
                             if get_mode(&mode) == Mode::Hijack {
                                 self.waiting.push_back((cfg, codec));
                                 (Hijack, true)
+                            } else if get_mode(&mode) == Mode::Discard {
+                                codec.data_received(&[], true)?;
+                                if body != BodyKind::Fixed(0) {
+                                    // we don't know where the body ends
+                                    // (or if it's ever sent), so close
+                                    cfg.do_close = true;
+                                    self.close_when_idle = true;
+                                }
+                                self.waiting.push_back((cfg, codec));
+                                self.read_deadline = Instant::now()
+                                    + self.config.keep_alive_timeout;
+                                (KeepAlive, true)
                             } else {
                                 let timeo = mode.timeout.unwrap_or(
                                     self.config.input_body_whole_timeout);
                                 self.read_deadline = Instant::now() + timeo;
+                                let limit = body_limit(get_mode(&mode),
+                                                       &self.config);
                                 (Body(BodyState {
                                     mode: get_mode(&mode),
                                     response_config: cfg,
-                                    progress: new_body(body, get_mode(&mode))?,
-                                    codec: codec }),
+                                    progress: new_body(body, limit)?,
+                                    codec: codec,
+                                    received: 0,
+                                    limit: limit,
+                                    expect_continue: expect }),
                                  true)
                             }
                         }
@@ -181,6 +230,9 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                     body.progress.parse(inbuf)
                         .map_err(ErrorEnum::ChunkParseError)?;
                     let (bytes, done) = body.progress.check_buf(inbuf);
+                    if body.received + bytes as u64 > body.limit {
+                        return Err(ErrorEnum::RequestTooLong.into());
+                    }
                     let operation = if done {
                         Some(body.codec.data_received(
                             &inbuf.in_buf[..bytes], true)?)
@@ -195,6 +247,7 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                     match operation {
                         Some(Async::Ready(consumed)) => {
                             body.progress.consume(inbuf, consumed);
+                            body.received += consumed as u64;
                             if done && consumed == bytes {
                                 changed = true;
                                 self.waiting.push_back(
@@ -235,6 +288,17 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
         loop {
             let (next, cont) = match mem::replace(&mut self.writing, Void) {
                 Idle(mut io) => {
+                    if self.waiting.len() == 0 {
+                        if let Body(ref mut body) = self.reading {
+                            if body.expect_continue {
+                                // previous responses are done, so it's
+                                // our turn to write to the connection
+                                body.expect_continue = false;
+                                io.out_buf.extend(
+                                    b"HTTP/1.1 100 Continue\r\n\r\n");
+                            }
+                        }
+                    }
                     let old_len = io.out_buf.len();
                     if old_len > 0 {
                         io.flush().map_err(ErrorEnum::Io)?;
@@ -246,7 +310,8 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                     if let Some((rc, mut codec)) = self.waiting.pop_front() {
                         self.response_deadline = Instant::now()
                             + self.config.output_body_whole_timeout;
-                        let e = encoder::new(io, rc);
+                        let e = encoder::new_with_config(io, rc,
+                                                         &self.config);
                         if matches!(self.reading, Hijack) {
                             (Switch(codec.start_response(e), codec), true)
                         } else {
@@ -259,7 +324,9 @@ impl<S, D: Dispatcher<S>> PureProto<S, D> {
                             => {
                                 (Idle(io), false)
                             }
-                            Body(BodyState { mode: Mode::Hijack, ..}) => {
+                            Body(BodyState { mode: Mode::Hijack, ..})
+                            | Body(BodyState { mode: Mode::Discard, ..})
+                            => {
                                 unreachable!();
                             }
                             Body(BodyState {
@@ -321,10 +388,21 @@ impl<S: AsyncRead+AsyncWrite, D: Dispatcher<S>> PureProto<S, D> {
         }
         if self.inbuf.as_ref().map(|x| x.done()).unwrap_or(true) {
             Ok(false)
+        } else if self.close_when_idle && self.is_idle() {
+            Ok(false)
         } else {
             Ok(true)
         }
     }
+    /// Returns true if there are no requests in progress and everything
+    /// is flushed
+    fn is_idle(&self) -> bool {
+        use self::InState::*;
+        matches!(self.reading, KeepAlive | Connected) &&
+            self.waiting.len() == 0 &&
+            matches!(self.writing,
+                     OutState::Idle(ref io) if io.out_buf.len() == 0)
+    }
     fn timeout(&mut self) -> Option<Instant> {
         use self::OutState::*;
 
@@ -389,10 +467,13 @@ mod test {
     use std::sync::atomic::{AtomicUsize, Ordering};
 
     use futures::{Empty, Async, empty};
+    use futures::future::{FutureResult, ok};
     use tk_bufstream::{MockData, ReadBuf, WriteBuf};
 
+    use enums::Status;
     use super::PureProto;
     use server::{Config, Dispatcher, Codec};
+    use server::config::without_date;
     use server::{Head, RecvMode, Error, Encoder, EncoderDone};
 
     struct MockDisp<'a> {
@@ -456,6 +537,55 @@ mod test {
         }
     }
 
+    /// Accepts bodies of `/upload` and rejects other requests
+    struct Uploads;
+
+    struct Upload {
+        accept: bool,
+    }
+
+    impl Dispatcher<MockData> for Uploads {
+        type Codec = Upload;
+
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Codec, Error>
+        {
+            assert!(headers.expects_continue());
+            assert_eq!(headers.header_raw("content-length"), Some(&b"3"[..]));
+            Ok(Upload { accept: headers.path() == Some("/upload") })
+        }
+    }
+
+    impl Codec<MockData> for Upload {
+        type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            if self.accept {
+                RecvMode::buffered_upfront(1024)
+            } else {
+                RecvMode::discard_body()
+            }
+        }
+        fn data_received(&mut self, data: &[u8], end: bool)
+            -> Result<Async<usize>, Error>
+        {
+            assert!(end);
+            assert_eq!(data.len(), if self.accept { 3 } else { 0 });
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            if self.accept {
+                e.status(Status::Ok);
+            } else {
+                e.status(Status::RequestEntityTooLarge);
+            }
+            e.add_length(0).unwrap();
+            e.done_headers().unwrap();
+            ok(e.done())
+        }
+    }
+
     #[test]
     fn simple_get_request() {
         let counter = AtomicUsize::new(0);
@@ -536,4 +666,55 @@ mod test {
         // counts as a request and as a websocket
         assert_eq!(counter.load(Ordering::SeqCst), 2);
     }
+
+    #[test]
+    fn body_limit() {
+        let counter = AtomicUsize::new(0);
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().max_request_body_size(4).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n");
+        assert!(proto.process().is_err());
+
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &Config::new().max_request_body_size(4).done(),
+            MockDisp { counter: &counter });
+        mock.add_input("POST / HTTP/1.1\r\n\
+                        Transfer-Encoding: chunked\r\n\r\n\
+                        3\r\nabc\r\n3\r\ndef\r\n");
+        assert!(proto.process().is_err());
+    }
+
+    #[test]
+    fn expect_continue() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &without_date(), Uploads);
+        mock.add_input("POST /upload HTTP/1.1\r\nHost: x\r\n\
+                        Content-Length: 3\r\n\
+                        Expect: 100-continue\r\n\r\n");
+        assert!(proto.process().unwrap());
+        assert_eq!(mock.output(..), b"HTTP/1.1 100 Continue\r\n\r\n");
+        mock.add_input("abc");
+        assert!(proto.process().unwrap());
+        assert_eq!(&mock.output(..)[25..],
+                   &b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn expect_continue_rejected() {
+        let mock = MockData::new();
+        let mut proto = PureProto::new(mock.clone(),
+            &without_date(), Uploads);
+        mock.add_input("POST /other HTTP/1.1\r\nHost: x\r\n\
+                        Content-Length: 3\r\n\
+                        Expect: 100-continue\r\n\r\n");
+        // connection is closed after the response
+        assert!(!proto.process().unwrap());
+        assert_eq!(String::from_utf8(mock.output(..)).unwrap(),
+                   "HTTP/1.1 413 Request Entity Too Large\r\n\
+                    Content-Length: 0\r\nConnection: close\r\n\r\n");
+    }
 }
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::Async;
+use tk_bufstream::{ReadBuf, WriteBuf};
+
+use enums::Status;
+use server::{Codec, Dispatcher, Encoder, EncoderDone, Error, Head, RecvMode};
+use server::short_circuit::{Reply, ReplyFuture, ShortCircuit};
+
+
+/// Number of per-IP buckets after which idle ones are removed
+const CLEANUP_THRESHOLD: usize = 1024;
+
+
+/// How requests are accounted by `RateLimit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitMode {
+    /// All the requests share a single limit
+    Global,
+    /// Every IP address of a peer has its own limit (the port is ignored
+    /// as every connection has a different one)
+    PerIp,
+}
+
+/// Token buckets shared by all the connections of `RateLimiter`
+///
+/// Each bucket holds up to `max_requests_per_second` tokens and is refilled
+/// at the same rate, so short bursts up to the limit are allowed. Buckets
+/// of peers that have been idle for a second are full and are removed
+/// from time to time.
+#[derive(Debug)]
+pub struct RateLimit {
+    max_requests_per_second: u32,
+    mode: RateLimitMode,
+    buckets: Mutex<HashMap<Option<IpAddr>, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A dispatcher wrapper that limits the rate of requests
+///
+/// When the limit is exceeded, the request is answered with `429 Too Many
+/// Requests` and a `Retry-After` header and never reaches the wrapped
+/// dispatcher:
+///
+/// ```rust,ignore
+/// let limit = RateLimit::new(100, RateLimitMode::PerIp);
+/// server.serve(move |socket, addr| {
+///     Proto::new(socket, &cfg, RateLimiter::new(
+///         BufferedDispatcher::new(addr, &h1, || service), addr, &limit),
+///         &h1)
+/// })
+/// ```
+pub struct RateLimiter<D> {
+    dispatcher: D,
+    addr: SocketAddr,
+    limit: Arc<RateLimit>,
+}
+
+/// A codec created by `RateLimiter`
+pub struct RateLimiterCodec<C> {
+    kind: ShortCircuit<Limited, C>,
+}
+
+struct Limited(Duration);
+
+impl RateLimit {
+    /// Create buckets allowing `max_requests_per_second` requests
+    ///
+    /// # Panics
+    ///
+    /// When `max_requests_per_second` is zero.
+    pub fn new(max_requests_per_second: u32, mode: RateLimitMode)
+        -> Arc<RateLimit>
+    {
+        assert!(max_requests_per_second > 0, "rate limit must be non-zero");
+        Arc::new(RateLimit {
+            max_requests_per_second: max_requests_per_second,
+            mode: mode,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+    /// Take a token for a request from `addr`
+    ///
+    /// Returns time after which a token is available if the bucket is
+    /// empty.
+    pub fn acquire(&self, addr: &SocketAddr) -> Result<(), Duration> {
+        self.acquire_at(addr, Instant::now())
+    }
+    fn acquire_at(&self, addr: &SocketAddr, now: Instant)
+        -> Result<(), Duration>
+    {
+        let rate = self.max_requests_per_second as f64;
+        let key = match self.mode {
+            RateLimitMode::Global => None,
+            RateLimitMode::PerIp => Some(addr.ip()),
+        };
+        let mut buckets = self.buckets.lock()
+            .expect("rate limit is not poisoned");
+        if buckets.len() >= CLEANUP_THRESHOLD {
+            buckets.retain(|_, b| now.duration_since(b.updated).as_secs() < 1);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: rate,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated);
+        let elapsed = elapsed.as_secs() as f64 +
+            elapsed.subsec_nanos() as f64 / 1e9;
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / rate;
+            Err(Duration::new(wait as u64,
+                              (wait.fract() * 1e9) as u32))
+        }
+    }
+}
+
+impl<D> RateLimiter<D> {
+    /// Wrap a dispatcher of the connection from `addr`
+    pub fn new(dispatcher: D, addr: SocketAddr, limit: &Arc<RateLimit>)
+        -> RateLimiter<D>
+    {
+        RateLimiter {
+            dispatcher: dispatcher,
+            addr: addr,
+            limit: limit.clone(),
+        }
+    }
+}
+
+impl<S, D: Dispatcher<S>> Dispatcher<S> for RateLimiter<D> {
+    type Codec = RateLimiterCodec<D::Codec>;
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Codec, Error>
+    {
+        if let Err(wait) = self.limit.acquire(&self.addr) {
+            debug!("Rate limit exceeded for {}", self.addr);
+            return Ok(RateLimiterCodec {
+                kind: ShortCircuit::Reply(Limited(wait)),
+            });
+        }
+        let codec = self.dispatcher.headers_received(headers)?;
+        Ok(RateLimiterCodec { kind: ShortCircuit::Pass(codec) })
+    }
+}
+
+impl Reply for Limited {
+    fn write<S>(&self, mut e: Encoder<S>) -> EncoderDone<S> {
+        // Retry-After is in whole seconds, round up
+        let Limited(wait) = *self;
+        let seconds = wait.as_secs() +
+            if wait.subsec_nanos() > 0 { 1 } else { 0 };
+        e.status(Status::TooManyRequests);
+        e.format_header("Retry-After", seconds).unwrap();
+        e.add_length(0).unwrap();
+        e.done_headers().unwrap();
+        e.done()
+    }
+}
+
+impl<S, C: Codec<S>> Codec<S> for RateLimiterCodec<C> {
+    type ResponseFuture = ReplyFuture<S, C::ResponseFuture>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.kind.recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.kind.data_received(data, end)
+    }
+    fn start_response(&mut self, e: Encoder<S>) -> Self::ResponseFuture {
+        self.kind.start_response(e)
+    }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        self.kind.hijack(output, input)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use enums::Status;
+    use server::test_util::{Stub, serve};
+    use super::{RateLimit, RateLimitMode, RateLimiter};
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn bucket() {
+        let limit = RateLimit::new(2, RateLimitMode::PerIp);
+        let start = Instant::now();
+        let a = addr("127.0.0.1:1000");
+        assert!(limit.acquire_at(&a, start).is_ok());
+        // same IP from another connection
+        assert!(limit.acquire_at(&addr("127.0.0.1:1001"), start).is_ok());
+        assert_eq!(limit.acquire_at(&a, start),
+                   Err(Duration::from_millis(500)));
+        assert!(limit.acquire_at(&addr("127.0.0.2:1000"), start).is_ok());
+        let later = start + Duration::from_millis(500);
+        assert!(limit.acquire_at(&a, later).is_ok());
+        assert!(limit.acquire_at(&a, later).is_err());
+    }
+
+    #[test]
+    fn global() {
+        let limit = RateLimit::new(1, RateLimitMode::Global);
+        let start = Instant::now();
+        assert!(limit.acquire_at(&addr("127.0.0.1:1000"), start).is_ok());
+        assert!(limit.acquire_at(&addr("127.0.0.2:1000"), start).is_err());
+    }
+
+    #[test]
+    fn too_many_requests() {
+        let limit = RateLimit::new(1, RateLimitMode::PerIp);
+        let limiter = RateLimiter::new(Stub(Status::Ok),
+                                       addr("127.0.0.1:1000"), &limit);
+        assert_eq!(serve(limiter, "GET / HTTP/1.1\r\nHost: x\r\n\r\n\
+                        GET / HTTP/1.1\r\nHost: x\r\n\r\n"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n\
+             HTTP/1.1 429 Too Many Requests\r\n\
+             Retry-After: 1\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn limited_with_body() {
+        let limit = RateLimit::new(1, RateLimitMode::PerIp);
+        let limiter = RateLimiter::new(Stub(Status::Ok),
+                                       addr("127.0.0.1:1000"), &limit);
+        assert_eq!(serve(limiter, "GET / HTTP/1.1\r\nHost: x\r\n\r\n\
+                        POST / HTTP/1.1\r\nHost: x\r\n\
+                        Content-Length: 3\r\n\r\nabc"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n\
+             HTTP/1.1 429 Too Many Requests\r\n\
+             Retry-After: 1\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+}
@@ -8,6 +8,7 @@ pub enum Mode {
     BufferedUpfront(usize),
     Progressive(usize),
     Hijack,
+    Discard,
 }
 
 impl RecvMode {
@@ -53,6 +54,18 @@ impl RecvMode {
     pub fn hijack() -> RecvMode {
         RecvMode { mode: Mode::Hijack, timeout: None }
     }
+    /// Don't pass request body to the codec, skip it instead
+    ///
+    /// `data_received` is called once with empty data right away, so the
+    /// response can be started without waiting for the body. This is
+    /// useful for rejecting requests, like with `413 Payload Too Large`.
+    ///
+    /// If request has a body, connection is closed after the response is
+    /// sent. In particular, `100 Continue` isn't sent to a client that
+    /// waits for it (see `Head::expects_continue`).
+    pub fn discard_body() -> RecvMode {
+        RecvMode { mode: Mode::Discard, timeout: None }
+    }
 
     /// Change timeout for reading the whole request body to this value
     /// instead of configured default
@@ -0,0 +1,523 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::borrow::Cow;
+use std::str::from_utf8;
+
+use httparse::{self, Header};
+
+use enums::Version;
+use headers;
+use server::{EntityTag, Error};
+use server::etag::split_list;
+use server::error::ErrorEnum;
+use server::websocket::bytes_trim;
+
+
+/// Number of headers to allocate on a stack
+const MIN_HEADERS: usize = 16;
+/// A hard limit on the number of headers
+const MAX_HEADERS: usize = 1024;
+
+
+/// A parsed request line and headers of the HTTP request
+///
+/// This is a standalone structure returned by `parse_request`. Unlike `Head`
+/// it isn't bound to the `Proto` and `Dispatcher` machinery, so it's useful
+/// when you implement your own protocol handling (e.g. on top of a hijacked
+/// connection). Note: it doesn't do the checks `Head` does (like `Host`
+/// validation), except for `Content-Length`.
+#[derive(Debug)]
+pub struct RequestHead<'a> {
+    method: &'a str,
+    path: &'a str,
+    version: Version,
+    headers: Vec<Header<'a>>,
+    content_length: Option<u64>,
+}
+
+/// Parses a comma-separated list of values with optional quality
+///
+/// Items with invalid quality value are skipped
+fn quality_list<'a>(headers: &[Header<'a>], name: &str)
+    -> Vec<(&'a str, f32)>
+{
+    let mut result = Vec::new();
+    for header in headers.iter() {
+        if !header.name.eq_ignore_ascii_case(name) {
+            continue;
+        }
+        let value = match from_utf8(header.value) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        'items: for item in value.split(',') {
+            let mut parts = item.split(';').map(|x| x.trim());
+            let token = parts.next().unwrap_or("");
+            if token.len() == 0 {
+                continue;
+            }
+            let mut quality = 1.0;
+            for param in parts {
+                let mut pair = param.splitn(2, '=').map(|x| x.trim());
+                let pname = pair.next().unwrap_or("");
+                if pname.eq_ignore_ascii_case("q") {
+                    match pair.next().and_then(|x| x.parse().ok()) {
+                        Some(q) if q >= 0.0 && q <= 1.0 => quality = q,
+                        _ => continue 'items,
+                    }
+                }
+            }
+            result.push((token, quality));
+        }
+    }
+    return result;
+}
+
+/// Returns true if any of the headers contains the token in the
+/// comma-separated list
+fn has_token(headers: &[Header], name: &str, token: &str) -> bool {
+    headers.iter()
+        .filter(|h| h.name.eq_ignore_ascii_case(name))
+        .filter_map(|h| from_utf8(h.value).ok())
+        .any(|v| v.split(',').any(|x| x.trim().eq_ignore_ascii_case(token)))
+}
+
+/// Returns acceptable values in the order of preference
+fn preferred<'a>(mut items: Vec<(&'a str, f32)>) -> Vec<&'a str> {
+    items.retain(|&(_, q)| q > 0.0);
+    // sort is stable, so values with same quality keep header order
+    items.sort_by(|&(_, a), &(_, b)| b.partial_cmp(&a).unwrap());
+    items.into_iter().map(|(x, _)| x).collect()
+}
+
+fn hex_digit(x: u8) -> Option<u8> {
+    match x {
+        b'0'..=b'9' => Some(x - b'0'),
+        b'a'..=b'f' => Some(x - b'a' + 10),
+        b'A'..=b'F' => Some(x - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes `application/x-www-form-urlencoded` component
+///
+/// Invalid percent escapes are kept as is, invalid utf-8 is replaced by
+/// `U+FFFD`. Allocates only if there is something to decode.
+fn form_decode(value: &str) -> Cow<str> {
+    if !value.bytes().any(|x| x == b'%' || x == b'+') {
+        return Cow::Borrowed(value);
+    }
+    let bytes = value.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'+' => result.push(b' '),
+            b'%' if idx + 2 < bytes.len() => {
+                match (hex_digit(bytes[idx+1]), hex_digit(bytes[idx+2])) {
+                    (Some(h), Some(l)) => {
+                        result.push(h << 4 | l);
+                        idx += 3;
+                        continue;
+                    }
+                    _ => result.push(b'%'),
+                }
+            }
+            x => result.push(x),
+        }
+        idx += 1;
+    }
+    match String::from_utf8(result) {
+        Ok(value) => Cow::Owned(value),
+        Err(e) => Cow::Owned(String::from_utf8_lossy(e.as_bytes())
+                             .into_owned()),
+    }
+}
+
+/// Parse request line and headers from the buffer
+///
+/// Returns `Ok(None)` if headers are not complete yet, otherwise returns
+/// parsed request and number of bytes occupied by headers (i.e. body starts
+/// at this offset).
+///
+/// Duplicate or invalid `Content-Length` headers are rejected with an error.
+pub fn parse_request(buf: &[u8])
+    -> Result<Option<(RequestHead, usize)>, Error>
+{
+    let mut vec;
+    let mut headers = [httparse::EMPTY_HEADER; MIN_HEADERS];
+    let mut raw = httparse::Request::new(&mut headers);
+    let mut result = raw.parse(buf);
+    if matches!(result, Err(httparse::Error::TooManyHeaders)) {
+        vec = vec![httparse::EMPTY_HEADER; MAX_HEADERS];
+        raw = httparse::Request::new(&mut vec);
+        result = raw.parse(buf);
+    }
+    let bytes = match result.map_err(ErrorEnum::ParseError)? {
+        httparse::Status::Complete(bytes) => bytes,
+        httparse::Status::Partial => return Ok(None),
+    };
+    let mut content_length = None;
+    for header in raw.headers.iter() {
+        if header.name.eq_ignore_ascii_case("Content-Length") {
+            if content_length.is_some() {
+                return Err(ErrorEnum::DuplicateContentLength.into());
+            }
+            let value = from_utf8(header.value)
+                .map_err(|_| ErrorEnum::ContentLengthInvalid)?;
+            content_length = Some(value.trim().parse()
+                .map_err(|_| ErrorEnum::ContentLengthInvalid)?);
+        }
+    }
+    let head = RequestHead {
+        method: raw.method.unwrap(),
+        path: raw.path.unwrap(),
+        version: if raw.version.unwrap() == 1
+            { Version::Http11 } else { Version::Http10 },
+        headers: raw.headers.to_vec(),
+        content_length: content_length,
+    };
+    Ok(Some((head, bytes)))
+}
+
+impl<'a> RequestHead<'a> {
+    /// Returns a HTTP method
+    pub fn method(&self) -> &'a str {
+        self.method
+    }
+    /// Returns request target as is (the middle part of the request line)
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+    /// Returns the path part of request target (everything before `?`)
+    pub fn path_without_query(&self) -> &'a str {
+        match self.path.find('?') {
+            Some(idx) => &self.path[..idx],
+            None => self.path,
+        }
+    }
+    /// Returns the query string (everything after `?`) without decoding
+    ///
+    /// Returns `None` if there is no `?` in the request target.
+    pub fn query_string(&self) -> Option<&'a str> {
+        self.path.find('?').map(|idx| &self.path[idx+1..])
+    }
+    /// Returns the value of the first query parameter named `key`
+    ///
+    /// The query is parsed as `application/x-www-form-urlencoded`, i.e.
+    /// `+` means a space, and percent-escapes are decoded both in the keys
+    /// and in the values. Parameter without `=` has an empty value.
+    /// The value is borrowed from the request if it has nothing to decode.
+    pub fn query_param(&self, key: &str) -> Option<Cow<'a, str>> {
+        let query = match self.query_string() {
+            Some(query) => query,
+            None => return None,
+        };
+        for pair in query.split('&') {
+            let mut pair = pair.splitn(2, '=');
+            let name = pair.next().unwrap_or("");
+            if form_decode(name) == key {
+                return Some(form_decode(pair.next().unwrap_or("")));
+            }
+        }
+        None
+    }
+    /// Version of HTTP request
+    pub fn version(&self) -> Version {
+        self.version
+    }
+    /// Returns the value of the first header with the specified name
+    ///
+    /// Header name is compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&'a [u8]> {
+        self.headers.iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value)
+    }
+    /// All headers of HTTP request (including hop-by-hop ones)
+    pub fn all_headers(&self) -> &[Header<'a>] {
+        &self.headers
+    }
+    /// Returns the value of the `Content-Length` header if there is one
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+    /// Returns `true` if client waits for `100 Continue` before sending
+    /// the request body (`Expect: 100-continue` in an HTTP/1.1 request)
+    ///
+    /// Write `HTTP/1.1 100 Continue\r\n\r\n` before reading the body, or
+    /// respond with a final status and close the connection to reject it.
+    pub fn expects_continue(&self) -> bool {
+        self.version == Version::Http11 && self.headers.iter()
+            .any(|h| h.name.eq_ignore_ascii_case("Expect") &&
+                     headers::is_continue(h.value))
+    }
+    /// Returns true if `Accept` header allows the specified mime type
+    ///
+    /// The most specific media range wins: `text/html` is more specific than
+    /// `text/*` which is more specific than `*/*`. Media type is acceptable
+    /// if its quality is non-zero. Parameters of the `mime` are ignored.
+    ///
+    /// If there is no `Accept` header any type is acceptable.
+    pub fn accepts(&self, mime: &str) -> bool {
+        let items = quality_list(&self.headers, "Accept");
+        if items.len() == 0 {
+            return true;
+        }
+        let mime = mime.split(';').next().unwrap_or("").trim();
+        let mtype = mime.split('/').next().unwrap_or("");
+        let mut best = None::<(u8, f32)>;
+        for (range, quality) in items {
+            let specificity = if range.eq_ignore_ascii_case(mime) {
+                3
+            } else if range == "*/*" {
+                1
+            } else if range.ends_with("/*") &&
+                range[..range.len()-2].eq_ignore_ascii_case(mtype)
+            {
+                2
+            } else {
+                continue;
+            };
+            if best.map(|(s, _)| specificity > s).unwrap_or(true) {
+                best = Some((specificity, quality));
+            }
+        }
+        best.map(|(_, q)| q > 0.0).unwrap_or(false)
+    }
+    /// Returns true if request is a websocket handshake
+    ///
+    /// Checks that `Connection` contains `upgrade` token, `Upgrade`
+    /// contains `websocket`, `Sec-WebSocket-Version` is `13` and there is
+    /// a single `Sec-WebSocket-Key`. Header names and tokens are compared
+    /// case-insensitively.
+    ///
+    /// Unlike `Head::get_websocket_upgrade` it doesn't check method and
+    /// body of the request.
+    pub fn is_websocket_upgrade(&self) -> bool {
+        has_token(&self.headers, "Connection", "upgrade") &&
+        has_token(&self.headers, "Upgrade", "websocket") &&
+        self.header("Sec-WebSocket-Version")
+            .map(|v| bytes_trim(v) == b"13").unwrap_or(false) &&
+        self.websocket_key().is_some()
+    }
+    /// Returns the value of `Sec-WebSocket-Key`
+    ///
+    /// Returns `None` if there is no header, or there are multiple ones.
+    /// Use `websocket::Accept::from_key_bytes` to make a response header.
+    pub fn websocket_key(&self) -> Option<&'a [u8]> {
+        let mut keys = self.headers.iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("Sec-WebSocket-Key"));
+        match (keys.next(), keys.next()) {
+            (Some(h), None) if bytes_trim(h.value).len() > 0 => {
+                Some(bytes_trim(h.value))
+            }
+            _ => None,
+        }
+    }
+    /// Returns encodings from `Accept-Encoding` in the order of preference
+    ///
+    /// Encodings with zero quality (i.e. explicitly not acceptable) are
+    /// dropped.
+    pub fn accepted_encodings(&self) -> Vec<&'a str> {
+        preferred(quality_list(&self.headers, "Accept-Encoding"))
+    }
+    /// Returns languages from `Accept-Language` in the order of preference
+    ///
+    /// Languages with zero quality (i.e. explicitly not acceptable) are
+    /// dropped.
+    pub fn accepted_languages(&self) -> Vec<&'a str> {
+        preferred(quality_list(&self.headers, "Accept-Language"))
+    }
+    /// Returns entity tags listed in `If-None-Match` headers
+    ///
+    /// Invalid tags and `*` are skipped, use `is_not_modified` to check
+    /// the request against the current tag of the resource.
+    pub fn if_none_match(&self) -> Vec<EntityTag> {
+        self.headers.iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("If-None-Match"))
+            .filter_map(|h| from_utf8(h.value).ok())
+            .flat_map(split_list)
+            .filter_map(EntityTag::parse)
+            .collect()
+    }
+    /// Returns `true` if `If-None-Match` matches the current tag
+    ///
+    /// I.e. if `304 Not Modified` should be returned for `GET` and `HEAD`
+    /// requests. Tags are compared using weak comparison, `*` matches any
+    /// tag. Returns `false` if there is no `If-None-Match` header.
+    pub fn is_not_modified(&self, current: &EntityTag) -> bool {
+        let mut values = self.headers.iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("If-None-Match"))
+            .filter_map(|h| from_utf8(h.value).ok())
+            .flat_map(split_list);
+        values.any(|v| v.trim() == "*" ||
+            EntityTag::parse(v).map(|t| t.weak_eq(current)).unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use enums::Version;
+    use server::EntityTag;
+    use super::{parse_request, form_decode};
+
+    #[test]
+    fn simple() {
+        let data = b"POST /hello HTTP/1.1\r\n\
+                     Host: example.com\r\n\
+                     content-length: 5\r\n\r\nhello";
+        let (head, bytes) = parse_request(data).unwrap().unwrap();
+        assert_eq!(head.method(), "POST");
+        assert_eq!(head.path(), "/hello");
+        assert_eq!(head.version(), Version::Http11);
+        assert_eq!(head.header("host"), Some(&b"example.com"[..]));
+        assert_eq!(head.header("X-Missing"), None);
+        assert_eq!(head.content_length(), Some(5));
+        assert_eq!(&data[bytes..], b"hello");
+    }
+
+    #[test]
+    fn expect_continue() {
+        let (head, _) = parse_request(b"PUT / HTTP/1.1\r\n\
+            Expect: 100-Continue\r\nContent-Length: 5\r\n\r\n")
+            .unwrap().unwrap();
+        assert!(head.expects_continue());
+        let (head, _) = parse_request(b"PUT / HTTP/1.0\r\n\
+            Expect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+            .unwrap().unwrap();
+        assert!(!head.expects_continue());
+    }
+
+    #[test]
+    fn query() {
+        let data = b"GET /chat?room=42&lang=en&a%20b=c+d%2B&flag \
+                     HTTP/1.1\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert_eq!(head.path_without_query(), "/chat");
+        assert_eq!(head.query_string(),
+                   Some("room=42&lang=en&a%20b=c+d%2B&flag"));
+        assert_matches!(head.query_param("room"),
+                        Some(Cow::Borrowed("42")));
+        assert_eq!(head.query_param("a b").unwrap(), "c d+");
+        assert_eq!(head.query_param("flag").unwrap(), "");
+        assert_eq!(head.query_param("nope"), None);
+
+        let (head, _) = parse_request(b"GET /chat HTTP/1.1\r\n\r\n")
+            .unwrap().unwrap();
+        assert_eq!(head.path_without_query(), "/chat");
+        assert_eq!(head.query_string(), None);
+        assert_eq!(head.query_param("room"), None);
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(form_decode("%41%zz%4"), "A%zz%4");
+        assert_eq!(form_decode("%D0%B4%FF"), "\u{434}\u{FFFD}");
+    }
+
+    #[test]
+    fn if_none_match() {
+        let data = b"GET / HTTP/1.1\r\n\
+            If-None-Match: W/\"a\", \"b\", bad\r\n\
+            If-None-Match: \"c\"\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert_eq!(head.if_none_match(), vec![EntityTag::weak("a"),
+            EntityTag::strong("b"), EntityTag::strong("c")]);
+        assert!(head.is_not_modified(&EntityTag::strong("a")));
+        assert!(head.is_not_modified(&EntityTag::weak("c")));
+        assert!(!head.is_not_modified(&EntityTag::strong("d")));
+
+        let data = b"GET / HTTP/1.1\r\n\
+            If-None-Match: \"a,b\", W/\"c,d\"\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert_eq!(head.if_none_match(), vec![EntityTag::strong("a,b"),
+            EntityTag::weak("c,d")]);
+        assert!(head.is_not_modified(&EntityTag::strong("a,b")));
+        assert!(!head.is_not_modified(&EntityTag::strong("a")));
+
+        let data = b"GET / HTTP/1.1\r\nIf-None-Match: *\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert_eq!(head.if_none_match(), vec![]);
+        assert!(head.is_not_modified(&EntityTag::strong("d")));
+
+        let data = b"GET / HTTP/1.1\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert!(!head.is_not_modified(&EntityTag::strong("d")));
+    }
+
+    #[test]
+    fn partial() {
+        assert!(parse_request(b"GET / HTTP/1.0\r\nHost: x")
+            .unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_content_length() {
+        assert!(parse_request(b"GET / HTTP/1.1\r\n\
+            Content-Length: x\r\n\r\n").is_err());
+        assert!(parse_request(b"GET / HTTP/1.1\r\n\
+            Content-Length: 1\r\nContent-Length: 1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn accept() {
+        let data = b"GET / HTTP/1.1\r\n\
+            Accept: text/html, text/*;q=0.5, image/png;q=0\r\n\
+            Accept: */*;q=0.1\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert!(head.accepts("text/html"));
+        assert!(head.accepts("text/plain; charset=utf-8"));
+        assert!(head.accepts("application/json"));
+        assert!(!head.accepts("image/png"));
+
+        let data = b"GET / HTTP/1.1\r\nAccept: text/html\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert!(!head.accepts("image/png"));
+
+        let data = b"GET / HTTP/1.1\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert!(head.accepts("image/png"));
+    }
+
+    #[test]
+    fn encodings_and_languages() {
+        let data = b"GET / HTTP/1.1\r\n\
+            Accept-Encoding: gzip;q=0.5, br, identity;q=0\r\n\
+            Accept-Language: en;q=0.8, de, fr;q=0.8\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert_eq!(head.accepted_encodings(), vec!["br", "gzip"]);
+        assert_eq!(head.accepted_languages(), vec!["de", "en", "fr"]);
+    }
+
+    #[test]
+    fn websocket_upgrade() {
+        let data = b"GET /chat HTTP/1.1\r\n\
+            Connection: keep-alive, Upgrade\r\n\
+            Upgrade: WebSocket\r\n\
+            sec-websocket-version: 13\r\n\
+            Sec-WebSocket-Key:  dGhlIHNhbXBsZSBub25jZQ== \r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert!(head.is_websocket_upgrade());
+        assert_eq!(head.websocket_key(),
+                   Some(&b"dGhlIHNhbXBsZSBub25jZQ=="[..]));
+
+        let data = b"GET /chat HTTP/1.1\r\n\
+            Connection: Upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 8\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert!(!head.is_websocket_upgrade());
+
+        let data = b"GET /chat HTTP/1.1\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: a\r\nSec-WebSocket-Key: b\r\n\r\n";
+        let (head, _) = parse_request(data).unwrap().unwrap();
+        assert!(!head.is_websocket_upgrade());
+        assert_eq!(head.websocket_key(), None);
+    }
+}
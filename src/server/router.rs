@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use server::RequestHead;
+
+
+/// A router that dispatches requests by method and path
+///
+/// Patterns consist of segments separated by slash. A segment may be:
+///
+/// * a literal that must match exactly, e.g. `/users`
+/// * a parameter `:name` that matches any non-empty segment
+/// * a glob `*name` that matches the rest of the path (possibly empty),
+///   it's only allowed as the last segment
+///
+/// Patterns are compiled into a trie of segments, so dispatching doesn't
+/// depend on the number of routes. When multiple patterns match the path,
+/// literals are preferred over parameters, and parameters over globs.
+///
+/// Query string is stripped before matching. Parameter values are returned
+/// as is, without percent-decoding.
+pub struct Router<H> {
+    root: Node<H>,
+}
+
+/// A result of the successful `Router::dispatch`
+#[derive(Debug)]
+pub struct RouteMatch<'a, H: 'a> {
+    /// The handler of the matched route
+    pub handler: &'a H,
+    /// Values of `:param` and `*glob` segments by name
+    pub params: HashMap<String, String>,
+}
+
+struct Route<H> {
+    method: String,
+    handler: H,
+    names: Vec<String>,
+}
+
+struct Node<H> {
+    literals: HashMap<String, Node<H>>,
+    param: Option<Box<Node<H>>>,
+    routes: Vec<Route<H>>,
+    globs: Vec<Route<H>>,
+}
+
+impl<H> Node<H> {
+    fn new() -> Node<H> {
+        Node {
+            literals: HashMap::new(),
+            param: None,
+            routes: Vec::new(),
+            globs: Vec::new(),
+        }
+    }
+    fn find<'a, 'p>(&'a self, method: &str, path: Option<&'p str>,
+        values: &mut Vec<&'p str>)
+        -> Option<&'a Route<H>>
+    {
+        let path = match path {
+            Some(path) => path,
+            None => return self.routes.iter().find(|r| r.method == method),
+        };
+        let (segment, tail) = match path.find('/') {
+            Some(idx) => (&path[..idx], Some(&path[idx+1..])),
+            None => (path, None),
+        };
+        if let Some(node) = self.literals.get(segment) {
+            if let Some(route) = node.find(method, tail, values) {
+                return Some(route);
+            }
+        }
+        if let Some(ref node) = self.param {
+            if segment.len() > 0 {
+                values.push(segment);
+                if let Some(route) = node.find(method, tail, values) {
+                    return Some(route);
+                }
+                values.pop();
+            }
+        }
+        if let Some(route) = self.globs.iter().find(|r| r.method == method) {
+            values.push(path);
+            return Some(route);
+        }
+        return None;
+    }
+}
+
+impl<H> Router<H> {
+    /// Create an empty router
+    pub fn new() -> Router<H> {
+        Router {
+            root: Node::new(),
+        }
+    }
+    /// Add a route
+    ///
+    /// # Panics
+    ///
+    /// When pattern doesn't start with a slash, when glob is not the last
+    /// segment, when parameter has no name or when the same method and
+    /// pattern are already registered.
+    pub fn route(&mut self, method: &str, pattern: &str, handler: H)
+        -> &mut Self
+    {
+        assert!(pattern.starts_with('/'),
+            "pattern {:?} must start with a slash", pattern);
+        let mut node = &mut self.root;
+        let mut names = Vec::new();
+        let mut segments = pattern[1..].split('/').peekable();
+        while let Some(segment) = segments.next() {
+            if segment.starts_with(':') {
+                assert!(segment.len() > 1,
+                    "parameter in {:?} has no name", pattern);
+                names.push(segment[1..].to_string());
+                let tmp = node;
+                node = tmp.param.get_or_insert_with(|| Box::new(Node::new()));
+            } else if segment.starts_with('*') {
+                assert!(segments.peek().is_none(),
+                    "glob must be the last segment in {:?}", pattern);
+                names.push(segment[1..].to_string());
+                assert!(!node.globs.iter().any(|r| r.method == method),
+                    "duplicate route {} {}", method, pattern);
+                node.globs.push(Route {
+                    method: method.to_string(),
+                    handler: handler,
+                    names: names,
+                });
+                return self;
+            } else {
+                let tmp = node;
+                node = tmp.literals.entry(segment.to_string())
+                    .or_insert_with(Node::new);
+            }
+        }
+        assert!(!node.routes.iter().any(|r| r.method == method),
+            "duplicate route {} {}", method, pattern);
+        node.routes.push(Route {
+            method: method.to_string(),
+            handler: handler,
+            names: names,
+        });
+        self
+    }
+    /// Find a route for the method and path
+    ///
+    /// This is the same as `dispatch` but is useful when request is not
+    /// parsed with `parse_request`.
+    pub fn find(&self, method: &str, path: &str) -> Option<RouteMatch<H>> {
+        let path = match path.find(|c| c == '?' || c == '#') {
+            Some(idx) => &path[..idx],
+            None => path,
+        };
+        if !path.starts_with('/') {
+            return None;
+        }
+        let mut values = Vec::new();
+        self.root.find(method, Some(&path[1..]), &mut values).map(|route| {
+            RouteMatch {
+                handler: &route.handler,
+                params: route.names.iter().cloned()
+                    .zip(values.iter().map(|x| x.to_string()))
+                    .collect(),
+            }
+        })
+    }
+    /// Find a route for the request
+    ///
+    /// Returns `None` if there is no route for the path or it has no
+    /// handler for the request method.
+    pub fn dispatch(&self, head: &RequestHead) -> Option<RouteMatch<H>> {
+        self.find(head.method(), head.path())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use server::parse_request;
+    use super::Router;
+
+    fn router() -> Router<u32> {
+        let mut r = Router::new();
+        r.route("GET", "/", 1)
+         .route("GET", "/users", 2)
+         .route("POST", "/users", 3)
+         .route("GET", "/users/me", 4)
+         .route("GET", "/users/:id", 5)
+         .route("GET", "/users/:id/posts/:post", 6)
+         .route("GET", "/static/*path", 7)
+         .route("GET", "/users/:name/*rest", 8);
+        r
+    }
+
+    fn find(r: &Router<u32>, method: &str, path: &str)
+        -> Option<(u32, Vec<(String, String)>)>
+    {
+        r.find(method, path).map(|m| {
+            let mut params = m.params.into_iter().collect::<Vec<_>>();
+            params.sort();
+            (*m.handler, params)
+        })
+    }
+
+    fn params(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn literals() {
+        let r = router();
+        assert_eq!(find(&r, "GET", "/"), Some((1, vec![])));
+        assert_eq!(find(&r, "GET", "/users?page=2"), Some((2, vec![])));
+        assert_eq!(find(&r, "POST", "/users"), Some((3, vec![])));
+        assert_eq!(find(&r, "DELETE", "/users"), None);
+        assert_eq!(find(&r, "GET", "/users/me"), Some((4, vec![])));
+        assert_eq!(find(&r, "GET", "/nothing"), None);
+        assert_eq!(find(&r, "GET", "*"), None);
+    }
+
+    #[test]
+    fn params_and_globs() {
+        let r = router();
+        assert_eq!(find(&r, "GET", "/users/42"),
+                   Some((5, params(&[("id", "42")]))));
+        assert_eq!(find(&r, "GET", "/users/42/posts/7"),
+                   Some((6, params(&[("id", "42"), ("post", "7")]))));
+        assert_eq!(find(&r, "GET", "/users/42/likes"),
+                   Some((8, params(&[("name", "42"), ("rest", "likes")]))));
+        assert_eq!(find(&r, "GET", "/static/css/main.css"),
+                   Some((7, params(&[("path", "css/main.css")]))));
+        assert_eq!(find(&r, "GET", "/static/"),
+                   Some((7, params(&[("path", "")]))));
+        assert_eq!(find(&r, "GET", "/users//posts/7"), None);
+    }
+
+    #[test]
+    fn dispatch() {
+        let r = router();
+        let (head, _) = parse_request(b"GET /users/me/x HTTP/1.1\r\n\r\n")
+            .unwrap().unwrap();
+        let m = r.dispatch(&head).unwrap();
+        assert_eq!(*m.handler, 8);
+        assert_eq!(m.params["name"], "me");
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate() {
+        router().route("GET", "/users/:other", 0);
+    }
+}
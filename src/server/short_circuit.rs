@@ -0,0 +1,59 @@
+use futures::Async;
+use futures::future::{Either, FutureResult, ok};
+use tk_bufstream::{ReadBuf, WriteBuf};
+
+use server::{Codec, Encoder, EncoderDone, Error, RecvMode};
+
+
+/// A response that middleware writes instead of the wrapped codec
+pub(crate) trait Reply {
+    fn write<S>(&self, e: Encoder<S>) -> EncoderDone<S>;
+}
+
+/// Response future of a codec that may reply by itself
+pub(crate) type ReplyFuture<S, F> =
+    Either<FutureResult<EncoderDone<S>, Error>, F>;
+
+/// Either a reply of the middleware, or a codec of wrapped dispatcher
+///
+/// Request body of a reply is discarded (connection is closed after the
+/// response if there is one), so middleware doesn't have to care how large
+/// the body is.
+pub(crate) enum ShortCircuit<R, C> {
+    Reply(R),
+    Pass(C),
+}
+
+impl<S, R: Reply, C: Codec<S>> Codec<S> for ShortCircuit<R, C> {
+    type ResponseFuture = ReplyFuture<S, C::ResponseFuture>;
+    fn recv_mode(&mut self) -> RecvMode {
+        match *self {
+            ShortCircuit::Reply(..) => RecvMode::discard_body(),
+            ShortCircuit::Pass(ref mut codec) => codec.recv_mode(),
+        }
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        match *self {
+            ShortCircuit::Reply(..) => Ok(Async::Ready(data.len())),
+            ShortCircuit::Pass(ref mut codec) => {
+                codec.data_received(data, end)
+            }
+        }
+    }
+    fn start_response(&mut self, e: Encoder<S>) -> Self::ResponseFuture {
+        match *self {
+            ShortCircuit::Reply(ref reply) => Either::A(ok(reply.write(e))),
+            ShortCircuit::Pass(ref mut codec) => {
+                Either::B(codec.start_response(e))
+            }
+        }
+    }
+    fn hijack(&mut self, output: WriteBuf<S>, input: ReadBuf<S>) {
+        match *self {
+            ShortCircuit::Reply(..) => unreachable!(),
+            ShortCircuit::Pass(ref mut codec) => codec.hijack(output, input),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{Future, Async, Poll};
+use futures::task::{self, Task};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use server::{Dispatcher, Proto};
+
+
+/// A handle to initiate graceful shutdown of the server
+///
+/// Wrap every connection into `GracefulShutdown` with a clone of the
+/// handle. When `initiate_shutdown` is called every connection is closed
+/// as soon as it finishes serving the current requests. You should also
+/// stop accepting connections, checking `is_shutting_down`, and wait for
+/// connections with `drain`:
+///
+/// ```rust,ignore
+/// let shutdown = ShutdownHandle::new();
+/// // in the accept loop
+/// handle.spawn(GracefulShutdown::new(&shutdown,
+///     Proto::new(socket, &cfg, dispatcher, &handle)));
+/// // on SIGTERM
+/// shutdown.initiate_shutdown();
+/// core.run(shutdown.drain(Duration::from_secs(30), &handle))
+/// ```
+///
+/// The handle is `Send`, so shutdown can be initiated from any thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// A connection future which is closed gracefully on shutdown
+///
+/// Created by `GracefulShutdown::new`. The connection is counted as active
+/// until this future is dropped.
+pub struct GracefulShutdown<F> {
+    future: F,
+    id: usize,
+    closing: bool,
+    shutdown: ShutdownHandle,
+}
+
+/// A future that resolves when all the connections are closed
+///
+/// Created by `ShutdownHandle::drain`. Resolves to the number of connections
+/// that are still active when `drain_timeout` expires (or to zero).
+pub struct Drain {
+    shutdown: ShutdownHandle,
+    timeout: Timeout,
+}
+
+/// A connection that can be closed when it's done serving current requests
+pub trait GracefulClose {
+    /// Don't accept new requests and finish when current ones are served
+    fn close_when_idle(&mut self);
+}
+
+struct Shared {
+    shutdown: bool,
+    next_id: usize,
+    connections: HashMap<usize, Option<Task>>,
+    drains: Vec<Task>,
+}
+
+impl ShutdownHandle {
+    /// Create a new handle
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle {
+            shared: Arc::new(Mutex::new(Shared {
+                shutdown: false,
+                next_id: 0,
+                connections: HashMap::new(),
+                drains: Vec::new(),
+            })),
+        }
+    }
+    /// Signal all the connections to close after the current request
+    pub fn initiate_shutdown(&self) {
+        let mut shared = self.shared.lock().expect("shutdown lock");
+        shared.shutdown = true;
+        for task in shared.connections.values_mut() {
+            if let Some(task) = task.take() {
+                task.notify();
+            }
+        }
+    }
+    /// Returns `true` if shutdown is initiated
+    pub fn is_shutting_down(&self) -> bool {
+        self.shared.lock().expect("shutdown lock").shutdown
+    }
+    /// Returns the number of active connections
+    pub fn connections(&self) -> usize {
+        self.shared.lock().expect("shutdown lock").connections.len()
+    }
+    /// Returns a future that resolves when all the connections are closed
+    /// or `drain_timeout` expires
+    ///
+    /// This doesn't initiate shutdown by itself.
+    pub fn drain(&self, drain_timeout: Duration, handle: &Handle) -> Drain {
+        Drain {
+            shutdown: self.clone(),
+            timeout: Timeout::new(drain_timeout, handle)
+                .expect("can always create a timeout"),
+        }
+    }
+}
+
+impl<F: GracefulClose> GracefulShutdown<F> {
+    /// Wrap a connection future
+    pub fn new(shutdown: &ShutdownHandle, future: F) -> GracefulShutdown<F> {
+        let mut shared = shutdown.shared.lock().expect("shutdown lock");
+        let id = shared.next_id;
+        shared.next_id = shared.next_id.wrapping_add(1);
+        shared.connections.insert(id, None);
+        GracefulShutdown {
+            future: future,
+            id: id,
+            closing: false,
+            shutdown: shutdown.clone(),
+        }
+    }
+}
+
+impl<F: Future + GracefulClose> Future for GracefulShutdown<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        if !self.closing {
+            let mut shared = self.shutdown.shared.lock()
+                .expect("shutdown lock");
+            if shared.shutdown {
+                self.closing = true;
+            } else {
+                shared.connections.insert(self.id, Some(task::current()));
+            }
+        }
+        if self.closing {
+            self.future.close_when_idle();
+        }
+        self.future.poll()
+    }
+}
+
+impl<F> Drop for GracefulShutdown<F> {
+    fn drop(&mut self) {
+        let mut shared = self.shutdown.shared.lock().expect("shutdown lock");
+        shared.connections.remove(&self.id);
+        for task in shared.drains.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+impl Future for Drain {
+    type Item = usize;
+    type Error = ();
+    fn poll(&mut self) -> Poll<usize, ()> {
+        let mut shared = self.shutdown.shared.lock().expect("shutdown lock");
+        if shared.connections.len() == 0 {
+            return Ok(Async::Ready(0));
+        }
+        match self.timeout.poll().expect("timeout can't fail on poll") {
+            Async::Ready(()) => Ok(Async::Ready(shared.connections.len())),
+            Async::NotReady => {
+                if !shared.drains.iter().any(|t| t.will_notify_current()) {
+                    shared.drains.push(task::current());
+                }
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl<S, D> GracefulClose for Proto<S, D>
+    where S: AsyncRead + AsyncWrite,
+          D: Dispatcher<S>,
+{
+    fn close_when_idle(&mut self) {
+        Proto::close_when_idle(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::{Async, Future};
+    use futures::future::{FutureResult, lazy, ok};
+    use tk_bufstream::MockData;
+    use tokio_core::reactor::Core;
+
+    use enums::Status;
+    use server::{Codec, Config, Dispatcher, Encoder, EncoderDone, Error};
+    use server::{Head, Proto, RecvMode};
+    use super::{ShutdownHandle, GracefulShutdown};
+
+    struct Hello;
+
+    impl Dispatcher<MockData> for Hello {
+        type Codec = Self;
+        fn headers_received(&mut self, _: &Head) -> Result<Hello, Error> {
+            Ok(Hello)
+        }
+    }
+
+    impl Codec<MockData> for Hello {
+        type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+        fn recv_mode(&mut self) -> RecvMode {
+            RecvMode::buffered_upfront(0)
+        }
+        fn data_received(&mut self, data: &[u8], _: bool)
+            -> Result<Async<usize>, Error>
+        {
+            Ok(Async::Ready(data.len()))
+        }
+        fn start_response(&mut self, mut e: Encoder<MockData>)
+            -> Self::ResponseFuture
+        {
+            e.status(Status::Ok);
+            e.add_length(2).unwrap();
+            e.done_headers().unwrap();
+            e.write_body(b"ok");
+            ok(e.done())
+        }
+    }
+
+    fn connect(lp: &Core, shutdown: &ShutdownHandle) -> MockData {
+        let mock = MockData::new();
+        let proto = Proto::new(mock.clone(), &Arc::new(Config::new()),
+            Hello, &lp.handle());
+        lp.handle().spawn(GracefulShutdown::new(shutdown, proto)
+            .map_err(|e| panic!("{}", e)));
+        mock
+    }
+
+    #[test]
+    fn idle_connections() {
+        let mut lp = Core::new().unwrap();
+        let shutdown = ShutdownHandle::new();
+        let mock = connect(&lp, &shutdown);
+        connect(&lp, &shutdown);
+        mock.add_input("GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+        lp.turn(Some(Duration::from_millis(10)));
+        assert!(mock.output(..).ends_with(b"\r\n\r\nok"));
+        assert_eq!(shutdown.connections(), 2);
+        shutdown.initiate_shutdown();
+        let drain = shutdown.drain(Duration::from_secs(10), &lp.handle());
+        assert_eq!(lp.run(drain).unwrap(), 0);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut lp = Core::new().unwrap();
+        let shutdown = ShutdownHandle::new();
+        let mock = connect(&lp, &shutdown);
+        connect(&lp, &shutdown);
+        mock.add_input("GET / HTTP/1.1\r\n");
+        lp.turn(Some(Duration::from_millis(10)));
+        shutdown.initiate_shutdown();
+        let drain = shutdown.drain(Duration::from_millis(50), &lp.handle());
+        assert_eq!(lp.run(drain).unwrap(), 1);
+        assert!(shutdown.is_shutting_down());
+    }
+    #[test]
+    fn repeated_poll() {
+        let mut lp = Core::new().unwrap();
+        let shutdown = ShutdownHandle::new();
+        connect(&lp, &shutdown);
+        let mut drain = shutdown.drain(Duration::from_secs(10), &lp.handle());
+        lp.run(lazy(|| {
+            for _ in 0..3 {
+                assert!(drain.poll().unwrap().is_not_ready());
+            }
+            Ok::<(), ()>(())
+        })).unwrap();
+        assert_eq!(shutdown.shared.lock().unwrap().drains.len(), 1);
+    }
+}
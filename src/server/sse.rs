@@ -0,0 +1,158 @@
+use std::io;
+
+use tokio_io::AsyncWrite;
+
+use base_serializer::HeaderError;
+use enums::Status;
+use server::{Encoder, EncoderDone};
+
+
+/// An encoder of the server-sent events (`text/event-stream`) response
+///
+/// Wraps the response `Encoder`, writes response headers and then each
+/// event as a separate chunk of the chunked body. The buffer is flushed
+/// after every event, so events don't linger in the buffer.
+///
+/// When the stream is over, return `done()` from your response future as
+/// you would do with the `Encoder`.
+pub struct SseEncoder<S> {
+    encoder: Encoder<S>,
+}
+
+fn write_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    // `\r\n`, `\r` and `\n` are all line breaks in the event stream
+    for line in value.split("\r\n").flat_map(|x| x.split(|c| {
+        c == '\r' || c == '\n'
+    })) {
+        buf.extend(name.as_bytes());
+        buf.extend(b": ");
+        buf.extend(line.as_bytes());
+        buf.push(b'\n');
+    }
+}
+
+fn assert_single_line(name: &str, value: &str) {
+    assert!(!value.contains(|c| c == '\r' || c == '\n'),
+        "event field `{}` must not contain line breaks", name);
+}
+
+impl<S> SseEncoder<S> {
+    /// Start the `200 OK` response with `Content-Type: text/event-stream`
+    ///
+    /// Additional headers (e.g. `Access-Control-Allow-Origin`) can be
+    /// added on `Encoder` before calling this method.
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written.
+    pub fn new(mut encoder: Encoder<S>)
+        -> Result<SseEncoder<S>, HeaderError>
+    {
+        encoder.status(Status::Ok);
+        encoder.add_header("Content-Type", "text/event-stream")?;
+        encoder.add_header("Cache-Control", "no-cache")?;
+        encoder.add_chunked()?;
+        encoder.done_headers()?;
+        Ok(SseEncoder { encoder: encoder })
+    }
+    fn send(&mut self, data: &[u8]) -> Result<(), io::Error>
+        where S: AsyncWrite
+    {
+        self.encoder.write_body(data);
+        self.encoder.flush()
+    }
+    /// Send an event and flush the buffer
+    ///
+    /// Empty `event` means default event type (`message`). Line breaks in
+    /// `data` are split into multiple `data` fields, so client receives
+    /// them as is.
+    ///
+    /// # Panics
+    ///
+    /// When `id` or `event` contain a line break.
+    pub fn send_event(&mut self, id: Option<&str>, event: &str, data: &str)
+        -> Result<(), io::Error>
+        where S: AsyncWrite
+    {
+        let mut buf = Vec::with_capacity(data.len() + event.len() + 16);
+        if let Some(id) = id {
+            assert_single_line("id", id);
+            write_field(&mut buf, "id", id);
+        }
+        if event.len() > 0 {
+            assert_single_line("event", event);
+            write_field(&mut buf, "event", event);
+        }
+        write_field(&mut buf, "data", data);
+        buf.push(b'\n');
+        self.send(&buf)
+    }
+    /// Send a comment and flush the buffer
+    ///
+    /// Comments are ignored by the client, they are useful as keep-alive
+    /// messages.
+    pub fn send_comment(&mut self, text: &str) -> Result<(), io::Error>
+        where S: AsyncWrite
+    {
+        let mut buf = Vec::with_capacity(text.len() + 4);
+        write_field(&mut buf, "", text);
+        self.send(&buf)
+    }
+    /// Returns bytes currently lying in the buffer
+    ///
+    /// These are bytes that could not be flushed because the client is
+    /// slow at reading events.
+    pub fn bytes_buffered(&mut self) -> usize {
+        self.encoder.bytes_buffered()
+    }
+    /// Finish the response
+    pub fn done(self) -> EncoderDone<S> {
+        self.encoder.done()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tk_bufstream::{MockData, IoBuf};
+
+    use enums::Version;
+    use server::encoder::{new, get_inner, ResponseConfig};
+    use super::SseEncoder;
+
+    fn sse<F>(fun: F) -> String
+        where F: FnOnce(&mut SseEncoder<MockData>)
+    {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        let mut sse = SseEncoder::new(new(tx, ResponseConfig {
+            is_head: false,
+            do_close: false,
+            version: Version::Http11,
+        })).unwrap();
+        fun(&mut sse);
+        get_inner(sse.done()).flush().unwrap();
+        String::from_utf8(mock.output(..)).unwrap()
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(sse(|sse| {
+            sse.send_event(Some("1"), "", "hello").unwrap();
+            sse.send_event(None, "update", "a\nb\r\nc").unwrap();
+            sse.send_comment("ping").unwrap();
+        }), "HTTP/1.1 200 OK\r\n\
+            Content-Type: text/event-stream\r\n\
+            Cache-Control: no-cache\r\n\
+            Transfer-Encoding: chunked\r\n\r\n\
+            13\r\nid: 1\ndata: hello\n\n\r\n\
+            27\r\nevent: update\ndata: a\ndata: b\ndata: c\n\n\r\n\
+            7\r\n: ping\n\r\n\
+            0\r\n\r\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn multiline_id() {
+        sse(|sse| sse.send_event(Some("1\n2"), "", "").unwrap());
+    }
+}
@@ -0,0 +1,273 @@
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{Future, Async, Poll};
+use futures::task::{self, Task};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Handle, Timeout};
+
+use {AnnotatedError, ConnectionId};
+
+
+/// Default limit of simultaneously served connections of `Server`
+const MAX_CONNECTIONS: usize = 10000;
+
+/// A delay before accepting again after an error (e.g. too many open files)
+const ACCEPT_ERROR_DELAY_MS: u64 = 100;
+
+
+/// Creates a future serving a connection accepted by `Server`
+///
+/// It's implemented for closures, so you usually don't need to implement
+/// it yourself:
+///
+/// ```rust,ignore
+/// server.serve(move |socket, addr, _id| {
+///     Proto::new(socket, &cfg, BufferedDispatcher::new(addr, &h1,
+///         || service), &h1)
+/// })
+/// ```
+///
+/// Errors of the future are logged by `Server` with the id of the
+/// connection (see `ConnectionId`). The same id is passed to the handler,
+/// pass it further (e.g. to `ServerHandshakeProto::with_id` or
+/// `Loop::server_with_id`) so that all log messages of the connection
+/// have the same id.
+pub trait Handler {
+    /// An error of the connection
+    type Error: Display;
+    /// A future that serves the connection
+    type Future: Future<Item=(), Error=Self::Error> + 'static;
+    /// Start serving the connection
+    fn handle(&mut self, conn: TcpStream, addr: SocketAddr, id: ConnectionId)
+        -> Self::Future;
+}
+
+/// A TCP listener that spawns a task per accepted connection
+///
+/// When `max_connections` connections are active, no more connections are
+/// accepted until some of them are closed, so the rest wait in the
+/// listen queue of the operating system.
+pub struct Server {
+    listener: TcpListener,
+    handle: Handle,
+    max_connections: usize,
+}
+
+/// A future returned by `Server::serve`
+///
+/// Never resolves, accept errors are logged and retried after a small
+/// delay.
+pub struct Serve<H> {
+    listener: TcpListener,
+    handle: Handle,
+    handler: H,
+    max_connections: usize,
+    counter: Rc<RefCell<Counter>>,
+    sleep: Option<Timeout>,
+}
+
+struct Counter {
+    active: usize,
+    task: Option<Task>,
+}
+
+/// The connection future which is counted as active until it's dropped
+struct Connection<F> {
+    id: ConnectionId,
+    future: F,
+    counter: Rc<RefCell<Counter>>,
+}
+
+impl<F, R> Handler for F
+    where F: FnMut(TcpStream, SocketAddr, ConnectionId) -> R,
+          R: Future<Item=()> + 'static,
+          R::Error: Display,
+{
+    type Error = R::Error;
+    type Future = R;
+    fn handle(&mut self, conn: TcpStream, addr: SocketAddr, id: ConnectionId)
+        -> R
+    {
+        self(conn, addr, id)
+    }
+}
+
+impl Server {
+    /// Bind a listening socket
+    pub fn bind(addr: &SocketAddr, handle: &Handle) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(addr, handle)?,
+            handle: handle.clone(),
+            max_connections: MAX_CONNECTIONS,
+        })
+    }
+    /// Set the maximum number of simultaneously served connections
+    ///
+    /// Default is 10000.
+    pub fn max_connections(mut self, value: usize) -> Server {
+        self.max_connections = value;
+        self
+    }
+    /// Returns the address the server is bound to
+    ///
+    /// Useful to find out the port when bound to port zero.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    /// Accept connections and spawn a future returned by `handler` for
+    /// each of them
+    pub fn serve<H: Handler>(self, handler: H) -> Serve<H> {
+        Serve {
+            listener: self.listener,
+            handle: self.handle,
+            handler: handler,
+            max_connections: self.max_connections,
+            counter: Rc::new(RefCell::new(Counter {
+                active: 0,
+                task: None,
+            })),
+            sleep: None,
+        }
+    }
+}
+
+impl<H: Handler> Serve<H> {
+    /// Returns the number of connections that are being served
+    pub fn active_connections(&self) -> usize {
+        self.counter.borrow().active
+    }
+}
+
+impl<H: Handler> Future for Serve<H> {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<(), ()> {
+        if let Some(mut sleep) = self.sleep.take() {
+            match sleep.poll() {
+                Ok(Async::NotReady) => {
+                    self.sleep = Some(sleep);
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(())) | Err(_) => {}
+            }
+        }
+        loop {
+            {
+                let mut counter = self.counter.borrow_mut();
+                if counter.active >= self.max_connections {
+                    counter.task = Some(task::current());
+                    return Ok(Async::NotReady);
+                }
+            }
+            match self.listener.accept() {
+                Ok((conn, addr)) => {
+                    let id = ConnectionId::next();
+                    debug!("[{}] Accepted connection from {}", id, addr);
+                    self.counter.borrow_mut().active += 1;
+                    self.handle.spawn(Connection {
+                        id: id,
+                        future: self.handler.handle(conn, addr, id),
+                        counter: self.counter.clone(),
+                    });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
+                    let delay = Duration::from_millis(ACCEPT_ERROR_DELAY_MS);
+                    let mut sleep = Timeout::new(delay, &self.handle)
+                        .expect("can always create a timeout");
+                    // register for a wakeup
+                    if let Ok(Async::NotReady) = sleep.poll() {
+                        self.sleep = Some(sleep);
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<F: Future<Item=()>> Future for Connection<F>
+    where F::Error: Display,
+{
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> Poll<(), ()> {
+        self.future.poll().map_err(|e| {
+            info!("Connection error: {}", AnnotatedError::new(self.id, e));
+        })
+    }
+}
+
+impl<F> Drop for Connection<F> {
+    fn drop(&mut self) {
+        let mut counter = self.counter.borrow_mut();
+        counter.active -= 1;
+        if let Some(task) = counter.task.take() {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::{Cell, RefCell};
+    use std::net::TcpStream as StdStream;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::Future;
+    use futures::future::{Either, ok};
+    use futures::sync::oneshot;
+    use tokio_core::reactor::Core;
+
+    use super::Server;
+
+    #[test]
+    fn max_connections() {
+        let mut lp = Core::new().unwrap();
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap(),
+                                  &lp.handle())
+            .unwrap()
+            .max_connections(1);
+        let addr = server.local_addr().unwrap();
+        let handled = Rc::new(Cell::new(0));
+        let (tx, rx) = oneshot::channel::<()>();
+        let mut rx = Some(rx);
+        let h = handled.clone();
+        let ids = Rc::new(RefCell::new(Vec::new()));
+        let i = ids.clone();
+        let serve = server.serve(move |conn, _, id| {
+            h.set(h.get() + 1);
+            i.borrow_mut().push(id);
+            // the first connection is served until `tx` is fired
+            let wait = match rx.take() {
+                Some(rx) => Either::A(rx.map_err(|_| "canceled")),
+                None => Either::B(ok(())),
+            };
+            wait.map(move |()| drop(conn))
+        });
+        lp.handle().spawn(serve);
+
+        let _c1 = StdStream::connect(&addr).unwrap();
+        let _c2 = StdStream::connect(&addr).unwrap();
+        for _ in 0..10 {
+            lp.turn(Some(Duration::from_millis(10)));
+        }
+        assert_eq!(handled.get(), 1);
+        tx.send(()).unwrap();
+        for _ in 0..10 {
+            lp.turn(Some(Duration::from_millis(10)));
+        }
+        assert_eq!(handled.get(), 2);
+        let ids = ids.borrow();
+        assert!(ids[0] != ids[1]);
+    }
+}
@@ -0,0 +1,70 @@
+//! Fixtures shared by the tests of server middleware
+use std::time::Duration;
+
+use futures::{Async, Future};
+use futures::future::{FutureResult, ok};
+use tk_bufstream::{MockData, ReadBuf, WriteBuf};
+use tokio_core::reactor::Core;
+
+use enums::Status;
+use server::{Codec, Dispatcher, Encoder, EncoderDone, Error};
+use server::config::without_date;
+use server::{Head, Proto, RecvMode};
+
+
+/// Dispatcher that replies to every request with the status and no body
+///
+/// `101 Switching Protocol` is replied with `Upgrade: websocket` and the
+/// connection is hijacked (and then dropped).
+#[derive(Clone, Copy)]
+pub struct Stub(pub Status);
+
+impl Dispatcher<MockData> for Stub {
+    type Codec = Self;
+    fn headers_received(&mut self, _: &Head) -> Result<Stub, Error> {
+        Ok(*self)
+    }
+}
+
+impl Codec<MockData> for Stub {
+    type ResponseFuture = FutureResult<EncoderDone<MockData>, Error>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::buffered_upfront(0)
+    }
+    fn data_received(&mut self, data: &[u8], _: bool)
+        -> Result<Async<usize>, Error>
+    {
+        Ok(Async::Ready(data.len()))
+    }
+    fn start_response(&mut self, mut e: Encoder<MockData>)
+        -> Self::ResponseFuture
+    {
+        e.status(self.0);
+        if self.0 == Status::SwitchingProtocol {
+            e.add_header("Upgrade", "websocket").unwrap();
+        } else {
+            e.add_length(0).unwrap();
+        }
+        e.done_headers().unwrap();
+        ok(e.done())
+    }
+    fn hijack(&mut self, _: WriteBuf<MockData>, _: ReadBuf<MockData>) {
+    }
+}
+
+/// Feeds the input to a connection served by `dispatcher` and returns
+/// everything written back
+pub fn serve<D>(dispatcher: D, input: &str) -> String
+    where D: Dispatcher<MockData> + 'static,
+{
+    let mut lp = Core::new().unwrap();
+    let mock = MockData::new();
+    let proto = Proto::new(mock.clone(), &without_date(), dispatcher,
+                           &lp.handle());
+    lp.handle().spawn(proto.map_err(|e| panic!("{}", e)));
+    mock.add_input(input);
+    for _ in 0..3 {
+        lp.turn(Some(Duration::from_millis(10)));
+    }
+    String::from_utf8(mock.output(..)).unwrap()
+}
@@ -18,7 +18,7 @@ pub struct WebsocketHandshake {
 }
 
 
-fn bytes_trim(mut x: &[u8]) -> &[u8] {
+pub fn bytes_trim(mut x: &[u8]) -> &[u8] {
     while x.len() > 0 && matches!(x[0], b'\r' | b'\n' | b' ' | b'\t') {
         x = &x[1..];
     }
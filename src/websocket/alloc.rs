@@ -11,6 +11,9 @@ pub enum Packet {
     /// Pong packet (with data)
     Pong(Vec<u8>),
     /// Text (utf-8) messsage
+    ///
+    /// Codecs validate utf-8 (for fragmented messages, after reassembly)
+    /// and fail with `InvalidUtf8` error if payload is not valid.
     Text(String),
     /// Binary message
     Binary(Vec<u8>),
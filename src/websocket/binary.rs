@@ -0,0 +1,142 @@
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use tk_bufstream::{Encode, Decode, WriteFramed, ReadFramed};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use websocket::{Packet, Error, ClientCodec};
+use websocket::error::ErrorEnum;
+
+
+/// A sink that sends every `Vec<u8>` as a binary message
+///
+/// This is a wrapper around the output returned by `HandshakeProto`, to
+/// use a websocket as a raw byte pipe:
+///
+/// ```rust,ignore
+/// HandshakeProto::new(sock, SimpleAuthorizer::new("example.com", "/"))
+/// .map(|(out, inp, ())| (BinarySink::new(out), BinaryStream::new(inp)))
+/// ```
+pub struct BinarySink<S, C=ClientCodec> {
+    output: WriteFramed<S, C>,
+}
+
+/// A stream of payloads of binary messages
+///
+/// Text message fails the stream with `UnexpectedText` error. Pings and
+/// pongs are skipped (they are not replied, use `PingPong` for that), and
+/// close message ends the stream.
+pub struct BinaryStream<S, C=ClientCodec> {
+    input: ReadFramed<S, C>,
+}
+
+impl<S, C> BinarySink<S, C> {
+    /// Wrap the output half of the connection
+    pub fn new(output: WriteFramed<S, C>) -> BinarySink<S, C> {
+        BinarySink { output: output }
+    }
+    /// Returns the original output
+    pub fn into_inner(self) -> WriteFramed<S, C> {
+        self.output
+    }
+}
+
+impl<S, C> BinaryStream<S, C> {
+    /// Wrap the input half of the connection
+    pub fn new(input: ReadFramed<S, C>) -> BinaryStream<S, C> {
+        BinaryStream { input: input }
+    }
+    /// Returns the original input
+    pub fn into_inner(self) -> ReadFramed<S, C> {
+        self.input
+    }
+}
+
+impl<S, C> Sink for BinarySink<S, C>
+    where C: Encode<Item=Packet>,
+          S: AsyncWrite,
+{
+    type SinkItem = Vec<u8>;
+    type SinkError = Error;
+    fn start_send(&mut self, item: Vec<u8>) -> StartSend<Vec<u8>, Error> {
+        match self.output.start_send(Packet::Binary(item))
+            .map_err(ErrorEnum::Io)?
+        {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(Packet::Binary(data)) => {
+                Ok(AsyncSink::NotReady(data))
+            }
+            AsyncSink::NotReady(_) => unreachable!(),
+        }
+    }
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        Ok(self.output.poll_complete().map_err(ErrorEnum::Io)?)
+    }
+}
+
+impl<S, C> Stream for BinaryStream<S, C>
+    where C: Decode<Item=Packet>,
+          S: AsyncRead,
+{
+    type Item = Vec<u8>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        loop {
+            match self.input.poll().map_err(ErrorEnum::Io)? {
+                Async::Ready(Some(Packet::Binary(data))) => {
+                    return Ok(Async::Ready(Some(data)));
+                }
+                Async::Ready(Some(Packet::Text(_))) => {
+                    return Err(ErrorEnum::UnexpectedText.into());
+                }
+                Async::Ready(Some(Packet::Ping(_))) |
+                Async::Ready(Some(Packet::Pong(_))) => continue,
+                Async::Ready(Some(Packet::Close(..))) |
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Sink, Stream};
+    use tk_bufstream::{IoBuf, MockData};
+
+    use websocket::{ServerCodec, Error};
+    use websocket::error::ErrorEnum;
+    use super::{BinarySink, BinaryStream};
+
+    #[test]
+    fn sink() {
+        let mock = MockData::new();
+        let (tx, _) = IoBuf::new(mock.clone()).split();
+        BinarySink::new(tx.framed(ServerCodec))
+            .send(b"hello".to_vec()).wait().unwrap();
+        assert_eq!(mock.output(..), &b"\x82\x05hello"[..]);
+    }
+
+    #[test]
+    fn stream() {
+        let mock = MockData::new();
+        let (_, rx) = IoBuf::new(mock.clone()).split();
+        // masked binary, ping, binary, close
+        mock.add_input(&b"\x82\x82\x00\x00\x00\x00hi\
+                          \x89\x80\x00\x00\x00\x00\
+                          \x82\x81\x00\x00\x00\x00!\
+                          \x88\x80\x00\x00\x00\x00"[..]);
+        let items = BinaryStream::new(rx.framed(ServerCodec))
+            .collect().wait().unwrap();
+        assert_eq!(items, vec![b"hi".to_vec(), b"!".to_vec()]);
+    }
+
+    #[test]
+    fn text() {
+        let mock = MockData::new();
+        let (_, rx) = IoBuf::new(mock.clone()).split();
+        mock.add_input(&b"\x81\x82\x00\x00\x00\x00hi"[..]);
+        let err = BinaryStream::new(rx.framed(ServerCodec))
+            .collect().wait().err().unwrap();
+        assert_eq!(err.to_string(),
+                   Error::from(ErrorEnum::UnexpectedText).to_string());
+    }
+}
@@ -0,0 +1,283 @@
+//! Close frame and a closing handshake state
+//!
+//! This module also contains constants for the close status codes as
+//! defined in RFC 6455 (section 7.4.1) and the IANA registry.
+use std::str::from_utf8;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use websocket::{Packet, Error};
+use websocket::error::ErrorEnum;
+
+
+/// Normal closure, the purpose of the connection has been fulfilled
+pub const NORMAL_CLOSURE: u16 = 1000;
+/// An endpoint is going away (e.g. server shutdown or browser navigation)
+pub const GOING_AWAY: u16 = 1001;
+/// Connection is terminated due to a protocol error
+pub const PROTOCOL_ERROR: u16 = 1002;
+/// An endpoint received a type of data it can't accept
+pub const UNSUPPORTED_DATA: u16 = 1003;
+/// Reserved, meaning might be defined in future
+pub const RESERVED: u16 = 1004;
+/// No status code was present (must not be sent in a close frame)
+pub const NO_STATUS_RECEIVED: u16 = 1005;
+/// Connection was closed abnormally (must not be sent in a close frame)
+pub const ABNORMAL_CLOSURE: u16 = 1006;
+/// Received data was inconsistent with the type of the message
+pub const INVALID_PAYLOAD: u16 = 1007;
+/// A message violates endpoint's policy
+pub const POLICY_VIOLATION: u16 = 1008;
+/// A message is too big to process
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+/// Client expected server to negotiate an extension
+pub const MANDATORY_EXTENSION: u16 = 1010;
+/// Server encountered an unexpected condition
+pub const INTERNAL_ERROR: u16 = 1011;
+/// Server is restarting
+pub const SERVICE_RESTART: u16 = 1012;
+/// Server is overloaded, client should try again later
+pub const TRY_AGAIN_LATER: u16 = 1013;
+/// Server acting as a gateway received an invalid response
+pub const BAD_GATEWAY: u16 = 1014;
+/// TLS handshake failure (must not be sent in a close frame)
+pub const TLS_HANDSHAKE: u16 = 1015;
+
+
+/// A close frame with status code and a reason
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// Status code, see constants in `websocket::close`
+    pub code: u16,
+    /// Human-readable reason, must be shorter than 123 bytes
+    pub reason: String,
+}
+
+/// Parsed payload of the close frame
+///
+/// This is an alias of `CloseFrame`: the payload is all the close frame
+/// carries, so `ClosePayload::parse` and `CloseFrame::parse` are the same
+/// function.
+pub type ClosePayload = CloseFrame;
+
+/// Close status code as an enum
+///
+/// Codes that are not registered (e.g. application-specific codes in the
+/// 4000-4999 range) are represented as `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CloseCode {
+    /// 1000, see `NORMAL_CLOSURE`
+    Normal,
+    /// 1001, see `GOING_AWAY`
+    GoingAway,
+    /// 1002, see `PROTOCOL_ERROR`
+    ProtocolError,
+    /// 1003, see `UNSUPPORTED_DATA`
+    UnsupportedData,
+    /// 1004, see `RESERVED`
+    Reserved,
+    /// 1005, see `NO_STATUS_RECEIVED`
+    NoStatusReceived,
+    /// 1006, see `ABNORMAL_CLOSURE`
+    Abnormal,
+    /// 1007, see `INVALID_PAYLOAD`
+    InvalidPayload,
+    /// 1008, see `POLICY_VIOLATION`
+    PolicyViolation,
+    /// 1009, see `MESSAGE_TOO_BIG`
+    MessageTooBig,
+    /// 1010, see `MANDATORY_EXTENSION`
+    MandatoryExtension,
+    /// 1011, see `INTERNAL_ERROR`
+    InternalError,
+    /// 1012, see `SERVICE_RESTART`
+    ServiceRestart,
+    /// 1013, see `TRY_AGAIN_LATER`
+    TryAgainLater,
+    /// 1014, see `BAD_GATEWAY`
+    BadGateway,
+    /// 1015, see `TLS_HANDSHAKE`
+    TlsHandshake,
+    /// Any other code
+    Other(u16),
+}
+
+/// State of the closing handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClosingState {
+    /// No close frame has been sent or received
+    Open,
+    /// We have sent a close frame and wait for the peer to reply
+    Initiated,
+    /// Close frames are exchanged, no more frames may be sent
+    Closed {
+        /// True if we have sent close frame first
+        initiated: bool,
+    },
+}
+
+impl CloseFrame {
+    /// Create a close frame
+    ///
+    /// # Panics
+    ///
+    /// When reason is longer than 123 bytes (limit of the control frame is
+    /// 125 bytes including the status code)
+    pub fn new<R: Into<String>>(code: u16, reason: R) -> CloseFrame {
+        let reason = reason.into();
+        assert!(reason.len() <= 123, "close reason is too long");
+        CloseFrame {
+            code: code,
+            reason: reason,
+        }
+    }
+    /// Parse payload of the close frame
+    ///
+    /// Empty payload means there is no status code, so `NO_STATUS_RECEIVED`
+    /// is returned. Payload of a single byte or a reason that is not valid
+    /// utf-8 is rejected with `InvalidClosePayload` error, and a status code
+    /// that must not be sent (e.g. `ABNORMAL_CLOSURE` or codes below 1000)
+    /// with `InvalidCloseCode` error.
+    pub fn parse(data: &[u8]) -> Result<CloseFrame, Error> {
+        let (code, reason) = parse_payload(data)?;
+        Ok(CloseFrame::new(code, reason))
+    }
+    /// Encode payload of the close frame (status code and the reason)
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0u8; 2];
+        BigEndian::write_u16(&mut data, self.code);
+        data.extend(self.reason.as_bytes());
+        return data;
+    }
+    /// Returns status code as an enum
+    pub fn close_code(&self) -> CloseCode {
+        CloseCode::from(self.code)
+    }
+}
+
+/// Returns `true` if the code may be received in a close frame
+///
+/// These are registered codes except the ones that must not be sent over
+/// the wire, and codes 3000-4999 for libraries and applications.
+fn is_valid_code(code: u16) -> bool {
+    match code {
+        1000..=1003 | 1007..=1014 | 3000..=4999 => true,
+        _ => false,
+    }
+}
+
+/// Parse payload of the close frame without copying the reason
+///
+/// This is the same as `CloseFrame::parse` and is used by frame decoders.
+pub(crate) fn parse_payload(data: &[u8]) -> Result<(u16, &str), ErrorEnum> {
+    if data.len() == 0 {
+        return Ok((NO_STATUS_RECEIVED, ""));
+    }
+    if data.len() < 2 || data.len() > 125 {
+        return Err(ErrorEnum::InvalidClosePayload);
+    }
+    let code = BigEndian::read_u16(&data[..2]);
+    if !is_valid_code(code) {
+        return Err(ErrorEnum::InvalidCloseCode(code));
+    }
+    let reason = from_utf8(&data[2..])
+        .map_err(|_| ErrorEnum::InvalidClosePayload)?;
+    Ok((code, reason))
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> CloseCode {
+        use self::CloseCode::*;
+        match code {
+            NORMAL_CLOSURE => Normal,
+            GOING_AWAY => GoingAway,
+            PROTOCOL_ERROR => ProtocolError,
+            UNSUPPORTED_DATA => UnsupportedData,
+            RESERVED => Reserved,
+            NO_STATUS_RECEIVED => NoStatusReceived,
+            ABNORMAL_CLOSURE => Abnormal,
+            INVALID_PAYLOAD => InvalidPayload,
+            POLICY_VIOLATION => PolicyViolation,
+            MESSAGE_TOO_BIG => MessageTooBig,
+            MANDATORY_EXTENSION => MandatoryExtension,
+            INTERNAL_ERROR => InternalError,
+            SERVICE_RESTART => ServiceRestart,
+            TRY_AGAIN_LATER => TryAgainLater,
+            BAD_GATEWAY => BadGateway,
+            TLS_HANDSHAKE => TlsHandshake,
+            code => Other(code),
+        }
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> u16 {
+        use self::CloseCode::*;
+        match code {
+            Normal => NORMAL_CLOSURE,
+            GoingAway => GOING_AWAY,
+            ProtocolError => PROTOCOL_ERROR,
+            UnsupportedData => UNSUPPORTED_DATA,
+            Reserved => RESERVED,
+            NoStatusReceived => NO_STATUS_RECEIVED,
+            Abnormal => ABNORMAL_CLOSURE,
+            InvalidPayload => INVALID_PAYLOAD,
+            PolicyViolation => POLICY_VIOLATION,
+            MessageTooBig => MESSAGE_TOO_BIG,
+            MandatoryExtension => MANDATORY_EXTENSION,
+            InternalError => INTERNAL_ERROR,
+            ServiceRestart => SERVICE_RESTART,
+            TryAgainLater => TRY_AGAIN_LATER,
+            BadGateway => BAD_GATEWAY,
+            TlsHandshake => TLS_HANDSHAKE,
+            Other(code) => code,
+        }
+    }
+}
+
+impl From<CloseFrame> for Packet {
+    fn from(frame: CloseFrame) -> Packet {
+        Packet::Close(frame.code, frame.reason)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CloseFrame, CloseCode, NO_STATUS_RECEIVED};
+    use super::{ClosePayload, ABNORMAL_CLOSURE, TLS_HANDSHAKE};
+
+    #[test]
+    fn codes() {
+        assert_eq!(CloseCode::from(1001), CloseCode::GoingAway);
+        assert_eq!(CloseCode::from(4000), CloseCode::Other(4000));
+        for code in 1000..1016 {
+            assert_eq!(u16::from(CloseCode::from(code)), code);
+            assert!(CloseCode::from(code) != CloseCode::Other(code));
+        }
+    }
+
+    #[test]
+    fn payload() {
+        let frame = CloseFrame::parse(b"\x03\xe9bye").unwrap();
+        assert_eq!(frame.close_code(), CloseCode::GoingAway);
+        assert_eq!(frame.reason, "bye");
+        assert_eq!(frame.encode(), b"\x03\xe9bye");
+        assert_eq!(CloseFrame::parse(b"").unwrap().code, NO_STATUS_RECEIVED);
+        assert!(CloseFrame::parse(b"\x03").is_err());
+        assert!(CloseFrame::parse(b"\x03\xe8\xff").is_err());
+    }
+
+    #[test]
+    fn reserved_codes() {
+        let code = |c: u16| ClosePayload::parse(&[(c >> 8) as u8, c as u8]);
+        assert_eq!(code(4000).unwrap().code, 4000);
+        assert!(code(NO_STATUS_RECEIVED).is_err());
+        assert!(code(ABNORMAL_CLOSURE).is_err());
+        assert!(code(TLS_HANDSHAKE).is_err());
+        assert!(code(999).is_err());
+        assert!(code(2000).is_err());
+        assert!(code(5000).is_err());
+        assert_eq!(code(1004).unwrap_err().to_string(),
+                   "invalid close status code: 1004");
+    }
+}
@@ -2,10 +2,13 @@ use std::io;
 use tk_bufstream::{Buf, Encode, Decode};
 
 use websocket::{Packet, Frame};
-use websocket::error::Error;
+use websocket::error::{Error, ErrorEnum};
+use websocket::zero_copy::{parse_raw, parse_header, FrameHeader};
 
 
 const MAX_PACKET_SIZE: usize = 10 << 20;
+/// Default limit of the frame received by `ClientCodec`
+const MAX_FRAME_SIZE: usize = 64 << 20;
 
 /// Websocket codec for use with tk-bufstream in `Codec::hijack()`
 ///
@@ -16,7 +19,164 @@ pub struct ServerCodec;
 /// Websocket codec for use with tk-bufstream
 ///
 /// This codec is used out of the box in `HandshakeProto`
-pub struct ClientCodec;
+///
+/// Frames larger than `max_frame_size` (64 MiB by default) are rejected
+/// with `FrameTooLarge` error as soon as frame header is received, so a
+/// malicious server can't make us buffer arbitrary amount of data.
+///
+/// By default fragmented messages are not supported, use
+/// `with_fragmentation` to enable them.
+///
+/// Note: this used to be a unit struct. `ClientCodec` used as a value
+/// (e.g. `tx.framed(ClientCodec)`) still compiles and means a codec with
+/// default settings, but the struct has private fields now, so it can't
+/// be matched on or constructed with braces.
+#[derive(Debug, Clone)]
+pub struct ClientCodec {
+    max_frame_size: usize,
+    fragmentation: FragmentationMode,
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+/// Defines how `ClientCodec` handles fragmented messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentationMode {
+    /// Every frame is decoded on its own, so fragmented frame is rejected
+    /// with `Fragmented` error (this is the default)
+    RawFrames,
+    /// Fragments are buffered until the final one and yielded as a single
+    /// `Text` or `Binary` packet
+    ///
+    /// Control frames (pings, pongs, close) interleaved with the fragments
+    /// are yielded immediately.
+    Reassemble {
+        /// Maximum size of the whole message, `TooLong` error is returned
+        /// if fragments exceed the limit
+        max_message_size: usize,
+    },
+}
+
+/// A `ClientCodec` with default settings, same as `ClientCodec::new()`
+///
+/// This keeps code written when `ClientCodec` was a unit struct compiling.
+#[allow(non_upper_case_globals)]
+pub const ClientCodec: ClientCodec = ClientCodec {
+    max_frame_size: MAX_FRAME_SIZE,
+    fragmentation: FragmentationMode::RawFrames,
+    partial: None,
+};
+
+impl ClientCodec {
+    /// Create a codec with default frame size limit (64 MiB)
+    pub fn new() -> ClientCodec {
+        ClientCodec {
+            max_frame_size: MAX_FRAME_SIZE,
+            fragmentation: FragmentationMode::RawFrames,
+            partial: None,
+        }
+    }
+    /// Set maximum size of the incoming frame (payload)
+    pub fn with_max_frame_size(mut self, limit: usize) -> ClientCodec {
+        self.max_frame_size = limit;
+        self
+    }
+    /// Set the way fragmented messages are handled
+    pub fn with_fragmentation(mut self, mode: FragmentationMode)
+        -> ClientCodec
+    {
+        self.fragmentation = mode;
+        self
+    }
+    /// Parse a header of the frame received from server
+    ///
+    /// Returns the header and its length in bytes (payload starts right
+    /// after it) or `None` if the buffer doesn't contain full header yet.
+    /// This is what `decode` uses internally, so custom codecs can use
+    /// it to decide how to decode the payload (e.g. by looking at the
+    /// reserved bits), before the whole frame is received.
+    pub fn decode_header(buf: &[u8])
+        -> Result<Option<(FrameHeader, usize)>, Error>
+    {
+        Ok(parse_header(buf, false)?)
+    }
+    fn reassemble(&mut self, buf: &mut Buf, max_message_size: usize)
+        -> Result<Option<Packet>, ErrorEnum>
+    {
+        loop {
+            let (packet, nbytes) = match parse_raw(buf,
+                self.max_frame_size, false)?
+            {
+                Some((raw, nbytes)) => {
+                    if raw.rsv != 0 {
+                        // no extensions are negotiated by this codec
+                        return Err(ErrorEnum::InvalidRsvBits(raw.rsv));
+                    }
+                    let packet = if raw.opcode & 0x8 != 0 {
+                        // control frames must not be fragmented
+                        if !raw.fin {
+                            return Err(ErrorEnum::Fragmented);
+                        }
+                        Some(Frame::from_raw(raw.opcode, raw.data)?.into())
+                    } else if raw.opcode == 0 {
+                        match self.partial {
+                            Some((_, ref mut data)) => {
+                                if data.len() + raw.data.len()
+                                    > max_message_size
+                                {
+                                    return Err(ErrorEnum::TooLong);
+                                }
+                                data.extend(raw.data);
+                            }
+                            // continuation of nothing
+                            None => return Err(ErrorEnum::Fragmented),
+                        }
+                        if raw.fin {
+                            let (opcode, data) = self.partial.take()
+                                .expect("message is started");
+                            Some(message(opcode, data)?)
+                        } else {
+                            None
+                        }
+                    } else if self.partial.is_some() {
+                        // new message before previous one is finished
+                        return Err(ErrorEnum::Fragmented);
+                    } else if raw.fin {
+                        Some(Frame::from_raw(raw.opcode, raw.data)?.into())
+                    } else {
+                        if raw.data.len() > max_message_size {
+                            return Err(ErrorEnum::TooLong);
+                        }
+                        self.partial = Some((raw.opcode, raw.data.to_vec()));
+                        None
+                    };
+                    (packet, nbytes)
+                }
+                None => return Ok(None),
+            };
+            buf.consume(nbytes);
+            if packet.is_some() {
+                return Ok(packet);
+            }
+        }
+    }
+}
+
+/// Makes a packet out of reassembled message
+pub(crate) fn message(opcode: u8, data: Vec<u8>) -> Result<Packet, ErrorEnum> {
+    match opcode {
+        0x1 => String::from_utf8(data)
+            .map(Packet::Text)
+            .map_err(|e| e.utf8_error().into()),
+        0x2 => Ok(Packet::Binary(data)),
+        x => Err(ErrorEnum::InvalidOpcode(x)),
+    }
+}
+
+impl Default for ClientCodec {
+    fn default() -> ClientCodec {
+        ClientCodec::new()
+    }
+}
 
 
 impl Encode for ServerCodec {
@@ -55,8 +215,16 @@ impl Encode for ClientCodec {
 impl Decode for ClientCodec {
     type Item = Packet;
     fn decode(&mut self, buf: &mut Buf) -> Result<Option<Packet>, io::Error> {
-        let parse_result = Frame::parse(buf, MAX_PACKET_SIZE, false)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+        if let FragmentationMode::Reassemble { max_message_size }
+            = self.fragmentation
+        {
+            return self.reassemble(buf, max_message_size)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other,
+                                            Error::from(e)));
+        }
+        let parse_result = Frame::parse(buf, self.max_frame_size, false)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other,
+                                        Error::from(e)))?
             .map(|(p, b)| (p.into(), b));
         if let Some((p, b)) = parse_result {
             buf.consume(b);
@@ -66,3 +234,116 @@ impl Decode for ClientCodec {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use tk_bufstream::{Buf, Decode};
+
+    use websocket::{Packet, Error};
+    use super::{ClientCodec, ServerCodec, FragmentationMode};
+
+    fn reassemble(limit: usize) -> ClientCodec {
+        ClientCodec::new().with_fragmentation(FragmentationMode::Reassemble {
+            max_message_size: limit,
+        })
+    }
+
+    #[test]
+    fn frame_size_limit() {
+        let mut buf = Buf::new();
+        buf.extend(b"\x82\x7e\x01\x00");  // header of 256-byte frame
+        let mut codec = ClientCodec::new().with_max_frame_size(255);
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert!(err.get_ref().unwrap().downcast_ref::<Error>().unwrap()
+                .is_frame_too_large());
+        assert_eq!(err.to_string(),
+                   "frame of 256 bytes is larger than the limit of 255");
+        let mut codec = ClientCodec::new().with_max_frame_size(256);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn default_frame_size_limit() {
+        let mut buf = Buf::new();
+        // header of 64 MiB + 1 byte frame
+        buf.extend(b"\x82\x7f\0\0\0\0\x04\0\0\x01");
+        assert!(ClientCodec::new().decode(&mut buf).is_err());
+        assert!(ClientCodec.decode(&mut buf).is_err());
+        let mut buf = Buf::new();
+        buf.extend(b"\x82\x7f\0\0\0\0\x04\0\0\0");
+        assert!(ClientCodec::new().decode(&mut buf).unwrap().is_none());
+        assert!(ClientCodec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn header() {
+        assert!(ClientCodec::decode_header(b"\x82").unwrap().is_none());
+        assert!(ClientCodec::decode_header(b"\x82\x7e\x01")
+                .unwrap().is_none());
+        let (header, len) = ClientCodec::decode_header(b"\x41\x7e\x01\x00")
+            .unwrap().unwrap();
+        assert_eq!(len, 4);
+        assert!(!header.fin());
+        assert!(header.rsv1() && !header.rsv2() && !header.rsv3());
+        assert_eq!(header.opcode(), 0x1);
+        assert_eq!(header.mask(), None);
+        assert_eq!(header.payload_len(), 256);
+        // server must not mask frames
+        assert!(ClientCodec::decode_header(b"\x82\x81abcd").is_err());
+    }
+
+    #[test]
+    fn fragments() {
+        let mut buf = Buf::new();
+        // "hel" + ping + "lo" + single-frame binary
+        buf.extend(b"\x01\x03hel\x89\x00\x80\x02lo\x82\x01x");
+        assert!(ClientCodec::new().decode(&mut buf).is_err());
+        let mut codec = reassemble(1024);
+        assert_matches!(codec.decode(&mut buf).unwrap(),
+                        Some(Packet::Ping(ref x)) if x.len() == 0);
+        assert_matches!(codec.decode(&mut buf).unwrap(),
+                        Some(Packet::Text(ref x)) if x == "hello");
+        assert_matches!(codec.decode(&mut buf).unwrap(),
+                        Some(Packet::Binary(ref x)) if x == b"x");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn fragment_errors() {
+        let mut buf = Buf::new();
+        buf.extend(b"\x02\x03abc\x80\x02de");
+        assert!(reassemble(4).decode(&mut buf).is_err());
+        let mut buf = Buf::new();
+        buf.extend(b"\x80\x02de");
+        assert!(reassemble(1024).decode(&mut buf).is_err());
+        let mut buf = Buf::new();
+        buf.extend(b"\x02\x01a\x82\x01b");
+        assert!(reassemble(1024).decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn rsv_bits() {
+        let mut buf = Buf::new();
+        buf.extend(b"\xC1\x02hi");
+        assert!(ClientCodec::new().decode(&mut buf).is_err());
+        assert!(reassemble(1024).decode(&mut buf).is_err());
+        let mut buf = Buf::new();
+        buf.extend(b"\xC1\x82\0\0\0\0hi");
+        assert!(ServerCodec.decode(&mut buf).is_err());
+        let mut buf = Buf::new();
+        buf.extend(b"\x81\x82\0\0\0\0hi");
+        assert!(ServerCodec.decode(&mut buf).unwrap().is_some());
+    }
+
+    #[test]
+    fn text_utf8() {
+        let mut buf = Buf::new();
+        buf.extend(b"\x81\x02\xc3\x28");
+        assert!(ClientCodec::new().decode(&mut buf).is_err());
+        // multi-byte character split between fragments is fine
+        let mut buf = Buf::new();
+        buf.extend(b"\x01\x01\xc3\x80\x01\xa9");
+        assert_matches!(reassemble(1024).decode(&mut buf).unwrap(),
+                        Some(Packet::Text(ref x)) if x == "\u{e9}");
+    }
+}
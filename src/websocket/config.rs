@@ -1,7 +1,7 @@
 use std::time::Duration;
 use std::sync::Arc;
 
-use websocket::{Config};
+use websocket::{Config, HandshakeConfig, FragmentationMode};
 
 impl Config {
     /// Create a config with defaults
@@ -103,3 +103,67 @@ impl Config {
         Arc::new(self.clone())
     }
 }
+
+impl HandshakeConfig {
+    /// Create a config with defaults
+    pub fn new() -> HandshakeConfig {
+        HandshakeConfig {
+            max_headers: 1024,
+            max_header_size: 64 << 10,
+            max_response_size: 1 << 20,
+            max_frame_size: 64 << 20,
+            fragmentation: FragmentationMode::RawFrames,
+        }
+    }
+
+    /// Maximum number of headers in the response
+    ///
+    /// Default is 1024. Response with more headers is rejected with
+    /// `TooManyHeaders` parse error.
+    pub fn max_headers(&mut self, num: usize) -> &mut Self {
+        self.max_headers = num;
+        self
+    }
+
+    /// Maximum size of a single header (name and value)
+    ///
+    /// Default is 64 KiB.
+    pub fn max_header_size(&mut self, size: usize) -> &mut Self {
+        self.max_header_size = size;
+        self
+    }
+
+    /// Maximum size of the response headers
+    ///
+    /// Default is 1 MiB. If server sends more than this without finishing
+    /// headers, handshake fails with `ResponseTooLarge` error instead of
+    /// buffering data indefinitely.
+    pub fn max_response_size(&mut self, size: usize) -> &mut Self {
+        self.max_response_size = size;
+        self
+    }
+
+    /// Maximum size of the incoming frame after handshake is done
+    ///
+    /// Default is 64 MiB. This limit is passed to the `ClientCodec`, see
+    /// `ClientCodec::with_max_frame_size`.
+    pub fn max_frame_size(&mut self, size: usize) -> &mut Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// The way fragmented messages are handled after handshake is done
+    ///
+    /// Default is `RawFrames`. See `ClientCodec::with_fragmentation`.
+    pub fn fragmentation(&mut self, mode: FragmentationMode) -> &mut Self {
+        self.fragmentation = mode;
+        self
+    }
+
+    /// Create a Arc'd config clone to pass to the constructor
+    ///
+    /// This is just a convenience method.
+    pub fn done(&mut self) -> Arc<HandshakeConfig> {
+        Arc::new(self.clone())
+    }
+}
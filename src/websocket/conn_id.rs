@@ -0,0 +1,12 @@
+use ConnectionId;
+
+
+/// Returns a new unique connection id
+///
+/// Ids are only used in log messages, so that messages of concurrent
+/// connections can be correlated. This is the same counter as
+/// `ConnectionId::next`, so ids are unique across HTTP and websocket
+/// connections.
+pub fn next() -> ConnectionId {
+    ConnectionId::next()
+}
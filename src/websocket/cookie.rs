@@ -0,0 +1,160 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+
+use ConnectionId;
+use websocket::client::HeaderValues;
+
+
+/// A cookie set by the server in the `Set-Cookie` header
+///
+/// Only attributes that are useful for the client are parsed. Parsing
+/// follows the algorithm of RFC 6265 (section 5.2), so invalid attributes
+/// are ignored rather than rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetCookie<'a> {
+    /// Name of the cookie
+    pub name: &'a str,
+    /// Value of the cookie (as is, including quotes if there are any)
+    pub value: &'a str,
+    /// `Domain` attribute, without leading dot
+    pub domain: Option<&'a str>,
+    /// `Path` attribute
+    pub path: Option<&'a str>,
+    /// Whether `Secure` attribute is set
+    pub secure: bool,
+    /// Whether `HttpOnly` attribute is set
+    pub http_only: bool,
+    /// `Max-Age` attribute in seconds (zero or negative means expired)
+    pub max_age: Option<i64>,
+}
+
+/// Iterator over cookies of the response
+///
+/// This iterator is created by `Head::cookies`. `Set-Cookie` headers that
+/// can't be parsed are skipped.
+pub struct Cookies<'a> {
+    headers: HeaderValues<'a>,
+    id: ConnectionId,
+}
+
+/// Parse a value of the `Set-Cookie` header
+pub fn parse_set_cookie(value: &[u8]) -> Option<SetCookie> {
+    let value = match from_utf8(value) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    let mut parts = value.split(';');
+    let pair = parts.next().unwrap_or("");
+    let eq = match pair.find('=') {
+        Some(eq) => eq,
+        None => return None,
+    };
+    let name = pair[..eq].trim();
+    if name.len() == 0 {
+        return None;
+    }
+    let mut cookie = SetCookie {
+        name: name,
+        value: pair[eq+1..].trim(),
+        domain: None,
+        path: None,
+        secure: false,
+        http_only: false,
+        max_age: None,
+    };
+    for attr in parts {
+        let mut pair = attr.splitn(2, '=');
+        let name = pair.next().unwrap_or("").trim();
+        let value = pair.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("Domain") {
+            let domain = if value.starts_with('.')
+                { &value[1..] } else { value };
+            if domain.len() > 0 {
+                cookie.domain = Some(domain);
+            }
+        } else if name.eq_ignore_ascii_case("Path") {
+            if value.starts_with('/') {
+                cookie.path = Some(value);
+            }
+        } else if name.eq_ignore_ascii_case("Secure") {
+            cookie.secure = true;
+        } else if name.eq_ignore_ascii_case("HttpOnly") {
+            cookie.http_only = true;
+        } else if name.eq_ignore_ascii_case("Max-Age") {
+            let digits = if value.starts_with('-')
+                { &value[1..] } else { value };
+            if digits.len() > 0 && digits.bytes().all(|x| x.is_ascii_digit())
+            {
+                // values that don't fit are clamped as the RFC suggests
+                cookie.max_age = Some(value.parse().unwrap_or(
+                    if value.starts_with('-') { i64::min_value() }
+                    else { i64::max_value() }));
+            }
+        }
+    }
+    Some(cookie)
+}
+
+pub fn cookies<'a>(headers: HeaderValues<'a>, id: ConnectionId)
+    -> Cookies<'a>
+{
+    Cookies { headers: headers, id: id }
+}
+
+impl<'a> Iterator for Cookies<'a> {
+    type Item = SetCookie<'a>;
+    fn next(&mut self) -> Option<SetCookie<'a>> {
+        while let Some(value) = self.headers.next() {
+            if let Some(cookie) = parse_set_cookie(value) {
+                return Some(cookie);
+            }
+            warn!("[{}] Invalid Set-Cookie header skipped: {:?}",
+                self.id, String::from_utf8_lossy(value));
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_set_cookie;
+
+    #[test]
+    fn simple() {
+        let c = parse_set_cookie(b"sid=abc123").unwrap();
+        assert_eq!(c.name, "sid");
+        assert_eq!(c.value, "abc123");
+        assert_eq!(c.domain, None);
+        assert_eq!(c.path, None);
+        assert!(!c.secure && !c.http_only);
+        assert_eq!(c.max_age, None);
+    }
+
+    #[test]
+    fn attributes() {
+        let c = parse_set_cookie(b"sid = \"a b\"; Domain=.example.com; \
+            path=/chat; Secure; HTTPONLY; Max-Age=3600; \
+            Expires=Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(c.name, "sid");
+        assert_eq!(c.value, "\"a b\"");
+        assert_eq!(c.domain, Some("example.com"));
+        assert_eq!(c.path, Some("/chat"));
+        assert!(c.secure && c.http_only);
+        assert_eq!(c.max_age, Some(3600));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_set_cookie(b"novalue").is_none());
+        assert!(parse_set_cookie(b"=value").is_none());
+        assert!(parse_set_cookie(b"a=\xff").is_none());
+        let c = parse_set_cookie(b"a=b; Path=chat; Max-Age=1x; Domain=")
+            .unwrap();
+        assert_eq!(c.path, None);
+        assert_eq!(c.max_age, None);
+        assert_eq!(c.domain, None);
+        assert_eq!(parse_set_cookie(b"a=b; Max-Age=-1").unwrap().max_age,
+                   Some(-1));
+    }
+}
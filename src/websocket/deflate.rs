@@ -0,0 +1,792 @@
+//! Implementation of `permessage-deflate` extension (RFC 7692)
+//!
+//! Only the client side is implemented for now.
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::io;
+use std::str::from_utf8;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use flate2::{Compress, Decompress, Compression, FlushCompress};
+use flate2::{FlushDecompress, Status as FlateStatus};
+use futures::{Future, Async, AsyncSink, Sink, StartSend, Poll};
+use tk_bufstream::{Buf, Encode, Decode, ReadFramed, WriteFramed};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use ConnectionId;
+use websocket::{Packet, Frame, Error, HandshakeConfig, FragmentationMode};
+use websocket::client::{Authorizer, Encoder, EncoderDone, Head};
+use websocket::client::{HandshakeProto, RedirectAction};
+use websocket::codec::message;
+use websocket::error::ErrorEnum;
+use websocket::zero_copy::{parse_raw, write_raw};
+
+
+/// A tail that is stripped from each compressed message
+const TAIL: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// Parameters of the extension as accepted by server
+#[derive(Debug, Clone, Copy)]
+struct Params {
+    client_no_context_takeover: bool,
+}
+
+/// Compression level of the outgoing messages
+///
+/// Level zero means no compression: messages are sent as plain frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionLevel(Compression);
+
+/// A handle to set compression level of the next message
+///
+/// Returned by `DeflateHandshakeProto::level_control`, the handle is
+/// shared with the codecs created by the handshake. The level applies to
+/// the next text or binary message encoded, so you can choose it for each
+/// message, e.g. `fast()` for large binary payloads and `default()` for
+/// small JSON messages. Messages sent without setting a level use the level
+/// of the connection (see `DeflateHandshakeProto::compression_level`).
+///
+/// Note: deflate implementation can't change level of an existing
+/// compressor, so when level changes, compressor is restarted and the data
+/// of the previous messages isn't used for compression (this is allowed
+/// by RFC 7692 even if context takeover is negotiated). Uncompressed
+/// messages (level zero) don't restart the compressor.
+#[derive(Debug, Clone)]
+pub struct LevelControl(Arc<AtomicUsize>);
+
+/// An outgoing message of `DeflateSink`
+///
+/// Carries a compression level for the message. Plain packets are
+/// converted with the level of the connection, so `sink.send(packet.into())`
+/// works too.
+#[derive(Debug, Clone)]
+pub struct DeflateMessage {
+    packet: Packet,
+    level: Option<CompressionLevel>,
+}
+
+/// A sink of `DeflateMessage` on top of a sink of packets
+///
+/// Wraps the output of `DeflateHandshakeProto` or anything built on top of
+/// it (`PingPong`, `WebSocketStream`) and sets level of each message via
+/// `LevelControl`. The inner sink must encode a packet in `start_send`, as
+/// all of the above do.
+#[derive(Debug)]
+pub struct DeflateSink<K> {
+    inner: K,
+    control: LevelControl,
+}
+
+/// Websocket codec that compresses data frames using `permessage-deflate`
+///
+/// This codec is used in `DeflateHandshakeProto` instead of `ClientCodec`.
+/// If server has not accepted the extension, the codec works like
+/// `ClientCodec`.
+///
+/// Frame size limit is taken from `HandshakeConfig::max_frame_size`.
+/// Fragmented messages are always reassembled, because compressed
+/// fragments can't be decoded on their own. The limit of the whole
+/// message (both compressed and decompressed) is `max_message_size` of
+/// `FragmentationMode::Reassemble` or `max_frame_size` if fragmentation
+/// is not configured.
+pub struct DeflateCodec {
+    params: Option<Params>,
+    level: CompressionLevel,
+    next_level: LevelControl,
+    max_frame_size: usize,
+    max_message_size: usize,
+    compress: Option<(Compress, CompressionLevel)>,
+    decompress: Option<Decompress>,
+    /// Opcode, compression flag and data of the fragmented message
+    partial: Option<(u8, bool, Vec<u8>)>,
+}
+
+/// Authorizer wrapper that negotiates the extension
+struct DeflateAuthorizer<A> {
+    inner: A,
+}
+
+/// A future that resolves to framed streams when websocket handshake is done
+///
+/// This is similar to `HandshakeProto` but also requests `permessage-deflate`
+/// extension and returns `DeflateCodec` instead of `ClientCodec`.
+pub struct DeflateHandshakeProto<S, A> {
+    inner: HandshakeProto<S, DeflateAuthorizer<A>>,
+    level: CompressionLevel,
+    control: LevelControl,
+    config: Arc<HandshakeConfig>,
+}
+
+fn parse_params(value: &str) -> Result<Option<Params>, ErrorEnum> {
+    let mut result = None;
+    for ext in value.split(',').map(|x| x.trim()).filter(|x| x.len() > 0) {
+        let mut items = ext.split(';').map(|x| x.trim());
+        let name = items.next().unwrap_or("");
+        if !name.eq_ignore_ascii_case("permessage-deflate") ||
+            result.is_some()
+        {
+            return Err(ErrorEnum::InvalidExtension);
+        }
+        let mut params = Params { client_no_context_takeover: false };
+        for item in items {
+            let pname = item.split('=').next().unwrap_or("").trim();
+            if pname.eq_ignore_ascii_case("client_no_context_takeover") {
+                params.client_no_context_takeover = true;
+            } else if pname.eq_ignore_ascii_case("server_no_context_takeover")
+                || pname.eq_ignore_ascii_case("server_max_window_bits")
+            {
+                // decompressor with the largest window handles both
+            } else {
+                return Err(ErrorEnum::InvalidExtension);
+            }
+        }
+        result = Some(params);
+    }
+    Ok(result)
+}
+
+fn compress(c: &mut Compress, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len()/2 + 64);
+    let start = c.total_in();
+    loop {
+        let consumed = (c.total_in() - start) as usize;
+        if out.len() == out.capacity() {
+            let cap = out.capacity();
+            out.reserve(cap);
+        }
+        c.compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+            .expect("compression never fails");
+        if (c.total_in() - start) as usize == data.len() &&
+            out.len() < out.capacity()
+        {
+            break;
+        }
+    }
+    if out.ends_with(&TAIL) {
+        let len = out.len() - TAIL.len();
+        out.truncate(len);
+    }
+    return out;
+}
+
+fn inflate(d: &mut Decompress, data: &[u8], out: &mut Vec<u8>, limit: usize)
+    -> Result<(), ErrorEnum>
+{
+    let start = d.total_in();
+    loop {
+        let consumed = (d.total_in() - start) as usize;
+        if out.len() == out.capacity() {
+            if out.len() >= limit {
+                return Err(ErrorEnum::TooLong);
+            }
+            let cap = out.capacity();
+            out.reserve(cap);
+        }
+        let (old_in, old_out) = (d.total_in(), d.total_out());
+        let status = d.decompress_vec(&data[consumed..], out,
+                                      FlushDecompress::Sync)
+            .map_err(|_| ErrorEnum::InvalidCompressedData)?;
+        if (d.total_in() - start) as usize == data.len() &&
+            out.len() < out.capacity()
+        {
+            break;
+        }
+        if status == FlateStatus::StreamEnd ||
+            (old_in == d.total_in() && old_out == d.total_out())
+        {
+            return Err(ErrorEnum::InvalidCompressedData);
+        }
+    }
+    if out.len() > limit {
+        return Err(ErrorEnum::TooLong);
+    }
+    Ok(())
+}
+
+impl CompressionLevel {
+    /// Create a level from 0 (no compression) to 9 (best compression)
+    ///
+    /// # Panics
+    ///
+    /// When level is larger than 9.
+    pub fn new(level: u32) -> CompressionLevel {
+        assert!(level <= 9, "compression level must be at most 9");
+        CompressionLevel(Compression::new(level))
+    }
+    /// Don't compress messages
+    pub fn none() -> CompressionLevel {
+        CompressionLevel(Compression::none())
+    }
+    /// Optimize for the best speed of compression
+    pub fn fast() -> CompressionLevel {
+        CompressionLevel(Compression::fast())
+    }
+    /// Optimize for the size of the output
+    pub fn best() -> CompressionLevel {
+        CompressionLevel(Compression::best())
+    }
+    /// Returns the level as a number from 0 to 9
+    pub fn level(&self) -> u32 {
+        self.0.level()
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> CompressionLevel {
+        CompressionLevel(Compression::default())
+    }
+}
+
+impl From<Compression> for CompressionLevel {
+    fn from(level: Compression) -> CompressionLevel {
+        CompressionLevel(level)
+    }
+}
+
+impl LevelControl {
+    fn new() -> LevelControl {
+        LevelControl(Arc::new(AtomicUsize::new(0)))
+    }
+    /// Set compression level of the next text or binary message
+    pub fn set_next_level(&self, level: CompressionLevel) {
+        self.0.store(level.level() as usize + 1, Ordering::SeqCst);
+    }
+    /// Reset the level set by `set_next_level`
+    pub fn clear(&self) {
+        self.0.store(0, Ordering::SeqCst);
+    }
+    fn take(&self) -> Option<CompressionLevel> {
+        match self.0.swap(0, Ordering::SeqCst) {
+            0 => None,
+            x => Some(CompressionLevel::new(x as u32 - 1)),
+        }
+    }
+}
+
+impl DeflateMessage {
+    /// A text message compressed with the specified level
+    pub fn text_compressed<T: Into<String>>(data: T, level: CompressionLevel)
+        -> DeflateMessage
+    {
+        DeflateMessage {
+            packet: Packet::Text(data.into()),
+            level: Some(level),
+        }
+    }
+    /// A binary message compressed with the specified level
+    pub fn binary_compressed<T: Into<Vec<u8>>>(data: T,
+        level: CompressionLevel)
+        -> DeflateMessage
+    {
+        DeflateMessage {
+            packet: Packet::Binary(data.into()),
+            level: Some(level),
+        }
+    }
+    /// Returns the packet of the message
+    pub fn packet(&self) -> &Packet {
+        &self.packet
+    }
+    /// Returns the compression level, `None` means level of the connection
+    pub fn level(&self) -> Option<CompressionLevel> {
+        self.level
+    }
+}
+
+impl From<Packet> for DeflateMessage {
+    fn from(packet: Packet) -> DeflateMessage {
+        DeflateMessage {
+            packet: packet,
+            level: None,
+        }
+    }
+}
+
+impl<K: Sink<SinkItem=Packet>> DeflateSink<K> {
+    /// Wrap a sink, `control` must be the one of the handshake
+    pub fn new(inner: K, control: LevelControl) -> DeflateSink<K> {
+        DeflateSink {
+            inner: inner,
+            control: control,
+        }
+    }
+    /// Returns the wrapped sink
+    pub fn into_inner(self) -> K {
+        self.inner
+    }
+}
+
+impl<K: Sink<SinkItem=Packet>> Sink for DeflateSink<K> {
+    type SinkItem = DeflateMessage;
+    type SinkError = K::SinkError;
+    fn start_send(&mut self, msg: DeflateMessage)
+        -> StartSend<DeflateMessage, K::SinkError>
+    {
+        let DeflateMessage { packet, level } = msg;
+        if let Some(level) = level {
+            self.control.set_next_level(level);
+        }
+        match self.inner.start_send(packet) {
+            Ok(AsyncSink::Ready) => Ok(AsyncSink::Ready),
+            Ok(AsyncSink::NotReady(packet)) => {
+                self.control.clear();
+                Ok(AsyncSink::NotReady(DeflateMessage {
+                    packet: packet,
+                    level: level,
+                }))
+            }
+            Err(e) => {
+                self.control.clear();
+                Err(e)
+            }
+        }
+    }
+    fn poll_complete(&mut self) -> Poll<(), K::SinkError> {
+        self.inner.poll_complete()
+    }
+}
+
+impl DeflateCodec {
+    fn new(params: Option<Params>, level: CompressionLevel,
+        control: &LevelControl, config: &HandshakeConfig)
+        -> DeflateCodec
+    {
+        let max_message_size = match config.fragmentation {
+            FragmentationMode::Reassemble { max_message_size } => {
+                max_message_size
+            }
+            FragmentationMode::RawFrames => config.max_frame_size,
+        };
+        DeflateCodec {
+            params: params,
+            level: level,
+            next_level: control.clone(),
+            max_frame_size: config.max_frame_size,
+            max_message_size: max_message_size,
+            compress: None,
+            decompress: None,
+            partial: None,
+        }
+    }
+    /// Returns true if server has accepted the extension
+    pub fn is_enabled(&self) -> bool {
+        self.params.is_some()
+    }
+    fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, ErrorEnum> {
+        let limit = self.max_message_size;
+        let d = self.decompress.get_or_insert_with(|| Decompress::new(false));
+        let mut out = Vec::with_capacity(data.len()*2 + 64);
+        inflate(d, data, &mut out, limit)?;
+        inflate(d, &TAIL, &mut out, limit)?;
+        Ok(out)
+    }
+    fn finish(&mut self, opcode: u8, compressed: bool, data: &[u8])
+        -> Result<Packet, ErrorEnum>
+    {
+        if compressed {
+            message(opcode, self.decompress(data)?)
+        } else {
+            message(opcode, data.to_vec())
+        }
+    }
+    fn decode_frame(&mut self, buf: &mut Buf)
+        -> Result<Option<Packet>, ErrorEnum>
+    {
+        let enabled = self.is_enabled();
+        loop {
+            let (packet, nbytes) = match parse_raw(buf,
+                self.max_frame_size, false)?
+            {
+                Some((raw, nbytes)) => {
+                    // rsv1 bit is only allowed on the first frame of
+                    // a data message
+                    let compressed = raw.rsv == 0x4 && enabled &&
+                        (raw.opcode == 0x1 || raw.opcode == 0x2);
+                    if raw.rsv != 0 && !compressed {
+                        return Err(ErrorEnum::InvalidRsvBits(raw.rsv));
+                    }
+                    let packet = if raw.opcode & 0x8 != 0 {
+                        // control frames must not be fragmented
+                        if !raw.fin {
+                            return Err(ErrorEnum::Fragmented);
+                        }
+                        Some(Frame::from_raw(raw.opcode, raw.data)?.into())
+                    } else if raw.opcode == 0 {
+                        match self.partial {
+                            Some((_, _, ref mut data)) => {
+                                if data.len() + raw.data.len()
+                                    > self.max_message_size
+                                {
+                                    return Err(ErrorEnum::TooLong);
+                                }
+                                data.extend(raw.data);
+                            }
+                            // continuation of nothing
+                            None => return Err(ErrorEnum::Fragmented),
+                        }
+                        if raw.fin {
+                            let (opcode, compressed, data) = self.partial
+                                .take().expect("message is started");
+                            Some(self.finish(opcode, compressed, &data)?)
+                        } else {
+                            None
+                        }
+                    } else if self.partial.is_some() {
+                        // new message before previous one is finished
+                        return Err(ErrorEnum::Fragmented);
+                    } else if raw.fin && compressed {
+                        Some(self.finish(raw.opcode, true, raw.data)?)
+                    } else if raw.fin {
+                        Some(Frame::from_raw(raw.opcode, raw.data)?.into())
+                    } else {
+                        if raw.data.len() > self.max_message_size {
+                            return Err(ErrorEnum::TooLong);
+                        }
+                        self.partial = Some((raw.opcode, compressed,
+                                             raw.data.to_vec()));
+                        None
+                    };
+                    (packet, nbytes)
+                }
+                None => return Ok(None),
+            };
+            buf.consume(nbytes);
+            if packet.is_some() {
+                return Ok(packet);
+            }
+        }
+    }
+}
+
+impl Encode for DeflateCodec {
+    type Item = Packet;
+    fn encode(&mut self, data: Packet, buf: &mut Buf) {
+        // control frames don't consume the level of the next message
+        let level = match data {
+            Packet::Text(..) | Packet::Binary(..) => {
+                self.next_level.take().unwrap_or(self.level)
+            }
+            _ => self.level,
+        };
+        let params = match self.params {
+            Some(params) => params,
+            None => return Frame::from(&data).write(buf, true),
+        };
+        let (opcode, bytes) = match data {
+            _ if level == CompressionLevel::none() => {
+                return Frame::from(&data).write(buf, true);
+            }
+            Packet::Text(ref x) => (0x1, x.as_bytes()),
+            Packet::Binary(ref x) => (0x2, &x[..]),
+            _ => return Frame::from(&data).write(buf, true),
+        };
+        if self.compress.as_ref().map(|&(_, l)| l != level).unwrap_or(true) {
+            self.compress = Some((Compress::new(level.0, false), level));
+        }
+        let c = &mut self.compress.as_mut().expect("compressor exists").0;
+        let compressed = compress(c, bytes);
+        if params.client_no_context_takeover {
+            c.reset();
+        }
+        // fin and rsv1 bits
+        write_raw(buf, 0x80 | 0x40 | opcode, &compressed, true);
+    }
+}
+
+impl Decode for DeflateCodec {
+    type Item = Packet;
+    fn decode(&mut self, buf: &mut Buf) -> Result<Option<Packet>, io::Error> {
+        self.decode_frame(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+impl<S, A: Authorizer<S>> Authorizer<S> for DeflateAuthorizer<A> {
+    type Result = (A::Result, Option<Params>);
+    fn write_headers(&mut self, mut e: Encoder<S>) -> EncoderDone<S> {
+        e.extensions("permessage-deflate");
+        self.inner.write_headers(e)
+    }
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Result, Error>
+    {
+        let params = if headers.raw_status().0 == 101 {
+            let mut params = None;
+            for value in headers.headers_iter("Sec-WebSocket-Extensions") {
+                let value = from_utf8(value)
+                    .map_err(|_| ErrorEnum::InvalidExtension)?;
+                if let Some(p) = parse_params(value)? {
+                    if params.is_some() {
+                        return Err(ErrorEnum::InvalidExtension.into());
+                    }
+                    params = Some(p);
+                }
+            }
+            params
+        } else {
+            None
+        };
+        let inner = self.inner.headers_received(headers)?;
+        Ok((inner, params))
+    }
+    fn on_redirect(&mut self, location: &str, status: u16)
+        -> RedirectAction
+    {
+        self.inner.on_redirect(location, status)
+    }
+    fn connection_id(&self) -> Option<ConnectionId> {
+        self.inner.connection_id()
+    }
+}
+
+impl<S, A: Authorizer<S>> DeflateHandshakeProto<S, A> {
+    /// Create an instance of future from already connected socket
+    pub fn new(transport: S, authorizer: A) -> DeflateHandshakeProto<S, A>
+        where S: AsyncRead + AsyncWrite
+    {
+        DeflateHandshakeProto::with_config(transport, authorizer,
+                                           &HandshakeConfig::new().done())
+    }
+    /// Same as `new` but allows to tune limits on the response headers
+    /// and on the incoming frames
+    pub fn with_config(transport: S, authorizer: A,
+        config: &Arc<HandshakeConfig>)
+        -> DeflateHandshakeProto<S, A>
+        where S: AsyncRead + AsyncWrite
+    {
+        DeflateHandshakeProto {
+            inner: HandshakeProto::with_config(transport, DeflateAuthorizer {
+                inner: authorizer,
+            }, config),
+            level: CompressionLevel::default(),
+            control: LevelControl::new(),
+            config: config.clone(),
+        }
+    }
+    /// Set compression level for the messages sent without an explicit one
+    ///
+    /// Default level is `CompressionLevel::default()`. Use
+    /// `level_control` to choose level for a specific message.
+    pub fn compression_level(mut self, level: CompressionLevel)
+        -> DeflateHandshakeProto<S, A>
+    {
+        self.level = level;
+        self
+    }
+    /// Returns a handle to set compression level of the next message
+    ///
+    /// The handle works for the codecs returned by this handshake, pass it
+    /// to `DeflateSink::new` to send `DeflateMessage` items.
+    pub fn level_control(&self) -> LevelControl {
+        self.control.clone()
+    }
+}
+
+impl<S, A> Future for DeflateHandshakeProto<S, A>
+    where A: Authorizer<S>,
+          S: AsyncRead + AsyncWrite
+{
+    type Item = (WriteFramed<S, DeflateCodec>, ReadFramed<S, DeflateCodec>,
+                 A::Result);
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Self::Item>, Error> {
+        match self.inner.poll()? {
+            Async::Ready((out, inp, (res, params))) => {
+                let out = out.into_inner()
+                    .framed(DeflateCodec::new(params, self.level,
+                                              &self.control, &self.config));
+                let inp = inp.into_inner()
+                    .framed(DeflateCodec::new(params, self.level,
+                                              &self.control, &self.config));
+                Ok(Async::Ready((out, inp, res)))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Sink};
+    use tk_bufstream::{Buf, Encode, Decode, IoBuf};
+    use flate2::{Compress, Compression};
+
+    use ConnectionId;
+    use mock::MockTransport;
+    use websocket::{Packet, HandshakeConfig, PingPong, WebSocketStream};
+    use super::{DeflateCodec, Params, parse_params, compress};
+    use super::{CompressionLevel, DeflateMessage, DeflateSink, LevelControl};
+
+    fn codec_with(config: &HandshakeConfig, control: &LevelControl)
+        -> DeflateCodec
+    {
+        DeflateCodec::new(Some(Params {
+            client_no_context_takeover: false,
+        }), CompressionLevel::default(), control, config)
+    }
+
+    fn codec() -> DeflateCodec {
+        codec_with(&HandshakeConfig::new(), &LevelControl::new())
+    }
+
+    /// Converts client frames into server ones (i.e. removes the mask)
+    fn unmask(buf: &mut Buf) -> Buf {
+        let mut server = Buf::new();
+        while buf.len() > 0 {
+            let len = (buf[1] & 0x7F) as usize;
+            let mask = [buf[2], buf[3], buf[4], buf[5]];
+            server.extend(&[buf[0], len as u8]);
+            for i in 0..len {
+                server.extend(&[buf[6+i] ^ mask[i % 4]]);
+            }
+            buf.consume(6 + len);
+        }
+        server
+    }
+
+    #[test]
+    fn params() {
+        assert!(parse_params("permessage-deflate").unwrap().is_some());
+        assert!(parse_params("permessage-deflate; \
+            client_no_context_takeover").unwrap().unwrap()
+            .client_no_context_takeover);
+        assert!(parse_params("x-webkit-deflate-frame").is_err());
+        assert!(parse_params("permessage-deflate; unknown").is_err());
+    }
+
+    #[test]
+    fn rfc_example() {
+        // Example from RFC 7692, section 7.2.3.1
+        let mut c = Compress::new(Compression::default(), false);
+        assert_eq!(compress(&mut c, b"Hello"),
+            b"\xf2\x48\xcd\xc9\xc9\x07\x00");
+
+        let mut codec = codec();
+        let mut buf = Buf::new();
+        buf.extend(b"\xc1\x07\xf2\x48\xcd\xc9\xc9\x07\x00");
+        assert_matches!(codec.decode(&mut buf).unwrap(),
+                        Some(Packet::Text(ref x)) if x == "Hello");
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut codec = codec();
+        let mut buf = Buf::new();
+        codec.encode(Packet::Text("hello hello hello".into()), &mut buf);
+        assert_eq!(buf[0], 0xc1);
+        let mut server = unmask(&mut buf);
+        assert_matches!(codec.decode(&mut server).unwrap(),
+                        Some(Packet::Text(ref x)) if x == "hello hello hello");
+    }
+
+    #[test]
+    fn levels() {
+        let control = LevelControl::new();
+        let mut client = codec_with(&HandshakeConfig::new(), &control);
+        let mut buf = Buf::new();
+        client.encode(Packet::Text("hello hello".into()), &mut buf);
+        control.set_next_level(CompressionLevel::none());
+        // control frames don't use the level
+        client.encode(Packet::Ping(Vec::new()), &mut buf);
+        client.encode(Packet::Text("hello hello".into()), &mut buf);
+        control.set_next_level(CompressionLevel::best());
+        client.encode(Packet::Binary(b"hello hello".to_vec()), &mut buf);
+        control.set_next_level(CompressionLevel::fast());
+        client.encode(Packet::Text("hello world".into()), &mut buf);
+
+        let mut server = unmask(&mut buf);
+        assert_eq!(server[0], 0xc1);
+        // a single decompressor reads all the messages
+        let mut codec = codec();
+        let mut packets = Vec::new();
+        while let Some(p) = codec.decode(&mut server).unwrap() {
+            packets.push(p);
+        }
+        assert_matches!(packets[0], Packet::Text(ref x) if x == "hello hello");
+        assert_matches!(packets[1], Packet::Ping(ref x) if x.len() == 0);
+        assert_matches!(packets[2], Packet::Text(ref x) if x == "hello hello");
+        assert_matches!(packets[3],
+                        Packet::Binary(ref x) if x == b"hello hello");
+        assert_matches!(packets[4], Packet::Text(ref x) if x == "hello world");
+        assert_eq!(packets.len(), 5);
+    }
+
+    #[test]
+    fn ping_pong() {
+        let mock = MockTransport::new();
+        let control = LevelControl::new();
+        let config = HandshakeConfig::new();
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        let pp = PingPong::with_id(tx.framed(codec_with(&config, &control)),
+                                   rx.framed(codec_with(&config, &control)),
+                                   ConnectionId(1));
+        let pp = pp.send(Packet::Text("hello hello".into())).wait().unwrap();
+        assert_eq!(mock.written()[0], 0xc1);
+        let sink = DeflateSink::new(pp, control.clone());
+        let sink = sink.send(DeflateMessage::text_compressed("hello",
+            CompressionLevel::none())).wait().unwrap();
+        let mut buf = Buf::new();
+        buf.extend(&mock.written());
+        let mut server = unmask(&mut buf);
+        let mut codec = codec();
+        assert_matches!(codec.decode(&mut server).unwrap(),
+                        Some(Packet::Text(ref x)) if x == "hello hello");
+        assert_eq!(server[0], 0x81);
+        assert_matches!(codec.decode(&mut server).unwrap(),
+                        Some(Packet::Text(ref x)) if x == "hello");
+        drop(sink.into_inner());
+    }
+
+    #[test]
+    fn websocket_stream() {
+        let mock = MockTransport::new();
+        let control = LevelControl::new();
+        let config = HandshakeConfig::new();
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        let ws = WebSocketStream::new(
+            tx.framed(codec_with(&config, &control)),
+            rx.framed(codec_with(&config, &control)));
+        let sink = DeflateSink::new(ws, control);
+        sink.send(DeflateMessage::binary_compressed(&b"data"[..],
+            CompressionLevel::best())).wait().unwrap();
+        assert_eq!(mock.written()[0], 0xc2);
+    }
+
+    #[test]
+    fn fragmented() {
+        let mut c = Compress::new(Compression::default(), false);
+        let data = compress(&mut c, b"hello hello hello");
+        let (head, tail) = data.split_at(data.len() / 2);
+        let mut buf = Buf::new();
+        // first fragment has rsv1 bit, a ping is in between
+        buf.extend(&[0x41, head.len() as u8]);
+        buf.extend(head);
+        buf.extend(b"\x89\x00");
+        buf.extend(&[0x80, tail.len() as u8]);
+        buf.extend(tail);
+        let mut codec = codec();
+        assert_matches!(codec.decode(&mut buf).unwrap(),
+                        Some(Packet::Ping(ref x)) if x.len() == 0);
+        assert_matches!(codec.decode(&mut buf).unwrap(),
+                        Some(Packet::Text(ref x)) if x == "hello hello hello");
+        assert_eq!(buf.len(), 0);
+
+        // rsv1 bit on a continuation frame
+        buf.extend(b"\x01\x01a\xc0\x01b");
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn frame_size_limit() {
+        let mut buf = Buf::new();
+        buf.extend(b"\xc2\x7e\x01\x00");  // header of 256-byte frame
+        let mut codec = codec_with(HandshakeConfig::new()
+                                   .max_frame_size(255), &LevelControl::new());
+        assert!(codec.decode(&mut buf).is_err());
+        let mut codec = codec_with(HandshakeConfig::new()
+                                   .max_frame_size(256), &LevelControl::new());
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+}
@@ -0,0 +1,389 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use rand::{thread_rng, Rng};
+
+use ConnectionId;
+use websocket::Error;
+use websocket::client::{Authorizer, Encoder, EncoderDone, Head};
+use websocket::client::{parse_challenge, RedirectAction};
+use websocket::error::ErrorEnum;
+use websocket::hash::{hex, md5_hex, sha256_hex};
+
+
+/// A hash algorithm of the digest authentication (RFC 7616)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    /// `MD5` (the default when server doesn't specify algorithm)
+    Md5,
+    /// `MD5-sess`
+    Md5Sess,
+    /// `SHA-256`
+    Sha256,
+    /// `SHA-256-sess`
+    Sha256Sess,
+}
+
+/// An authorizer that answers HTTP digest authentication challenge
+///
+/// The first request is made without credentials. When server responds
+/// with `401 Unauthorized` and `WWW-Authenticate: Digest ...` challenge,
+/// handshake fails with a special error, and `RetryingHandshake` (so
+/// `Client` too) immediately retries it with the `Authorization` header.
+/// Challenge is shared between clones of the authorizer, so make a clone
+/// for every attempt:
+///
+/// ```rust,ignore
+/// let auth = DigestAuthorizer::new(
+///     SimpleAuthorizer::new("example.com", "/chat"), "user", "password");
+/// RetryingHandshake::new(move || {
+///     let auth = auth.clone();
+///     TcpStream::connect(&addr, &handle).map_err(Error::from)
+///     .and_then(move |sock| HandshakeProto::new(sock, auth))
+/// }, FixedBackoff::new(Duration::from_secs(1)), &handle)
+/// ```
+///
+/// Subsequent connections (e.g. reconnects) send credentials upfront,
+/// using the last challenge. If credentials are rejected, the response is
+/// passed to the inner authorizer, which fails with `ServerRejected`
+/// unless it's a `stale` nonce.
+///
+/// The inner authorizer is never called for a challenge response. It
+/// must not write the `Authorization` header itself. Only `qop=auth` is
+/// supported (or no `qop` at all, as in RFC 2069).
+#[derive(Clone)]
+pub struct DigestAuthorizer<A> {
+    inner: A,
+    username: String,
+    password: String,
+    state: Arc<Mutex<State>>,
+    sent: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    challenge: Option<Challenge>,
+    nonce_count: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: DigestAlgorithm,
+    qop: bool,
+    stale: bool,
+}
+
+/// The `Authorization` header written by `Encoder::done`
+pub(crate) struct DigestResponse {
+    challenge: Challenge,
+    username: String,
+    password: String,
+    nonce_count: u32,
+    cnonce: String,
+    pub(crate) method: String,
+    pub(crate) uri: String,
+}
+
+impl DigestAlgorithm {
+    /// Parse the name of the algorithm (case-insensitive)
+    pub fn from_name(name: &str) -> Option<DigestAlgorithm> {
+        use self::DigestAlgorithm::*;
+        [Md5, Md5Sess, Sha256, Sha256Sess].iter()
+            .find(|a| a.name().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+    /// Returns the name of the algorithm as used in headers
+    pub fn name(&self) -> &'static str {
+        use self::DigestAlgorithm::*;
+        match *self {
+            Md5 => "MD5",
+            Md5Sess => "MD5-sess",
+            Sha256 => "SHA-256",
+            Sha256Sess => "SHA-256-sess",
+        }
+    }
+    fn hash(&self, data: &str) -> String {
+        use self::DigestAlgorithm::*;
+        match *self {
+            Md5 | Md5Sess => md5_hex(data.as_bytes()),
+            Sha256 | Sha256Sess => sha256_hex(data.as_bytes()),
+        }
+    }
+    fn is_session(&self) -> bool {
+        use self::DigestAlgorithm::*;
+        matches!(*self, Md5Sess | Sha256Sess)
+    }
+}
+
+impl<A> DigestAuthorizer<A> {
+    /// Wrap an authorizer
+    pub fn new(inner: A, username: &str, password: &str)
+        -> DigestAuthorizer<A>
+    {
+        DigestAuthorizer {
+            inner: inner,
+            username: username.to_string(),
+            password: password.to_string(),
+            state: Arc::new(Mutex::new(State {
+                challenge: None,
+                nonce_count: 0,
+            })),
+            sent: false,
+        }
+    }
+}
+
+impl<S, A: Authorizer<S>> Authorizer<S> for DigestAuthorizer<A> {
+    type Result = A::Result;
+    fn write_headers(&mut self, mut e: Encoder<S>) -> EncoderDone<S> {
+        {
+            let mut state = self.state.lock()
+                .expect("digest state is not poisoned");
+            if let Some(challenge) = state.challenge.clone() {
+                state.nonce_count += 1;
+                let mut cnonce = [0u8; 16];
+                thread_rng().fill_bytes(&mut cnonce[..]);
+                e.digest(DigestResponse {
+                    challenge: challenge,
+                    username: self.username.clone(),
+                    password: self.password.clone(),
+                    nonce_count: state.nonce_count,
+                    cnonce: hex(&cnonce),
+                    method: String::new(),
+                    uri: String::new(),
+                });
+                self.sent = true;
+            }
+        }
+        self.inner.write_headers(e)
+    }
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Result, Error>
+    {
+        if headers.raw_status().0 == 401 {
+            let challenge = headers.headers_iter("WWW-Authenticate")
+                .filter_map(Challenge::parse)
+                .next();
+            match challenge {
+                Some(ref c) if self.sent && !c.stale => {}
+                Some(c) => {
+                    let mut state = self.state.lock()
+                        .expect("digest state is not poisoned");
+                    state.challenge = Some(c);
+                    state.nonce_count = 0;
+                    return Err(ErrorEnum::DigestChallenge.into());
+                }
+                None => {}
+            }
+        }
+        self.inner.headers_received(headers)
+    }
+    fn on_redirect(&mut self, location: &str, status: u16)
+        -> RedirectAction
+    {
+        self.inner.on_redirect(location, status)
+    }
+    fn connection_id(&self) -> Option<ConnectionId> {
+        self.inner.connection_id()
+    }
+}
+
+impl Challenge {
+    fn parse(value: &[u8]) -> Option<Challenge> {
+        let params = match parse_challenge(value, "Digest") {
+            Some(params) => params,
+            None => return None,
+        };
+        let mut realm = String::new();
+        let mut nonce = None;
+        let mut opaque = None;
+        let mut algorithm = DigestAlgorithm::Md5;
+        let mut qop = None;
+        let mut stale = false;
+        for (name, value) in params {
+            match &name.to_ascii_lowercase()[..] {
+                "realm" => realm = value,
+                "nonce" => nonce = Some(value),
+                "opaque" => opaque = Some(value),
+                "algorithm" => {
+                    algorithm = match DigestAlgorithm::from_name(&value) {
+                        Some(algorithm) => algorithm,
+                        None => return None,
+                    };
+                }
+                "qop" => qop = Some(value),
+                "stale" => stale = value.eq_ignore_ascii_case("true"),
+                _ => {}
+            }
+        }
+        let qop = match qop {
+            Some(ref value) if value.split(',').any(|x| x.trim() == "auth")
+            => true,
+            Some(_) => return None,
+            None => false,
+        };
+        nonce.map(|nonce| Challenge {
+            realm: realm,
+            nonce: nonce,
+            opaque: opaque,
+            algorithm: algorithm,
+            qop: qop,
+            stale: stale,
+        })
+    }
+}
+
+/// Writes a quoted string, escaping quotes and backslashes
+fn quoted(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            f.write_str("\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for DigestResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = &self.challenge;
+        let alg = c.algorithm;
+        let nc = format!("{:08x}", self.nonce_count);
+        let mut ha1 = alg.hash(&format!("{}:{}:{}",
+            self.username, c.realm, self.password));
+        if alg.is_session() {
+            ha1 = alg.hash(&format!("{}:{}:{}", ha1, c.nonce, self.cnonce));
+        }
+        let ha2 = alg.hash(&format!("{}:{}", self.method, self.uri));
+        let response = if c.qop {
+            alg.hash(&format!("{}:{}:{}:{}:auth:{}",
+                ha1, c.nonce, nc, self.cnonce, ha2))
+        } else {
+            alg.hash(&format!("{}:{}:{}", ha1, c.nonce, ha2))
+        };
+        f.write_str("Digest username=")?;
+        quoted(f, &self.username)?;
+        f.write_str(", realm=")?;
+        quoted(f, &c.realm)?;
+        f.write_str(", uri=")?;
+        quoted(f, &self.uri)?;
+        write!(f, ", algorithm={}", alg.name())?;
+        f.write_str(", nonce=")?;
+        quoted(f, &c.nonce)?;
+        if c.qop {
+            write!(f, ", nc={}, cnonce=\"{}\", qop=auth", nc, self.cnonce)?;
+        }
+        write!(f, ", response=\"{}\"", response)?;
+        if let Some(ref opaque) = c.opaque {
+            f.write_str(", opaque=")?;
+            quoted(f, opaque)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use tokio_core::reactor::Core;
+
+    use mock::MockTransport;
+    use websocket::{FixedBackoff, RetryingHandshake};
+    use websocket::client::{HandshakeProto, SimpleAuthorizer};
+    use super::{Challenge, DigestAlgorithm, DigestAuthorizer};
+    use super::DigestResponse;
+
+    const CHALLENGE: &'static str = "Digest \
+        realm=\"http-auth@example.org\", \
+        qop=\"auth, auth-int\", \
+        algorithm=SHA-256, \
+        nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+        opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+
+    fn response(algorithm: DigestAlgorithm) -> String {
+        let mut challenge = Challenge::parse(CHALLENGE.as_bytes()).unwrap();
+        challenge.algorithm = algorithm;
+        DigestResponse {
+            challenge: challenge,
+            username: "Mufasa".into(),
+            password: "Circle of Life".into(),
+            nonce_count: 1,
+            cnonce: "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ".into(),
+            method: "GET".into(),
+            uri: "/dir/index.html".into(),
+        }.to_string()
+    }
+
+    #[test]
+    fn rfc7616_example() {
+        let md5 = response(DigestAlgorithm::Md5);
+        assert!(md5.starts_with("Digest username=\"Mufasa\", \
+            realm=\"http-auth@example.org\", uri=\"/dir/index.html\", \
+            algorithm=MD5, "));
+        assert!(md5.contains(", nc=00000001, \
+            cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+            qop=auth, response=\"8ca523f5e9506fed4657c9700eebdbec\", \
+            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""));
+        assert!(response(DigestAlgorithm::Sha256).contains(", response=\"\
+            753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\
+            \""));
+    }
+
+    #[test]
+    fn parse() {
+        let c = Challenge::parse(CHALLENGE.as_bytes()).unwrap();
+        assert_eq!(c.algorithm, DigestAlgorithm::Sha256);
+        assert!(c.qop);
+        assert!(!c.stale);
+        assert!(Challenge::parse(b"Basic realm=\"x\"").is_none());
+        assert!(Challenge::parse(b"Digest realm=\"x\"").is_none());
+        assert!(Challenge::parse(b"Digest nonce=1, algorithm=SHA1")
+                .is_none());
+        assert!(Challenge::parse(b"Digest nonce=1, qop=auth-int")
+                .is_none());
+        let c = Challenge::parse(b"Digest nonce=1, stale=TRUE").unwrap();
+        assert_eq!(c.algorithm, DigestAlgorithm::Md5);
+        assert!(!c.qop);
+        assert!(c.stale);
+    }
+
+    #[test]
+    fn retry() {
+        let mut lp = Core::new().unwrap();
+        let auth = DigestAuthorizer::new(
+            SimpleAuthorizer::new("example.com", "/chat"),
+            "Mufasa", "Circle of Life");
+        let mocks = Rc::new(RefCell::new(Vec::new()));
+        let m = mocks.clone();
+        let retry = RetryingHandshake::new(move || {
+            let mock = MockTransport::new();
+            mock.feed(format!("HTTP/1.1 401 Unauthorized\r\n\
+                WWW-Authenticate: Basic realm=\"x\"\r\n\
+                WWW-Authenticate: {}\r\n\
+                Content-Length: 0\r\n\r\n", CHALLENGE).as_bytes());
+            m.borrow_mut().push(mock.clone());
+            HandshakeProto::new(mock, auth.clone())
+        }, FixedBackoff::new(Duration::from_secs(10)), &lp.handle());
+        let err = lp.run(retry).err().unwrap();
+        // credentials are rejected second time
+        assert_eq!(err.to_string(),
+            "server rejected websocket connection: 401 Unauthorized");
+        let mocks = mocks.borrow();
+        assert_eq!(mocks.len(), 2);
+        let first = String::from_utf8(mocks[0].written()).unwrap();
+        assert!(!first.contains("Authorization"));
+        let second = String::from_utf8(mocks[1].written()).unwrap();
+        assert!(second.starts_with("GET /chat HTTP/1.1\r\n"));
+        assert!(second.contains("\r\nAuthorization: Digest \
+            username=\"Mufasa\", realm=\"http-auth@example.org\", \
+            uri=\"/chat\", algorithm=SHA-256, "));
+        assert!(second.contains(", nc=00000001, "));
+    }
+}
@@ -11,7 +11,9 @@ use tk_bufstream::{Encode};
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_core::reactor::{Handle, Timeout};
 
+use ConnectionId;
 use websocket::{Frame, Config, Packet, Error, ServerCodec, ClientCodec};
+use websocket::conn_id;
 use websocket::error::ErrorEnum;
 use websocket::zero_copy::{write_packet, write_close};
 
@@ -49,6 +51,7 @@ pub struct Loop<S, T, D: Dispatcher> {
     last_ping: Instant,
     last_byte: Instant,
     timeout: Timeout,
+    id: ConnectionId,
 }
 
 
@@ -85,6 +88,22 @@ impl<S, T, D, E> Loop<S, T, D>
         handle: &Handle)
         -> Loop<S, T, D>
     {
+        Loop::server_with_id(outp, inp, stream, dispatcher, config, handle,
+                             conn_id::next())
+    }
+    /// Same as `server` but logs with the id of the connection
+    ///
+    /// Pass the id of `ServerHandshakeProto` (or the one of the accepted
+    /// connection) so that all the log messages of the connection can be
+    /// correlated.
+    pub fn server_with_id(
+        outp: WriteFramed<S, ServerCodec>,
+        inp: ReadFramed<S, ServerCodec>,
+        stream: T, dispatcher: D, config: &Arc<Config>,
+        handle: &Handle, id: ConnectionId)
+        -> Loop<S, T, D>
+    {
+        debug!("[{}] Websocket loop started", id);
         Loop {
             config: config.clone(),
             input: inp.into_inner(),
@@ -105,6 +124,7 @@ impl<S, T, D, E> Loop<S, T, D>
                     min(config.ping_interval, config.message_timeout)),
                 handle)
                 .expect("Can always set timeout"),
+            id: id,
         }
     }
     /// Create a new websocket Loop (client-side)
@@ -116,6 +136,20 @@ impl<S, T, D, E> Loop<S, T, D>
         stream: T, dispatcher: D, config: &Arc<Config>, handle: &Handle)
         -> Loop<S, T, D>
     {
+        Loop::client_with_id(outp, inp, stream, dispatcher, config, handle,
+                             conn_id::next())
+    }
+    /// Same as `client` but logs with the id of the connection
+    ///
+    /// Pass `HandshakeProto::connection_id` here.
+    pub fn client_with_id(
+        outp: WriteFramed<S, ClientCodec>,
+        inp: ReadFramed<S, ClientCodec>,
+        stream: T, dispatcher: D, config: &Arc<Config>, handle: &Handle,
+        id: ConnectionId)
+        -> Loop<S, T, D>
+    {
+        debug!("[{}] Websocket loop started", id);
         Loop {
             config: config.clone(),
             input: inp.into_inner(),
@@ -136,6 +170,7 @@ impl<S, T, D, E> Loop<S, T, D>
                     min(config.ping_interval, config.message_timeout)),
                 handle)
                 .expect("Can always set timeout"),
+            id: id,
         }
     }
 }
@@ -164,6 +199,19 @@ impl<S> Loop<S, stream::Empty<Packet, VoidError>, BlackHole>
         handle: &Handle)
         -> Loop<S, stream::Empty<Packet, VoidError>, BlackHole>
     {
+        Loop::closing_with_id(outp, inp, reason, text, config, handle,
+                              conn_id::next())
+    }
+    /// Same as `closing` but logs with the id of the connection
+    pub fn closing_with_id(
+        outp: WriteFramed<S, ServerCodec>,
+        inp: ReadFramed<S, ServerCodec>,
+        reason: u16, text: &str,
+        config: &Arc<Config>,
+        handle: &Handle, id: ConnectionId)
+        -> Loop<S, stream::Empty<Packet, VoidError>, BlackHole>
+    {
+        debug!("[{}] Closing websocket: [{}]{:?}", id, reason, text);
         let mut out = outp.into_inner();
         write_close(&mut out.out_buf, reason, text, false);
         Loop {
@@ -187,6 +235,7 @@ impl<S> Loop<S, stream::Empty<Packet, VoidError>, BlackHole>
                     min(config.ping_interval, config.message_timeout)),
                 handle)
                 .expect("Can always set timeout"),
+            id: id,
         }
     }
 }
@@ -212,13 +261,15 @@ impl<S, T, D, E> Loop<S, T, D>
                                 ServerCodec.encode(pkt,
                                     &mut self.output.out_buf);
                             } else {
-                                ClientCodec.encode(pkt,
+                                ClientCodec::new().encode(pkt,
                                     &mut self.output.out_buf);
                             }
                         }
                         None => {
                             match self.state {
                                 LoopState::Open => {
+                                    debug!("[{}] Output stream is done, \
+                                        closing websocket", self.id);
                                     // send close
                                     write_close(&mut self.output.out_buf,
                                                 1000, "", !self.server);
@@ -274,8 +325,8 @@ impl<S, T, D, E> Loop<S, T, D>
                                 None
                             }
                             Frame::Close(code, reply) => {
-                                debug!("Websocket closed by peer [{}]{:?}",
-                                    code, reply);
+                                debug!("[{}] Websocket closed by peer \
+                                    [{}]{:?}", self.id, code, reply);
                                 self.state = LoopState::CloseReceived;
                                 Some(self.dispatcher.frame(
                                     &Frame::Close(code, reply)))
@@ -361,7 +412,7 @@ impl<S, T, D, E> Future for Loop<S, T, D>
                     } else if Instant::now() >
                         self.last_ping + self.config.ping_interval
                     {
-                        debug!("Sending ping");
+                        debug!("[{}] Sending ping", self.id);
                         let old_val = self.output.out_buf.len();
                         write_packet(&mut self.output.out_buf,
                                      0x9, b"tk-http-ping", !self.server);
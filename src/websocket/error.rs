@@ -1,17 +1,22 @@
+use std::error::Error as StdError;
+use std::fmt;
 use std::io;
 use std::str::{Utf8Error};
 
 use httparse;
 
+use {AnnotatedError, ConnectionId};
+
 quick_error! {
     /// Websocket Error works both for client and server connections
     #[derive(Debug)]
-    pub enum Error wraps pub ErrorEnum {
+    pub enum ErrorEnum {
         /// Socket IO error
         Io(err: io::Error) {
             description("IO error")
             display("IO error: {}", err)
             from()
+            cause(err)
         }
         /// Error when polling timeout future (unreachable)
         Timeout {
@@ -23,6 +28,7 @@ quick_error! {
             description("Error decoding text frame")
             display("Error decoding text frame: {}", err)
             from()
+            cause(err)
         }
         /// Got websocket message with wrong opcode
         InvalidOpcode(code: u8) {
@@ -34,14 +40,35 @@ quick_error! {
         Unmasked {
             description("Received unmasked frame")
         }
-        /// Got fragmented frame (fragmented frames are not supported yet)
+        /// Got masked frame from server (they must not be masked)
+        Masked {
+            description("Received masked frame")
+        }
+        /// Got fragmented frame when fragmentation is not enabled, or
+        /// fragments are out of order
         Fragmented {
             description("Received fragmented frame")
         }
-        /// Received frame that is longer than configured limit
+        /// Received message that is longer than configured limit
         TooLong {
             description("Received frame that is too long")
         }
+        /// Received frame header with the payload length larger than
+        /// configured limit
+        FrameTooLarge { size: u64, limit: usize } {
+            description("received frame is too large")
+            display("frame of {} bytes is larger than the limit of {}",
+                    size, limit)
+        }
+        /// Payload of the close frame is invalid
+        InvalidClosePayload {
+            description("invalid close frame payload")
+        }
+        /// Status code of the close frame must not be sent over the wire
+        InvalidCloseCode(code: u16) {
+            description("invalid close status code")
+            display("invalid close status code: {}", code)
+        }
         /// Currently this error means that channel to/from websocket closed
         ///
         /// In future we expect this condition (processor dropping channel) to
@@ -58,6 +85,119 @@ quick_error! {
             description("parse error")
             display("parse error: {:?}", err)
             from()
+            cause(err)
+        }
+        /// Invalid websocket handshake request received (server-side)
+        InvalidHandshake(reason: &'static str) {
+            description("invalid websocket handshake")
+            display("invalid websocket handshake: {}", reason)
+        }
+        /// Server responded with invalid or missing `Sec-WebSocket-Accept`
+        InvalidAcceptHeader {
+            description("invalid Sec-WebSocket-Accept header")
+        }
+        /// Server chose a subprotocol that was not requested
+        InvalidSubprotocol {
+            description("server chose unsupported subprotocol")
+        }
+        /// None of the subprotocols offered by client is supported
+        /// (server-side)
+        UnsupportedSubprotocol {
+            description("none of the offered subprotocols is supported")
+        }
+        /// Server returned an extension that was not requested
+        InvalidExtension {
+            description("server returned unsupported extension")
+        }
+        /// Reserved bits are set in the frame but no extension uses them
+        InvalidRsvBits(bits: u8) {
+            description("reserved bits are set in the frame")
+            display("reserved bits are set in the frame: {:#x}", bits)
+        }
+        /// Error decompressing a frame
+        InvalidCompressedData {
+            description("error decompressing a frame")
+        }
+        /// Response headers exceed limits set in `HandshakeConfig`
+        ResponseTooLarge {
+            description("response headers are too large")
+        }
+        /// Domain name can't be used for TLS connection
+        InvalidDomain {
+            description("invalid domain name for TLS connection")
+        }
+        /// Server accepted websocket connection using HTTP/1.0 (websocket
+        /// upgrade requires at least HTTP/1.1)
+        VersionTooOld {
+            description("websocket upgrade response uses HTTP/1.0")
+        }
+        /// Websocket handshake has not completed in time
+        HandshakeTimeout {
+            description("websocket handshake timed out")
+        }
+        /// Server responded with a status other than 101 (Switching
+        /// Protocols)
+        ServerRejected { status: u16, reason: String } {
+            description("server rejected websocket connection")
+            display("server rejected websocket connection: {} {}",
+                    status, reason)
+        }
+        /// Server rejected bearer token (`WWW-Authenticate: Bearer` with
+        /// an `error` parameter)
+        Unauthorized { realm: String, error: String } {
+            description("bearer token rejected by server")
+            display("bearer token rejected by server (realm {:?}): {}",
+                    realm, error)
+        }
+        /// Server responded with a digest authentication challenge
+        ///
+        /// This is returned by `DigestAuthorizer` to make
+        /// `RetryingHandshake` repeat the request with credentials.
+        DigestChallenge {
+            description("server requested digest authentication")
+        }
+        /// Server redirected handshake and authorizer decided to follow
+        ///
+        /// This is handled by `FollowRedirects`.
+        Redirect { status: u16, location: String } {
+            description("websocket handshake is redirected")
+            display("websocket handshake is redirected ({}) to {:?}",
+                    status, location)
+        }
+        /// Redirect limit of `FollowRedirects` is exceeded or a redirect
+        /// loop is detected
+        TooManyRedirects {
+            description("too many redirects")
+        }
+        /// Handshake request or a packet has not been written to the
+        /// network in time
+        WriteTimeout {
+            description("timed out writing to the network")
+        }
+        /// Text message received by `BinaryStream`
+        UnexpectedText {
+            description("text message received instead of binary")
+        }
+        /// Sending a packet would exceed the limit of the output buffer
+        /// set by `WebSocketStream::set_max_write_buffer`
+        WriteBufferFull {
+            description("websocket output buffer is full")
+        }
+        /// Server switched to a protocol other than requested by
+        /// `RawUpgradeProto`
+        InvalidUpgradeHeader {
+            description("server upgraded to a different protocol")
+        }
+        /// Server responded to `RawUpgradeProto` with a status other than
+        /// 101 (Switching Protocols)
+        UpgradeRejected { status: u16, reason: String } {
+            description("server rejected protocol upgrade")
+            display("server rejected protocol upgrade: {} {}",
+                    status, reason)
+        }
+        /// Upgrade response has no `upgrade` token in `Connection` header
+        InvalidConnectionHeader {
+            description("no upgrade token in Connection header")
         }
         PrematureResponseHeaders {
             description("response headers before request are sent")
@@ -70,6 +210,38 @@ quick_error! {
     }
 }
 
+/// Websocket Error works both for client and server connections
+#[derive(Debug)]
+pub struct Error(ErrorEnum);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<ErrorEnum> for Error {
+    fn from(err: ErrorEnum) -> Error {
+        Error(err)
+    }
+}
+
+impl StdError for Error {
+    #[allow(deprecated)]
+    fn cause(&self) -> Option<&StdError> {
+        self.0.cause()
+    }
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match self.0 {
+            ErrorEnum::Io(ref err) => Some(err),
+            ErrorEnum::InvalidUtf8(ref err) => Some(err),
+            ErrorEnum::HeaderError(ref err) => Some(err),
+            ErrorEnum::Custom(ref err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
 impl Error {
     /// Create an error instance wrapping custom error
     pub fn custom<E: Into<Box<::std::error::Error + Send + Sync>>>(err: E)
@@ -77,6 +249,91 @@ impl Error {
     {
         Error(ErrorEnum::Custom(err.into()))
     }
+    /// Wrap the error with the id of the connection to make it more
+    /// useful in logs
+    pub fn with_connection_id(self, id: ConnectionId)
+        -> AnnotatedError<Error>
+    {
+        AnnotatedError::new(id, self)
+    }
+    /// Returns the redirect target if handshake failed because authorizer
+    /// decided to follow a redirect
+    pub fn redirect_location(&self) -> Option<&str> {
+        match self.0 {
+            ErrorEnum::Redirect { ref location, .. } => Some(location),
+            _ => None,
+        }
+    }
+    /// Returns `true` if received frame is larger than the configured limit
+    pub fn is_frame_too_large(&self) -> bool {
+        matches!(self.0, ErrorEnum::FrameTooLarge { .. })
+    }
+    /// Returns the response status if server rejected the handshake
+    /// (or the upgrade requested by `RawUpgradeProto`)
+    pub fn rejected_status(&self) -> Option<u16> {
+        match self.0 {
+            ErrorEnum::ServerRejected { status, .. } => Some(status),
+            ErrorEnum::UpgradeRejected { status, .. } => Some(status),
+            _ => None,
+        }
+    }
+    /// Returns the realm and the `error` parameter of the challenge if
+    /// server rejected the bearer token
+    pub fn unauthorized(&self) -> Option<(&str, &str)> {
+        match self.0 {
+            ErrorEnum::Unauthorized { ref realm, ref error } => {
+                Some((realm, error))
+            }
+            _ => None,
+        }
+    }
+    /// Returns `true` if the request should be repeated with credentials
+    pub(crate) fn is_auth_challenge(&self) -> bool {
+        matches!(self.0, ErrorEnum::DigestChallenge)
+    }
+    /// Returns `true` if the error is likely to go away on retry
+    ///
+    /// These are IO errors of kind `ConnectionRefused`, `ConnectionReset`,
+    /// `ConnectionAborted`, `NotConnected`, `AddrNotAvailable`, `BrokenPipe`,
+    /// `TimedOut` and `UnexpectedEof`, and connection closed before response
+    /// headers are received, i.e. the peer is not reachable for now or the
+    /// connection was broken by the network. Everything else, including
+    /// other IO errors, parse errors, protocol violations and handshake
+    /// rejections, is considered fatal: repeating the same request to the
+    /// same peer is unlikely to succeed.
+    ///
+    /// This is the classification used by `RetryingHandshake` and
+    /// `ReconnectingStream`. It's just a reasonable default, inspect the
+    /// error yourself if your application needs a different policy.
+    pub fn is_retriable(&self) -> bool {
+        use std::io::ErrorKind::*;
+        match self.0 {
+            ErrorEnum::Io(ref e) => match e.kind() {
+                ConnectionRefused | ConnectionReset | ConnectionAborted |
+                NotConnected | AddrNotAvailable | BrokenPipe |
+                TimedOut | UnexpectedEof => true,
+                _ => false,
+            },
+            ErrorEnum::PrematureResponseHeaders => true,
+            _ => false,
+        }
+    }
+    /// Returns `true` if the error is not retriable
+    ///
+    /// This is the inverse of `is_retriable`.
+    pub fn is_fatal(&self) -> bool {
+        !self.is_retriable()
+    }
+}
+
+impl From<Error> for io::Error {
+    /// Unwraps IO errors, other errors are wrapped with `ErrorKind::Other`
+    fn from(err: Error) -> io::Error {
+        match err.0 {
+            ErrorEnum::Io(e) => e,
+            other => io::Error::new(io::ErrorKind::Other, Error(other)),
+        }
+    }
 }
 
 #[test]
@@ -84,3 +341,62 @@ fn send_sync() {
     fn send_sync<T: Send+Sync>(_: T) {}
     send_sync(Error::from(ErrorEnum::TooLong));
 }
+
+#[test]
+fn retriable() {
+    use std::io::ErrorKind;
+    let io = |kind| Error::from(ErrorEnum::from(io::Error::new(kind, "test")));
+    assert!(io(ErrorKind::ConnectionReset).is_retriable());
+    assert!(io(ErrorKind::ConnectionRefused).is_retriable());
+    assert!(io(ErrorKind::AddrNotAvailable).is_retriable());
+    assert!(io(ErrorKind::BrokenPipe).is_retriable());
+    assert!(io(ErrorKind::TimedOut).is_retriable());
+    assert!(io(ErrorKind::PermissionDenied).is_fatal());
+    assert!(Error::from(ErrorEnum::PrematureResponseHeaders).is_retriable());
+    assert!(Error::from(ErrorEnum::Unmasked).is_fatal());
+    assert!(Error::from(ErrorEnum::from(httparse::Error::Token)).is_fatal());
+}
+
+#[test]
+fn into_io_error() {
+    let err = Error::from(ErrorEnum::from(
+        io::Error::new(io::ErrorKind::BrokenPipe, "test")));
+    assert_eq!(io::Error::from(err).kind(), io::ErrorKind::BrokenPipe);
+    let err = io::Error::from(Error::from(ErrorEnum::Unmasked));
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert_eq!(err.to_string(),
+               Error::from(ErrorEnum::Unmasked).to_string());
+}
+
+#[test]
+fn source() {
+    use std::error::Error as StdError;
+    let err = Error::from(ErrorEnum::from(
+        io::Error::new(io::ErrorKind::BrokenPipe, "test")));
+    assert_eq!(err.source().unwrap().to_string(), "test");
+    let err = Error::from(ErrorEnum::from(httparse::Error::Token));
+    assert!(err.source().unwrap().is::<httparse::Error>());
+    let err = Error::custom("oops");
+    assert_eq!(err.source().unwrap().to_string(), "oops");
+    assert!(Error::from(ErrorEnum::Unmasked).source().is_none());
+}
+
+#[test]
+fn unauthorized() {
+    let err = Error::from(ErrorEnum::Unauthorized {
+        realm: "example".into(),
+        error: "invalid_token".into(),
+    });
+    assert_eq!(err.unauthorized(), Some(("example", "invalid_token")));
+    assert_eq!(Error::from(ErrorEnum::Unmasked).unauthorized(), None);
+}
+
+#[test]
+fn rejected_status() {
+    let err = Error::from(ErrorEnum::ServerRejected {
+        status: 403,
+        reason: "Forbidden".into(),
+    });
+    assert_eq!(err.rejected_status(), Some(403));
+    assert_eq!(Error::from(ErrorEnum::Unmasked).rejected_status(), None);
+}
@@ -0,0 +1,76 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+
+use websocket::Error;
+use websocket::client::Head;
+use websocket::error::ErrorEnum;
+
+
+/// A client-side negotiator of a websocket extension
+///
+/// Negotiators are passed to `SimpleAuthorizer::with_extensions`. The
+/// offer of every negotiator is sent in the `Sec-WebSocket-Extensions`
+/// header, and each extension that server returns is passed to `accept`
+/// of the negotiator with the same extension name (the part of the offer
+/// before the first `;`). If server returns an extension that was not
+/// offered, or the same extension twice, handshake fails with
+/// `InvalidExtension` error.
+///
+/// Negotiator only configures itself, so it's up to the implementation
+/// to share the negotiated parameters with the codec (e.g. via `Arc`).
+pub trait ExtensionNegotiator: Send {
+    /// Returns an offer, e.g. `permessage-foo; client_max_bits=10`
+    fn offer(&self) -> &str;
+    /// Validate and apply the parameters chosen by server
+    ///
+    /// The value is a single extension from the response header,
+    /// including its name and parameters.
+    fn accept(&mut self, response_value: &str) -> Result<(), Error>;
+    /// Returns a boxed copy of the negotiator
+    ///
+    /// This is needed because authorizer is cloned for every connection
+    /// attempt.
+    fn box_clone(&self) -> Box<ExtensionNegotiator>;
+}
+
+impl Clone for Box<ExtensionNegotiator> {
+    fn clone(&self) -> Box<ExtensionNegotiator> {
+        self.box_clone()
+    }
+}
+
+fn extension_name(value: &str) -> &str {
+    value.split(';').next().unwrap_or("").trim()
+}
+
+/// Writes the value of the `Sec-WebSocket-Extensions` request header
+pub(crate) fn offer(negotiators: &[Box<ExtensionNegotiator>]) -> String {
+    negotiators.iter().map(|n| n.offer()).collect::<Vec<_>>().join(", ")
+}
+
+/// Passes extensions returned by server to the matching negotiators
+pub(crate) fn negotiate(negotiators: &mut [Box<ExtensionNegotiator>],
+    headers: &Head)
+    -> Result<(), Error>
+{
+    let mut accepted = Vec::new();
+    for value in headers.headers_iter("Sec-WebSocket-Extensions") {
+        let value = from_utf8(value)
+            .map_err(|_| ErrorEnum::InvalidExtension)?;
+        for ext in value.split(',').map(|x| x.trim()).filter(|x| x.len() > 0)
+        {
+            let name = extension_name(ext);
+            let idx = negotiators.iter()
+                .position(|n| extension_name(n.offer())
+                                .eq_ignore_ascii_case(name))
+                .ok_or(ErrorEnum::InvalidExtension)?;
+            if accepted.contains(&idx) {
+                return Err(ErrorEnum::InvalidExtension.into());
+            }
+            accepted.push(idx);
+            negotiators[idx].accept(ext)?;
+        }
+    }
+    Ok(())
+}
@@ -22,6 +22,9 @@ pub struct Key([u8; 16]);
 
 impl Key {
     /// Create a new (random) key, eligible to use for client connection
+    ///
+    /// The key is 16 bytes taken from the thread-local cryptographically
+    /// secure random number generator (as required by RFC 6455).
     pub fn new() -> Key {
         let mut key = [0u8; 16];
         thread_rng().fill_bytes(&mut key);
@@ -108,3 +111,77 @@ impl fmt::Debug for Key {
         write!(f, "websocket::Key({})", self)
     }
 }
+
+/// Returns base64 alphabet character for a 6-bit value
+///
+/// There are no table lookups and no branches, so the time doesn't depend
+/// on the data being encoded (which may be a password).
+fn base64_char(x: u8) -> u8 {
+    let x = x as i16;
+    let mut c = x + b'A' as i16;
+    c += ((25 - x) >> 8) & 6;   // 'a' - 26 - 'A'
+    c -= ((51 - x) >> 8) & 75;  // 'a' - 26 - ('0' - 52)
+    c -= ((61 - x) >> 8) & 15;  // '0' - 52 - ('+' - 62)
+    c += ((62 - x) >> 8) & 3;   // '/' - 63 - ('+' - 62)
+    return c as u8;
+}
+
+/// Encode data as base64 (standard alphabet, with padding)
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut buf = Vec::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let n = ((chunk[0] as u32) << 16) |
+                ((*chunk.get(1).unwrap_or(&0) as u32) << 8) |
+                 (*chunk.get(2).unwrap_or(&0) as u32);
+        buf.push(base64_char((n >> 18) as u8 & 63));
+        buf.push(base64_char((n >> 12) as u8 & 63));
+        if chunk.len() > 1 {
+            buf.push(base64_char((n >> 6) as u8 & 63));
+        } else {
+            buf.push(b'=');
+        }
+        if chunk.len() > 2 {
+            buf.push(base64_char(n as u8 & 63));
+        } else {
+            buf.push(b'=');
+        }
+    }
+    return String::from_utf8(buf).expect("base64 is ascii");
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Key, Accept, base64_encode};
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"Aladdin:open sesame"),
+                   "QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        assert_eq!(base64_encode(b"\xfb\xff\xbf"), "+/+/");
+        assert_eq!(base64_encode(b"the sample nonce"),
+                   Key(*b"the sample nonce").to_string());
+    }
+
+    #[test]
+    fn sample_key() {
+        // Example from RFC 6455
+        let key = Key(*b"the sample nonce");
+        assert_eq!(key.to_string(), "dGhlIHNhbXBsZSBub25jZQ==");
+        assert_eq!(Accept::from_key_bytes(key.to_string().as_bytes())
+                   .to_string(),
+                   "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn random_key() {
+        let a = Key::new().to_string();
+        let b = Key::new().to_string();
+        assert_eq!(a.len(), 24);
+        assert!(a.ends_with("=="));
+        assert!(a != b);
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use futures::Poll;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+
+/// Counters of a websocket connection
+///
+/// Bytes are counted by the `Tracked` transport wrapper, frames by the
+/// `WebSocketStream`, and handshake duration must be recorded by the
+/// caller using `record_handshake`. Counters are updated with relaxed
+/// ordering, so they are only meant to be exported as statistics.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Bytes read from the transport (including handshake)
+    pub bytes_read: AtomicU64,
+    /// Bytes written to the transport (including handshake)
+    pub bytes_written: AtomicU64,
+    /// Number of frames sent
+    pub frames_sent: AtomicU64,
+    /// Number of frames received
+    pub frames_received: AtomicU64,
+    /// Duration of the websocket handshake in microseconds
+    pub handshake_duration_us: AtomicU64,
+}
+
+/// A transport wrapper that counts bytes read and written into `Metrics`
+///
+/// Wrap the socket before handshake and pass the same metrics to
+/// `WebSocketStream::with_metrics`:
+///
+/// ```rust,ignore
+/// let metrics = Arc::new(Metrics::new());
+/// let started = Instant::now();
+/// HandshakeProto::new(Tracked::new(sock, &metrics), authorizer)
+/// .map(move |(out, inp, ())| {
+///     metrics.record_handshake(started.elapsed());
+///     WebSocketStream::with_metrics(out, inp, &metrics)
+/// })
+/// ```
+#[derive(Debug)]
+pub struct Tracked<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl Metrics {
+    /// Create zeroed counters
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+    /// Store the duration of the handshake
+    pub fn record_handshake(&self, duration: Duration) {
+        let us = duration.as_secs() * 1_000_000 +
+            (duration.subsec_nanos() / 1000) as u64;
+        self.handshake_duration_us.store(us, Ordering::Relaxed);
+    }
+    pub(crate) fn frame_sent(&self) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn frame_received(&self) {
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<S> Tracked<S> {
+    /// Wrap a transport
+    pub fn new(inner: S, metrics: &Arc<Metrics>) -> Tracked<S> {
+        Tracked {
+            inner: inner,
+            metrics: metrics.clone(),
+        }
+    }
+    /// Returns the metrics updated by this transport
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+    /// Returns a reference to the underlying transport
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+    /// Returns a mutable reference to the underlying transport
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    /// Returns the underlying transport
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for Tracked<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.inner.read(buf)?;
+        self.metrics.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+        Ok(bytes)
+    }
+}
+
+impl<S: Write> Write for Tracked<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = self.inner.write(buf)?;
+        self.metrics.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        Ok(bytes)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Tracked<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for Tracked<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use futures::{Future, Sink, Stream};
+    use tk_bufstream::{IoBuf, MockData};
+
+    use websocket::{ServerCodec, Packet, WebSocketStream};
+    use super::{Metrics, Tracked};
+
+    #[test]
+    fn counters() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_handshake(Duration::from_millis(12));
+        let mock = MockData::new();
+        let (tx, rx) = IoBuf::new(Tracked::new(mock.clone(), &metrics))
+            .split();
+        let ws = WebSocketStream::with_metrics(tx.framed(ServerCodec),
+            rx.framed(ServerCodec), &metrics);
+        mock.add_input(&b"\x81\x82\0\0\0\0hi"[..]);
+        let (packet, ws) = ws.into_future().wait()
+            .map_err(|(e, _)| e).unwrap();
+        assert_matches!(packet, Some(Packet::Text(_)));
+        let ws = ws.send(Packet::Text("hello".into())).wait().unwrap();
+        assert!(Arc::ptr_eq(&ws.metrics(), &metrics));
+        assert_eq!(metrics.bytes_read.load(Ordering::Relaxed), 8);
+        assert_eq!(metrics.bytes_written.load(Ordering::Relaxed), 7);
+        assert_eq!(metrics.frames_received.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.frames_sent.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.handshake_duration_us.load(Ordering::Relaxed),
+                   12000);
+    }
+}
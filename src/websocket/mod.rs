@@ -1,24 +1,67 @@
 //! Websocket support stuff
 //!
-//! Websockets are initiated by server implementation, this module only
-//! contains websocket message types and similar stuff.
+//! Websockets are usually initiated by server implementation, this module
+//! contains websocket message types and similar stuff. It also contains
+//! standalone client and server handshake implementations.
 use std::time::Duration;
 
 mod alloc;
+mod binary;
 mod codec;
 mod config;
+mod conn_id;
+mod cookie;
 mod dispatcher;
+mod digest;
 mod error;
+mod extension;
+mod hash;
 mod keys;
+mod metrics;
+mod ping_pong;
+mod raw_upgrade;
+mod reconnect;
+mod redirect;
+mod retry;
+mod stream;
+mod subprotocol;
+mod write_queue;
 mod zero_copy;
+#[cfg(feature="deflate")] mod deflate;
 pub mod client;
+pub mod close;
+pub mod server;
+#[cfg(feature="tls")] pub mod tls;
 
 pub use self::alloc::Packet;
-pub use self::codec::{ServerCodec, ClientCodec};
+pub use self::binary::{BinarySink, BinaryStream};
+pub use self::close::{CloseFrame, ClosePayload, CloseCode, ClosingState};
+pub use self::codec::{ServerCodec, ClientCodec, FragmentationMode};
+pub use self::cookie::{SetCookie, Cookies};
 pub use self::dispatcher::{Loop, Dispatcher};
+pub use self::digest::{DigestAuthorizer, DigestAlgorithm};
 pub use self::error::Error;
+pub use self::extension::ExtensionNegotiator;
 pub use self::keys::{GUID, Accept, Key};
-pub use self::zero_copy::Frame;
+pub use self::metrics::{Metrics, Tracked};
+pub use self::ping_pong::{PingPong, PingStream};
+pub use self::raw_upgrade::RawUpgradeProto;
+pub use self::reconnect::{Client, ReconnectPolicy, ReconnectingStream};
+pub use self::reconnect::{Connect, TcpFactory};
+pub use self::redirect::{FollowRedirects, RedirectFactory};
+pub use self::retry::{RetryingHandshake, HandshakeFactory};
+pub use self::retry::{Backoff, FixedBackoff, ExponentialBackoff};
+pub use self::stream::{WebSocketStream, SendFragmented, SendWithDeadline};
+pub use self::stream::{Broadcast, broadcast, SendVectored};
+pub use self::subprotocol::SubprotocolRegistry;
+pub use self::write_queue::WriteQueue;
+pub use self::zero_copy::{Frame, FrameHeader, FrameBuilder};
+#[cfg(feature="deflate")]
+pub use self::deflate::{DeflateCodec, DeflateHandshakeProto};
+#[cfg(feature="deflate")]
+pub use self::deflate::{CompressionLevel, DeflateMessage, DeflateSink};
+#[cfg(feature="deflate")]
+pub use self::deflate::LevelControl;
 
 
 /// Configuration of a `websocket::Loop` object (a server-side websocket
@@ -30,3 +73,13 @@ pub struct Config {
     byte_timeout: Duration,
     max_packet_size: usize,
 }
+
+/// Configuration of a client-side websocket handshake (`HandshakeProto`)
+#[derive(Debug, Clone)]
+pub struct HandshakeConfig {
+    max_headers: usize,
+    max_header_size: usize,
+    max_response_size: usize,
+    max_frame_size: usize,
+    fragmentation: FragmentationMode,
+}
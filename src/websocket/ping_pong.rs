@@ -0,0 +1,385 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll, Sink, StartSend, Stream};
+use futures::unsync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
+use tk_bufstream::{Encode, Decode, WriteFramed, ReadFramed};
+use tk_bufstream::{FutureWriteRaw, WriteRaw};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use ConnectionId;
+use websocket::{Packet, Error};
+use websocket::close::{CloseFrame, ClosingState};
+use websocket::close::{NORMAL_CLOSURE, NO_STATUS_RECEIVED, ABNORMAL_CLOSURE};
+use websocket::conn_id;
+use websocket::error::ErrorEnum;
+
+
+/// Maximum payload of a control frame
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+
+/// A wrapper around websocket streams that handles ping and pong frames
+///
+/// Every incoming `Ping` is replied with a `Pong` with the same payload, and
+/// neither pings nor pongs are yielded from the stream. So the stream yields
+/// only `Text`, `Binary` and `Close` packets.
+///
+/// This is useful for the connections established by `HandshakeProto`,
+/// because unlike server-side `Loop` it doesn't handle pings by itself.
+///
+/// The wrapper also tracks the closing handshake. When peer sends `Close`,
+/// the close frame is replied automatically, the `Close` packet is yielded
+/// and then the stream ends. Before the end of stream is returned, the
+/// output is flushed and shut down (see `AsyncWrite::shutdown`), so that
+/// peer sees the connection closed. You can start closing handshake
+/// yourself with `send_close`.
+pub struct PingPong<S, C> {
+    output: Output<S, C>,
+    input: ReadFramed<S, C>,
+    pings: VecDeque<(Vec<u8>, Instant)>,
+    rtt: Option<UnboundedSender<Duration>>,
+    closing: ClosingState,
+    id: ConnectionId,
+}
+
+/// Output stream, shut down when closing handshake is complete
+enum Output<S, C> {
+    Framed(WriteFramed<S, C>),
+    Flushing(FutureWriteRaw<S>),
+    ShuttingDown(WriteRaw<S>),
+    Done,
+}
+
+/// A stream of round-trip times of the pings sent with `send_ping`
+///
+/// Created by `PingPong::ping_stream`.
+pub struct PingStream(UnboundedReceiver<Duration>);
+
+impl<S, C> PingPong<S, C>
+    where C: Encode<Item=Packet> + Decode<Item=Packet>,
+          S: AsyncRead + AsyncWrite,
+{
+    /// Wrap output and input streams (as returned by `HandshakeProto`)
+    ///
+    /// A new connection id is used in log messages, use `with_id` to keep
+    /// the one of the handshake.
+    pub fn new(output: WriteFramed<S, C>, input: ReadFramed<S, C>)
+        -> PingPong<S, C>
+    {
+        PingPong::with_id(output, input, conn_id::next())
+    }
+    /// Same as `new` but logs with the specified connection id
+    ///
+    /// Pass `HandshakeProto::connection_id()` here so that log messages of
+    /// the handshake and of the connection itself can be correlated.
+    pub fn with_id(output: WriteFramed<S, C>, input: ReadFramed<S, C>,
+        id: ConnectionId)
+        -> PingPong<S, C>
+    {
+        PingPong {
+            output: Output::Framed(output),
+            input: input,
+            pings: VecDeque::new(),
+            rtt: None,
+            closing: ClosingState::Open,
+            id: id,
+        }
+    }
+    /// Send a ping with the specified payload
+    ///
+    /// The ping is written into the output buffer immediately, it's sent to
+    /// the network on the next `poll` or `poll_complete`. The ping is
+    /// ignored if the connection is already shut down.
+    ///
+    /// # Panics
+    ///
+    /// When payload is larger than 125 bytes (limit of the control frame)
+    pub fn send_ping(&mut self, payload: &[u8]) {
+        assert!(payload.len() <= MAX_CONTROL_PAYLOAD,
+            "ping payload must be at most 125 bytes");
+        let output = match self.output {
+            Output::Framed(ref mut output) => output,
+            _ => return,
+        };
+        // WriteFramed neither fails nor returns NotReady on start_send
+        output.start_send(Packet::Ping(payload.to_vec()))
+            .expect("can always write into a buffer");
+        self.pings.push_back((payload.to_vec(), Instant::now()));
+    }
+    /// Returns a stream of round-trip times for pings sent with `send_ping`
+    ///
+    /// Only one stream may be active at a time, calling this method again
+    /// stops the previous stream. Note: the values are delivered only when
+    /// `PingPong` itself is polled.
+    pub fn ping_stream(&mut self) -> PingStream {
+        let (tx, rx) = unbounded();
+        self.rtt = Some(tx);
+        PingStream(rx)
+    }
+    /// Start closing handshake
+    ///
+    /// The close frame is written into the output buffer immediately. After
+    /// this call no more packets can be sent, but incoming packets are still
+    /// yielded until peer replies with its own close frame.
+    ///
+    /// Returns `Closed` error if close frame has already been sent.
+    pub fn send_close(&mut self, frame: CloseFrame) -> Result<(), Error> {
+        if self.closing != ClosingState::Open {
+            return Err(ErrorEnum::Closed.into());
+        }
+        debug!("[{}] Closing websocket: {:?}", self.id, frame);
+        self.framed().start_send(frame.into()).map_err(ErrorEnum::Io)?;
+        self.closing = ClosingState::Initiated;
+        Ok(())
+    }
+    /// Returns current state of the closing handshake
+    pub fn closing_state(&self) -> ClosingState {
+        self.closing
+    }
+    /// Returns underlying output and input streams
+    ///
+    /// # Panics
+    ///
+    /// When the stream has already ended after the closing handshake, as
+    /// the output is shut down at this point.
+    pub fn into_inner(self) -> (WriteFramed<S, C>, ReadFramed<S, C>) {
+        match self.output {
+            Output::Framed(output) => (output, self.input),
+            _ => panic!("output is already shut down"),
+        }
+    }
+    fn framed(&mut self) -> &mut WriteFramed<S, C> {
+        match self.output {
+            Output::Framed(ref mut output) => output,
+            // only happens after closing handshake is complete
+            _ => unreachable!(),
+        }
+    }
+    fn poll_shutdown(&mut self) -> Poll<(), Error> {
+        loop {
+            self.output = match mem::replace(&mut self.output, Output::Done) {
+                Output::Framed(output) => {
+                    Output::Flushing(output.into_inner().borrow_raw())
+                }
+                Output::Flushing(mut future) => {
+                    match future.poll().map_err(ErrorEnum::Io)? {
+                        Async::Ready(raw) => Output::ShuttingDown(raw),
+                        Async::NotReady => {
+                            self.output = Output::Flushing(future);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                Output::ShuttingDown(mut raw) => {
+                    match raw.shutdown().map_err(ErrorEnum::Io)? {
+                        Async::Ready(()) => Output::Done,
+                        Async::NotReady => {
+                            self.output = Output::ShuttingDown(raw);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                Output::Done => return Ok(Async::Ready(())),
+            };
+        }
+    }
+    fn pong_received(&mut self, payload: &[u8]) {
+        // Server may reply only to the latest of the multiple pings, so
+        // all older pings are discarded
+        let idx = match self.pings.iter().position(|&(ref p, _)| p == payload)
+        {
+            Some(idx) => idx,
+            None => {
+                warn!("[{}] Unsolicited pong received: {:?}",
+                    self.id, String::from_utf8_lossy(payload));
+                return;
+            }
+        };
+        let (_, time) = self.pings.drain(..idx+1).last().unwrap();
+        if let Some(ref tx) = self.rtt {
+            tx.unbounded_send(time.elapsed()).ok();
+        }
+    }
+}
+
+impl<S, C> Stream for PingPong<S, C>
+    where C: Encode<Item=Packet> + Decode<Item=Packet>,
+          S: AsyncRead + AsyncWrite,
+{
+    type Item = Packet;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Packet>, Error> {
+        loop {
+            if matches!(self.closing, ClosingState::Closed {..}) {
+                return Ok(self.poll_shutdown()?.map(|()| None));
+            }
+            let packet = self.input.poll().map_err(ErrorEnum::Io)?;
+            match packet {
+                Async::Ready(Some(Packet::Close(code, reason))) => {
+                    debug!("[{}] Websocket closed by peer [{}]{:?}",
+                        self.id, code, reason);
+                    if self.closing == ClosingState::Open {
+                        let reply = match code {
+                            NO_STATUS_RECEIVED | ABNORMAL_CLOSURE
+                            => NORMAL_CLOSURE,
+                            code => code,
+                        };
+                        self.framed()
+                            .start_send(Packet::Close(reply, "".into()))
+                            .map_err(ErrorEnum::Io)?;
+                        self.closing = ClosingState::Closed {
+                            initiated: false,
+                        };
+                    } else {
+                        self.closing = ClosingState::Closed {
+                            initiated: true,
+                        };
+                    }
+                    self.framed().poll_complete().map_err(ErrorEnum::Io)?;
+                    return Ok(Async::Ready(Some(Packet::Close(code, reason))));
+                }
+                Async::Ready(Some(Packet::Ping(data))) => {
+                    self.framed().start_send(Packet::Pong(data))
+                        .map_err(ErrorEnum::Io)?;
+                }
+                Async::Ready(Some(Packet::Pong(data))) => {
+                    self.pong_received(&data);
+                }
+                Async::Ready(pkt) => {
+                    self.framed().poll_complete().map_err(ErrorEnum::Io)?;
+                    return Ok(Async::Ready(pkt));
+                }
+                Async::NotReady => {
+                    self.framed().poll_complete().map_err(ErrorEnum::Io)?;
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
+
+impl<S, C> Sink for PingPong<S, C>
+    where C: Encode<Item=Packet> + Decode<Item=Packet>,
+          S: AsyncRead + AsyncWrite,
+{
+    type SinkItem = Packet;
+    type SinkError = Error;
+    fn start_send(&mut self, item: Packet) -> StartSend<Packet, Error> {
+        if self.closing != ClosingState::Open {
+            return Err(ErrorEnum::Closed.into());
+        }
+        if let Packet::Close(code, ref reason) = item {
+            debug!("[{}] Closing websocket: [{}]{:?}",
+                self.id, code, reason);
+            self.closing = ClosingState::Initiated;
+        }
+        Ok(self.framed().start_send(item).map_err(ErrorEnum::Io)?)
+    }
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        if matches!(self.closing, ClosingState::Closed {..}) {
+            return self.poll_shutdown();
+        }
+        Ok(self.framed().poll_complete().map_err(ErrorEnum::Io)?)
+    }
+}
+
+impl Stream for PingStream {
+    type Item = Duration;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Duration>, Error> {
+        match self.0.poll() {
+            Ok(x) => Ok(x),
+            Err(()) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Stream;
+    use tk_bufstream::IoBuf;
+
+    use ConnectionId;
+    use websocket::{ClientCodec, Packet, CloseFrame, ClosingState};
+    use websocket::close::{NORMAL_CLOSURE, GOING_AWAY};
+    use mock::MockTransport;
+    use super::PingPong;
+
+    fn ping_pong(mock: &MockTransport)
+        -> PingPong<MockTransport, ClientCodec>
+    {
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        PingPong::with_id(tx.framed(ClientCodec::new()),
+                          rx.framed(ClientCodec::new()), ConnectionId(1))
+    }
+
+    #[test]
+    fn reply_to_ping() {
+        let mock = MockTransport::new();
+        let mut pp = ping_pong(&mock);
+        mock.feed(&b"\x89\x02hi\x81\x03abc"[..]);
+        assert_matches!(pp.by_ref().wait().next(),
+                        Some(Ok(Packet::Text(ref x))) if x == "abc");
+        let output = mock.written();
+        // masked pong with two bytes of payload
+        assert_eq!(&output[..2], b"\x8a\x82");
+        let mask = &output[2..6];
+        assert_eq!(output[6] ^ mask[0], b'h');
+        assert_eq!(output[7] ^ mask[1], b'i');
+    }
+
+    #[test]
+    fn round_trip() {
+        let mock = MockTransport::new();
+        let mut pp = ping_pong(&mock);
+        let rtt = pp.ping_stream();
+        pp.send_ping(b"1");
+        pp.send_ping(b"2");
+        mock.feed(&b"\x8a\x012\x81\x01a"[..]);
+        assert_matches!(pp.by_ref().wait().next(),
+                        Some(Ok(Packet::Text(ref x))) if x == "a");
+        drop(pp);
+        assert_eq!(rtt.wait().count(), 1);
+    }
+
+    #[test]
+    fn reply_to_close() {
+        let mock = MockTransport::new();
+        let mut pp = ping_pong(&mock);
+        mock.feed(&b"\x88\x02\x03\xe9"[..]);
+        assert_matches!(pp.by_ref().wait().next(),
+                        Some(Ok(Packet::Close(GOING_AWAY, _))));
+        assert_eq!(pp.closing_state(),
+                   ClosingState::Closed { initiated: false });
+        assert!(!mock.is_shut_down());
+        assert!(pp.by_ref().wait().next().is_none());
+        assert!(mock.is_shut_down());
+        let output = mock.written();
+        assert_eq!(&output[..2], b"\x88\x82");
+        assert_eq!(output[6] ^ output[2], 0x03);
+        assert_eq!(output[7] ^ output[3], 0xe9);
+    }
+
+    #[test]
+    fn initiate_close() {
+        let mock = MockTransport::new();
+        let mut pp = ping_pong(&mock);
+        pp.send_close(CloseFrame::new(NORMAL_CLOSURE, "bye")).unwrap();
+        assert_eq!(pp.closing_state(), ClosingState::Initiated);
+        assert!(pp.send_close(CloseFrame::new(NORMAL_CLOSURE, "")).is_err());
+        mock.feed(&b"\x81\x01a\x88\x02\x03\xe8"[..]);
+        assert_matches!(pp.by_ref().wait().next(),
+                        Some(Ok(Packet::Text(ref x))) if x == "a");
+        assert_matches!(pp.by_ref().wait().next(),
+                        Some(Ok(Packet::Close(NORMAL_CLOSURE, _))));
+        assert_eq!(pp.closing_state(),
+                   ClosingState::Closed { initiated: true });
+        assert!(pp.by_ref().wait().next().is_none());
+        assert!(mock.is_shut_down());
+        // only our own close frame is sent
+        assert_eq!(&mock.written()[..2], b"\x88\x85");
+        assert_eq!(mock.written().len(), 11);
+    }
+}
@@ -0,0 +1,240 @@
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use futures::{Future, Async};
+use httparse;
+use tk_bufstream::{IoBuf, ReadBuf, WriteBuf};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use base_serializer::MessageState;
+use enums::Version;
+use websocket::{Error, HandshakeConfig};
+use websocket::error::ErrorEnum;
+
+
+/// Number of headers to allocate on a stack
+const MIN_HEADERS: usize = 16;
+
+
+/// A future that upgrades HTTP connection to a raw byte stream
+///
+/// This is similar to `HandshakeProto` but for protocols other than
+/// websocket: it sends a `GET` request with `Connection: upgrade` and
+/// `Upgrade: <protocol>` headers and waits for `101 Switching Protocols`.
+/// The resulting buffers are returned as is without any codec, so any
+/// bytes that server sent right after the response headers are left in
+/// the input buffer.
+///
+/// If server responds with a status other than 101 the future fails with
+/// `UpgradeRejected` error (`Error::rejected_status` returns the status),
+/// if it switches to another protocol it fails with `InvalidUpgradeHeader`,
+/// and if there is no `upgrade` token in `Connection` header it fails with
+/// `InvalidConnectionHeader`.
+pub struct RawUpgradeProto<S> {
+    input: Option<ReadBuf<S>>,
+    output: Option<WriteBuf<S>>,
+    protocol: String,
+    config: Arc<HandshakeConfig>,
+}
+
+impl<S: AsyncRead + AsyncWrite> RawUpgradeProto<S> {
+    /// Create an instance of future from already connected socket
+    ///
+    /// # Panics
+    ///
+    /// When host or protocol contain invalid characters.
+    pub fn new(transport: S, host: &str, path: &str, protocol: &str)
+        -> RawUpgradeProto<S>
+    {
+        RawUpgradeProto::with_config(transport, host, path, protocol,
+                                     &HandshakeConfig::new().done())
+    }
+    /// Same as `new` but allows to tune limits on the response headers
+    pub fn with_config(transport: S, host: &str, path: &str,
+        protocol: &str, config: &Arc<HandshakeConfig>)
+        -> RawUpgradeProto<S>
+    {
+        let (mut tx, rx) = IoBuf::new(transport).split();
+        {
+            let ref mut buf = tx.out_buf;
+            let mut msg = MessageState::RequestStart;
+            msg.request_line(buf, "GET", path, Version::Http11);
+            msg.add_header(buf, "Host", host.as_bytes()).unwrap();
+            msg.add_header(buf, "Connection", b"upgrade").unwrap();
+            msg.add_header(buf, "Upgrade", protocol.as_bytes()).unwrap();
+            msg.done_headers(buf).map(|ignore| assert!(ignore)).unwrap();
+            msg.done(buf);
+        }
+        RawUpgradeProto {
+            input: Some(rx),
+            output: Some(tx),
+            protocol: protocol.to_string(),
+            config: config.clone(),
+        }
+    }
+    fn parse_headers(&mut self) -> Result<bool, Error> {
+        let ref mut buf = self.input.as_mut()
+            .expect("buffer still exists")
+            .in_buf;
+        let bytes = {
+            let mut vec;
+            let mut headers = [httparse::EMPTY_HEADER; MIN_HEADERS];
+            let ref cfg = self.config;
+            let stack = min(MIN_HEADERS, cfg.max_headers);
+            let mut raw = httparse::Response::new(&mut headers[..stack]);
+            let mut result = raw.parse(&buf[..]);
+            if matches!(result, Err(httparse::Error::TooManyHeaders)) &&
+                cfg.max_headers > MIN_HEADERS
+            {
+                vec = vec![httparse::EMPTY_HEADER; cfg.max_headers];
+                raw = httparse::Response::new(&mut vec);
+                result = raw.parse(&buf[..]);
+            }
+            let bytes = match result.map_err(ErrorEnum::HeaderError)? {
+                httparse::Status::Complete(bytes) => bytes,
+                httparse::Status::Partial => {
+                    if buf.len() > cfg.max_response_size {
+                        return Err(ErrorEnum::ResponseTooLarge.into());
+                    }
+                    return Ok(false);
+                }
+            };
+            if bytes > cfg.max_response_size || raw.headers.iter()
+                .any(|h| h.name.len() + h.value.len() > cfg.max_header_size)
+            {
+                return Err(ErrorEnum::ResponseTooLarge.into());
+            }
+            let code = raw.code.unwrap();
+            debug!("Raw upgrade response received: {} {}",
+                code, raw.reason.unwrap());
+            if code != 101 {
+                return Err(ErrorEnum::UpgradeRejected {
+                    status: code,
+                    reason: raw.reason.unwrap().to_string(),
+                }.into());
+            }
+            if raw.version.unwrap() != 1 {
+                return Err(ErrorEnum::VersionTooOld.into());
+            }
+            let connection = raw.headers.iter()
+                .filter(|h| h.name.eq_ignore_ascii_case("Connection"))
+                .filter_map(|h| from_utf8(h.value).ok())
+                .any(|v| v.split(',')
+                          .any(|x| x.trim().eq_ignore_ascii_case("upgrade")));
+            if !connection {
+                return Err(ErrorEnum::InvalidConnectionHeader.into());
+            }
+            let upgrade = raw.headers.iter()
+                .find(|h| h.name.eq_ignore_ascii_case("Upgrade"))
+                .map(|h| h.value);
+            match upgrade {
+                Some(value) if value.eq_ignore_ascii_case(
+                    self.protocol.as_bytes()) => {}
+                _ => return Err(ErrorEnum::InvalidUpgradeHeader.into()),
+            }
+            bytes
+        };
+        buf.consume(bytes);
+        Ok(true)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Future for RawUpgradeProto<S> {
+    type Item = (WriteBuf<S>, ReadBuf<S>);
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Self::Item>, Error> {
+        self.output.as_mut().expect("poll after complete")
+            .flush().map_err(ErrorEnum::Io)?;
+        loop {
+            let bytes = self.input.as_mut().expect("poll after complete")
+                .read().map_err(ErrorEnum::Io)?;
+            if self.parse_headers()? {
+                let inp = self.input.take().expect("input still here");
+                let out = self.output.take().expect("output still here");
+                debug!("Upgrade to {:?} complete", self.protocol);
+                return Ok(Async::Ready((out, inp)));
+            }
+            if self.input.as_mut().expect("poll after complete").done() {
+                return Err(ErrorEnum::PrematureResponseHeaders.into());
+            }
+            if bytes == 0 {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Async};
+    use futures::future::lazy;
+
+    use mock::MockTransport;
+    use websocket::Error;
+    use websocket::error::ErrorEnum;
+    use super::RawUpgradeProto;
+
+    #[test]
+    fn upgrade() {
+        let mock = MockTransport::new();
+        let mut proto = RawUpgradeProto::new(mock.clone(),
+            "example.com", "/tunnel", "my-proto/1");
+        lazy(|| {
+            assert!(proto.poll().unwrap().is_not_ready());
+            mock.assert_written(b"GET /tunnel HTTP/1.1\r\n\
+                Host: example.com\r\nConnection: upgrade\r\n\
+                Upgrade: my-proto/1\r\n\r\n");
+            mock.feed(b"HTTP/1.1 101 Switching Protocols\r\n\
+                Connection: upgrade\r\nUpgrade: My-Proto/1\r\n\r\n\
+                \x00\x01raw");
+            match proto.poll().unwrap() {
+                Async::Ready((_, inp)) => {
+                    assert_eq!(&inp.in_buf[..], b"\x00\x01raw");
+                }
+                Async::NotReady => panic!("upgrade is not done"),
+            }
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn rejected() {
+        let mock = MockTransport::new();
+        mock.feed(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        let err = RawUpgradeProto::new(mock, "example.com", "/", "x")
+            .wait().err().unwrap();
+        assert_eq!(err.to_string(),
+            "server rejected protocol upgrade: 404 Not Found");
+        assert_eq!(err.rejected_status(), Some(404));
+    }
+
+    #[test]
+    fn wrong_protocol() {
+        let mock = MockTransport::new();
+        mock.feed(b"HTTP/1.1 101 Switching Protocols\r\n\
+            Connection: upgrade\r\nUpgrade: websocket\r\n\r\n");
+        let err = RawUpgradeProto::new(mock, "example.com", "/", "x")
+            .wait().err().unwrap();
+        assert_eq!(err.to_string(),
+            Error::from(ErrorEnum::InvalidUpgradeHeader).to_string());
+    }
+    #[test]
+    fn no_connection_header() {
+        let mock = MockTransport::new();
+        mock.feed(b"HTTP/1.1 101 Switching Protocols\r\n\
+            Connection: keep-alive\r\nUpgrade: x\r\n\r\n");
+        let err = RawUpgradeProto::new(mock, "example.com", "/", "x")
+            .wait().err().unwrap();
+        assert_eq!(err.to_string(),
+            Error::from(ErrorEnum::InvalidConnectionHeader).to_string());
+
+        let mock = MockTransport::new();
+        mock.feed(b"HTTP/1.1 101 Switching Protocols\r\n\
+            Connection: keep-alive, Upgrade\r\nUpgrade: x\r\n\r\n");
+        assert!(RawUpgradeProto::new(mock, "example.com", "/", "x")
+            .wait().is_ok());
+    }
+}
@@ -0,0 +1,447 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use tk_bufstream::{ReadFramed, WriteFramed};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use websocket::{ClientCodec, Error, Packet, WebSocketStream};
+use websocket::client::{Authorizer, HandshakeProto};
+use websocket::error::ErrorEnum;
+use websocket::retry::{Backoff, ExponentialBackoff, HandshakeFactory};
+use websocket::retry::RetryingHandshake;
+
+
+/// Default number of retries of every (re)connection
+const MAX_RETRIES: u32 = 3;
+
+/// Connection that was up for this number of seconds is considered stable
+const STABLE_SECONDS: u64 = 10;
+
+
+/// What `Client` does when connection is lost
+#[derive(Debug, Clone)]
+pub enum ReconnectPolicy {
+    /// Don't reconnect, return the error
+    None,
+    /// Reconnect immediately, also retry failed handshakes without a delay
+    Immediate,
+    /// Reconnect immediately, but wait between failed handshakes and
+    /// before reconnecting after a connection that was lost right away
+    Backoff(ExponentialBackoff),
+}
+
+/// A websocket client that reconnects when connection is lost
+///
+/// ```rust,ignore
+/// let client = Client::new(&handle)
+///     .reconnect_policy(ReconnectPolicy::Backoff(ExponentialBackoff::new(
+///         Duration::from_millis(100), Duration::from_secs(10))));
+/// client.connect(addr, SimpleAuthorizer::new("example.com", "/chat"))
+/// .and_then(|stream| stream.send(Packet::Text("hello".into())))
+/// ```
+///
+/// The same policy is used for the initial connection: failed handshakes
+/// are retried at most `max_retries` times (see `RetryingHandshake` for
+/// the errors that are retried).
+#[derive(Debug, Clone)]
+pub struct Client {
+    handle: Handle,
+    policy: ReconnectPolicy,
+    max_retries: u32,
+}
+
+/// Creates a TCP connection and a handshake for `Client::connect`
+pub struct TcpFactory<A> {
+    addr: SocketAddr,
+    authorizer: A,
+    handle: Handle,
+}
+
+/// A future returned by `Client::connect`
+pub struct Connect<S, F: HandshakeFactory> {
+    stream: Option<ReconnectingStream<S, F>>,
+}
+
+/// A `Stream` and `Sink` of packets that survives connection loss
+///
+/// When the connection is broken by the network (see
+/// `Error::is_retriable`) or is closed without a close frame (IO layer
+/// reports connection reset as end of stream), a new one is established
+/// using the same factory (and authorizer). The stream and the sink
+/// return `NotReady` until the new connection is ready. Packets that were
+/// in the output buffer when connection is lost are dropped, and nothing
+/// special is received to signal the reconnection, so the application
+/// protocol must tolerate lost messages.
+///
+/// Reconnect is immediate only if the lost connection was stable: it
+/// has received a packet or was up for at least 10 seconds. Otherwise
+/// the delay grows with each connection lost in a row, the same way as
+/// between failed handshakes.
+///
+/// The stream ends when connection is closed after a close frame is
+/// either sent or received.
+pub struct ReconnectingStream<S, F: HandshakeFactory> {
+    stream: Option<WebSocketStream<S>>,
+    handshake: RetryingHandshake<F, ReconnectPolicy>,
+    reconnect: bool,
+    closing: bool,
+    connected_at: Instant,
+    received: bool,
+    reconnects: u32,
+}
+
+impl Backoff for ReconnectPolicy {
+    fn delay(&mut self, attempt: u32) -> Duration {
+        match *self {
+            ReconnectPolicy::None => Duration::new(0, 0),
+            ReconnectPolicy::Immediate => Duration::new(0, 0),
+            ReconnectPolicy::Backoff(ref mut b) => b.delay(attempt),
+        }
+    }
+}
+
+impl Client {
+    /// Create a client with the default policy of immediate reconnect
+    pub fn new(handle: &Handle) -> Client {
+        Client {
+            handle: handle.clone(),
+            policy: ReconnectPolicy::Immediate,
+            max_retries: MAX_RETRIES,
+        }
+    }
+    /// Set the reconnect policy
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Client {
+        self.policy = policy;
+        self
+    }
+    /// Set the maximum number of retries of a failed handshake
+    ///
+    /// Default is 3. Retries are counted for every reconnect separately.
+    /// With `ReconnectPolicy::None` handshakes are never retried.
+    pub fn max_retries(mut self, value: u32) -> Client {
+        self.max_retries = value;
+        self
+    }
+    /// Connect to the websocket server at `addr`
+    ///
+    /// The authorizer is cloned for each connection attempt.
+    pub fn connect<A>(&self, addr: SocketAddr, authorizer: A)
+        -> Connect<TcpStream, TcpFactory<A>>
+        where A: Authorizer<TcpStream> + Clone + 'static,
+    {
+        self.connect_with(TcpFactory {
+            addr: addr,
+            authorizer: authorizer,
+            handle: self.handle.clone(),
+        })
+    }
+    /// Connect using a custom factory of handshakes
+    ///
+    /// This is useful for transports other than plain TCP, e.g. TLS.
+    pub fn connect_with<S, F: HandshakeFactory>(&self, factory: F)
+        -> Connect<S, F>
+    {
+        let reconnect = !matches!(self.policy, ReconnectPolicy::None);
+        let handshake = RetryingHandshake::new(factory, self.policy.clone(),
+            &self.handle)
+            .max_retries(if reconnect { self.max_retries } else { 0 });
+        Connect {
+            stream: Some(ReconnectingStream {
+                stream: None,
+                handshake: handshake,
+                reconnect: reconnect,
+                closing: false,
+                connected_at: Instant::now(),
+                received: false,
+                reconnects: 0,
+            }),
+        }
+    }
+}
+
+impl<A> HandshakeFactory for TcpFactory<A>
+    where A: Authorizer<TcpStream> + Clone + 'static,
+{
+    type Future = Box<Future<Item=(WriteFramed<TcpStream, ClientCodec>,
+                                   ReadFramed<TcpStream, ClientCodec>,
+                                   A::Result),
+                             Error=Error>>;
+    fn start(&mut self) -> Self::Future {
+        let authorizer = self.authorizer.clone();
+        Box::new(TcpStream::connect(&self.addr, &self.handle)
+            .map_err(ErrorEnum::Io).map_err(Error::from)
+            .and_then(move |sock| HandshakeProto::new(sock, authorizer)))
+    }
+}
+
+impl<S, F, R> ReconnectingStream<S, F>
+    where F: HandshakeFactory,
+          F::Future: Future<Item=(WriteFramed<S, ClientCodec>,
+                                  ReadFramed<S, ClientCodec>, R),
+                            Error=Error>,
+          S: AsyncRead + AsyncWrite,
+{
+    /// Returns `true` if connection is established (i.e. not reconnecting)
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+    fn poll_connected(&mut self) -> Poll<(), Error> {
+        if self.stream.is_none() {
+            match self.handshake.poll()? {
+                Async::Ready((output, input, _)) => {
+                    self.stream = Some(WebSocketStream::new(output, input));
+                    self.connected_at = Instant::now();
+                    self.received = false;
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+    fn restart(&mut self) {
+        let stable = self.received ||
+            self.connected_at.elapsed().as_secs() >= STABLE_SECONDS;
+        self.reconnects = if stable { 0 } else { self.reconnects + 1 };
+        self.stream = None;
+        self.closing = false;
+        self.handshake.restart(self.reconnects);
+    }
+    fn check<T>(&mut self, result: Result<T, Error>)
+        -> Result<Option<T>, Error>
+    {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(ref e) if self.reconnect && e.is_retriable() => {
+                debug!("Websocket connection lost: {}, reconnecting", e);
+                self.restart();
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<S, F, R> Future for Connect<S, F>
+    where F: HandshakeFactory,
+          F::Future: Future<Item=(WriteFramed<S, ClientCodec>,
+                                  ReadFramed<S, ClientCodec>, R),
+                            Error=Error>,
+          S: AsyncRead + AsyncWrite,
+{
+    type Item = ReconnectingStream<S, F>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<ReconnectingStream<S, F>, Error> {
+        let ready = self.stream.as_mut()
+            .expect("future is polled after completion")
+            .poll_connected()?;
+        match ready {
+            Async::Ready(()) => Ok(Async::Ready(self.stream.take().unwrap())),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<S, F, R> Stream for ReconnectingStream<S, F>
+    where F: HandshakeFactory,
+          F::Future: Future<Item=(WriteFramed<S, ClientCodec>,
+                                  ReadFramed<S, ClientCodec>, R),
+                            Error=Error>,
+          S: AsyncRead + AsyncWrite,
+{
+    type Item = Packet;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Packet>, Error> {
+        loop {
+            if self.poll_connected()?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            let result = self.stream.as_mut().unwrap().poll();
+            match self.check(result)? {
+                Some(Async::Ready(None)) if self.reconnect && !self.closing
+                => {
+                    debug!("Websocket connection closed unexpectedly, \
+                        reconnecting");
+                    self.restart();
+                }
+                Some(value) => {
+                    match value {
+                        Async::Ready(Some(Packet::Close(..))) => {
+                            self.closing = true;
+                        }
+                        Async::Ready(Some(_)) => self.received = true,
+                        _ => {}
+                    }
+                    return Ok(value);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+impl<S, F, R> Sink for ReconnectingStream<S, F>
+    where F: HandshakeFactory,
+          F::Future: Future<Item=(WriteFramed<S, ClientCodec>,
+                                  ReadFramed<S, ClientCodec>, R),
+                            Error=Error>,
+          S: AsyncRead + AsyncWrite,
+{
+    type SinkItem = Packet;
+    type SinkError = Error;
+    fn start_send(&mut self, item: Packet) -> StartSend<Packet, Error> {
+        if self.poll_connected()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        if let Packet::Close(..) = item {
+            self.closing = true;
+        }
+        self.stream.as_mut().unwrap().start_send(item)
+    }
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        loop {
+            if self.poll_connected()?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            let result = self.stream.as_mut().unwrap().poll_complete();
+            if let Some(value) = self.check(result)? {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
+
+    use std::time::Duration;
+
+    use futures::{Async, Future, Stream};
+    use futures::future::{FutureResult, lazy, ok};
+    use tk_bufstream::{IoBuf, ReadFramed, WriteFramed};
+    use tokio_core::reactor::Core;
+    use tokio_io::{AsyncRead, AsyncWrite};
+
+    use websocket::{Backoff, ClientCodec, Error, Packet, ExponentialBackoff};
+    use super::{Client, ReconnectPolicy};
+
+    /// A connection that returns `data` and then fails (or is closed)
+    struct Flaky(Vec<u8>, bool);
+
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.len() == 0 && self.1 {
+                return Err(io::ErrorKind::TimedOut.into());
+            } else if self.0.len() == 0 {
+                return Ok(0);
+            }
+            let bytes = ::std::cmp::min(buf.len(), self.0.len());
+            buf[..bytes].copy_from_slice(&self.0[..bytes]);
+            self.0.drain(..bytes);
+            Ok(bytes)
+        }
+    }
+    impl AsyncRead for Flaky {}
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+    impl AsyncWrite for Flaky {
+        fn shutdown(&mut self) -> io::Result<Async<()>> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    type Connection = FutureResult<(WriteFramed<Flaky, ClientCodec>,
+                                    ReadFramed<Flaky, ClientCodec>, ()),
+                                   Error>;
+
+    fn factory(calls: &Rc<Cell<u32>>) -> Box<FnMut() -> Connection> {
+        let calls = calls.clone();
+        Box::new(move || {
+            calls.set(calls.get() + 1);
+            let text = format!("conn{}", calls.get());
+            let mut frame = vec![0x81, text.len() as u8];
+            frame.extend(text.as_bytes());
+            let fail = calls.get() % 2 == 1;
+            let (tx, rx) = IoBuf::new(Flaky(frame, fail)).split();
+            ok((tx.framed(ClientCodec::new()),
+                rx.framed(ClientCodec::new()), ()))
+        })
+    }
+
+    #[test]
+    fn reconnect() {
+        let mut lp = Core::new().unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let client = Client::new(&lp.handle());
+        let stream = lp.run(client.connect_with(factory(&calls))).unwrap();
+        assert!(stream.is_connected());
+        let packets = lp.run(stream.take(3).collect()).unwrap();
+        let texts = packets.iter().map(|p| match *p {
+            Packet::Text(ref x) => &x[..],
+            _ => unreachable!(),
+        }).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["conn1", "conn2", "conn3"]);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn no_reconnect() {
+        let mut lp = Core::new().unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let client = Client::new(&lp.handle())
+            .reconnect_policy(ReconnectPolicy::None);
+        let stream = lp.run(client.connect_with(factory(&calls))).unwrap();
+        let err = lp.run(stream.collect()).err().unwrap();
+        assert!(err.is_retriable());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn backoff_on_dropped_connections() {
+        let mut lp = Core::new().unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        // delays are long enough to never elapse while the test runs
+        let mut backoff = ExponentialBackoff::new(Duration::from_secs(10),
+                                                  Duration::from_secs(30));
+        let client = Client::new(&lp.handle())
+            .reconnect_policy(ReconnectPolicy::Backoff(backoff.clone()));
+        // every connection is lost before anything is received
+        let mut stream = lp.run(client.connect_with(move || {
+            c.set(c.get() + 1);
+            let (tx, rx) = IoBuf::new(Flaky(Vec::new(), true)).split();
+            ok((tx.framed(ClientCodec::new()),
+                rx.framed(ClientCodec::new()), ()))
+        })).unwrap();
+        let mut delays = Vec::new();
+        lp.run(lazy(|| {
+            assert!(stream.poll().unwrap().is_not_ready());
+            assert!(!stream.is_connected());
+            delays.push(backoff.delay(stream.reconnects));
+            // next connections are lost too
+            for _ in 0..2 {
+                stream.restart();
+                delays.push(backoff.delay(stream.reconnects));
+            }
+            Ok::<(), ()>(())
+        })).unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(delays, vec![Duration::from_secs(10),
+                                Duration::from_secs(20),
+                                Duration::from_secs(30)]);
+        // a connection that received something resets the backoff
+        stream.received = true;
+        stream.restart();
+        assert_eq!(stream.reconnects, 0);
+        assert_eq!(calls.get(), 2);
+    }
+}
@@ -0,0 +1,205 @@
+use futures::{Future, Poll};
+
+use websocket::Error;
+use websocket::error::ErrorEnum;
+
+
+/// Default number of redirects followed by `FollowRedirects`
+const MAX_REDIRECTS: usize = 5;
+
+
+/// Creates a handshake future for every request of `FollowRedirects`
+///
+/// Location is `None` for the first request and is the target of the
+/// redirect (as sent by server, so it may be relative) for the subsequent
+/// ones. It's implemented for closures, so you usually don't need to
+/// implement it yourself.
+pub trait RedirectFactory {
+    /// A handshake future
+    type Future: Future<Error=Error>;
+    /// Connect to the location and start a new handshake
+    fn start(&mut self, location: Option<&str>) -> Self::Future;
+}
+
+/// A future that follows redirects of websocket handshake
+///
+/// Redirects are followed only if `Authorizer::on_redirect` returns
+/// `RedirectAction::Follow`, otherwise handshake fails as usual. When
+/// a location is visited twice or there are more than `max_redirects`
+/// redirects, the future fails with `TooManyRedirects` error.
+///
+/// ```rust,ignore
+/// FollowRedirects::new(move |location: Option<&str>| {
+///     let url = location.unwrap_or("ws://example.com/chat");
+///     let auth = SimpleAuthorizer::from_url(url).unwrap();
+///     connect(url).and_then(|sock| HandshakeProto::new(sock, auth))
+/// })
+/// ```
+pub struct FollowRedirects<F: RedirectFactory> {
+    factory: F,
+    max_redirects: usize,
+    visited: Vec<String>,
+    handshake: F::Future,
+}
+
+impl<F, H> RedirectFactory for F
+    where F: FnMut(Option<&str>) -> H,
+          H: Future<Error=Error>,
+{
+    type Future = H;
+    fn start(&mut self, location: Option<&str>) -> H {
+        self(location)
+    }
+}
+
+impl<F: RedirectFactory> FollowRedirects<F> {
+    /// Start the first handshake
+    ///
+    /// By default at most 5 redirects are followed.
+    pub fn new(mut factory: F) -> FollowRedirects<F> {
+        let handshake = factory.start(None);
+        FollowRedirects {
+            factory: factory,
+            max_redirects: MAX_REDIRECTS,
+            visited: Vec::new(),
+            handshake: handshake,
+        }
+    }
+    /// Set the maximum number of redirects
+    pub fn max_redirects(mut self, value: usize) -> FollowRedirects<F> {
+        self.max_redirects = value;
+        self
+    }
+    /// Returns locations of the redirects followed so far
+    pub fn redirects(&self) -> &[String] {
+        &self.visited
+    }
+}
+
+impl<F: RedirectFactory> Future for FollowRedirects<F> {
+    type Item = <F::Future as Future>::Item;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        loop {
+            let location = match self.handshake.poll() {
+                Ok(result) => return Ok(result),
+                Err(e) => match e.redirect_location() {
+                    Some(location) => location.to_string(),
+                    None => return Err(e),
+                },
+            };
+            if self.visited.len() >= self.max_redirects ||
+                self.visited.contains(&location)
+            {
+                return Err(ErrorEnum::TooManyRedirects.into());
+            }
+            self.handshake = self.factory.start(Some(&location));
+            self.visited.push(location);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use futures::Future;
+
+    use mock::MockTransport;
+    use websocket::Error;
+    use websocket::client::{Authorizer, Encoder, EncoderDone, Head};
+    use websocket::client::{HandshakeProto, SimpleAuthorizer};
+    use websocket::client::RedirectAction;
+    use websocket::error::ErrorEnum;
+    use super::FollowRedirects;
+
+    struct Follow(SimpleAuthorizer);
+
+    impl<S> Authorizer<S> for Follow {
+        type Result = Option<String>;
+        fn write_headers(&mut self, e: Encoder<S>) -> EncoderDone<S> {
+            self.0.write_headers(e)
+        }
+        fn headers_received(&mut self, headers: &Head)
+            -> Result<Self::Result, Error>
+        {
+            Authorizer::<S>::headers_received(&mut self.0, headers)
+        }
+        fn on_redirect(&mut self, _location: &str, _status: u16)
+            -> RedirectAction
+        {
+            RedirectAction::Follow
+        }
+    }
+
+    fn handshake(response: &[u8]) -> HandshakeProto<MockTransport, Follow> {
+        let mock = MockTransport::new();
+        mock.feed(response);
+        mock.close();
+        HandshakeProto::new(mock,
+            Follow(SimpleAuthorizer::new("example.com", "/")))
+    }
+
+    fn redirect(location: &str) -> Vec<u8> {
+        format!("HTTP/1.1 302 Found\r\nLocation: {}\r\n\
+                 Content-Length: 0\r\n\r\n", location).into_bytes()
+    }
+
+    #[test]
+    fn follow() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let c = calls.clone();
+        let future = FollowRedirects::new(move |loc: Option<&str>| {
+            c.borrow_mut().push(loc.map(|x| x.to_string()));
+            match loc {
+                None => handshake(&redirect("/a")),
+                Some("/a") => handshake(&redirect("/b")),
+                _ => handshake(b"HTTP/1.1 403 Forbidden\r\n\
+                                 Content-Length: 0\r\n\r\n"),
+            }
+        });
+        let err = future.wait().err().unwrap();
+        assert_eq!(err.to_string(),
+            "server rejected websocket connection: 403 Forbidden");
+        assert_eq!(*calls.borrow(), vec![
+            None, Some("/a".to_string()), Some("/b".to_string())]);
+    }
+
+    #[test]
+    fn redirect_loop() {
+        let future = FollowRedirects::new(|loc: Option<&str>| {
+            match loc {
+                Some("/a") => handshake(&redirect("/b")),
+                _ => handshake(&redirect("/a")),
+            }
+        });
+        let err = future.wait().err().unwrap();
+        assert_eq!(err.to_string(),
+                   Error::from(ErrorEnum::TooManyRedirects).to_string());
+    }
+
+    #[test]
+    fn max_redirects() {
+        let future = FollowRedirects::new(|loc: Option<&str>| {
+            let next = format!("{}x", loc.unwrap_or("/"));
+            handshake(&redirect(&next))
+        }).max_redirects(2);
+        let err = future.wait().err().unwrap();
+        assert_eq!(err.to_string(),
+                   Error::from(ErrorEnum::TooManyRedirects).to_string());
+    }
+
+    #[test]
+    fn reject_by_default() {
+        let mock = MockTransport::new();
+        mock.feed(&redirect("/a"));
+        mock.close();
+        let err = HandshakeProto::new(mock,
+            SimpleAuthorizer::new("example.com", "/"))
+            .wait().err().unwrap();
+        assert_eq!(err.to_string(),
+            "server rejected websocket connection: 302 Found");
+        assert!(err.redirect_location().is_none());
+    }
+}
@@ -0,0 +1,300 @@
+use std::cmp::min;
+use std::time::Duration;
+
+use futures::{Future, Async, Poll};
+use tokio_core::reactor::{Handle, Timeout};
+
+use websocket::Error;
+use websocket::error::ErrorEnum;
+
+
+/// Default number of retries of `RetryingHandshake`
+const MAX_RETRIES: u32 = 3;
+
+
+/// A strategy that chooses a delay before the next handshake attempt
+pub trait Backoff {
+    /// Returns delay before retry number `attempt` (starting from 1)
+    fn delay(&mut self, attempt: u32) -> Duration;
+}
+
+/// A backoff strategy that always waits for the same time
+#[derive(Debug, Clone)]
+pub struct FixedBackoff {
+    delay: Duration,
+}
+
+/// A backoff strategy that doubles the delay after each attempt
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+}
+
+/// Creates a handshake future for every attempt of `RetryingHandshake`
+///
+/// It's implemented for closures returning `HandshakeProto` (or any other
+/// future with websocket `Error`), so you usually don't need to implement
+/// it yourself.
+pub trait HandshakeFactory {
+    /// A handshake future
+    type Future: Future<Error=Error>;
+    /// Start a new handshake attempt
+    fn start(&mut self) -> Self::Future;
+}
+
+/// A future that repeats websocket handshake on transient failures
+///
+/// Handshake is retried when the connection is refused, broken by the
+/// network or closed before response headers are received (see
+/// `Error::is_retriable`).
+/// Other errors, like `ServerRejected`, are returned immediately.
+///
+/// A factory is called for each attempt, so it should establish a new
+/// connection every time. If connection itself should be established
+/// asynchronously, make factory return a combined future:
+///
+/// ```rust,ignore
+/// RetryingHandshake::new(move || {
+///     TcpStream::connect(&addr, &handle).map_err(Error::from)
+///     .and_then(|sock| HandshakeProto::new(sock, SimpleAuthorizer::new(
+///         "example.com", "/chat")))
+/// }, ExponentialBackoff::new(Duration::from_millis(100),
+///                            Duration::from_secs(5)), &handle)
+/// ```
+pub struct RetryingHandshake<F: HandshakeFactory, B> {
+    factory: F,
+    backoff: B,
+    max_retries: u32,
+    retries: u32,
+    reconnects: u32,
+    handle: Handle,
+    state: State<F::Future>,
+}
+
+enum State<H> {
+    Connecting(H),
+    Sleeping(Timeout),
+}
+
+impl FixedBackoff {
+    /// Wait for `delay` before every retry
+    pub fn new(delay: Duration) -> FixedBackoff {
+        FixedBackoff { delay: delay }
+    }
+}
+
+impl Backoff for FixedBackoff {
+    fn delay(&mut self, _attempt: u32) -> Duration {
+        self.delay
+    }
+}
+
+impl ExponentialBackoff {
+    /// Wait for `initial` before the first retry, doubling the delay after
+    /// each retry but never waiting longer than `max`
+    pub fn new(initial: Duration, max: Duration) -> ExponentialBackoff {
+        ExponentialBackoff {
+            initial: initial,
+            max: max,
+        }
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn delay(&mut self, attempt: u32) -> Duration {
+        let factor = 1u32 << min(attempt.saturating_sub(1), 31);
+        min(self.initial.checked_mul(factor).unwrap_or(self.max), self.max)
+    }
+}
+
+impl<F, H> HandshakeFactory for F
+    where F: FnMut() -> H,
+          H: Future<Error=Error>,
+{
+    type Future = H;
+    fn start(&mut self) -> H {
+        self()
+    }
+}
+
+impl<F: HandshakeFactory, B: Backoff> RetryingHandshake<F, B> {
+    /// Start the first handshake attempt
+    ///
+    /// By default handshake is retried at most 3 times.
+    pub fn new(mut factory: F, backoff: B, handle: &Handle)
+        -> RetryingHandshake<F, B>
+    {
+        let handshake = factory.start();
+        RetryingHandshake {
+            factory: factory,
+            backoff: backoff,
+            max_retries: MAX_RETRIES,
+            retries: 0,
+            reconnects: 0,
+            handle: handle.clone(),
+            state: State::Connecting(handshake),
+        }
+    }
+    /// Set the maximum number of retries
+    ///
+    /// The last error is returned when all the retries have failed.
+    pub fn max_retries(mut self, value: u32) -> RetryingHandshake<F, B> {
+        self.max_retries = value;
+        self
+    }
+    /// Returns the number of retries made so far
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+    /// Start over with a new handshake after connection is lost
+    ///
+    /// `reconnects` is the number of connections in a row that were lost
+    /// soon after the handshake. If it's non-zero, handshake is started
+    /// after `delay(reconnects)` and delays of the retries continue to grow
+    /// from there, so a server that drops connections right away isn't
+    /// hammered with reconnects. The number of retries is reset anyway.
+    pub(crate) fn restart(&mut self, reconnects: u32) {
+        self.retries = 0;
+        self.reconnects = reconnects;
+        self.state = if reconnects == 0 {
+            State::Connecting(self.factory.start())
+        } else {
+            let delay = self.backoff.delay(reconnects);
+            debug!("Websocket connection was lost again, \
+                reconnecting in {:?}", delay);
+            State::Sleeping(Timeout::new(delay, &self.handle)
+                .expect("can always create a timeout"))
+        };
+    }
+}
+
+impl<F: HandshakeFactory, B: Backoff> Future for RetryingHandshake<F, B> {
+    type Item = <F::Future as Future>::Item;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        loop {
+            let next = match self.state {
+                State::Connecting(ref mut handshake) => {
+                    match handshake.poll() {
+                        Ok(result) => return Ok(result),
+                        // credentials are known now, no reason to wait
+                        Err(ref e) if e.is_auth_challenge() &&
+                            self.retries < self.max_retries
+                        => {
+                            self.retries += 1;
+                            debug!("Websocket handshake requires \
+                                authentication, retrying");
+                            State::Connecting(self.factory.start())
+                        }
+                        Err(ref e) if e.is_retriable() &&
+                            self.retries < self.max_retries
+                        => {
+                            self.retries += 1;
+                            let delay = self.backoff.delay(
+                                self.reconnects + self.retries);
+                            debug!("Websocket handshake failed: {}, \
+                                retrying in {:?}", e, delay);
+                            State::Sleeping(Timeout::new(delay, &self.handle)
+                                .expect("can always create a timeout"))
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                State::Sleeping(ref mut timeout) => {
+                    match timeout.poll().map_err(|_| ErrorEnum::Timeout)? {
+                        Async::Ready(()) => {
+                            State::Connecting(self.factory.start())
+                        }
+                        Async::NotReady => return Ok(Async::NotReady),
+                    }
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::io;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::future::err;
+    use tokio_core::reactor::Core;
+
+    use mock::MockTransport;
+    use websocket::Error;
+    use websocket::error::ErrorEnum;
+    use websocket::client::{HandshakeProto, SimpleAuthorizer};
+    use super::{Backoff, ExponentialBackoff, FixedBackoff, RetryingHandshake};
+
+    fn handshake(calls: &Rc<Cell<u32>>, response: &'static [u8])
+        -> HandshakeProto<MockTransport, SimpleAuthorizer>
+    {
+        calls.set(calls.get() + 1);
+        let mock = MockTransport::new();
+        mock.feed(response);
+        mock.close();
+        HandshakeProto::new(mock, SimpleAuthorizer::new("example.com", "/"))
+    }
+
+    #[test]
+    fn exponential() {
+        let mut b = ExponentialBackoff::new(Duration::from_millis(100),
+                                            Duration::from_secs(1));
+        assert_eq!(b.delay(1), Duration::from_millis(100));
+        assert_eq!(b.delay(2), Duration::from_millis(200));
+        assert_eq!(b.delay(4), Duration::from_millis(800));
+        assert_eq!(b.delay(5), Duration::from_secs(1));
+        assert_eq!(b.delay(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn retry_closed() {
+        let mut lp = Core::new().unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        let retry = RetryingHandshake::new(move || handshake(&c, b""),
+            FixedBackoff::new(Duration::from_millis(1)), &lp.handle())
+            .max_retries(2);
+        let err = lp.run(retry).err().unwrap();
+        assert!(err.is_retriable());
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn connection_refused() {
+        let mut lp = Core::new().unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        let retry = RetryingHandshake::new(move || {
+                c.set(c.get() + 1);
+                err::<(), _>(Error::from(ErrorEnum::Io(io::Error::new(
+                    io::ErrorKind::ConnectionRefused, "refused"))))
+            },
+            FixedBackoff::new(Duration::from_millis(1)), &lp.handle())
+            .max_retries(2);
+        let err = lp.run(retry).err().unwrap();
+        assert_eq!(err.to_string(), "IO error: refused");
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn rejected() {
+        let mut lp = Core::new().unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        let retry = RetryingHandshake::new(
+            move || handshake(&c, b"HTTP/1.1 403 Forbidden\r\n\
+                                     Content-Length: 0\r\n\r\n"),
+            FixedBackoff::new(Duration::from_millis(1)), &lp.handle());
+        let err = lp.run(retry).err().unwrap();
+        assert!(!err.is_retriable());
+        assert_eq!(err.to_string(),
+            "server rejected websocket connection: 403 Forbidden");
+        assert_eq!(calls.get(), 1);
+    }
+}
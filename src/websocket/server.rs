@@ -0,0 +1,566 @@
+//! Websocket server handshake implementation
+//!
+//! This is a standalone handshake for the cases where you accept websocket
+//! connections without the full `server::Proto` (i.e. when the socket only
+//! ever serves websockets). If you serve both plain HTTP and websockets on
+//! the same port use `server::Head::get_websocket_upgrade` instead.
+//!
+#[allow(unused_imports)]
+use std::ascii::AsciiExt;
+use std::fmt::Display;
+use std::str::from_utf8;
+
+use futures::{Future, Async};
+use httparse::{self, Header};
+use tk_bufstream::{IoBuf, ReadBuf, WriteBuf, WriteFramed, ReadFramed};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use ConnectionId;
+use base_serializer::{MessageState, HeaderError};
+use base_serializer::Body;
+use websocket::{Error};
+use websocket::conn_id;
+use websocket::error::ErrorEnum;
+use enums::{Version, Status};
+use websocket::{ServerCodec, Accept};
+
+
+/// Number of headers to allocate on a stack
+const MIN_HEADERS: usize = 16;
+/// A hard limit on the number of headers
+const MAX_HEADERS: usize = 1024;
+
+/// This a response writer that you receive in `Acceptor`
+///
+/// Methods of this structure ensure that everything you write into a buffer
+/// is consistent and valid protocol
+pub struct Encoder<S> {
+    message: MessageState,
+    accept: Accept,
+    subprotocol: bool,
+    buf: WriteBuf<S>,
+}
+
+/// This structure returned from `Encoder::done` and works as a continuation
+/// that should be returned from the future that writes response.
+pub struct EncoderDone<S> {
+    buf: WriteBuf<S>,
+}
+
+/// Acceptor checks request headers and sends all the necessary response
+/// headers to establish websocket connection
+///
+/// This is a server-side counterpart of the `client::Authorizer`.
+pub trait Acceptor<S> {
+    /// The type that may be returned from a `headers_received`. It should
+    /// encompass everything parsed from input headers.
+    type Result: Sized;
+    /// A handler of request headers
+    ///
+    /// It's called when a valid websocket upgrade request is received. Return
+    /// an error to reject the connection (connection is closed in this case).
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Result, Error>;
+    /// Write response headers
+    ///
+    /// Status line and websocket-specific headers like `Connection`,
+    /// `Upgrade`, and `Sec-Websocket-Accept` are written automatically.
+    /// Other headers like `Sec-WebSocket-Protocol` must be written by this
+    /// method (unless `subprotocols` is implemented).
+    ///
+    /// Default implementation writes nothing else.
+    fn write_headers(&mut self, e: Encoder<S>) -> EncoderDone<S> {
+        e.done()
+    }
+    /// Names of supported subprotocols in the order of preference
+    ///
+    /// If the list is non-empty and client offers some subprotocols, the
+    /// first supported one is available as `Head::subprotocol` and is
+    /// written in the `Sec-WebSocket-Protocol` response header
+    /// automatically. If none of the offered subprotocols is supported,
+    /// `400 Bad Request` is sent and handshake fails with
+    /// `UnsupportedSubprotocol` error (`headers_received` isn't called).
+    ///
+    /// Default is an empty list, so subprotocols are left to the acceptor.
+    /// See `SubprotocolRegistry` for an implementation.
+    fn subprotocols(&self) -> Vec<&str> {
+        Vec::new()
+    }
+}
+
+/// A borrowed structure that represents request headers
+///
+/// It's passed to `Acceptor::headers_received` and you are
+/// free to store or discard any needed fields and headers from it.
+///
+#[derive(Debug)]
+pub struct Head<'a> {
+    method: &'a str,
+    path: &'a str,
+    version: Version,
+    key: &'a [u8],
+    subprotocol: Option<&'a str>,
+    headers: &'a [Header<'a>],
+}
+
+/// A future that resolves to framed streams when websocket handshake is done
+pub struct ServerHandshakeProto<S, A> {
+    input: Option<ReadBuf<S>>,
+    output: Option<WriteBuf<S>>,
+    acceptor: A,
+    id: ConnectionId,
+}
+
+/// Default handshake handler, if you just want to get websocket accepted
+pub struct SimpleAcceptor;
+
+impl<S> Acceptor<S> for SimpleAcceptor {
+    type Result = ();
+    fn headers_received(&mut self, _headers: &Head)
+        -> Result<Self::Result, Error>
+    {
+        Ok(())
+    }
+}
+
+fn check_header(name: &str) {
+    if name.eq_ignore_ascii_case("Connection") ||
+        name.eq_ignore_ascii_case("Upgrade") ||
+        name.eq_ignore_ascii_case("Sec-Websocket-Accept")
+    {
+        panic!("You shouldn't set websocket specific headers yourself");
+    }
+}
+
+fn bytes_trim(mut x: &[u8]) -> &[u8] {
+    while x.len() > 0 && matches!(x[0], b'\r' | b'\n' | b' ' | b'\t') {
+        x = &x[1..];
+    }
+    while x.len() > 0 && matches!(x[x.len()-1],  b'\r' | b'\n' | b' ' | b'\t')
+    {
+        x = &x[..x.len()-1];
+    }
+    return x;
+}
+
+/// Returns tokens of all `Sec-WebSocket-Protocol` headers
+fn offered_subprotocols<'x>(headers: &[Header<'x>]) -> Vec<&'x str> {
+    headers.iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("Sec-WebSocket-Protocol"))
+        .filter_map(|h| from_utf8(h.value).ok())
+        .flat_map(|v| v.split(','))
+        .map(|x| x.trim())
+        .filter(|x| x.len() > 0)
+        .collect()
+}
+
+/// Checks that key is a base64-encoded 16-byte value
+fn valid_key(key: &[u8]) -> bool {
+    key.len() == 24 && key.ends_with(b"==") &&
+        key[..22].iter().all(|&x| matches!(x,
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'+' | b'/'))
+}
+
+impl<S> Encoder<S> {
+    /// Add a header to the websocket response
+    ///
+    /// Header is written into the output buffer immediately. And is sent
+    /// as soon as the next loop iteration
+    ///
+    /// We return Result here to make implementing proxies easier. In the
+    /// application handler it's okay to unwrap the result and to get
+    /// a meaningful panic (that is basically an assertion).
+    ///
+    /// # Panics
+    ///
+    /// When you add a special header `Connection`, `Upgrade`,
+    /// `Sec-Websocket-Accept`, because they are written automatically
+    /// (as is `Sec-WebSocket-Protocol` selected by `Acceptor::subprotocols`)
+    pub fn add_header<V: AsRef<[u8]>>(&mut self, name: &str, value: V)
+        -> Result<(), HeaderError>
+    {
+        self.check_header(name);
+        self.message.add_header(&mut self.buf.out_buf, name, value.as_ref())
+    }
+
+    /// Same as `add_header` but allows value to be formatted directly into
+    /// the buffer
+    ///
+    /// Useful for dates and numeric headers, as well as some strongly typed
+    /// wrappers
+    pub fn format_header<D: Display>(&mut self, name: &str, value: D)
+        -> Result<(), HeaderError>
+    {
+        self.check_header(name);
+        self.message.format_header(&mut self.buf.out_buf, name, value)
+    }
+    fn check_header(&self, name: &str) {
+        check_header(name);
+        if self.subprotocol &&
+            name.eq_ignore_ascii_case("Sec-WebSocket-Protocol")
+        {
+            panic!("Sec-WebSocket-Protocol is already written");
+        }
+    }
+    /// Finish writing headers and return `EncoderDone` which can be moved to
+    ///
+    /// # Panics
+    ///
+    /// Panics when the response is in a wrong state.
+    pub fn done(mut self) -> EncoderDone<S> {
+        self.message.add_header(&mut self.buf.out_buf,
+            "Connection", b"upgrade").unwrap();
+        self.message.add_header(&mut self.buf.out_buf,
+            "Upgrade", b"websocket").unwrap();
+        self.message.format_header(&mut self.buf.out_buf,
+            "Sec-WebSocket-Accept", &self.accept).unwrap();
+        self.message.done_headers(&mut self.buf.out_buf)
+            .map(|expect_body| assert!(!expect_body)).unwrap();
+        self.message.done(&mut self.buf.out_buf);
+        EncoderDone { buf: self.buf }
+    }
+}
+
+fn encoder<S>(mut io: WriteBuf<S>, accept: Accept, subprotocol: Option<&str>)
+    -> Encoder<S>
+{
+    let mut message = MessageState::ResponseStart {
+        version: Version::Http11,
+        body: Body::Normal,
+        close: false,
+    };
+    let status = Status::SwitchingProtocol;
+    message.response_status(&mut io.out_buf, status.code(), status.reason());
+    if let Some(name) = subprotocol {
+        message.add_header(&mut io.out_buf,
+            "Sec-WebSocket-Protocol", name.as_bytes()).unwrap();
+    }
+    Encoder {
+        message: message,
+        accept: accept,
+        subprotocol: subprotocol.is_some(),
+        buf: io,
+    }
+}
+
+fn bad_request<S>(io: &mut WriteBuf<S>) {
+    let mut message = MessageState::ResponseStart {
+        version: Version::Http11,
+        body: Body::Normal,
+        close: true,
+    };
+    let status = Status::BadRequest;
+    message.response_status(&mut io.out_buf, status.code(), status.reason());
+    message.add_length(&mut io.out_buf, 0).unwrap();
+    message.done_headers(&mut io.out_buf).unwrap();
+    message.done(&mut io.out_buf);
+}
+
+impl<S, A: Acceptor<S>> ServerHandshakeProto<S, A> {
+    /// Create an instance of future from already accepted socket
+    pub fn new(transport: S, acceptor: A) -> ServerHandshakeProto<S, A>
+        where S: AsyncRead + AsyncWrite
+    {
+        ServerHandshakeProto::with_id(transport, acceptor, conn_id::next())
+    }
+    /// Same as `new` but logs with the id of the connection
+    ///
+    /// Use the id passed to `server::Handler` so that log messages of a
+    /// connection accepted by `server::Server` can be correlated.
+    pub fn with_id(transport: S, acceptor: A, id: ConnectionId)
+        -> ServerHandshakeProto<S, A>
+        where S: AsyncRead + AsyncWrite
+    {
+        let (tx, rx) = IoBuf::new(transport).split();
+        debug!("[{}] Waiting for websocket handshake", id);
+        ServerHandshakeProto {
+            acceptor: acceptor,
+            input: Some(rx),
+            output: Some(tx),
+            id: id,
+        }
+    }
+    /// Returns the id of the connection used in log messages
+    ///
+    /// Pass it to `Loop::server_with_id` after the handshake is done.
+    pub fn connection_id(&self) -> ConnectionId {
+        self.id
+    }
+    fn parse_headers(&mut self) -> Result<Option<A::Result>, Error> {
+        let ref mut buf = self.input.as_mut()
+            .expect("buffer still exists")
+            .in_buf;
+        let (res, accept, subprotocol, bytes) = {
+            let mut vec;
+            let mut headers = [httparse::EMPTY_HEADER; MIN_HEADERS];
+            let (method, path, headers, bytes) = {
+                let mut raw = httparse::Request::new(&mut headers);
+                let mut result = raw.parse(&buf[..]);
+                if matches!(result, Err(httparse::Error::TooManyHeaders)) {
+                    vec = vec![httparse::EMPTY_HEADER; MAX_HEADERS];
+                    raw = httparse::Request::new(&mut vec);
+                    result = raw.parse(&buf[..]);
+                }
+                match result.map_err(ErrorEnum::HeaderError)? {
+                    httparse::Status::Complete(bytes) => {
+                        if raw.version.unwrap() != 1 {
+                            return Err(ErrorEnum::InvalidHandshake(
+                                "websocket requires HTTP/1.1").into());
+                        }
+                        (raw.method.unwrap(), raw.path.unwrap(),
+                         raw.headers, bytes)
+                    }
+                    _ => return Ok(None),
+                }
+            };
+            debug!("[{}] Request headers received: {} {}",
+                self.id, method, path);
+            let key = check_handshake(method, headers)?;
+            let offered = offered_subprotocols(headers);
+            let subprotocol = {
+                let supported = self.acceptor.subprotocols();
+                if supported.len() == 0 || offered.len() == 0 {
+                    None
+                } else {
+                    match supported.iter().find(|x| offered.contains(x)) {
+                        Some(name) => offered.iter()
+                            .find(|x| x == &name).map(|x| *x),
+                        None => {
+                            debug!("[{}] Unsupported subprotocols {:?}",
+                                self.id, offered);
+                            bad_request(self.output.as_mut()
+                                .expect("buffer still exists"));
+                            return Err(
+                                ErrorEnum::UnsupportedSubprotocol.into());
+                        }
+                    }
+                }
+            };
+            let head = Head {
+                method: method,
+                path: path,
+                version: Version::Http11,
+                key: key,
+                subprotocol: subprotocol,
+                headers: headers,
+            };
+            let data = self.acceptor.headers_received(&head)?;
+            (data, Accept::from_key_bytes(key),
+             subprotocol.map(|x| x.to_string()), bytes)
+        };
+        buf.consume(bytes);
+        let out = self.output.take().expect("buffer still exists");
+        let done = self.acceptor.write_headers(
+            encoder(out, accept, subprotocol.as_ref().map(|x| &x[..])));
+        self.output = Some(done.buf);
+        return Ok(Some(res));
+    }
+}
+
+/// Validates websocket-specific headers and returns the key
+fn check_handshake<'x>(method: &str, headers: &'x [Header<'x>])
+    -> Result<&'x [u8], ErrorEnum>
+{
+    use websocket::error::ErrorEnum::InvalidHandshake;
+    if method != "GET" {
+        return Err(InvalidHandshake("websocket requires GET method"));
+    }
+    let mut upgrade = false;
+    let mut connection = false;
+    let mut version = false;
+    let mut key = None;
+    for h in headers {
+        if h.name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+            if key.is_some() {
+                return Err(InvalidHandshake("duplicate Sec-WebSocket-Key"));
+            }
+            let value = bytes_trim(h.value);
+            if !valid_key(value) {
+                return Err(InvalidHandshake("invalid Sec-WebSocket-Key"));
+            }
+            key = Some(value);
+        } else if h.name.eq_ignore_ascii_case("Sec-WebSocket-Version") {
+            // Only version 13 is supported
+            if bytes_trim(h.value) != b"13" {
+                return Err(InvalidHandshake(
+                    "unsupported Sec-WebSocket-Version"));
+            }
+            version = true;
+        } else if h.name.eq_ignore_ascii_case("Connection") {
+            if h.value.split(|&x| x == b',')
+                .any(|tok| bytes_trim(tok).eq_ignore_ascii_case(b"upgrade"))
+            {
+                connection = true;
+            }
+        } else if h.name.eq_ignore_ascii_case("Upgrade") {
+            if bytes_trim(h.value).eq_ignore_ascii_case(b"websocket") {
+                upgrade = true;
+            }
+        } else if h.name.eq_ignore_ascii_case("Content-Length") ||
+            h.name.eq_ignore_ascii_case("Transfer-Encoding")
+        {
+            return Err(InvalidHandshake("websocket handshake has payload"));
+        }
+    }
+    if !connection || !upgrade {
+        return Err(InvalidHandshake("no upgrade headers"));
+    }
+    if !version {
+        return Err(InvalidHandshake("no Sec-WebSocket-Version"));
+    }
+    key.ok_or(InvalidHandshake("no Sec-WebSocket-Key"))
+}
+
+impl<S, A> Future for ServerHandshakeProto<S, A>
+    where A: Acceptor<S>,
+          S: AsyncRead + AsyncWrite
+{
+    type Item = (WriteFramed<S, ServerCodec>, ReadFramed<S, ServerCodec>,
+                 A::Result);
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Self::Item>, Error> {
+        loop {
+            let bytes = self.input.as_mut().expect("poll after complete")
+                .read().map_err(ErrorEnum::Io)?;
+            let parsed = match self.parse_headers() {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    // send error response if any, but don't wait for it
+                    if let Some(ref mut out) = self.output {
+                        out.flush().ok();
+                    }
+                    return Err(e);
+                }
+            };
+            if let Some(x) = parsed {
+                let mut out = self.output.take()
+                    .expect("output still here");
+                out.flush().map_err(ErrorEnum::Io)?;
+                let inp = self.input.take()
+                    .expect("input still here")
+                    .framed(ServerCodec);
+                debug!("[{}] Websocket upgrade complete", self.id);
+                return Ok(Async::Ready((out.framed(ServerCodec), inp, x)));
+            }
+            if self.input.as_ref().expect("input still here").done() {
+                return Err(ErrorEnum::Closed.into());
+            }
+            if bytes == 0 {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+impl<'a> Head<'a> {
+    /// Returns a HTTP method (always `GET` for a valid handshake)
+    pub fn method(&self) -> &'a str {
+        self.method
+    }
+    /// Returns a request target (path) as passed in request line
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+    /// Version of HTTP request
+    pub fn version(&self) -> Version {
+        self.version
+    }
+    /// Returns the (already validated) value of `Sec-WebSocket-Key` header
+    pub fn key(&self) -> &'a [u8] {
+        self.key
+    }
+    /// Returns the subprotocol selected from `Acceptor::subprotocols`
+    ///
+    /// This is `None` if the acceptor supports no subprotocols or if the
+    /// client offered none.
+    pub fn subprotocol(&self) -> Option<&'a str> {
+        self.subprotocol
+    }
+    /// Returns subprotocols offered by client in `Sec-WebSocket-Protocol`
+    pub fn offered_subprotocols(&self) -> Vec<&'a str> {
+        offered_subprotocols(self.headers)
+    }
+    /// All headers of HTTP request
+    ///
+    /// This includes websocket-specific headers.
+    pub fn all_headers(&self) -> &'a [Header<'a>] {
+        self.headers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Async, Future};
+    use futures::future::lazy;
+    use tk_bufstream::MockData;
+
+    use ConnectionId;
+    use mock::MockTransport;
+
+    use super::{ServerHandshakeProto, SimpleAcceptor};
+
+    #[test]
+    fn simple_handshake() {
+        let mock = MockData::new();
+        mock.add_input("GET /chat HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            \r\n");
+        let (_, _, ()) = ServerHandshakeProto::new(mock.clone(),
+                                                   SimpleAcceptor)
+            .wait().unwrap();
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 101 Switching Protocol\r\n\
+             Connection: upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+             \r\n");
+    }
+
+    #[test]
+    fn with_id() {
+        let proto = ServerHandshakeProto::with_id(MockData::new(),
+            SimpleAcceptor, ConnectionId(5));
+        assert_eq!(proto.connection_id(), ConnectionId(5));
+        let proto = ServerHandshakeProto::new(MockData::new(),
+                                              SimpleAcceptor);
+        assert!(proto.connection_id() != ConnectionId(5));
+    }
+
+    #[test]
+    fn split_request() {
+        let mock = MockTransport::new();
+        mock.feed(b"GET /chat HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Upgrade: websocket\r\n");
+        mock.feed(b"Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            \r\n");
+        let mut proto = ServerHandshakeProto::new(mock.clone(),
+                                                  SimpleAcceptor);
+        lazy(|| {
+            // both chunks are read in a single poll
+            assert!(matches!(proto.poll().unwrap(), Async::Ready(..)));
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+        assert!(mock.written().starts_with(
+            b"HTTP/1.1 101 Switching Protocol\r\n"));
+    }
+
+    #[test]
+    fn no_key() {
+        let mock = MockData::new();
+        mock.add_input("GET /chat HTTP/1.1\r\n\
+            Host: example.com\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            \r\n");
+        assert!(ServerHandshakeProto::new(mock.clone(), SimpleAcceptor)
+            .wait().is_err());
+    }
+}
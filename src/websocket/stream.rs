@@ -0,0 +1,744 @@
+use std::cmp::min;
+use std::io::{self, IoSlice};
+use std::mem;
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use tk_bufstream::{Buf, Encode, Decode, WriteFramed, ReadFramed};
+use tk_bufstream::{FutureWriteRaw, WriteRaw};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use websocket::{Packet, Error, ClientCodec, ServerCodec};
+use websocket::error::ErrorEnum;
+use websocket::metrics::Metrics;
+use websocket::zero_copy::{Frame, write_raw, write_header};
+use websocket::zero_copy::{mask_key, apply_mask};
+
+
+/// Default payload size from which `send_vectored` bypasses the buffer
+const VECTORED_THRESHOLD: usize = 1024;
+
+
+/// A websocket connection as a single `Stream` and `Sink` of packets
+///
+/// This holds both framed halves returned by `HandshakeProto` (or
+/// `ServerHandshakeProto`, with `ServerCodec`), so they can be passed
+/// around and used with stream combinators together:
+///
+/// ```rust,ignore
+/// HandshakeProto::new(sock, SimpleAuthorizer::new("example.com", "/"))
+/// .map(|(out, inp, ())| WebSocketStream::new(out, inp))
+/// ```
+///
+/// Packets are passed as is, pings are neither replied nor filtered out.
+/// Use `PingPong` if you want control frames to be handled for you.
+///
+/// Sent and received frames are counted in `Metrics`.
+pub struct WebSocketStream<S, C=ClientCodec> {
+    output: WriteFramed<S, C>,
+    input: ReadFramed<S, C>,
+    metrics: Arc<Metrics>,
+    vectored_threshold: usize,
+    max_write_buffer: Option<usize>,
+}
+
+/// A future returned by `WebSocketStream::send_vectored`
+///
+/// A binary message not smaller than the threshold is written directly to
+/// the socket: the frame header and the payload are passed as two slices
+/// to `Write::write_vectored`, so the payload isn't copied into the output
+/// buffer (the transport should implement `write_vectored` to combine them
+/// into a single `writev` system call). Client frames are masked in-place.
+/// Smaller messages are buffered as usual. Resolves to the stream when the
+/// message is written.
+pub struct SendVectored<S, C> {
+    state: VectoredState<S, C>,
+    header: Buf,
+    data: Vec<u8>,
+    written: usize,
+}
+
+enum VectoredState<S, C> {
+    Flushing(WebSocketStream<S, C>),
+    Locking(FutureWriteRaw<S>, Parts<S, C>),
+    Writing(WriteRaw<S>, Parts<S, C>),
+    Done,
+}
+
+/// Parts of `WebSocketStream` kept while the output is borrowed
+struct Parts<S, C> {
+    input: ReadFramed<S, C>,
+    metrics: Arc<Metrics>,
+    vectored_threshold: usize,
+    max_write_buffer: Option<usize>,
+    // `WriteFramed` can't give its codec back, but encoding is stateless
+    codec: C,
+}
+
+/// A future returned by `WebSocketStream::send_fragmented`
+///
+/// The first frame is `Binary` and the rest are continuation frames, the
+/// `FIN` bit is set on the last one only. Output is flushed after each
+/// fragment, so at most one fragment is buffered in memory. Resolves to
+/// the stream when the last fragment is flushed.
+pub struct SendFragmented<S, C> {
+    stream: Option<WebSocketStream<S, C>>,
+    data: Vec<u8>,
+    offset: usize,
+    frame_size: usize,
+    masked: bool,
+    done: bool,
+}
+
+/// A future returned by `broadcast`
+///
+/// Resolves to the indices (in the original vector) of the connections
+/// that failed and were removed, in ascending order.
+pub struct Broadcast<'a, S: 'a> {
+    connections: &'a mut Vec<WebSocketStream<S, ServerCodec>>,
+    data: Vec<u8>,
+    written: bool,
+    state: Vec<SendState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SendState {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Send a packet to all the connections concurrently
+///
+/// The packet is serialized once and the same bytes are written to every
+/// connection (this is why only server-side connections are supported:
+/// client frames must be masked with a unique key each). Connections
+/// that fail to flush or are closed by peer are removed from the vector.
+///
+/// The future doesn't resolve until every connection either flushed the
+/// packet or failed, so a single peer that doesn't read can stall it
+/// forever. Use a timeout if peers are not trusted.
+pub fn broadcast<'a, S>(
+    connections: &'a mut Vec<WebSocketStream<S, ServerCodec>>,
+    packet: &Packet)
+    -> Broadcast<'a, S>
+{
+    let mut buf = Buf::new();
+    Frame::from(packet).write(&mut buf, false);
+    Broadcast {
+        state: vec![SendState::Pending; connections.len()],
+        connections: connections,
+        data: buf[..].to_vec(),
+        written: false,
+    }
+}
+
+impl<S, C> WebSocketStream<S, C>
+    where C: Encode<Item=Packet> + Decode<Item=Packet>,
+          S: AsyncRead + AsyncWrite,
+{
+    /// Join output and input streams (as returned by `HandshakeProto`)
+    pub fn new(output: WriteFramed<S, C>, input: ReadFramed<S, C>)
+        -> WebSocketStream<S, C>
+    {
+        WebSocketStream::with_metrics(output, input,
+                                      &Arc::new(Metrics::new()))
+    }
+    /// Same as `new` but updates existing metrics
+    ///
+    /// Use this with `Tracked` transport to get all the counters in
+    /// a single `Metrics` instance.
+    pub fn with_metrics(output: WriteFramed<S, C>, input: ReadFramed<S, C>,
+        metrics: &Arc<Metrics>)
+        -> WebSocketStream<S, C>
+    {
+        WebSocketStream {
+            output: output,
+            input: input,
+            metrics: metrics.clone(),
+            vectored_threshold: VECTORED_THRESHOLD,
+            max_write_buffer: None,
+        }
+    }
+    /// Set the payload size from which `send_vectored` writes messages
+    /// directly to the socket
+    ///
+    /// Default is 1 KiB.
+    pub fn set_vectored_threshold(&mut self, bytes: usize) {
+        self.vectored_threshold = bytes;
+    }
+    /// Set the maximum number of bytes in the output buffer
+    ///
+    /// When a packet sent with `start_send` would make the buffer larger
+    /// than this, the packet is dropped and `WriteBufferFull` error is
+    /// returned, to prevent runaway memory use when packets are produced
+    /// faster than the network drains them. Use `WriteQueue` if you want
+    /// to wait for the buffer to drain instead. By default the buffer is
+    /// unlimited.
+    pub fn set_max_write_buffer(&mut self, bytes: usize) {
+        self.max_write_buffer = Some(bytes);
+    }
+    /// Returns connection metrics
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+    /// Send a packet and flush the output, failing with `WriteTimeout` if
+    /// it's not written before `deadline`
+    ///
+    /// Everything buffered before the packet must also be flushed before
+    /// the deadline. On timeout the packet may be partially written, so
+    /// the connection should be dropped.
+    pub fn send_with_deadline(&mut self, packet: Packet, deadline: Instant,
+        handle: &Handle)
+        -> SendWithDeadline<S, C>
+    {
+        SendWithDeadline {
+            stream: self,
+            packet: Some(packet),
+            timeout: Timeout::new_at(deadline, handle)
+                .expect("can always create a timeout"),
+        }
+    }
+    /// Returns underlying output and input streams
+    pub fn into_inner(self) -> (WriteFramed<S, C>, ReadFramed<S, C>) {
+        (self.output, self.input)
+    }
+}
+
+/// A future returned by `WebSocketStream::send_with_deadline`
+pub struct SendWithDeadline<'a, S: 'a, C: 'a> {
+    stream: &'a mut WebSocketStream<S, C>,
+    packet: Option<Packet>,
+    timeout: Timeout,
+}
+
+impl<S: AsyncWrite> WebSocketStream<S, ClientCodec> {
+    /// Send a binary message split into frames of at most `frame_size`
+    /// bytes
+    ///
+    /// See `SendFragmented` for details.
+    ///
+    /// # Panics
+    ///
+    /// When `frame_size` is zero.
+    pub fn send_fragmented(self, data: Vec<u8>, frame_size: usize)
+        -> SendFragmented<S, ClientCodec>
+    {
+        SendFragmented::new(self, data, frame_size, true)
+    }
+    /// Send a binary message, avoiding a copy of large payloads
+    ///
+    /// See `SendVectored` for details.
+    pub fn send_vectored(self, data: Vec<u8>)
+        -> SendVectored<S, ClientCodec>
+    {
+        SendVectored::new(self, data, true, ClientCodec::new())
+    }
+}
+
+impl<S: AsyncWrite> WebSocketStream<S, ServerCodec> {
+    /// Send a binary message split into frames of at most `frame_size`
+    /// bytes
+    ///
+    /// See `SendFragmented` for details.
+    ///
+    /// # Panics
+    ///
+    /// When `frame_size` is zero.
+    pub fn send_fragmented(self, data: Vec<u8>, frame_size: usize)
+        -> SendFragmented<S, ServerCodec>
+    {
+        SendFragmented::new(self, data, frame_size, false)
+    }
+    /// Send a binary message, avoiding a copy of large payloads
+    ///
+    /// See `SendVectored` for details.
+    pub fn send_vectored(self, data: Vec<u8>)
+        -> SendVectored<S, ServerCodec>
+    {
+        SendVectored::new(self, data, false, ServerCodec)
+    }
+}
+
+impl<S: AsyncWrite, C> SendVectored<S, C> {
+    fn new(mut stream: WebSocketStream<S, C>, mut data: Vec<u8>,
+        masked: bool, codec: C)
+        -> SendVectored<S, C>
+    {
+        let mut header = Buf::new();
+        if data.len() < stream.vectored_threshold {
+            write_raw(&mut stream.output.get_mut().out_buf, 0x82,
+                      &data, masked);
+            stream.metrics.frame_sent();
+            return SendVectored {
+                state: VectoredState::Flushing(stream),
+                header: header,
+                data: Vec::new(),
+                written: 0,
+            };
+        }
+        let key = if masked { Some(mask_key()) } else { None };
+        write_header(&mut header, 0x82, data.len(), key);
+        if let Some(key) = key {
+            apply_mask(&mut data, key);
+        }
+        let WebSocketStream {
+            output, input, metrics, vectored_threshold, max_write_buffer,
+        } = stream;
+        SendVectored {
+            state: VectoredState::Locking(output.into_inner().borrow_raw(),
+                Parts {
+                    input: input,
+                    metrics: metrics,
+                    vectored_threshold: vectored_threshold,
+                    max_write_buffer: max_write_buffer,
+                    codec: codec,
+                }),
+            header: header,
+            data: data,
+            written: 0,
+        }
+    }
+    fn write(&mut self, raw: &mut WriteRaw<S>) -> Poll<(), io::Error> {
+        let hlen = self.header.len();
+        while self.written < hlen + self.data.len() {
+            let sock = raw.get_mut();
+            let result = if self.written < hlen {
+                sock.write_vectored(&[
+                    IoSlice::new(&self.header[self.written..]),
+                    IoSlice::new(&self.data),
+                ])
+            } else {
+                sock.write(&self.data[self.written - hlen..])
+            };
+            match result {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(bytes) => self.written += bytes,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<S, C> SendFragmented<S, C> {
+    fn new(stream: WebSocketStream<S, C>, data: Vec<u8>, frame_size: usize,
+        masked: bool)
+        -> SendFragmented<S, C>
+    {
+        assert!(frame_size > 0, "frame size must be non-zero");
+        SendFragmented {
+            stream: Some(stream),
+            data: data,
+            offset: 0,
+            frame_size: frame_size,
+            masked: masked,
+            done: false,
+        }
+    }
+}
+
+impl<S: AsyncWrite, C> Future for SendFragmented<S, C> {
+    type Item = WebSocketStream<S, C>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<WebSocketStream<S, C>, Error> {
+        loop {
+            {
+                let stream = self.stream.as_mut()
+                    .expect("future is polled after completion");
+                let out = stream.output.get_mut();
+                out.flush().map_err(ErrorEnum::Io)?;
+                if out.out_buf.len() > 0 {
+                    return Ok(Async::NotReady);
+                }
+                if !self.done {
+                    let end = min(self.offset + self.frame_size,
+                                  self.data.len());
+                    let opcode = if self.offset == 0 { 0x2 } else { 0x0 };
+                    self.done = end == self.data.len();
+                    let fin = if self.done { 0x80 } else { 0 };
+                    write_raw(&mut out.out_buf, fin | opcode,
+                        &self.data[self.offset..end], self.masked);
+                    stream.metrics.frame_sent();
+                    self.offset = end;
+                    continue;
+                }
+            }
+            return Ok(Async::Ready(self.stream.take().unwrap()));
+        }
+    }
+}
+
+impl<S: AsyncWrite, C: Encode> Future for SendVectored<S, C> {
+    type Item = WebSocketStream<S, C>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<WebSocketStream<S, C>, Error> {
+        loop {
+            match mem::replace(&mut self.state, VectoredState::Done) {
+                VectoredState::Flushing(mut stream) => {
+                    {
+                        let out = stream.output.get_mut();
+                        out.flush().map_err(ErrorEnum::Io)?;
+                        if out.out_buf.len() > 0 {
+                            self.state = VectoredState::Flushing(stream);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                    return Ok(Async::Ready(stream));
+                }
+                VectoredState::Locking(mut future, parts) => {
+                    match future.poll().map_err(ErrorEnum::Io)? {
+                        Async::Ready(raw) => {
+                            self.state = VectoredState::Writing(raw, parts);
+                        }
+                        Async::NotReady => {
+                            self.state = VectoredState::Locking(future,
+                                                                parts);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                VectoredState::Writing(mut raw, parts) => {
+                    match self.write(&mut raw).map_err(ErrorEnum::Io)? {
+                        Async::Ready(()) => {
+                            parts.metrics.frame_sent();
+                            return Ok(Async::Ready(WebSocketStream {
+                                output: raw.into_buf().framed(parts.codec),
+                                input: parts.input,
+                                metrics: parts.metrics,
+                                vectored_threshold: parts.vectored_threshold,
+                                max_write_buffer: parts.max_write_buffer,
+                            }));
+                        }
+                        Async::NotReady => {
+                            self.state = VectoredState::Writing(raw, parts);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                VectoredState::Done => {
+                    panic!("future is polled after completion");
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S, C> Future for SendWithDeadline<'a, S, C>
+    where C: Encode<Item=Packet> + Decode<Item=Packet>,
+          S: AsyncRead + AsyncWrite,
+{
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<(), Error> {
+        if let Some(packet) = self.packet.take() {
+            if let AsyncSink::NotReady(packet) =
+                self.stream.start_send(packet)?
+            {
+                self.packet = Some(packet);
+            }
+        }
+        if self.packet.is_none() {
+            self.stream.poll_complete()?;
+            // `WriteFramed` is ready even if not everything is written
+            if self.stream.output.get_ref().out_buf.len() == 0 {
+                return Ok(Async::Ready(()));
+            }
+        }
+        match self.timeout.poll().map_err(|_| ErrorEnum::Timeout)? {
+            Async::Ready(()) => Err(ErrorEnum::WriteTimeout.into()),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<'a, S: AsyncWrite> Future for Broadcast<'a, S> {
+    type Item = Vec<usize>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Vec<usize>, Error> {
+        if !self.written {
+            self.written = true;
+            for conn in self.connections.iter_mut() {
+                conn.output.get_mut().out_buf.extend(&self.data);
+                conn.metrics.frame_sent();
+            }
+        }
+        let mut pending = false;
+        let items = self.connections.iter_mut().zip(self.state.iter_mut());
+        for (conn, state) in items {
+            if *state != SendState::Pending {
+                continue;
+            }
+            let out = conn.output.get_mut();
+            // broken pipe is not an error but marks the buffer as done
+            if out.flush().is_err() || out.done() {
+                *state = SendState::Failed;
+            } else if out.out_buf.len() == 0 {
+                *state = SendState::Done;
+            } else {
+                pending = true;
+            }
+        }
+        if pending {
+            return Ok(Async::NotReady);
+        }
+        let failed = self.state.iter().enumerate()
+            .filter(|&(_, s)| *s == SendState::Failed)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        for &idx in failed.iter().rev() {
+            self.connections.remove(idx);
+        }
+        self.state.clear();
+        Ok(Async::Ready(failed))
+    }
+}
+
+impl<S, C> Stream for WebSocketStream<S, C>
+    where C: Encode<Item=Packet> + Decode<Item=Packet>,
+          S: AsyncRead + AsyncWrite,
+{
+    type Item = Packet;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Packet>, Error> {
+        let result = self.input.poll().map_err(ErrorEnum::Io)?;
+        if let Async::Ready(Some(_)) = result {
+            self.metrics.frame_received();
+        }
+        Ok(result)
+    }
+}
+
+impl<S, C> Sink for WebSocketStream<S, C>
+    where C: Encode<Item=Packet> + Decode<Item=Packet>,
+          S: AsyncRead + AsyncWrite,
+{
+    type SinkItem = Packet;
+    type SinkError = Error;
+    fn start_send(&mut self, item: Packet) -> StartSend<Packet, Error> {
+        let old_len = self.output.get_ref().out_buf.len();
+        let result = self.output.start_send(item).map_err(ErrorEnum::Io)?;
+        if let Some(limit) = self.max_write_buffer {
+            let ref mut buf = self.output.get_mut().out_buf;
+            if buf.len() > limit {
+                buf.remove_range(old_len..);
+                return Err(ErrorEnum::WriteBufferFull.into());
+            }
+        }
+        if result.is_ready() {
+            self.metrics.frame_sent();
+        }
+        Ok(result)
+    }
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        Ok(self.output.poll_complete().map_err(ErrorEnum::Io)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use std::time::{Duration, Instant};
+
+    use futures::{Async, Future, Sink, Stream};
+    use tk_bufstream::{IoBuf, MockData};
+    use tokio_core::reactor::Core;
+    use tokio_io::{AsyncRead, AsyncWrite};
+
+    use websocket::{ServerCodec, Packet, Error};
+    use websocket::error::ErrorEnum;
+    use super::{WebSocketStream, broadcast};
+    use websocket::ClientCodec;
+
+    /// A connection that peer doesn't read from
+    struct Stuck;
+
+    impl Read for Stuck {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+    impl AsyncRead for Stuck {}
+    impl Write for Stuck {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+    impl AsyncWrite for Stuck {
+        fn shutdown(&mut self) -> io::Result<Async<()>> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn echo() {
+        let mock = MockData::new();
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        let ws = WebSocketStream::new(tx.framed(ServerCodec),
+                                      rx.framed(ServerCodec));
+        // masked text frame with zero mask
+        mock.add_input(&b"\x81\x82\0\0\0\0hi\x89\x80\0\0\0\0"[..]);
+        let (sink, stream) = ws.split();
+        let mut packets = stream.wait();
+        assert_matches!(packets.next(),
+                        Some(Ok(Packet::Text(ref x))) if x == "hi");
+        assert_matches!(packets.next(), Some(Ok(Packet::Ping(_))));
+        sink.send(Packet::Text("hello".into())).wait().unwrap();
+        assert_eq!(mock.output(..), b"\x81\x05hello");
+    }
+
+    #[test]
+    fn fragmented() {
+        let mock = MockData::new();
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        let ws = WebSocketStream::new(tx.framed(ServerCodec),
+                                      rx.framed(ServerCodec));
+        let ws = ws.send_fragmented(b"hello world".to_vec(), 4)
+            .wait().unwrap();
+        assert_eq!(mock.output(..),
+                   &b"\x02\x04hell\x00\x04o wo\x80\x03rld"[..]);
+        let ws = ws.send_fragmented(b"four".to_vec(), 4).wait().unwrap();
+        ws.send_fragmented(Vec::new(), 4).wait().unwrap();
+        assert_eq!(mock.output(17..), &b"\x82\x04four\x82\x00"[..]);
+    }
+
+    #[test]
+    fn vectored() {
+        let mock = MockData::new();
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        let mut ws = WebSocketStream::new(tx.framed(ServerCodec),
+                                          rx.framed(ServerCodec));
+        ws.set_vectored_threshold(4);
+        let ws = ws.send_vectored(b"hi".to_vec()).wait().unwrap();
+        let ws = ws.send_vectored(b"hello".to_vec()).wait().unwrap();
+        assert_eq!(mock.output(..), &b"\x82\x02hi\x82\x05hello"[..]);
+        // stream is usable after the output was borrowed
+        ws.send(Packet::Text("ok".into())).wait().unwrap();
+        assert_eq!(mock.output(11..), &b"\x81\x02ok"[..]);
+
+        let data = (0..2000).map(|x| x as u8).collect::<Vec<_>>();
+        let mock = MockData::new();
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        let ws = WebSocketStream::new(tx.framed(ClientCodec::new()),
+                                      rx.framed(ClientCodec::new()));
+        ws.send_vectored(data.clone()).wait().unwrap();
+        let output = mock.output(..);
+        assert_eq!(&output[..4], &b"\x82\xFE\x07\xD0"[..]);
+        let key = &output[4..8];
+        let payload = output[8..].iter().enumerate()
+            .map(|(i, x)| x ^ key[i % 4])
+            .collect::<Vec<_>>();
+        assert_eq!(payload, data);
+    }
+
+    #[test]
+    fn max_write_buffer() {
+        let (tx, rx) = IoBuf::new(Stuck).split();
+        let mut ws = WebSocketStream::new(tx.framed(ServerCodec),
+                                          rx.framed(ServerCodec));
+        ws.set_max_write_buffer(10);
+        assert!(ws.start_send(Packet::Binary(vec![0; 4])).is_ok());
+        let err = ws.start_send(Packet::Binary(vec![0; 4])).err().unwrap();
+        assert_eq!(err.to_string(),
+                   Error::from(ErrorEnum::WriteBufferFull).to_string());
+        // the rejected packet is not partially buffered
+        assert!(ws.start_send(Packet::Binary(vec![0; 2])).is_ok());
+        assert_eq!(ws.output.get_ref().out_buf.len(), 10);
+    }
+
+    /// A connection that peer has closed
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+    impl AsyncRead for Broken {}
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+    impl AsyncWrite for Broken {
+        fn shutdown(&mut self) -> io::Result<Async<()>> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn broadcast_all() {
+        enum Peer { Ok(MockData), Broken(Broken) }
+        impl Read for Peer {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match *self {
+                    Peer::Ok(ref mut x) => x.read(buf),
+                    Peer::Broken(ref mut x) => x.read(buf),
+                }
+            }
+        }
+        impl AsyncRead for Peer {}
+        impl Write for Peer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                match *self {
+                    Peer::Ok(ref mut x) => x.write(buf),
+                    Peer::Broken(ref mut x) => x.write(buf),
+                }
+            }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+        impl AsyncWrite for Peer {
+            fn shutdown(&mut self) -> io::Result<Async<()>> {
+                Ok(Async::Ready(()))
+            }
+        }
+
+        let mocks = vec![MockData::new(), MockData::new()];
+        let mut connections = vec![
+            Peer::Broken(Broken),
+            Peer::Ok(mocks[0].clone()),
+            Peer::Broken(Broken),
+            Peer::Ok(mocks[1].clone()),
+        ].into_iter().map(|peer| {
+            let (tx, rx) = IoBuf::new(peer).split();
+            WebSocketStream::new(tx.framed(ServerCodec),
+                                 rx.framed(ServerCodec))
+        }).collect::<Vec<_>>();
+        let removed = broadcast(&mut connections, &Packet::Text("hi".into()))
+            .wait().unwrap();
+        assert_eq!(removed, vec![0, 2]);
+        assert_eq!(connections.len(), 2);
+        for mock in &mocks {
+            assert_eq!(mock.output(..), b"\x81\x02hi");
+        }
+    }
+
+    #[test]
+    fn deadline() {
+        let mut lp = Core::new().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(10);
+
+        let mock = MockData::new();
+        let (tx, rx) = IoBuf::new(mock.clone()).split();
+        let mut ws = WebSocketStream::new(tx.framed(ServerCodec),
+                                          rx.framed(ServerCodec));
+        lp.run(ws.send_with_deadline(Packet::Text("hi".into()), deadline,
+                                     &lp.handle())).unwrap();
+        assert_eq!(mock.output(..), b"\x81\x02hi");
+
+        let (tx, rx) = IoBuf::new(Stuck).split();
+        let mut ws = WebSocketStream::new(tx.framed(ServerCodec),
+                                          rx.framed(ServerCodec));
+        let err = lp.run(ws.send_with_deadline(Packet::Text("hi".into()),
+                                               deadline, &lp.handle()))
+            .err().unwrap();
+        assert_eq!(err.to_string(), ErrorEnum::WriteTimeout.to_string());
+    }
+}
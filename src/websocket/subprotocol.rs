@@ -0,0 +1,150 @@
+use websocket::Error;
+use websocket::server::{Acceptor, Head};
+
+
+/// A list of supported subprotocols with a handler for each of them
+///
+/// Subprotocols are selected in the order of registration, i.e. the first
+/// registered subprotocol that client offers wins regardless of the order
+/// of `Sec-WebSocket-Protocol` tokens.
+///
+/// Registry is itself an `Acceptor` resolving to the selected subprotocol
+/// and a clone of its handler (or `None` if client offered no
+/// subprotocols):
+///
+/// ```rust,ignore
+/// let mut protocols = SubprotocolRegistry::new();
+/// protocols.register("chat.v2", Chat::V2);
+/// protocols.register("chat.v1", Chat::V1);
+/// ServerHandshakeProto::new(sock, protocols)
+/// .map(|(out, inp, selected)| ...)
+/// ```
+#[derive(Debug, Clone)]
+pub struct SubprotocolRegistry<H> {
+    protocols: Vec<(String, H)>,
+}
+
+impl<H> SubprotocolRegistry<H> {
+    /// Create an empty registry
+    pub fn new() -> SubprotocolRegistry<H> {
+        SubprotocolRegistry {
+            protocols: Vec::new(),
+        }
+    }
+    /// Add a subprotocol
+    ///
+    /// Names are compared case-sensitively as required by RFC 6455.
+    /// If the name is already registered, the handler is replaced.
+    pub fn register(&mut self, name: &str, handler: H) -> &mut Self {
+        match self.protocols.iter().position(|&(ref x, _)| x == name) {
+            Some(idx) => self.protocols[idx].1 = handler,
+            None => self.protocols.push((name.to_string(), handler)),
+        }
+        self
+    }
+    /// Returns the first registered subprotocol that is in `offered` list
+    pub fn select(&self, offered: &[&str]) -> Option<(&str, &H)> {
+        self.protocols.iter()
+            .find(|&&(ref name, _)| offered.iter().any(|x| x == name))
+            .map(|&(ref name, ref handler)| (&name[..], handler))
+    }
+    /// Returns a handler of the subprotocol
+    pub fn get(&self, name: &str) -> Option<&H> {
+        self.protocols.iter()
+            .find(|&&(ref x, _)| x == name)
+            .map(|&(_, ref handler)| handler)
+    }
+    /// Returns names of registered subprotocols in the registration order
+    pub fn names(&self) -> Vec<&str> {
+        self.protocols.iter().map(|&(ref name, _)| &name[..]).collect()
+    }
+}
+
+impl<S, H: Clone> Acceptor<S> for SubprotocolRegistry<H> {
+    type Result = Option<(String, H)>;
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Result, Error>
+    {
+        Ok(headers.subprotocol()
+            .and_then(|name| self.get(name)
+                .map(|h| (name.to_string(), h.clone()))))
+    }
+    fn subprotocols(&self) -> Vec<&str> {
+        self.names()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::Future;
+    use tk_bufstream::MockData;
+
+    use websocket::server::ServerHandshakeProto;
+    use super::SubprotocolRegistry;
+
+    const REQUEST: &'static str = "GET /chat HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n";
+
+    fn registry() -> SubprotocolRegistry<u32> {
+        let mut registry = SubprotocolRegistry::new();
+        registry.register("chat.v2", 2).register("chat.v1", 1);
+        registry
+    }
+
+    #[test]
+    fn select() {
+        let registry = registry();
+        assert_eq!(registry.select(&["chat.v1", "chat.v2"]),
+                   Some(("chat.v2", &2)));
+        assert_eq!(registry.select(&["chat.v1"]), Some(("chat.v1", &1)));
+        assert_eq!(registry.select(&["Chat.v1", "x"]), None);
+        assert_eq!(registry.names(), vec!["chat.v2", "chat.v1"]);
+    }
+
+    #[test]
+    fn handshake() {
+        let mock = MockData::new();
+        mock.add_input(format!("{}Sec-WebSocket-Protocol: x, chat.v1\r\n\
+                                \r\n", REQUEST));
+        let (_, _, selected) = ServerHandshakeProto::new(mock.clone(),
+                                                         registry())
+            .wait().unwrap();
+        assert_eq!(selected, Some(("chat.v1".to_string(), 1)));
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 101 Switching Protocol\r\n\
+             Sec-WebSocket-Protocol: chat.v1\r\n\
+             Connection: upgrade\r\n\
+             Upgrade: websocket\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+             \r\n");
+    }
+
+    #[test]
+    fn not_offered() {
+        let mock = MockData::new();
+        mock.add_input(format!("{}\r\n", REQUEST));
+        let (_, _, selected) = ServerHandshakeProto::new(mock.clone(),
+                                                         registry())
+            .wait().unwrap();
+        assert_eq!(selected, None);
+        assert!(!String::from_utf8_lossy(&mock.output(..))
+                .contains("Sec-WebSocket-Protocol"));
+    }
+
+    #[test]
+    fn unsupported() {
+        let mock = MockData::new();
+        mock.add_input(format!("{}Sec-WebSocket-Protocol: x\r\n\r\n",
+                               REQUEST));
+        assert!(ServerHandshakeProto::new(mock.clone(), registry())
+            .wait().is_err());
+        assert_eq!(String::from_utf8_lossy(&mock.output(..)),
+            "HTTP/1.1 400 Bad Request\r\n\
+             Content-Length: 0\r\n\
+             Connection: close\r\n\r\n");
+    }
+}
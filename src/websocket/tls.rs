@@ -0,0 +1,178 @@
+//! TLS (`wss://`) support for websocket client using `tokio-rustls`
+//!
+//! Note: default port for `wss://` urls is 443, you can get it using
+//! `url.port_or_known_default()`.
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::Future;
+use futures::future::err;
+use rustls::{Certificate, ClientSession, Session};
+use tk_bufstream::{ReadFramed, WriteFramed};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_rustls::ClientConfigExt;
+use untrusted::Input;
+use webpki::{self, DNSNameRef, EndEntityCert};
+
+pub use rustls::ClientConfig;
+
+use websocket::{ClientCodec, Error};
+use websocket::client::{Authorizer, HandshakeProto};
+use websocket::error::ErrorEnum;
+
+
+/// TLS stream that websocket connection is established over
+pub type TlsStream = ::tokio_rustls::TlsStream<TcpStream, ClientSession>;
+
+quick_error! {
+    /// Error returned by `verify_host`
+    #[derive(Debug)]
+    pub enum VerifyError {
+        /// Host is not a valid DNS name
+        InvalidHost {
+            description("host is not a valid DNS name")
+        }
+        /// Certificate can't be parsed
+        BadCertificate(err: webpki::Error) {
+            description("can't parse certificate")
+            display("can't parse certificate: {:?}", err)
+        }
+        /// No name in the certificate matches the host
+        HostMismatch {
+            description("certificate is not valid for the host")
+        }
+    }
+}
+
+/// Check that certificate is issued for the host
+///
+/// The port is stripped from the `host` if present, so the value of the
+/// `Host` header may be passed as is. Names are matched against
+/// `subjectAltName` of the certificate, wildcards match a single
+/// leftmost label as described in RFC 6125 (`*.example.com` matches
+/// `www.example.com` but neither `example.com` nor `a.b.example.com`).
+/// Like browsers do, the `commonName` is not checked.
+///
+/// Note: this doesn't validate the certificate chain, it's done by rustls
+/// during the handshake.
+pub fn verify_host(cert: &Certificate, host: &str)
+    -> Result<(), VerifyError>
+{
+    let name = DNSNameRef::try_from_ascii_str(strip_port(host))
+        .map_err(|()| VerifyError::InvalidHost)?;
+    let cert = EndEntityCert::from(Input::from(&cert.0))
+        .map_err(VerifyError::BadCertificate)?;
+    cert.verify_is_valid_for_dns_name(name)
+        .map_err(|_| VerifyError::HostMismatch)
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(idx) if !host.starts_with('[') &&
+            host[idx+1..].bytes().all(|x| x.is_ascii_digit())
+        => &host[..idx],
+        _ => host,
+    }
+}
+
+impl<A> HandshakeProto<TlsStream, A>
+    where A: Authorizer<TlsStream> + 'static,
+{
+    /// Connect to a `wss://` endpoint and do websocket handshake
+    ///
+    /// The `domain` is used both for SNI and for certificate validation,
+    /// so it must be a valid DNS name (not an IP address). The `Host` header
+    /// is still written by the authorizer. After the TLS handshake the
+    /// server certificate is checked by `verify_host` for the `domain`.
+    ///
+    /// Resolves to the same value as the `HandshakeProto` itself.
+    pub fn connect_tls(addr: SocketAddr, domain: &str,
+        connector: &Arc<ClientConfig>, authorizer: A, handle: &Handle)
+        -> Box<Future<Item=(WriteFramed<TlsStream, ClientCodec>,
+                            ReadFramed<TlsStream, ClientCodec>,
+                            A::Result),
+                      Error=Error>>
+    {
+        if DNSNameRef::try_from_ascii_str(domain).is_err() {
+            return Box::new(err(ErrorEnum::InvalidDomain.into()));
+        }
+        let domain = domain.to_string();
+        let host = domain.clone();
+        let connector = connector.clone();
+        Box::new(
+            TcpStream::connect(&addr, handle)
+            .and_then(move |sock| {
+                let name = DNSNameRef::try_from_ascii_str(&domain)
+                    .expect("domain is checked");
+                connector.connect_async(name, sock)
+            })
+            .map_err(ErrorEnum::Io).map_err(Error::from)
+            .and_then(move |sock| {
+                check_peer(&sock, &host)?;
+                Ok(sock)
+            })
+            .and_then(move |sock| HandshakeProto::new(sock, authorizer)))
+    }
+}
+
+fn check_peer(sock: &TlsStream, host: &str) -> Result<(), Error> {
+    let certs = sock.get_ref().1.get_peer_certificates()
+        .unwrap_or_else(Vec::new);
+    match certs.first() {
+        Some(cert) => verify_host(cert, host).map_err(Error::custom),
+        None => Err(Error::custom(VerifyError::HostMismatch)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio_core::reactor::Core;
+
+    use websocket::client::{HandshakeProto, SimpleAuthorizer};
+    use websocket::error::ErrorEnum;
+    use rustls::Certificate;
+    use super::{ClientConfig, VerifyError, verify_host, strip_port};
+
+    /// Self-signed certificate for `*.example.com` and `example.com`
+    const WILDCARD: &'static [u8] = include_bytes!("testdata/wildcard.der");
+
+    #[test]
+    fn ip_is_not_a_domain() {
+        let mut core = Core::new().unwrap();
+        let res = core.run(HandshakeProto::connect_tls(
+            "127.0.0.1:443".parse().unwrap(), "127.0.0.1",
+            &Arc::new(ClientConfig::new()),
+            SimpleAuthorizer::new("127.0.0.1", "/"), &core.handle()));
+        match res {
+            Err(e) => assert_eq!(e.to_string(),
+                                 ErrorEnum::InvalidDomain.to_string()),
+            Ok(_) => panic!("connection must fail"),
+        }
+    }
+
+    #[test]
+    fn port() {
+        assert_eq!(strip_port("example.com:8443"), "example.com");
+        assert_eq!(strip_port("example.com"), "example.com");
+        assert_eq!(strip_port("[::1]:443"), "[::1]:443");
+    }
+
+    #[test]
+    fn wildcard() {
+        let cert = Certificate(WILDCARD.to_vec());
+        assert!(verify_host(&cert, "example.com").is_ok());
+        assert!(verify_host(&cert, "www.example.com:443").is_ok());
+        assert!(verify_host(&cert, "WWW.Example.com").is_ok());
+        assert_matches!(verify_host(&cert, "a.b.example.com"),
+                        Err(VerifyError::HostMismatch));
+        assert_matches!(verify_host(&cert, "example.org"),
+                        Err(VerifyError::HostMismatch));
+        assert_matches!(verify_host(&cert, "127.0.0.1"),
+                        Err(VerifyError::InvalidHost));
+        assert_matches!(verify_host(&Certificate(vec![1, 2, 3]), "x.com"),
+                        Err(VerifyError::BadCertificate(_)));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::io;
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+use tk_bufstream::{Encode, WriteFramed};
+use tokio_io::AsyncWrite;
+
+
+/// Default number of bytes in the output buffer above which packets are
+/// kept in the queue instead of being serialized
+const WATERMARK: usize = 65536;
+
+
+/// A bounded queue of outgoing packets in front of `WriteFramed`
+///
+/// `WriteFramed` serializes every packet into the output buffer right away
+/// and never rejects a packet, so if peer reads slower than packets are
+/// produced the buffer grows without a limit. This wrapper serializes
+/// packets only while the output buffer is below the watermark, keeps at
+/// most `capacity` packets in the queue, and returns `AsyncSink::NotReady`
+/// from `start_send` when the queue is full.
+///
+/// Like any other sink, it must be polled with `poll_complete` for queued
+/// packets to make progress.
+pub struct WriteQueue<S, C: Encode> {
+    output: WriteFramed<S, C>,
+    queue: VecDeque<C::Item>,
+    capacity: usize,
+    watermark: usize,
+}
+
+impl<S, C: Encode> WriteQueue<S, C> {
+    /// Wrap the output stream, queueing at most `capacity` packets
+    ///
+    /// # Panics
+    ///
+    /// When `capacity` is zero.
+    pub fn new(output: WriteFramed<S, C>, capacity: usize)
+        -> WriteQueue<S, C>
+    {
+        assert!(capacity > 0, "queue capacity must be non-zero");
+        WriteQueue {
+            output: output,
+            queue: VecDeque::with_capacity(capacity),
+            capacity: capacity,
+            watermark: WATERMARK,
+        }
+    }
+    /// Set the size of the output buffer above which packets are queued
+    ///
+    /// Default is 64 KiB.
+    pub fn watermark(mut self, bytes: usize) -> WriteQueue<S, C> {
+        self.watermark = bytes;
+        self
+    }
+    /// Returns number of packets waiting in the queue
+    ///
+    /// This doesn't include packets already serialized into the buffer.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+    /// Returns `true` if there are no packets waiting in the queue
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+    /// Returns `true` if the queue is full, so `start_send` would not
+    /// accept a packet until some data is flushed
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
+    }
+    /// Returns bytes lying in the output buffer
+    pub fn bytes_buffered(&self) -> usize {
+        self.output.get_ref().out_buf.len()
+    }
+}
+
+impl<S: AsyncWrite, C: Encode> WriteQueue<S, C> {
+    /// Returns the underlying output stream
+    ///
+    /// Packets remaining in the queue are serialized into the output buffer
+    /// so they are not lost.
+    pub fn into_inner(mut self) -> WriteFramed<S, C> {
+        for item in self.queue.drain(..) {
+            // WriteFramed neither fails nor returns NotReady on start_send
+            self.output.start_send(item)
+                .expect("can always write into a buffer");
+        }
+        self.output
+    }
+    fn serialize(&mut self) {
+        while self.bytes_buffered() < self.watermark {
+            match self.queue.pop_front() {
+                Some(item) => {
+                    self.output.start_send(item)
+                        .expect("can always write into a buffer");
+                }
+                None => break,
+            }
+        }
+    }
+    fn flush(&mut self) -> Result<(), io::Error> {
+        loop {
+            self.serialize();
+            let before = self.bytes_buffered();
+            self.output.poll_complete()?;
+            let after = self.bytes_buffered();
+            // flush stops at `WouldBlock`, so we may only retry if more
+            // packets were serialized after the buffer is drained
+            if self.queue.len() == 0 || after >= before {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite, C: Encode> Sink for WriteQueue<S, C> {
+    type SinkItem = C::Item;
+    type SinkError = io::Error;
+    fn start_send(&mut self, item: C::Item)
+        -> StartSend<C::Item, io::Error>
+    {
+        if self.is_full() {
+            self.flush()?;
+            if self.is_full() {
+                return Ok(AsyncSink::NotReady(item));
+            }
+        }
+        self.queue.push_back(item);
+        self.serialize();
+        Ok(AsyncSink::Ready)
+    }
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.flush()?;
+        if self.queue.len() == 0 && self.bytes_buffered() == 0 {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::{Cell, RefCell};
+    use std::io::{self, Read, Write};
+    use std::rc::Rc;
+
+    use futures::{AsyncSink, Async, Sink};
+    use futures::future::{lazy, Future};
+    use tk_bufstream::IoBuf;
+    use tokio_io::{AsyncRead, AsyncWrite};
+
+    use websocket::{Packet, ServerCodec};
+    use super::WriteQueue;
+
+    /// A connection which accepts only `budget` bytes
+    #[derive(Clone)]
+    struct Slow {
+        output: Rc<RefCell<Vec<u8>>>,
+        budget: Rc<Cell<usize>>,
+    }
+
+    impl Read for Slow {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+    impl AsyncRead for Slow {}
+    impl Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let bytes = ::std::cmp::min(buf.len(), self.budget.get());
+            if bytes == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.budget.set(self.budget.get() - bytes);
+            self.output.borrow_mut().extend(&buf[..bytes]);
+            Ok(bytes)
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+    impl AsyncWrite for Slow {
+        fn shutdown(&mut self) -> io::Result<Async<()>> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn text(n: usize) -> Packet {
+        Packet::Text(format!("{:03}", n))
+    }
+
+    #[test]
+    fn backpressure() {
+        lazy(|| {
+            let conn = Slow {
+                output: Rc::new(RefCell::new(Vec::new())),
+                budget: Rc::new(Cell::new(0)),
+            };
+            let (tx, _rx) = IoBuf::new(conn.clone()).split();
+            let mut queue = WriteQueue::new(tx.framed(ServerCodec), 2)
+                .watermark(10);
+            // two packets of 5 bytes are serialized, two more are queued
+            for i in 0..4 {
+                assert_matches!(queue.start_send(text(i)),
+                                Ok(AsyncSink::Ready));
+            }
+            assert_eq!(queue.bytes_buffered(), 10);
+            assert_eq!(queue.len(), 2);
+            assert!(queue.is_full());
+            assert_matches!(queue.start_send(text(4)),
+                            Ok(AsyncSink::NotReady(Packet::Text(_))));
+            assert_matches!(queue.poll_complete(), Ok(Async::NotReady));
+
+            conn.budget.set(7);
+            assert_matches!(queue.start_send(text(4)),
+                            Ok(AsyncSink::Ready));
+            assert_eq!(queue.len(), 1);
+            assert_eq!(queue.bytes_buffered(), 13);
+
+            conn.budget.set(100);
+            assert_matches!(queue.poll_complete(), Ok(Async::Ready(())));
+            assert_eq!(queue.len(), 0);
+            let output = conn.output.borrow();
+            assert_eq!(output.len(), 25);
+            assert_eq!(&output[..5], b"\x81\x03000");
+            assert_eq!(&output[20..], b"\x81\x03004");
+            Ok::<(), ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -5,6 +5,7 @@ use tk_bufstream::Buf;
 use byteorder::{BigEndian, ByteOrder};
 
 use super::{Packet};
+use websocket::close::parse_payload;
 use websocket::error::ErrorEnum;
 
 
@@ -51,79 +52,257 @@ impl<'a> Into<Packet> for &'a Frame<'a> {
     }
 }
 
+/// A builder of a frame with arbitrary header bits
+///
+/// This is useful for extensions, which use reserved bits to mark frames
+/// (e.g. `RSV1` marks a compressed message). Nothing is validated, so
+/// it's up to the caller to negotiate the extension first.
+#[derive(Debug, Clone)]
+pub struct FrameBuilder<'a> {
+    fin: bool,
+    rsv: u8,
+    opcode: u8,
+    data: &'a [u8],
+}
+
+/// A frame header and a payload, without interpreting the opcode
+///
+/// This is used by codecs that need to handle reserved bits (extensions)
+#[derive(Debug)]
+pub(crate) struct RawFrame<'a> {
+    pub fin: bool,
+    /// Reserved bits, shifted to the lowest three bits (RSV1 is `0x4`)
+    pub rsv: u8,
+    pub opcode: u8,
+    pub data: &'a [u8],
+}
+
+/// A header of a websocket frame
+///
+/// Returned by `ClientCodec::decode_header`, it's useful for extensions
+/// that need to know reserved bits or the opcode before decoding payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    fin: bool,
+    rsv: u8,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    payload_len: u64,
+}
+
+impl FrameHeader {
+    /// Returns `true` if this is the last frame of a message
+    pub fn fin(&self) -> bool {
+        self.fin
+    }
+    /// Returns `true` if reserved bit 1 is set (e.g. a compressed message)
+    pub fn rsv1(&self) -> bool {
+        self.rsv & 0x4 != 0
+    }
+    /// Returns `true` if reserved bit 2 is set
+    pub fn rsv2(&self) -> bool {
+        self.rsv & 0x2 != 0
+    }
+    /// Returns `true` if reserved bit 3 is set
+    pub fn rsv3(&self) -> bool {
+        self.rsv & 0x1 != 0
+    }
+    /// Returns the opcode (`0x0` for continuation frames)
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+    /// Returns the masking key if payload is masked
+    pub fn mask(&self) -> Option<[u8; 4]> {
+        self.mask
+    }
+    /// Returns the length of the payload
+    pub fn payload_len(&self) -> u64 {
+        self.payload_len
+    }
+}
+
+/// Parse a frame header
+///
+/// Returns a header and its length (including the masking key) or None
+/// if the buffer doesn't contain full header yet.
+pub(crate) fn parse_header(buf: &[u8], masked: bool)
+    -> Result<Option<(FrameHeader, usize)>, ErrorEnum>
+{
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    // Client must mask every frame, server must not mask any (RFC 6455,
+    // section 5.1), check it early so we don't wait for bytes that never come
+    let mask = buf[1] & 0x80 != 0;
+    if mask && !masked {
+        return Err(ErrorEnum::Masked);
+    }
+    if !mask && masked {
+        return Err(ErrorEnum::Unmasked);
+    }
+    let (size, fsize) = {
+        match buf[1] & 0x7F {
+            126 => {
+                if buf.len() < 4 {
+                    return Ok(None);
+                }
+                (BigEndian::read_u16(&buf[2..4]) as u64, 4)
+            }
+            127 => {
+                if buf.len() < 10 {
+                    return Ok(None);
+                }
+                (BigEndian::read_u64(&buf[2..10]), 10)
+            }
+            size => (size as u64, 2),
+        }
+    };
+    let start = fsize + if masked { 4 } else { 0 } /* mask size */;
+    if buf.len() < start {
+        return Ok(None);
+    }
+    let header = FrameHeader {
+        fin: buf[0] & 0x80 != 0,
+        rsv: (buf[0] & 0x70) >> 4,
+        opcode: buf[0] & 0x0F,
+        mask: if mask {
+            Some([buf[start-4], buf[start-3], buf[start-2], buf[start-1]])
+        } else {
+            None
+        },
+        payload_len: size,
+    };
+    Ok(Some((header, start)))
+}
+
+/// Parse a frame header and unmask payload in-place
+///
+/// Returns a frame and a number of bytes or None if no full frame was
+/// in the buffer.
+pub(crate) fn parse_raw<'x>(buf: &'x mut Buf, limit: usize, masked: bool)
+    -> Result<Option<(RawFrame<'x>, usize)>, ErrorEnum>
+{
+    let (header, start) = match parse_header(&buf[..], masked)? {
+        Some(pair) => pair,
+        None => return Ok(None),
+    };
+    if header.payload_len > limit as u64 {
+        return Err(ErrorEnum::FrameTooLarge {
+            size: header.payload_len,
+            limit: limit,
+        });
+    }
+    let size = header.payload_len as usize;
+    if buf.len() < start + size {
+        return Ok(None);
+    }
+    if let Some(mask) = header.mask {
+        apply_mask(&mut buf[start..(start + size)], mask);
+    }
+    let frame = RawFrame {
+        fin: header.fin,
+        rsv: header.rsv,
+        opcode: header.opcode,
+        data: &buf[start..(start + size)],
+    };
+    return Ok(Some((frame, start + size)));
+}
+
+impl<'a> FrameBuilder<'a> {
+    /// Start a frame with the specified opcode and payload
+    ///
+    /// By default the frame is final and all the reserved bits are unset.
+    ///
+    /// # Panics
+    ///
+    /// When opcode doesn't fit four bits
+    pub fn new(opcode: u8, data: &'a [u8]) -> FrameBuilder<'a> {
+        assert!(opcode & 0xF0 == 0, "opcode must fit four bits");
+        FrameBuilder {
+            fin: true,
+            rsv: 0,
+            opcode: opcode,
+            data: data,
+        }
+    }
+    /// Set `FIN` bit, i.e. whether it's the last frame of a message
+    pub fn fin(mut self, value: bool) -> FrameBuilder<'a> {
+        self.fin = value;
+        self
+    }
+    /// Set `RSV1` bit
+    pub fn rsv1(self, value: bool) -> FrameBuilder<'a> {
+        self.rsv_bit(0x4, value)
+    }
+    /// Set `RSV2` bit
+    pub fn rsv2(self, value: bool) -> FrameBuilder<'a> {
+        self.rsv_bit(0x2, value)
+    }
+    /// Set `RSV3` bit
+    pub fn rsv3(self, value: bool) -> FrameBuilder<'a> {
+        self.rsv_bit(0x1, value)
+    }
+    fn rsv_bit(mut self, bit: u8, value: bool) -> FrameBuilder<'a> {
+        if value {
+            self.rsv |= bit;
+        } else {
+            self.rsv &= !bit;
+        }
+        self
+    }
+    /// Write a frame into specified buffer
+    ///
+    /// `masked` should be true for client socket and false for servers socket
+    /// according to the spec
+    pub fn write(&self, buf: &mut Buf, masked: bool) {
+        let fin = if self.fin { 0x80 } else { 0 };
+        write_raw(buf, fin | (self.rsv << 4) | self.opcode, self.data, masked)
+    }
+}
+
 impl<'a> Frame<'a> {
     /// Parse a frame for the specified buffer
     ///
     /// Returns a frame and a number of bytes or None if no full frame was
     /// in the buffer. After frame is processes you should use
     /// `buf.consume(nbytes)`.
+    ///
+    /// Frames with reserved bits set are rejected, as no extensions are
+    /// supported by this method.
     pub fn parse<'x>(buf: &'x mut Buf, limit: usize, masked: bool)
         -> Result<Option<(Frame<'x>, usize)>, ErrorEnum>
     {
-        use self::Frame::*;
-
-        if buf.len() < 2 {
-            return Ok(None);
-        }
-        let (size, fsize) = {
-            match buf[1] & 0x7F {
-                126 => {
-                    if buf.len() < 4 {
-                        return Ok(None);
-                    }
-                    (BigEndian::read_u16(&buf[2..4]) as u64, 4)
+        match parse_raw(buf, limit, masked)? {
+            Some((raw, nbytes)) => {
+                if raw.rsv != 0 {
+                    return Err(ErrorEnum::InvalidRsvBits(raw.rsv));
                 }
-                127 => {
-                    if buf.len() < 10 {
-                        return Ok(None);
-                    }
-                    (BigEndian::read_u64(&buf[2..10]), 10)
+                if !raw.fin {
+                    return Err(ErrorEnum::Fragmented);
                 }
-                size => (size as u64, 2),
+                Ok(Some((Frame::from_raw(raw.opcode, raw.data)?, nbytes)))
             }
-        };
-        if size > limit as u64 {
-            return Err(ErrorEnum::TooLong);
-        }
-        let size = size as usize;
-        let start = fsize + if masked { 4 } else { 0 } /* mask size */;
-        if buf.len() < start + size {
-            return Ok(None);
+            None => Ok(None),
         }
+    }
 
-        let fin = buf[0] & 0x80 != 0;
-        let opcode = buf[0] & 0x0F;
-        // TODO(tailhook) should we assert that reserved bits are zero?
-        let mask = buf[1] & 0x80 != 0;
-        if !fin {
-            return Err(ErrorEnum::Fragmented);
-        }
-        if mask != masked {
-            return Err(ErrorEnum::Unmasked);
-        }
-        if mask {
-            let mask = [buf[start-4], buf[start-3], buf[start-2], buf[start-1]];
-            for idx in 0..size { // hopefully llvm is smart enough to optimize it
-                buf[start + idx] ^= mask[idx % 4];
-            }
-        }
-        let data = &buf[start..(start + size)];
+    /// Make a frame from opcode and (already unmasked) payload
+    pub(crate) fn from_raw(opcode: u8, data: &'a [u8])
+        -> Result<Frame<'a>, ErrorEnum>
+    {
+        use self::Frame::*;
         let frame = match opcode {
             0x9 => Ping(data),
             0xA => Pong(data),
             0x1 => Text(from_utf8(data)?),
             0x2 => Binary(data),
-            // TODO(tailhook) implement shutdown packets
             0x8 => {
-                if data.len() < 2 {
-                    Close(1006, "")
-                } else {
-                    Close(BigEndian::read_u16(&data[..2]), from_utf8(&data[2..])?)
-                }
+                let (code, reason) = parse_payload(data)?;
+                Close(code, reason)
             }
             x => return Err(ErrorEnum::InvalidOpcode(x)),
         };
-        return Ok(Some((frame, start + size)));
+        Ok(frame)
     }
 
     /// Write a frame into specified buffer
@@ -145,9 +324,27 @@ impl<'a> Frame<'a> {
 pub(crate) fn write_packet(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool)
 {
     debug_assert!(opcode & 0xF0 == 0);
-    let first_byte = opcode | 0x80;  // always fin
-    let mask_bit = if mask { 0x80 } else { 0 };
-    match data.len() {
+    write_raw(buf, opcode | 0x80 /* always fin */, data, mask)
+}
+
+/// Write a frame with the first byte (fin, rsv bits and opcode) as specified
+pub(crate) fn write_raw(buf: &mut Buf, first_byte: u8, data: &[u8], mask: bool)
+{
+    let key = if mask { Some(mask_key()) } else { None };
+    write_header(buf, first_byte, data.len(), key);
+    let start = buf.len();
+    buf.extend(data);
+    if let Some(key) = key {
+        apply_mask(&mut buf[start..], key);
+    }
+}
+
+/// Write a frame header (including the mask key if any) without payload
+pub(crate) fn write_header(buf: &mut Buf, first_byte: u8, len: usize,
+    mask: Option<[u8; 4]>)
+{
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match len {
         len @ 0...125 => {
             buf.extend(&[first_byte, (len as u8) | mask_bit]);
         }
@@ -167,20 +364,23 @@ pub(crate) fn write_packet(buf: &mut Buf, opcode: u8, data: &[u8], mask: bool)
                 (len & 0xFF) as u8]);
         }
     }
-    let mask_data = if mask {
-        let mut bytes = [0u8; 4];
-        thread_rng().fill_bytes(&mut bytes[..]);
-        buf.extend(&bytes[..]);
-        Some((buf.len(), bytes))
-    } else {
-        None
-    };
-    buf.extend(data);
-    if let Some((start, bytes)) = mask_data {
-        for idx in 0..(buf.len() - start) { // hopefully llvm will optimize it
-            buf[start + idx] ^= bytes[idx % 4];
-        }
-    };
+    if let Some(key) = mask {
+        buf.extend(&key[..]);
+    }
+}
+
+/// Generate a random mask key for a client frame
+pub(crate) fn mask_key() -> [u8; 4] {
+    let mut bytes = [0u8; 4];
+    thread_rng().fill_bytes(&mut bytes[..]);
+    bytes
+}
+
+/// Mask (or unmask) payload in-place
+pub(crate) fn apply_mask(data: &mut [u8], key: [u8; 4]) {
+    for idx in 0..data.len() { // hopefully llvm will optimize it
+        data[idx] ^= key[idx % 4];
+    }
 }
 
 /// Write close message to websocket
@@ -210,7 +410,7 @@ pub(crate) fn write_close(buf: &mut Buf, code: u16, reason: &str, mask: bool) {
 mod test {
     use netbuf::Buf;
     use std::iter::repeat;
-    use super::Frame;
+    use super::{Frame, FrameBuilder, parse_header};
     use super::Frame::*;
 
     #[test]
@@ -226,7 +426,26 @@ mod test {
         let data = b"\x88\x80\x00\x00\x00\x00";
         buf.extend(data);
         assert_eq!(Frame::parse(&mut buf, 1000, true).unwrap(),
-                   Some((Close(1006, ""), 6)));
+                   Some((Close(1005, ""), 6)));
+    }
+
+    #[test]
+    fn close_frame_payload() {
+        let mut buf = Buf::new();
+        buf.extend(b"\x88\x05\x03\xe8bye");
+        assert_eq!(Frame::parse(&mut buf, 1000, false).unwrap(),
+                   Some((Close(1000, "bye"), 7)));
+        // single byte payload
+        let mut buf = Buf::new();
+        buf.extend(b"\x88\x01\x03");
+        assert!(Frame::parse(&mut buf, 1000, false).is_err());
+        // codes that must not be sent: 1005, 1006, 1015, 999
+        for code in &[b"\x03\xed", b"\x03\xee", b"\x03\xf7", b"\x03\xe7"] {
+            let mut buf = Buf::new();
+            buf.extend(b"\x88\x02");
+            buf.extend(&code[..]);
+            assert!(Frame::parse(&mut buf, 1000, false).is_err());
+        }
     }
 
     #[test]
@@ -334,4 +553,45 @@ mod test {
         assert_eq!(Frame::parse(&mut buf, 4096, false).unwrap(),
             Some((Text(&repeat('x').take(4096).collect::<String>()), 4100)));
     }
+
+    #[test]
+    fn masking_mismatch() {
+        use websocket::error::ErrorEnum;
+        // masked frame from server
+        let mut buf = Buf::new();
+        buf.extend(b"\x81\x85\x00\x00");
+        assert_matches!(Frame::parse(&mut buf, 1000, false),
+                        Err(ErrorEnum::Masked));
+        // unmasked frame from client
+        let mut buf = Buf::new();
+        buf.extend(b"\x81\x05hel");
+        assert_matches!(Frame::parse(&mut buf, 1000, true),
+                        Err(ErrorEnum::Unmasked));
+    }
+
+    #[test]
+    fn rsv_bits() {
+        use websocket::error::ErrorEnum;
+        let mut buf = Buf::new();
+        FrameBuilder::new(0x1, b"hi").rsv1(true).rsv3(true)
+            .write(&mut buf, false);
+        assert_eq!(&buf[..], b"\xD1\x02hi");
+        let (header, _) = parse_header(&buf[..], false).unwrap().unwrap();
+        assert!(header.rsv1() && !header.rsv2() && header.rsv3());
+        assert_matches!(Frame::parse(&mut buf, 1000, false),
+                        Err(ErrorEnum::InvalidRsvBits(0x5)));
+        let mut buf = Buf::new();
+        FrameBuilder::new(0x2, b"x").fin(false).rsv2(true).rsv2(false)
+            .write(&mut buf, false);
+        assert_eq!(&buf[..], b"\x02\x01x");
+    }
+
+    #[test]
+    fn write_masked() {
+        let mut buf = Buf::new();
+        Frame::Text("hello").write(&mut buf, true);
+        assert_eq!(&buf[..2], b"\x81\x85");
+        assert_eq!(Frame::parse(&mut buf, 1000, true).unwrap(),
+            Some((Text("hello"), 11)));
+    }
 }